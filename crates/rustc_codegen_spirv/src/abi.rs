@@ -9,12 +9,13 @@ use rustc_errors::ErrorReported;
 use rustc_middle::bug;
 use rustc_middle::ty::layout::{FnAbiExt, TyAndLayout};
 use rustc_middle::ty::subst::SubstsRef;
-use rustc_middle::ty::{GeneratorSubsts, PolyFnSig, Ty, TyKind, TypeAndMut};
+use rustc_middle::ty::{GeneratorSubsts, ParamEnv, PolyFnSig, Ty, TyKind, TypeAndMut};
 use rustc_span::def_id::DefId;
 use rustc_span::Span;
 use rustc_target::abi::call::{CastTarget, FnAbi, PassMode, Reg, RegKind};
 use rustc_target::abi::{
-    Abi, Align, FieldsShape, LayoutOf, Primitive, Scalar, Size, TagEncoding, VariantIdx, Variants,
+    Abi, Align, FieldsShape, Integer, LayoutOf, Primitive, Scalar, Size, TagEncoding, VariantIdx,
+    Variants,
 };
 use std::cell::RefCell;
 use std::collections::hash_map::Entry;
@@ -116,6 +117,8 @@ impl<'tcx> RecursivePointeeCache<'tcx> {
 enum PointeeTy<'tcx> {
     Ty(TyAndLayout<'tcx>),
     Fn(PolyFnSig<'tcx>),
+    /// The vtable of a `dyn Trait` (see `CodegenCx::type_vtable_ptr`).
+    Vtable,
 }
 
 impl fmt::Display for PointeeTy<'_> {
@@ -123,6 +126,7 @@ impl fmt::Display for PointeeTy<'_> {
         match self {
             PointeeTy::Ty(ty) => write!(f, "{}", ty.ty),
             PointeeTy::Fn(ty) => write!(f, "{}", ty),
+            PointeeTy::Vtable => write!(f, "vtable"),
         }
     }
 }
@@ -151,12 +155,17 @@ impl<'tcx> ConvSpirvType<'tcx> for PointeeTy<'tcx> {
         match *self {
             PointeeTy::Ty(ty) => ty.spirv_type(span, cx),
             PointeeTy::Fn(ty) => FnAbi::of_fn_ptr(cx, ty, &[]).spirv_type(span, cx),
+            PointeeTy::Vtable => SpirvType::Opaque {
+                name: crate::linker::VTABLE_TYPE_NAME.to_string(),
+            }
+            .def(span, cx),
         }
     }
     fn spirv_type_immediate(&self, span: Span, cx: &CodegenCx<'tcx>) -> Word {
         match *self {
             PointeeTy::Ty(ty) => ty.spirv_type_immediate(span, cx),
             PointeeTy::Fn(ty) => FnAbi::of_fn_ptr(cx, ty, &[]).spirv_type_immediate(span, cx),
+            PointeeTy::Vtable => self.spirv_type(span, cx),
         }
     }
 }
@@ -414,6 +423,9 @@ fn trans_scalar<'tcx>(
     }
 
     match scalar.value {
+        Primitive::Int(width, _) if width.size().bits() == 16 && is_f16_scalar(cx, ty, index) => {
+            SpirvType::Float(16).def(span, cx)
+        }
         Primitive::Int(width, mut signedness) => {
             if cx.kernel_mode {
                 signedness = false;
@@ -442,6 +454,46 @@ fn trans_scalar<'tcx>(
     }
 }
 
+/// `#[spirv(f16)]` types have the layout of a `u16`, so they get passed around as integer scalars
+/// by rustc. When a 16-bit integer scalar is found in a type wrapping one of them (e.g. a newtype,
+/// or a `(f16, f16)` pair), the scalar has to be an `OpTypeFloat 16` as well, to match the type of
+/// the field it came from.
+fn is_f16_scalar<'tcx>(cx: &CodegenCx<'tcx>, ty: TyAndLayout<'tcx>, index: Option<usize>) -> bool {
+    if let TyKind::Adt(adt, _) = *ty.ty.kind() {
+        let attrs = AggregatedSpirvAttributes::parse(cx, cx.tcx.get_attrs(adt.did));
+        if let Some(IntrinsicType::Float16) = attrs.intrinsic_type.map(|attr| attr.value) {
+            return index.is_none();
+        }
+    }
+    match *ty.ty.kind() {
+        TyKind::Tuple(_) | TyKind::Adt(..) | TyKind::Closure(..) => {}
+        _ => return false,
+    }
+    if let Variants::Multiple { .. } = ty.variants {
+        return false;
+    }
+    let offset = match (&ty.abi, index) {
+        (Abi::Scalar(_), None) | (Abi::ScalarPair(..), Some(0)) => Size::ZERO,
+        (Abi::ScalarPair(one, two), Some(1)) => {
+            one.value.size(cx).align_to(two.value.align(cx).abi)
+        }
+        _ => return false,
+    };
+    (0..ty.fields.count()).any(|i| {
+        let field = ty.field(cx, i);
+        if field.is_zst() {
+            false
+        } else if field.size == ty.size {
+            // Newtype, forward the scalar (pair) index into the field.
+            is_f16_scalar(cx, field, index)
+        } else if let Abi::Scalar(_) = field.abi {
+            ty.fields.offset(i) == offset && is_f16_scalar(cx, field, None)
+        } else {
+            false
+        }
+    })
+}
+
 // This is a really weird function, strap in...
 // So, rustc_codegen_ssa is designed around scalar pointers being opaque, you shouldn't know the type behind the
 // pointer. Unfortunately, that's impossible for us, we need to know the underlying pointee type for various reasons. In
@@ -464,7 +516,14 @@ fn dig_scalar_pointee<'tcx>(
                 None => PointeeTy::Ty(elem),
                 Some(index) => {
                     if elem.is_unsized() {
-                        dig_scalar_pointee(cx, ty.field(cx, index), None)
+                        let tail = cx
+                            .tcx
+                            .struct_tail_erasing_lifetimes(elem_ty, ParamEnv::reveal_all());
+                        if index == 1 && matches!(tail.kind(), TyKind::Dynamic(..)) {
+                            PointeeTy::Vtable
+                        } else {
+                            dig_scalar_pointee(cx, ty.field(cx, index), None)
+                        }
                     } else {
                         // This can sometimes happen in weird cases when going through the Adt case below - an ABI
                         // of ScalarPair could be deduced, but it's actually e.g. a sized pointer followed by some other
@@ -810,5 +869,76 @@ fn trans_intrinsic_type<'tcx>(
                 Err(ErrorReported)
             }
         }
+        IntrinsicType::Float16 => {
+            let float16 = SpirvType::Float(16).def(span, cx);
+            match ty.abi {
+                Abi::Scalar(ref scalar) if scalar.value == Primitive::Int(Integer::I16, false) => {
+                    Ok(float16)
+                }
+                Abi::Vector { ref element, count }
+                    if element.value == Primitive::Int(Integer::I16, false) =>
+                {
+                    Ok(SpirvType::Vector {
+                        element: float16,
+                        count: count as u32,
+                    }
+                    .def(span, cx))
+                }
+                _ => {
+                    cx.tcx
+                        .sess
+                        .err("#[spirv(f16)] type must wrap a `u16`, or be a SIMD vector of `u16`s");
+                    Err(ErrorReported)
+                }
+            }
+        }
+        IntrinsicType::Matrix => {
+            let field_types = ty
+                .fields
+                .index_by_increasing_offset()
+                .map(|i| trans_type_impl(cx, span, ty.field(cx, i), false))
+                .collect::<Vec<_>>();
+            let element = match field_types[..] {
+                [first, ..] if field_types.iter().all(|&field| field == first) => first,
+                _ => {
+                    cx.tcx
+                        .sess
+                        .err("#[spirv(matrix)] type fields must all be the same type");
+                    return Err(ErrorReported);
+                }
+            };
+            match cx.lookup_type(element) {
+                SpirvType::Vector { element, .. }
+                    if matches!(cx.lookup_type(element), SpirvType::Float(_)) => {}
+                other => {
+                    cx.tcx
+                        .sess
+                        .struct_err("#[spirv(matrix)] type fields must all be float vectors")
+                        .note(&format!("field type is {}", other.debug(element, cx)))
+                        .emit();
+                    return Err(ErrorReported);
+                }
+            }
+            if !(2..=4).contains(&field_types.len()) {
+                cx.tcx
+                    .sess
+                    .err("#[spirv(matrix)] type must have 2, 3, or 4 columns");
+                return Err(ErrorReported);
+            }
+            // see SpirvType::sizeof - columns must be tightly packed, as that is
+            // what the `MatrixStride` decoration of the matrix will assume.
+            let column_size = cx.lookup_type(element).sizeof(cx).unwrap();
+            if ty.size != column_size * field_types.len() as u64 {
+                cx.tcx
+                    .sess
+                    .err("#[spirv(matrix)] type must not contain any padding between columns");
+                return Err(ErrorReported);
+            }
+            Ok(SpirvType::Matrix {
+                element,
+                count: field_types.len() as u32,
+            }
+            .def(span, cx))
+        }
     }
 }
@@ -72,6 +72,8 @@ pub enum IntrinsicType {
     },
     Sampler,
     SampledImage,
+    Matrix,
+    Float16,
 }
 
 // NOTE(eddyb) when adding new `#[spirv(...)]` attributes, the tests found inside
@@ -94,7 +96,8 @@ pub enum SpirvAttribute {
     Invariant,
 
     // `fn`/closure attributes:
-    UnrollLoops,
+    /// With the maximum trip count of loops to unroll, if given (`unroll_loops(max = N)`).
+    UnrollLoops(Option<u32>),
 }
 
 // HACK(eddyb) this is similar to `rustc_span::Spanned` but with `value` as the
@@ -126,7 +129,7 @@ pub struct AggregatedSpirvAttributes {
     pub invariant: Option<Spanned<()>>,
 
     // `fn`/closure attributes:
-    pub unroll_loops: Option<Spanned<()>>,
+    pub unroll_loops: Option<Spanned<Option<u32>>>,
 }
 
 struct MultipleAttrs {
@@ -207,7 +210,12 @@ impl AggregatedSpirvAttributes {
             Binding(value) => try_insert(&mut self.binding, value, span, "#[spirv(binding)]"),
             Flat => try_insert(&mut self.flat, (), span, "#[spirv(flat)]"),
             Invariant => try_insert(&mut self.invariant, (), span, "#[spirv(invariant)]"),
-            UnrollLoops => try_insert(&mut self.unroll_loops, (), span, "#[spirv(unroll_loops)]"),
+            UnrollLoops(value) => try_insert(
+                &mut self.unroll_loops,
+                value,
+                span,
+                "#[spirv(unroll_loops)]",
+            ),
         }
     }
 }
@@ -333,7 +341,7 @@ impl CheckSpirvAttrVisitor<'_> {
                     _ => Err(Expected("function parameter")),
                 },
 
-                SpirvAttribute::UnrollLoops => match target {
+                SpirvAttribute::UnrollLoops(_) => match target {
                     Target::Fn
                     | Target::Closure
                     | Target::Method(MethodKind::Trait { body: true })
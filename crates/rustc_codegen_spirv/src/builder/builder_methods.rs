@@ -2,7 +2,9 @@ use super::Builder;
 use crate::builder_spirv::{BuilderCursor, SpirvConst, SpirvValue, SpirvValueExt, SpirvValueKind};
 use crate::spirv_type::SpirvType;
 use rspirv::dr::{InsertPoint, Instruction, Operand};
-use rspirv::spirv::{Capability, MemoryModel, MemorySemantics, Op, Scope, StorageClass, Word};
+use rspirv::spirv::{
+    Capability, MemoryAccess, MemoryModel, MemorySemantics, Op, Scope, StorageClass, Word,
+};
 use rustc_codegen_ssa::common::{
     AtomicOrdering, AtomicRmwBinOp, IntPredicate, RealPredicate, SynchronizationScope,
 };
@@ -17,7 +19,7 @@ use rustc_middle::ty::Ty;
 use rustc_span::Span;
 use rustc_target::abi::{Abi, Align, Scalar, Size};
 use std::convert::TryInto;
-use std::iter::empty;
+use std::iter::{empty, once};
 use std::ops::Range;
 
 macro_rules! simple_op {
@@ -40,6 +42,7 @@ macro_rules! simple_op {
                                 let x = match const_val {
                                     SpirvConst::U32(_, x) => x as u128,
                                     SpirvConst::U64(_, x) => x as u128,
+                                    SpirvConst::U128(_, x) => x,
                                     _ => return None,
                                 };
                                 Some(if signed {
@@ -140,6 +143,48 @@ fn memset_dynamic_scalar(
 }
 
 impl<'a, 'tcx> Builder<'a, 'tcx> {
+    /// Memory operands for an `OpLoad`/`OpStore` with `flags`.
+    ///
+    /// NOTE: with the Vulkan memory model, volatile accesses to memory shared with other
+    /// invocations also have to be made available/visible, but that's only valid for some
+    /// storage classes, which aren't known until after specialization (see
+    /// `linker::volatile`).
+    fn memory_access_for_flags(&self, flags: MemFlags) -> Option<MemoryAccess> {
+        let mut memory_access = MemoryAccess::NONE;
+        if flags.contains(MemFlags::VOLATILE) {
+            memory_access |= MemoryAccess::VOLATILE;
+        }
+        // `Nontemporal` is only a hint, so it can be dropped where it's not available.
+        if flags.contains(MemFlags::NONTEMPORAL) && self.emit().version().unwrap() > (1, 3) {
+            memory_access |= MemoryAccess::NONTEMPORAL;
+        }
+        // NOTE: `MemFlags::UNALIGNED` needs nothing, as no `Aligned` operand is emitted.
+        if memory_access.is_empty() {
+            None
+        } else {
+            Some(memory_access)
+        }
+    }
+
+    fn load_with_flags(&mut self, ptr: SpirvValue, flags: MemFlags) -> SpirvValue {
+        // See comment on `SpirvValueKind::ConstantPointer`
+        if let Some(value) = ptr.const_ptr_val(self) {
+            return value;
+        }
+        let ty = match self.lookup_type(ptr.ty) {
+            SpirvType::Pointer { pointee } => pointee,
+            ty => self.fatal(&format!(
+                "load called on variable that wasn't a pointer: {:?}",
+                ty
+            )),
+        };
+        let memory_access = self.memory_access_for_flags(flags);
+        self.emit()
+            .load(ty, None, ptr.def(self), memory_access, empty())
+            .unwrap()
+            .with_type(ty)
+    }
+
     fn ordering_to_semantics_def(&self, ordering: AtomicOrdering) -> SpirvValue {
         let mut invalid_seq_cst = false;
         let semantics = match ordering {
@@ -176,10 +221,14 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
         semantics
     }
 
+    /// The value of type `ty` with all of its bytes set to `fill_byte`.
+    ///
+    /// NOTE: pointers can only be filled with zeros (i.e. null), any other pattern
+    /// needs `OpConvertUToPtr`, which is zombied without the `Addresses` capability.
     fn memset_const_pattern(&self, ty: &SpirvType, fill_byte: u8) -> Word {
         match *ty {
             SpirvType::Void => self.fatal("memset invalid on void pattern"),
-            SpirvType::Bool => self.fatal("memset invalid on bool pattern"),
+            SpirvType::Bool => self.constant_bool(self.span(), fill_byte != 0).def(self),
             SpirvType::Integer(width, _signedness) => match width {
                 8 => self.constant_u8(self.span(), fill_byte).def(self),
                 16 => self
@@ -197,6 +246,9 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
                 )),
             },
             SpirvType::Float(width) => match width {
+                16 => self
+                    .constant_f16_bits(self.span(), memset_fill_u16(fill_byte))
+                    .def(self),
                 32 => self
                     .constant_f32(self.span(), f32::from_bits(memset_fill_u32(fill_byte)))
                     .def(self),
@@ -208,9 +260,18 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
                     width
                 )),
             },
-            SpirvType::Adt { .. } => self.fatal("memset on structs not implemented yet"),
+            SpirvType::Adt {
+                ref field_types, ..
+            } => {
+                let field_pats = field_types
+                    .iter()
+                    .map(|&field| self.memset_const_pattern(&self.lookup_type(field), fill_byte))
+                    .collect::<Vec<_>>();
+                self.constant_composite(ty.clone().def(self.span(), self), field_pats)
+                    .def(self)
+            }
             SpirvType::Opaque { .. } => self.fatal("memset on opaque type is invalid"),
-            SpirvType::Vector { element, count } => {
+            SpirvType::Vector { element, count } | SpirvType::Matrix { element, count } => {
                 let elem_pat = self.memset_const_pattern(&self.lookup_type(element), fill_byte);
                 self.constant_composite(
                     ty.clone().def(self.span(), self),
@@ -225,10 +286,23 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
                     .def(self)
             }
             SpirvType::RuntimeArray { .. } => {
-                self.fatal("memset on runtime arrays not implemented yet")
+                self.fatal("memset on runtime arrays nested in other types is invalid")
             }
-            SpirvType::Pointer { .. } => self.fatal("memset on pointers not implemented yet"),
-            SpirvType::Function { .. } => self.fatal("memset on functions not implemented yet"),
+            SpirvType::Pointer { .. } => {
+                let ty = ty.clone().def(self.span(), self);
+                if fill_byte == 0 {
+                    self.constant_null(ty).def(self)
+                } else {
+                    let addr = self.constant_u32(self.span(), memset_fill_u32(fill_byte));
+                    let ptr = self
+                        .emit()
+                        .convert_u_to_ptr(ty, None, addr.def(self))
+                        .unwrap();
+                    self.zombie_convert_u_to_ptr(ptr);
+                    ptr
+                }
+            }
+            SpirvType::Function { .. } => self.fatal("cannot memset function"),
             SpirvType::Image { .. } => self.fatal("cannot memset image"),
             SpirvType::Sampler => self.fatal("cannot memset sampler"),
             SpirvType::SampledImage { .. } => self.fatal("cannot memset sampled image"),
@@ -239,7 +313,12 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
     fn memset_dynamic_pattern(&self, ty: &SpirvType, fill_var: Word) -> Word {
         match *ty {
             SpirvType::Void => self.fatal("memset invalid on void pattern"),
-            SpirvType::Bool => self.fatal("memset invalid on bool pattern"),
+            SpirvType::Bool => {
+                let zero = self.constant_u8(self.span(), 0).def(self);
+                self.emit()
+                    .i_not_equal(ty.clone().def(self.span(), self), None, fill_var, zero)
+                    .unwrap()
+            }
             SpirvType::Integer(width, _signedness) => match width {
                 8 => fill_var,
                 16 => memset_dynamic_scalar(self, fill_var, 2, false),
@@ -251,6 +330,7 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
                 )),
             },
             SpirvType::Float(width) => match width {
+                16 => memset_dynamic_scalar(self, fill_var, 2, true),
                 32 => memset_dynamic_scalar(self, fill_var, 4, true),
                 64 => memset_dynamic_scalar(self, fill_var, 8, true),
                 _ => self.fatal(&format!(
@@ -258,7 +338,17 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
                     width
                 )),
             },
-            SpirvType::Adt { .. } => self.fatal("memset on structs not implemented yet"),
+            SpirvType::Adt {
+                ref field_types, ..
+            } => {
+                let field_pats = field_types
+                    .iter()
+                    .map(|&field| self.memset_dynamic_pattern(&self.lookup_type(field), fill_var))
+                    .collect::<Vec<_>>();
+                self.emit()
+                    .composite_construct(ty.clone().def(self.span(), self), None, field_pats)
+                    .unwrap()
+            }
             SpirvType::Opaque { .. } => self.fatal("memset on opaque type is invalid"),
             SpirvType::Array { element, count } => {
                 let elem_pat = self.memset_dynamic_pattern(&self.lookup_type(element), fill_var);
@@ -271,7 +361,7 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
                     )
                     .unwrap()
             }
-            SpirvType::Vector { element, count } => {
+            SpirvType::Vector { element, count } | SpirvType::Matrix { element, count } => {
                 let elem_pat = self.memset_dynamic_pattern(&self.lookup_type(element), fill_var);
                 self.emit()
                     .composite_construct(
@@ -282,10 +372,18 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
                     .unwrap()
             }
             SpirvType::RuntimeArray { .. } => {
-                self.fatal("memset on runtime arrays not implemented yet")
+                self.fatal("memset on runtime arrays nested in other types is invalid")
             }
-            SpirvType::Pointer { .. } => self.fatal("memset on pointers not implemented yet"),
-            SpirvType::Function { .. } => self.fatal("memset on functions not implemented yet"),
+            SpirvType::Pointer { .. } => {
+                let addr = memset_dynamic_scalar(self, fill_var, 4, false);
+                let ptr = self
+                    .emit()
+                    .convert_u_to_ptr(ty.clone().def(self.span(), self), None, addr)
+                    .unwrap();
+                self.zombie_convert_u_to_ptr(ptr);
+                ptr
+            }
+            SpirvType::Function { .. } => self.fatal("cannot memset function"),
             SpirvType::Image { .. } => self.fatal("cannot memset image"),
             SpirvType::Sampler => self.fatal("cannot memset sampler"),
             SpirvType::SampledImage { .. } => self.fatal("cannot memset sampled image"),
@@ -293,79 +391,147 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
         }
     }
 
-    fn memset_constant_size(&mut self, ptr: SpirvValue, pat: SpirvValue, size_bytes: u64) {
+    /// Pointer to the `index`-th element of the memory `ptr` points to, where `ptr`
+    /// is either a pointer to the first element, or (`ptr_to_array`) to a whole array.
+    fn mem_elem_ptr(
+        &mut self,
+        ptr: SpirvValue,
+        index: SpirvValue,
+        ptr_to_array: bool,
+    ) -> SpirvValue {
+        if ptr_to_array {
+            let zero = self.constant_int(index.ty, 0);
+            self.inbounds_gep(ptr, &[zero, index])
+        } else {
+            self.gep(ptr, &[index])
+        }
+    }
+
+    /// Emit a loop calling `body` with each index in `0..count`, continuing after it.
+    fn counted_loop(&mut self, count: SpirvValue, mut body: impl FnMut(&mut Self, SpirvValue)) {
+        let zero = self.constant_int(count.ty, 0);
+        let one = self.constant_int(count.ty, 1);
+        let zero_align = Align::from_bytes(0).unwrap();
+
+        let mut header = self.build_sibling_block("loop_header");
+        let mut body_bx = self.build_sibling_block("loop_body");
+        let exit = self.build_sibling_block("loop_exit");
+
+        let index = self.alloca(count.ty, zero_align);
+        self.store(zero, index, zero_align);
+        self.br(header.llbb());
+
+        let current_index = header.load(index, zero_align);
+        let cond = header.icmp(IntPredicate::IntULT, current_index, count);
+        header.cond_br(cond, body_bx.llbb(), exit.llbb());
+
+        body(&mut body_bx, current_index);
+        let current_index_plus_1 = body_bx.add(current_index, one);
+        body_bx.store(current_index_plus_1, index, zero_align);
+        body_bx.br(header.llbb());
+
+        *self = exit;
+    }
+
+    fn memset_constant_size(
+        &mut self,
+        ptr: SpirvValue,
+        pat: SpirvValue,
+        size_bytes: u64,
+        ptr_to_array: bool,
+    ) {
         let size_elem = self
             .lookup_type(pat.ty)
             .sizeof(self)
             .expect("Memset on unsized values not supported");
         let count = size_bytes / size_elem.bytes();
-        if count == 1 {
+        if count == 1 && !ptr_to_array {
             self.store(pat, ptr, Align::from_bytes(0).unwrap());
         } else {
             for index in 0..count {
                 let const_index = self.constant_u32(self.span(), index as u32);
-                let gep_ptr = self.gep(ptr, &[const_index]);
+                let gep_ptr = self.mem_elem_ptr(ptr, const_index, ptr_to_array);
                 self.store(pat, gep_ptr, Align::from_bytes(0).unwrap());
             }
         }
     }
 
     // TODO: Test this is correct
-    fn memset_dynamic_size(&mut self, ptr: SpirvValue, pat: SpirvValue, size_bytes: SpirvValue) {
+    fn memset_dynamic_size(
+        &mut self,
+        ptr: SpirvValue,
+        pat: SpirvValue,
+        size_bytes: SpirvValue,
+        ptr_to_array: bool,
+    ) {
         let size_elem = self
             .lookup_type(pat.ty)
             .sizeof(self)
             .expect("Unable to memset a dynamic sized object");
         let size_elem_const = self.constant_int(size_bytes.ty, size_elem.bytes());
-        let zero = self.constant_int(size_bytes.ty, 0);
-        let one = self.constant_int(size_bytes.ty, 1);
-        let zero_align = Align::from_bytes(0).unwrap();
-
-        let mut header = self.build_sibling_block("memset_header");
-        let mut body = self.build_sibling_block("memset_body");
-        let exit = self.build_sibling_block("memset_exit");
 
         let count = self.udiv(size_bytes, size_elem_const);
-        let index = self.alloca(count.ty, zero_align);
-        self.store(zero, index, zero_align);
-        self.br(header.llbb());
-
-        let current_index = header.load(index, zero_align);
-        let cond = header.icmp(IntPredicate::IntULT, current_index, count);
-        header.cond_br(cond, body.llbb(), exit.llbb());
+        self.counted_loop(count, |bx, index| {
+            let gep_ptr = bx.mem_elem_ptr(ptr, index, ptr_to_array);
+            bx.store(pat, gep_ptr, Align::from_bytes(0).unwrap());
+        });
+    }
 
-        let gep_ptr = body.gep(ptr, &[current_index]);
-        body.store(pat, gep_ptr, zero_align);
-        let current_index_plus_1 = body.add(current_index, one);
-        body.store(current_index_plus_1, index, zero_align);
-        body.br(header.llbb());
+    /// The size of the elements `memcpy` can copy one at a time, between pointers of
+    /// types `dst_ty` and `src_ty`, and whether those point to whole arrays of them.
+    fn memcpy_elem_size(&self, dst_ty: Word, src_ty: Word) -> Option<(Size, bool)> {
+        let pointee = match (self.lookup_type(dst_ty), self.lookup_type(src_ty)) {
+            (
+                SpirvType::Pointer { pointee },
+                SpirvType::Pointer {
+                    pointee: src_pointee,
+                },
+            ) if pointee == src_pointee => pointee,
+            _ => return None,
+        };
+        let (elem_ty, ptr_to_array) = match self.lookup_type(pointee) {
+            SpirvType::Array { element, .. } | SpirvType::RuntimeArray { element } => {
+                (element, true)
+            }
+            _ => (pointee, false),
+        };
+        let elem_size = self.lookup_type(elem_ty).sizeof(self)?;
+        if elem_size.bytes() == 0 {
+            None
+        } else {
+            Some((elem_size, ptr_to_array))
+        }
+    }
 
-        *self = exit;
+    /// Lower a `switch` to a chain of conditional branches, one for each case.
+    fn switch_as_if_chain(
+        &mut self,
+        v: SpirvValue,
+        else_llbb: Word,
+        cases: impl Iterator<Item = (u128, Word)>,
+    ) {
+        for (value, target) in cases {
+            let case_value = self.const_uint_big(v.ty, value);
+            let cond = self.icmp(IntPredicate::IntEQ, v, case_value);
+            let next = self.build_sibling_block("switch_next_case");
+            self.cond_br(cond, target, next.llbb());
+            *self = next;
+        }
+        self.br(else_llbb);
     }
 
+    // NOTE: `PhysicalStorageBufferAddresses` isn't enough here, as it only allows
+    // converting 64-bit integers to/from `PhysicalStorageBuffer` pointers, while Rust
+    // pointers are 32-bit (see `spirv_std::arch::convert_{ptr_to_u,u_to_ptr}` instead).
     fn zombie_convert_ptr_to_u(&self, def: Word) {
-        if !self.builder.has_capability(Capability::Addresses)
-            && !self
-                .builder
-                .has_capability(Capability::PhysicalStorageBufferAddresses)
-        {
-            self.zombie(
-                def,
-                "OpConvertPtrToU without OpCapability Addresses or PhysicalStorageBufferAddresses",
-            );
+        if !self.builder.has_capability(Capability::Addresses) {
+            self.zombie(def, "OpConvertPtrToU without OpCapability Addresses");
         }
     }
 
     fn zombie_convert_u_to_ptr(&self, def: Word) {
-        if !self.builder.has_capability(Capability::Addresses)
-            && !self
-                .builder
-                .has_capability(Capability::PhysicalStorageBufferAddresses)
-        {
-            self.zombie(
-                def,
-                "OpConvertUToPtr OpCapability Addresses or PhysicalStorageBufferAddresses",
-            );
+        if !self.builder.has_capability(Capability::Addresses) {
+            self.zombie(def, "OpConvertUToPtr without OpCapability Addresses");
         }
     }
 
@@ -425,7 +591,8 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
                 }
                 SpirvType::Vector { element, .. }
                 | SpirvType::Array { element, .. }
-                | SpirvType::RuntimeArray { element } => {
+                | SpirvType::RuntimeArray { element }
+                | SpirvType::Matrix { element, .. } => {
                     ty = element;
                     ty_kind = self.lookup_type(ty);
 
@@ -448,13 +615,15 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
         let cursor_fn = cx.builder.select_function_by_id(llfn.def_cx(cx));
         let label = cx.emit_with_cursor(cursor_fn).begin_block(None).unwrap();
         let cursor = cx.builder.select_block_by_id(label);
-        Self {
+        let mut bx = Self {
             cx,
             cursor,
             current_fn: llfn,
             basic_block: label,
             current_span: Default::default(),
-        }
+        };
+        bx.define_debug_function();
+        bx
     }
 
     fn with_cx(cx: &'a Self::CodegenCx) -> Self {
@@ -497,6 +666,13 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
 
     fn set_span(&mut self, span: Span) {
         self.current_span = Some(span);
+
+        // NOTE: this is done even without debuginfo (`OpLine`s are stripped when optimizing
+        // those builds), so that `spirv-val` errors can be mapped back to the source.
+        if !span.is_dummy() {
+            let (file, line, col) = self.src_loc(span.lo());
+            self.emit_line(file, line, col);
+        }
     }
 
     fn position_at_end(&mut self, llbb: Self::BasicBlock) {
@@ -592,6 +768,18 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
         // pass in signed into the closure to be able to unify closure types
         let (signed, construct_case) = match self.lookup_type(v.ty) {
             SpirvType::Integer(width, signed) => {
+                // NOTE: integers the linker has to lower (see `linker::int_lowering`) can't
+                // be `OpSwitch` selectors, so each case gets its own comparison instead.
+                if width > 64
+                    || (width == 64
+                        && self
+                            .tcx
+                            .sess
+                            .target_features
+                            .contains(&self.sym.emulate_int64))
+                {
+                    return self.switch_as_if_chain(v, else_llbb, cases);
+                }
                 let construct_case = match width {
                     8 => construct_8,
                     16 => construct_16,
@@ -835,28 +1023,11 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
     }
 
     fn load(&mut self, ptr: Self::Value, _align: Align) -> Self::Value {
-        // See comment on `SpirvValueKind::ConstantPointer`
-        if let Some(value) = ptr.const_ptr_val(self) {
-            return value;
-        }
-        let ty = match self.lookup_type(ptr.ty) {
-            SpirvType::Pointer { pointee } => pointee,
-            ty => self.fatal(&format!(
-                "load called on variable that wasn't a pointer: {:?}",
-                ty
-            )),
-        };
-        self.emit()
-            .load(ty, None, ptr.def(self), None, empty())
-            .unwrap()
-            .with_type(ty)
+        self.load_with_flags(ptr, MemFlags::empty())
     }
 
     fn volatile_load(&mut self, ptr: Self::Value) -> Self::Value {
-        // TODO: Implement this
-        let result = self.load(ptr, Align::from_bytes(0).unwrap());
-        self.zombie(result.def(self), "volatile load is not supported yet");
-        result
+        self.load_with_flags(ptr, MemFlags::VOLATILE)
     }
 
     fn atomic_load(&mut self, ptr: Self::Value, order: AtomicOrdering, _size: Size) -> Self::Value {
@@ -959,7 +1130,17 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
         // ignore
     }
 
-    fn store(&mut self, val: Self::Value, ptr: Self::Value, _align: Align) -> Self::Value {
+    fn store(&mut self, val: Self::Value, ptr: Self::Value, align: Align) -> Self::Value {
+        self.store_with_flags(val, ptr, align, MemFlags::empty())
+    }
+
+    fn store_with_flags(
+        &mut self,
+        val: Self::Value,
+        ptr: Self::Value,
+        _align: Align,
+        flags: MemFlags,
+    ) -> Self::Value {
         let ptr_elem_ty = match self.lookup_type(ptr.ty) {
             SpirvType::Pointer { pointee } => pointee,
             ty => self.fatal(&format!(
@@ -968,28 +1149,13 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
             )),
         };
         assert_ty_eq!(self, ptr_elem_ty, val.ty);
+        let memory_access = self.memory_access_for_flags(flags);
         self.emit()
-            .store(ptr.def(self), val.def(self), None, empty())
+            .store(ptr.def(self), val.def(self), memory_access, empty())
             .unwrap();
         val
     }
 
-    fn store_with_flags(
-        &mut self,
-        val: Self::Value,
-        ptr: Self::Value,
-        align: Align,
-        flags: MemFlags,
-    ) -> Self::Value {
-        if flags != MemFlags::empty() {
-            self.err(&format!(
-                "store_with_flags is not supported yet: {:?}",
-                flags
-            ));
-        }
-        self.store(val, ptr, align)
-    }
-
     fn atomic_store(
         &mut self,
         val: Self::Value,
@@ -1042,13 +1208,14 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
             } => field_types[idx as usize],
             SpirvType::Array { element, .. }
             | SpirvType::RuntimeArray { element, .. }
-            | SpirvType::Vector { element, .. } => element,
+            | SpirvType::Vector { element, .. }
+            | SpirvType::Matrix { element, .. } => element,
             SpirvType::InterfaceBlock { inner_type } => {
                 assert_eq!(idx, 0);
                 inner_type
             }
             other => self.fatal(&format!(
-                "struct_gep not on struct, array, or vector type: {:?}, index {}",
+                "struct_gep not on struct, array, vector, or matrix type: {:?}, index {}",
                 other, idx
             )),
         };
@@ -1069,7 +1236,8 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
                 SpirvType::Adt { field_offsets, .. } => field_offsets[idx as usize],
                 SpirvType::Array { element, .. }
                 | SpirvType::RuntimeArray { element, .. }
-                | SpirvType::Vector { element, .. } => {
+                | SpirvType::Vector { element, .. }
+                | SpirvType::Matrix { element, .. } => {
                     self.lookup_type(element).sizeof(self).unwrap() * idx
                 }
                 _ => unreachable!(),
@@ -1361,8 +1529,22 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
                 other
             )),
         };
+        let is_dyn_data = matches!(
+            self.lookup_type(dest_pointee),
+            SpirvType::Adt { size: None, ref field_types, .. } if field_types.is_empty()
+        );
         if val.ty == dest_ty {
             val
+        } else if is_dyn_data {
+            // Erasing the type of the data behind a `dyn Trait` (the only unsized type without
+            // fields), which `linker::devirtualize` undoes for the receivers of method calls.
+            let result = self.emit().bitcast(dest_ty, None, val.def(self)).unwrap();
+            self.zombie_even_in_user_code(
+                result,
+                self.span(),
+                "Cannot use `dyn Trait` other than to call its methods",
+            );
+            result.with_type(dest_ty)
         } else if let Some(indices) =
             self.recover_access_chain_from_offset(val_pointee, dest_pointee, Size::ZERO)
         {
@@ -1671,6 +1853,32 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
                     .copy_memory(dst.def(self), src.def(self), None, None, empty())
                     .unwrap();
             }
+        } else if let Some((elem_size, ptr_to_array)) = self
+            .memcpy_elem_size(dst.ty, src.ty)
+            .filter(|&(elem_size, _)| const_size.map_or(true, |size| size % elem_size.bytes() == 0))
+        {
+            // Copying one element at a time avoids `OpCopyMemorySized` (and `Addresses`).
+            let copy_elem = |bx: &mut Self, index| {
+                let dst_elem = bx.mem_elem_ptr(dst, index, ptr_to_array);
+                let src_elem = bx.mem_elem_ptr(src, index, ptr_to_array);
+                assert_ty_eq!(bx, dst_elem.ty, src_elem.ty);
+                bx.emit()
+                    .copy_memory(dst_elem.def(bx), src_elem.def(bx), None, None, empty())
+                    .unwrap();
+            };
+            match const_size {
+                Some(size) => {
+                    for index in 0..size / elem_size.bytes() {
+                        let index = self.constant_u32(self.span(), index as u32);
+                        copy_elem(self, index);
+                    }
+                }
+                None => {
+                    let elem_size = self.constant_int(size.ty, elem_size.bytes());
+                    let count = self.udiv(size, elem_size);
+                    self.counted_loop(count, copy_elem);
+                }
+            }
         } else {
             self.emit()
                 .copy_memory_sized(
@@ -1724,15 +1932,24 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
                 self.debug_type(ptr.ty)
             )),
         };
+        let const_size = self.builder.lookup_const_u64(size);
+        // Runtime arrays can't be stored to as a whole, so their elements are set instead.
+        // The same goes for arrays with a dynamic size, as stepping over whole arrays
+        // would need `OpPtrAccessChain` (like in `memcpy`).
+        let (elem_ty, ptr_to_array) = match self.lookup_type(elem_ty) {
+            SpirvType::RuntimeArray { element } => (element, true),
+            SpirvType::Array { element, .. } if const_size.is_none() => (element, true),
+            _ => (elem_ty, false),
+        };
         let elem_ty_spv = self.lookup_type(elem_ty);
         let pat = match self.builder.lookup_const_u64(fill_byte) {
             Some(fill_byte) => self.memset_const_pattern(&elem_ty_spv, fill_byte as u8),
             None => self.memset_dynamic_pattern(&elem_ty_spv, fill_byte.def(self)),
         }
         .with_type(elem_ty);
-        match self.builder.lookup_const_u64(size) {
-            Some(size) => self.memset_constant_size(ptr, pat, size),
-            None => self.memset_dynamic_size(ptr, pat, size),
+        match const_size {
+            Some(size) => self.memset_constant_size(ptr, pat, size, ptr_to_array),
+            None => self.memset_dynamic_size(ptr, pat, size, ptr_to_array),
         }
     }
 
@@ -1807,6 +2024,7 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
     fn extract_value(&mut self, agg_val: Self::Value, idx: u64) -> Self::Value {
         let result_type = match self.lookup_type(agg_val.ty) {
             SpirvType::Adt { field_types, .. } => field_types[idx as usize],
+            SpirvType::Matrix { element, .. } => element,
             other => self.fatal(&format!(
                 "extract_value not implemented on type {:?}",
                 other
@@ -1828,6 +2046,7 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
             SpirvType::Adt { field_types, .. } => {
                 assert_ty_eq!(self, field_types[idx as usize], elt.ty)
             }
+            SpirvType::Matrix { element, .. } => assert_ty_eq!(self, element, elt.ty),
             other => self.fatal(&format!("insert_value not implemented on type {:?}", other)),
         };
         self.emit()
@@ -2092,43 +2311,38 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
         // NOTE(eddyb) see the comment on `SpirvValueKind::FnAddr`, this should
         // be fixed upstream, so we never see any "function pointer" values being
         // created just to perform direct calls.
-        let (callee_val, result_type, argument_types) = match self.lookup_type(callee.ty) {
-            // HACK(eddyb) this seems to be needed, but it's not what `get_fn_addr`
-            // produces, are these coming from inside `rustc_codegen_spirv`?
-            SpirvType::Function {
-                return_type,
-                arguments,
-            } => (callee.def(self), return_type, arguments),
-
-            SpirvType::Pointer { pointee } => match self.lookup_type(pointee) {
+        let (callee_val, result_type, argument_types, is_indirect) =
+            match self.lookup_type(callee.ty) {
+                // HACK(eddyb) this seems to be needed, but it's not what `get_fn_addr`
+                // produces, are these coming from inside `rustc_codegen_spirv`?
                 SpirvType::Function {
                     return_type,
                     arguments,
-                } => (
-                    match callee.kind {
-                        SpirvValueKind::FnAddr { function } => function,
+                } => (callee.def(self), return_type, arguments, false),
+
+                SpirvType::Pointer { pointee } => match self.lookup_type(pointee) {
+                    SpirvType::Function {
+                        return_type,
+                        arguments,
+                    } => match callee.kind {
+                        SpirvValueKind::FnAddr { function } => {
+                            (function, return_type, arguments, false)
+                        }
 
                         // Truly indirect call.
-                        _ => {
-                            let fn_ptr_val = callee.def(self);
-                            self.zombie(fn_ptr_val, "indirect calls are not supported in SPIR-V");
-                            fn_ptr_val
-                        }
+                        _ => (callee.def(self), return_type, arguments, true),
                     },
-                    return_type,
-                    arguments,
-                ),
+                    _ => bug!(
+                        "call expected `fn` pointer to point to function type, got `{}`",
+                        self.debug_type(pointee)
+                    ),
+                },
+
                 _ => bug!(
-                    "call expected `fn` pointer to point to function type, got `{}`",
-                    self.debug_type(pointee)
+                    "call expected function or `fn` pointer type, got `{}`",
+                    self.debug_type(callee.ty)
                 ),
-            },
-
-            _ => bug!(
-                "call expected function or `fn` pointer type, got `{}`",
-                self.debug_type(callee.ty)
-            ),
-        };
+            };
 
         for (argument, argument_type) in args.iter().zip(argument_types) {
             assert_ty_eq!(self, argument.ty, argument_type);
@@ -2152,6 +2366,23 @@ impl<'a, 'tcx> BuilderMethods<'a, 'tcx> for Builder<'a, 'tcx> {
             // needing to materialize `&core::panic::Location` or `format_args!`.
             self.abort();
             self.undef(result_type)
+        } else if is_indirect {
+            // NOTE: `OpFunctionPointerCallINTEL` is only used as a placeholder, all indirect
+            // calls are replaced with direct ones by `linker::devirtualize`.
+            let operands = once(callee_val)
+                .chain(args.iter().map(|arg| arg.def(self)))
+                .map(Operand::IdRef)
+                .collect();
+            let mut emit = self.emit();
+            let result_id = emit.id();
+            let inst = Instruction::new(
+                Op::FunctionPointerCallINTEL,
+                Some(result_type),
+                Some(result_id),
+                operands,
+            );
+            emit.insert_into_block(InsertPoint::End, inst).unwrap();
+            result_id.with_type(result_type)
         } else {
             let args = args.iter().map(|arg| arg.def(self)).collect::<Vec<_>>();
             self.emit()
@@ -0,0 +1,139 @@
+use super::Builder;
+use crate::builder_spirv::SpirvValueKind;
+use crate::debuginfo::{self, DebugInfoOp};
+use crate::spirv_type::SpirvType;
+use rspirv::dr::{InsertPoint, Instruction, Operand};
+use rspirv::spirv::{Op, Word};
+use rustc_codegen_ssa::traits::DebugInfoBuilderMethods;
+use rustc_target::abi::Size;
+
+impl<'a, 'tcx> Builder<'a, 'tcx> {
+    /// Emits a debug info instruction into the current block.
+    pub fn emit_debug_inst(&mut self, op: DebugInfoOp, operands: &[Word]) -> Word {
+        let import = self.debug_info_import();
+        let void = SpirvType::Void.def(self.span(), self);
+        self.emit()
+            .ext_inst(
+                void,
+                None,
+                import,
+                op as u32,
+                operands.iter().map(|&id| Operand::IdRef(id)),
+            )
+            .unwrap()
+    }
+
+    /// Attaches the `DebugFunction` of the current function to it, if it was just created by
+    /// `create_function_debug_context` (i.e. the current block is its entry block).
+    pub fn define_debug_function(&mut self) {
+        let function = self.current_fn.def(self);
+        let debug_fn = self
+            .debug_info
+            .borrow_mut()
+            .pending_definitions
+            .remove(&function);
+        if let Some(debug_fn) = debug_fn {
+            self.emit_debug_inst(DebugInfoOp::DebugFunctionDefinition, &[debug_fn, function]);
+        }
+    }
+
+    /// Sets the source location of the instructions emitted after this, with an `OpLine`.
+    pub fn emit_line(&mut self, file: Word, line: u32, col: u32) {
+        self.pop_trailing_debug_locs(None);
+        self.emit()
+            .insert_into_block(
+                InsertPoint::End,
+                Instruction::new(
+                    Op::Line,
+                    None,
+                    None,
+                    vec![
+                        Operand::IdRef(file),
+                        Operand::LiteralInt32(line),
+                        Operand::LiteralInt32(col),
+                    ],
+                ),
+            )
+            .unwrap();
+    }
+
+    /// Removes any `OpLine` (and `DebugScope`, given the debug info import) at the end of the
+    /// current block, as they no longer have any instructions to apply to.
+    fn pop_trailing_debug_locs(&mut self, debug_info_import: Option<Word>) {
+        let mut emit = self.emit();
+        let (function, block) = (
+            emit.selected_function().unwrap(),
+            emit.selected_block().unwrap(),
+        );
+        let insts = &mut emit.module_mut().functions[function].blocks[block].instructions;
+        while insts.last().map_or(false, |inst| {
+            inst.class.opcode == Op::Line
+                || debuginfo::is_debug_inst(inst, debug_info_import, DebugInfoOp::DebugScope)
+        }) {
+            insts.pop();
+        }
+    }
+
+    fn is_local_variable(&self, id: Word) -> bool {
+        let emit = self.emit();
+        let function = &emit.module_ref().functions[emit.selected_function().unwrap()];
+        function.blocks[0]
+            .instructions
+            .iter()
+            .any(|inst| inst.class.opcode == Op::Variable && inst.result_id == Some(id))
+    }
+}
+
+impl<'a, 'tcx> DebugInfoBuilderMethods for Builder<'a, 'tcx> {
+    fn dbg_var_addr(
+        &mut self,
+        dbg_var: Self::DIVariable,
+        dbg_loc: Self::DILocation,
+        variable_alloca: Self::Value,
+        direct_offset: Size,
+        // NB: each offset implies a deref (i.e. they're steps in a pointer chain).
+        indirect_offsets: &[Size],
+    ) {
+        // FIXME: anything other than a whole `OpVariable` (e.g. a variable behind a reference)
+        // would need a `DebugExpression` with `Deref`/offset operations, which isn't emitted yet.
+        let variable = match variable_alloca.kind {
+            SpirvValueKind::Def(id) => id,
+            _ => return,
+        };
+        if direct_offset != Size::ZERO
+            || !indirect_offsets.is_empty()
+            || !self.is_local_variable(variable)
+        {
+            return;
+        }
+        self.set_dbg_loc(dbg_loc);
+        let expression = self.debug_empty_expression();
+        self.emit_debug_inst(DebugInfoOp::DebugDeclare, &[dbg_var, variable, expression]);
+    }
+
+    fn set_dbg_loc(&mut self, dbg_loc: Self::DILocation) {
+        let import = self.debug_info_import();
+        self.pop_trailing_debug_locs(Some(import));
+
+        let scope = [dbg_loc.scope]
+            .iter()
+            .copied()
+            .chain(dbg_loc.inlined_at)
+            .collect::<Vec<_>>();
+        self.emit_debug_inst(DebugInfoOp::DebugScope, &scope);
+        self.emit_line(dbg_loc.file, dbg_loc.line, dbg_loc.col);
+    }
+
+    fn insert_reference_to_gdb_debug_scripts_section_global(&mut self) {
+        // Ignore.
+    }
+
+    fn set_var_name(&mut self, value: Self::Value, name: &str) {
+        // Constants may be shared by unrelated values, so only name instructions.
+        if let SpirvValueKind::Def(id) = value.kind {
+            if self.builder.lookup_const(value).is_none() {
+                self.emit_global().name(id, name.to_string());
+            }
+        }
+    }
+}
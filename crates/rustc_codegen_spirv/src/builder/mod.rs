@@ -1,4 +1,5 @@
 mod builder_methods;
+mod debuginfo;
 mod ext_inst;
 mod intrinsics;
 pub mod libm_intrinsics;
@@ -9,7 +10,7 @@ use rustc_span::DUMMY_SP;
 pub use spirv_asm::InstructionTable;
 
 use crate::abi::ConvSpirvType;
-use crate::builder_spirv::{BuilderCursor, SpirvValue, SpirvValueExt};
+use crate::builder_spirv::{BuilderCursor, SpirvValue, SpirvValueExt, SpirvValueKind};
 use crate::codegen_cx::CodegenCx;
 use crate::spirv_type::SpirvType;
 use rspirv::spirv::Word;
@@ -17,7 +18,7 @@ use rustc_codegen_ssa::mir::operand::OperandValue;
 use rustc_codegen_ssa::mir::place::PlaceRef;
 use rustc_codegen_ssa::traits::{
     AbiBuilderMethods, ArgAbiMethods, BackendTypes, BuilderMethods, CoverageInfoBuilderMethods,
-    DebugInfoBuilderMethods, HasCodegen, StaticBuilderMethods,
+    HasCodegen, StaticBuilderMethods,
 };
 use rustc_errors::DiagnosticBuilder;
 use rustc_middle::mir::coverage::{
@@ -28,8 +29,9 @@ use rustc_middle::ty::{Instance, ParamEnv, Ty, TyCtxt};
 use rustc_span::def_id::DefId;
 use rustc_span::source_map::Span;
 use rustc_target::abi::call::{ArgAbi, FnAbi, PassMode};
-use rustc_target::abi::{HasDataLayout, LayoutOf, Size, TargetDataLayout};
+use rustc_target::abi::{HasDataLayout, LayoutOf, TargetDataLayout};
 use rustc_target::spec::{HasTargetSpec, Target};
+use std::iter::empty;
 use std::ops::Deref;
 
 pub struct Builder<'a, 'tcx> {
@@ -99,6 +101,28 @@ impl<'a, 'tcx> Builder<'a, 'tcx> {
         indices: &[SpirvValue],
         is_inbounds: bool,
     ) -> SpirvValue {
+        // Reading from a vtable, which `linker::devirtualize` replaces with constants.
+        if let SpirvValueKind::LogicalPtrCast {
+            original_ptr,
+            original_pointee_ty,
+            zombie_target_undef: _,
+        } = ptr.kind
+        {
+            let original_ptr_ty = SpirvType::Pointer {
+                pointee: original_pointee_ty,
+            }
+            .def(self.span(), self);
+            if self.is_vtable_ptr_type(original_ptr_ty) && indices.len() == 1 {
+                let index = indices[0].def(self);
+                let mut emit = self.emit();
+                let slots = emit.bitcast(ptr.ty, None, original_ptr).unwrap();
+                return emit
+                    .in_bounds_ptr_access_chain(ptr.ty, None, slots, index, empty())
+                    .unwrap()
+                    .with_type(ptr.ty);
+            }
+        }
+
         // The first index is an offset to the pointer, the rest are actual members.
         // https://llvm.org/docs/GetElementPtr.html
         // "An OpAccessChain instruction is the equivalent of an LLVM getelementptr instruction where the first index element is zero."
@@ -248,32 +272,6 @@ impl<'a, 'tcx> CoverageInfoBuilderMethods<'tcx> for Builder<'a, 'tcx> {
     }
 }
 
-impl<'a, 'tcx> DebugInfoBuilderMethods for Builder<'a, 'tcx> {
-    fn dbg_var_addr(
-        &mut self,
-        _dbg_var: Self::DIVariable,
-        _scope_metadata: Self::DILocation,
-        _variable_alloca: Self::Value,
-        _direct_offset: Size,
-        // NB: each offset implies a deref (i.e. they're steps in a pointer chain).
-        _indirect_offsets: &[Size],
-    ) {
-        todo!()
-    }
-
-    fn set_dbg_loc(&mut self, _: Self::DILocation) {
-        todo!()
-    }
-
-    fn insert_reference_to_gdb_debug_scripts_section_global(&mut self) {
-        todo!()
-    }
-
-    fn set_var_name(&mut self, _value: Self::Value, _name: &str) {
-        todo!()
-    }
-}
-
 impl<'a, 'tcx> ArgAbiMethods<'tcx> for Builder<'a, 'tcx> {
     fn store_fn_arg(
         &mut self,
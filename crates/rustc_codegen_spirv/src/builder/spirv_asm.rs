@@ -270,6 +270,11 @@ impl<'cx, 'tcx> Builder<'cx, 'tcx> {
                 count: inst.operands[1].unwrap_literal_int32(),
             }
             .def(self.span(), self),
+            Op::TypeMatrix => SpirvType::Matrix {
+                element: inst.operands[0].unwrap_id_ref(),
+                count: inst.operands[1].unwrap_literal_int32(),
+            }
+            .def(self.span(), self),
             Op::TypeArray => {
                 self.err("OpTypeArray in asm! is not supported yet");
                 return;
@@ -566,6 +571,7 @@ impl<'cx, 'tcx> Builder<'cx, 'tcx> {
                     (TyPat::Void, SpirvType::Void) => Ok(None),
                     (TyPat::Pointer(_, pat), SpirvType::Pointer { pointee: ty, .. })
                     | (TyPat::Vector(pat), SpirvType::Vector { element: ty, .. })
+                    | (TyPat::Matrix(pat), SpirvType::Matrix { element: ty, .. })
                     | (
                         TyPat::Vector4(pat),
                         SpirvType::Vector {
@@ -588,7 +594,8 @@ impl<'cx, 'tcx> Builder<'cx, 'tcx> {
         }
 
         // FIXME(eddyb) try multiple signatures until one fits.
-        let mut sig = match instruction_signatures(instruction.class.opcode)? {
+        let sigs = instruction_signatures(instruction.class.opcode, self.addressing_model())?;
+        let mut sig = match sigs {
             [sig
             @ InstSig {
                 output_type: Some(_),
@@ -99,21 +99,13 @@ impl SpirvValue {
     pub fn def_with_span(self, cx: &CodegenCx<'_>, span: Span) -> Word {
         match self.kind {
             SpirvValueKind::Def(word) => word,
-            SpirvValueKind::FnAddr { .. } => {
-                if cx.is_system_crate() {
-                    *cx.zombie_undefs_for_system_fn_addrs
-                        .borrow()
-                        .get(&self.ty)
-                        .expect("FnAddr didn't go through proper undef registration")
-                } else {
-                    cx.tcx
-                        .sess
-                        .err("Cannot use this function pointer for anything other than calls");
-                    // Because we never get beyond compilation (into e.g. linking),
-                    // emitting an invalid ID reference here is OK.
-                    0
-                }
-            }
+            // NOTE: function pointers are only usable in limited ways (e.g. indirect calls, vtables),
+            // which `linker::devirtualize` replaces with direct calls.
+            SpirvValueKind::FnAddr { function } => *cx
+                .fn_addr_constants
+                .borrow()
+                .get(&function)
+                .expect("FnAddr didn't go through proper address constant registration"),
 
             SpirvValueKind::ConstantPointer {
                 initializer: _,
@@ -173,6 +165,10 @@ impl SpirvValueExt for Word {
 pub enum SpirvConst {
     U32(Word, u32),
     U64(Word, u64),
+    /// Emitted as a composite of its 32-bit words, see `linker::int_lowering`.
+    U128(Word, u128),
+    /// f16 isn't a Rust type (see `spirv_std::f16`), so store bits
+    F16(Word, u16),
     /// f32 isn't hash, so store bits
     F32(Word, u32),
     /// f64 isn't hash, so store bits
@@ -248,6 +244,7 @@ impl BuilderSpirv {
         builder.capability(Capability::Int8);
         builder.capability(Capability::Int16);
         builder.capability(Capability::Int64);
+        builder.capability(Capability::Float16);
         builder.capability(Capability::Float64);
         if kernel_mode {
             builder.capability(Capability::Addresses);
@@ -337,6 +334,15 @@ impl BuilderSpirv {
         let id = match val {
             SpirvConst::U32(ty, v) => builder.constant_u32(ty, v).with_type(ty),
             SpirvConst::U64(ty, v) => builder.constant_u64(ty, v).with_type(ty),
+            SpirvConst::U128(ty, v) => {
+                let u32 = builder.type_int(32, 0);
+                let words = (0..4)
+                    .map(|i| builder.constant_u32(u32, (v >> (32 * i)) as u32))
+                    .collect::<Vec<_>>();
+                builder.constant_composite(ty, words).with_type(ty)
+            }
+            // NOTE: 16-bit literals are encoded as the low-order bits of one word.
+            SpirvConst::F16(ty, v) => builder.constant_u32(ty, v as u32).with_type(ty),
             SpirvConst::F32(ty, v) => builder.constant_f32(ty, f32::from_bits(v)).with_type(ty),
             SpirvConst::F64(ty, v) => builder.constant_f64(ty, f64::from_bits(v)).with_type(ty),
             SpirvConst::Bool(ty, v) => {
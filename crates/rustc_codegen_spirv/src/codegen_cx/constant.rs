@@ -61,11 +61,14 @@ impl<'tcx> CodegenCx<'tcx> {
                     .sess
                     .fatal(&format!("Invalid constant value for bool: {}", val)),
             },
-            SpirvType::Integer(128, _) => {
-                let result = self.undef(ty);
-                self.zombie_no_span(result.def_cx(self), "u128 constant");
-                result
-            }
+            SpirvType::Integer(128, signed) => self.builder.def_constant(SpirvConst::U128(
+                ty,
+                if signed {
+                    val as i64 as i128 as u128
+                } else {
+                    val as u128
+                },
+            )),
             other => self.tcx.sess.fatal(&format!(
                 "constant_int invalid on type {}",
                 other.debug(ty, self)
@@ -73,6 +76,11 @@ impl<'tcx> CodegenCx<'tcx> {
         }
     }
 
+    pub fn constant_f16_bits(&self, span: Span, bits: u16) -> SpirvValue {
+        let ty = SpirvType::Float(16).def(span, self);
+        self.builder.def_constant(SpirvConst::F16(ty, bits))
+    }
+
     pub fn constant_f32(&self, span: Span, val: f32) -> SpirvValue {
         let ty = SpirvType::Float(32).def(span, self);
         self.builder
@@ -132,7 +140,10 @@ impl<'tcx> ConstMethods<'tcx> for CodegenCx<'tcx> {
         self.constant_int(t, i)
     }
     fn const_uint_big(&self, t: Self::Type, u: u128) -> Self::Value {
-        self.constant_int(t, u as u64)
+        match self.lookup_type(t) {
+            SpirvType::Integer(128, _) => self.builder.def_constant(SpirvConst::U128(t, u)),
+            _ => self.constant_int(t, u as u64),
+        }
     }
     fn const_bool(&self, val: bool) -> Self::Value {
         self.constant_bool(DUMMY_SP, val)
@@ -189,6 +200,9 @@ impl<'tcx> ConstMethods<'tcx> for CodegenCx<'tcx> {
         self.builder.lookup_const_u64(v)
     }
     fn const_to_opt_u128(&self, v: Self::Value, sign_ext: bool) -> Option<u128> {
+        if let Some(SpirvConst::U128(_, v)) = self.builder.lookup_const(v) {
+            return Some(v);
+        }
         self.builder.lookup_const_u64(v).map(|v| {
             if sign_ext {
                 v as i64 as i128 as u128
@@ -216,7 +230,7 @@ impl<'tcx> ConstMethods<'tcx> for CodegenCx<'tcx> {
                             if !self.kernel_mode {
                                 assert_eq!(spirv_signedness, int_signedness);
                             }
-                            self.constant_int(ty, data as u64)
+                            self.const_uint_big(ty, data)
                         }
                         SpirvType::Bool => match data {
                             0 => self.constant_bool(DUMMY_SP, false),
@@ -226,6 +240,11 @@ impl<'tcx> ConstMethods<'tcx> for CodegenCx<'tcx> {
                                 .sess
                                 .fatal(&format!("Invalid constant value for bool: {}", data)),
                         },
+                        // `#[spirv(f16)]` types have the layout of `u16`.
+                        SpirvType::Float(16) => {
+                            assert_eq!(int_size.size().bits(), 16);
+                            self.constant_f16_bits(DUMMY_SP, data as u16)
+                        }
                         other => self.tcx.sess.fatal(&format!(
                             "scalar_to_backend Primitive::Int not supported on type {}",
                             other.debug(ty, self)
@@ -319,6 +338,11 @@ impl<'tcx> ConstMethods<'tcx> for CodegenCx<'tcx> {
     fn const_ptrcast(&self, val: Self::Value, ty: Self::Type) -> Self::Value {
         if val.ty == ty {
             val
+        } else if self.is_vtable_ptr_type(val.ty) {
+            // HACK: `rustc_codegen_ssa` casts vtable pointers to the type of the vtable
+            // field of `dyn Trait` fat pointers (`&[usize; 3]`), but `trans_scalar` uses a
+            // dedicated type for that field (see `PointeeTy::Vtable`), which should be kept.
+            val
         } else {
             // constant ptrcast is not supported in spir-v
             let result = val.def_cx(self).with_type(ty);
@@ -390,6 +414,8 @@ impl<'tcx> CodegenCx<'tcx> {
                         Primitive::Int(integer, int_signedness)
                     }
                     SpirvType::Float(float_size) => match float_size {
+                        // `#[spirv(f16)]` types have the layout of `u16`.
+                        16 => Primitive::Int(Integer::I16, false),
                         32 => Primitive::F32,
                         64 => Primitive::F64,
                         other => {
@@ -464,6 +490,15 @@ impl<'tcx> CodegenCx<'tcx> {
                     .collect::<Vec<_>>();
                 self.constant_composite(ty, values)
             }
+            SpirvType::Matrix { element, count } => {
+                let values = (0..count)
+                    .map(|_| {
+                        self.create_const_alloc2(alloc, offset, element)
+                            .def_cx(self)
+                    })
+                    .collect::<Vec<_>>();
+                self.constant_composite(ty, values)
+            }
             SpirvType::Vector { element, count } => {
                 let total_size = ty_concrete
                     .sizeof(self)
@@ -0,0 +1,530 @@
+use super::CodegenCx;
+use crate::debuginfo::{self, encoding, flags, DebugInfoOp};
+use crate::spirv_type::SpirvType;
+use rspirv::dr::InsertPoint;
+use rspirv::spirv::{SourceLanguage, StorageClass, Word};
+use rustc_codegen_ssa::mir::debuginfo::{DebugScope, FunctionDebugContext, VariableKind};
+use rustc_codegen_ssa::traits::DebugInfoMethods;
+use rustc_middle::mir::{Body, SourceScope};
+use rustc_middle::ty::layout::{FnAbiExt, TyAndLayout};
+use rustc_middle::ty::print::with_no_trimmed_paths;
+use rustc_middle::ty::{self, Instance, ParamEnv, Ty};
+use rustc_session::config::DebugInfo;
+use rustc_span::def_id::CRATE_DEF_ID;
+use rustc_span::symbol::Symbol;
+use rustc_span::{BytePos, Pos, SourceFile, Span, DUMMY_SP};
+use rustc_target::abi::call::{FnAbi, PassMode};
+use rustc_target::abi::{Abi, FieldsShape, LayoutOf, Variants};
+use std::collections::{HashMap, HashSet};
+
+/// Source location of instructions, as set by `Builder::set_dbg_loc` (i.e. `OpLine` and
+/// `DebugScope`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DebugLoc {
+    /// The `DebugFunction` or `DebugLexicalBlock` the instructions are in.
+    pub scope: Word,
+    /// The `DebugInlinedAt` for `scope`, if it was inlined (by MIR inlining) into another function.
+    pub inlined_at: Option<Word>,
+    /// The `OpString` of the file name.
+    pub file: Word,
+    pub line: u32,
+    pub col: u32,
+}
+
+/// All the (module-scoped) debug info instructions emitted so far, to reuse them where possible.
+#[derive(Default)]
+pub struct DebugInfoCache<'tcx> {
+    import: Option<Word>,
+    compilation_unit: Option<Word>,
+    info_none: Option<Word>,
+    empty_expression: Option<Word>,
+    /// The `OpString` of each file name, keyed by the `start_pos` of its `SourceFile`.
+    file_names: HashMap<BytePos, Word>,
+    /// The `DebugSource` of each file, keyed by the `start_pos` of its `SourceFile`.
+    files: HashMap<BytePos, Word>,
+    types: HashMap<Ty<'tcx>, Word>,
+    functions: HashMap<Instance<'tcx>, Word>,
+    inlined_at: HashMap<DebugLoc, Word>,
+    /// Functions being defined, and their `DebugFunction`, which still has to be attached to
+    /// them with a `DebugFunctionDefinition` in their entry block, once that gets created.
+    pub pending_definitions: HashMap<Word, Word>,
+}
+
+impl<'tcx> CodegenCx<'tcx> {
+    pub fn debug_info_import(&self) -> Word {
+        if let Some(import) = self.debug_info.borrow().import {
+            return import;
+        }
+        let import = {
+            let mut emit = self.emit_global();
+            emit.extension("SPV_KHR_non_semantic_info");
+            emit.ext_inst_import(debuginfo::NON_SEMANTIC_DEBUG_INFO)
+        };
+        self.debug_info.borrow_mut().import = Some(import);
+        import
+    }
+
+    /// Emits a module-scoped debug info instruction (any other instruction it refers to has to
+    /// have been emitted already, as forward references aren't allowed).
+    fn debug_global_inst(&self, op: DebugInfoOp, operands: &[Word]) -> Word {
+        let import = self.debug_info_import();
+        let void = SpirvType::Void.def(DUMMY_SP, self);
+        let mut emit = self.emit_global();
+        let id = emit.id();
+        let inst = debuginfo::debug_inst(import, void, id, op, operands.iter().copied());
+        emit.insert_types_global_values(InsertPoint::End, inst);
+        id
+    }
+
+    fn debug_u32(&self, val: u32) -> Word {
+        self.constant_u32(DUMMY_SP, val).def_cx(self)
+    }
+
+    fn debug_string(&self, s: &str) -> Word {
+        self.emit_global().string(s)
+    }
+
+    fn debug_info_none(&self) -> Word {
+        if let Some(info_none) = self.debug_info.borrow().info_none {
+            return info_none;
+        }
+        let info_none = self.debug_global_inst(DebugInfoOp::DebugInfoNone, &[]);
+        self.debug_info.borrow_mut().info_none = Some(info_none);
+        info_none
+    }
+
+    pub fn debug_empty_expression(&self) -> Word {
+        if let Some(expression) = self.debug_info.borrow().empty_expression {
+            return expression;
+        }
+        let expression = self.debug_global_inst(DebugInfoOp::DebugExpression, &[]);
+        self.debug_info.borrow_mut().empty_expression = Some(expression);
+        expression
+    }
+
+    fn debug_file(&self, file: &SourceFile) -> Word {
+        if let Some(&source) = self.debug_info.borrow().files.get(&file.start_pos) {
+            return source;
+        }
+        let name = self.src_file_name(file);
+        let source = self.debug_global_inst(DebugInfoOp::DebugSource, &[name]);
+        self.debug_info
+            .borrow_mut()
+            .files
+            .insert(file.start_pos, source);
+        source
+    }
+
+    /// The `OpString` of the name of `file` (also declared with `OpSource`).
+    fn src_file_name(&self, file: &SourceFile) -> Word {
+        if let Some(&name) = self.debug_info.borrow().file_names.get(&file.start_pos) {
+            return name;
+        }
+        let name = self.debug_string(&file.name.to_string());
+        self.emit_global()
+            .source(SourceLanguage::Unknown, 0, Some(name), None::<String>);
+        self.debug_info
+            .borrow_mut()
+            .file_names
+            .insert(file.start_pos, name);
+        name
+    }
+
+    /// The `OpString` of the file name, line and (1-based) column of `pos`, for `OpLine`.
+    pub fn src_loc(&self, pos: BytePos) -> (Word, u32, u32) {
+        let loc = self.tcx.sess.source_map().lookup_char_pos(pos);
+        (
+            self.src_file_name(&loc.file),
+            loc.line as u32,
+            loc.col.to_u32() + 1,
+        )
+    }
+
+    /// The `DebugSource`, line and (1-based) column of `pos`.
+    fn debug_pos(&self, pos: BytePos) -> (Word, u32, u32) {
+        let loc = self.tcx.sess.source_map().lookup_char_pos(pos);
+        let file = self.debug_file(&loc.file);
+        (file, loc.line as u32, loc.col.to_u32() + 1)
+    }
+
+    fn debug_compilation_unit(&self) -> Word {
+        if let Some(compilation_unit) = self.debug_info.borrow().compilation_unit {
+            return compilation_unit;
+        }
+        let (file, _, _) = self.debug_pos(self.tcx.def_span(CRATE_DEF_ID.to_def_id()).lo());
+        let operands = [
+            self.debug_u32(debuginfo::VERSION),
+            self.debug_u32(debuginfo::DWARF_VERSION),
+            file,
+            // FIXME: there's no `SourceLanguage` for Rust.
+            self.debug_u32(SourceLanguage::Unknown as u32),
+        ];
+        let compilation_unit = self.debug_global_inst(DebugInfoOp::DebugCompilationUnit, &operands);
+        self.debug_info.borrow_mut().compilation_unit = Some(compilation_unit);
+        compilation_unit
+    }
+
+    pub fn debug_type(&self, ty: Ty<'tcx>) -> Word {
+        if let Some(&debug_type) = self.debug_info.borrow().types.get(&ty) {
+            return debug_type;
+        }
+        let layout = self.layout_of(ty);
+        let debug_type = match *ty.kind() {
+            ty::Bool => self.debug_basic_type(layout, encoding::BOOLEAN),
+            ty::Char | ty::Uint(_) => self.debug_basic_type(layout, encoding::UNSIGNED),
+            ty::Int(_) => self.debug_basic_type(layout, encoding::SIGNED),
+            ty::Float(_) => self.debug_basic_type(layout, encoding::FLOAT),
+            ty::Ref(_, pointee, _) | ty::RawPtr(ty::TypeAndMut { ty: pointee, .. })
+                if matches!(layout.abi, Abi::Scalar(_)) =>
+            {
+                // NOTE: the storage class is only known after the linker specializes pointers.
+                let operands = [
+                    self.debug_type(pointee),
+                    self.debug_u32(StorageClass::Generic as u32),
+                    self.debug_u32(0),
+                ];
+                self.debug_global_inst(DebugInfoOp::DebugTypePointer, &operands)
+            }
+            ty::Array(element, _) => {
+                let operands = [
+                    self.debug_type(element),
+                    self.debug_u32(layout.fields.count() as u32),
+                ];
+                self.debug_global_inst(DebugInfoOp::DebugTypeArray, &operands)
+            }
+            _ => match (&layout.variants, &layout.fields) {
+                (Variants::Single { .. }, FieldsShape::Arbitrary { .. }) => {
+                    self.debug_composite_type(layout)
+                }
+                _ => self.debug_info_none(),
+            },
+        };
+        self.debug_info.borrow_mut().types.insert(ty, debug_type);
+        debug_type
+    }
+
+    fn debug_basic_type(&self, layout: TyAndLayout<'tcx>, encoding: u32) -> Word {
+        let operands = [
+            self.debug_string(&with_no_trimmed_paths(|| layout.ty.to_string())),
+            self.debug_u32(layout.size.bits() as u32),
+            self.debug_u32(encoding),
+            self.debug_u32(0),
+        ];
+        self.debug_global_inst(DebugInfoOp::DebugTypeBasic, &operands)
+    }
+
+    fn debug_composite_type(&self, layout: TyAndLayout<'tcx>) -> Word {
+        // Recursive types (through pointers) can't refer back to themselves, as that would
+        // require a forward reference, so they get `DebugInfoNone` instead.
+        let info_none = self.debug_info_none();
+        self.debug_info
+            .borrow_mut()
+            .types
+            .insert(layout.ty, info_none);
+
+        let def_span = match *layout.ty.kind() {
+            ty::Adt(def, _) => self.tcx.def_span(def.did),
+            ty::Closure(def_id, _) => self.tcx.def_span(def_id),
+            _ => self.tcx.def_span(CRATE_DEF_ID.to_def_id()),
+        };
+        let (file, line, col) = self.debug_pos(def_span.lo());
+        let (line, col) = (self.debug_u32(line), self.debug_u32(col));
+        let no_flags = self.debug_u32(0);
+        let name = self.debug_string(&with_no_trimmed_paths(|| layout.ty.to_string()));
+
+        let field_name = |i: usize| match (layout.ty.kind(), &layout.variants) {
+            (ty::Adt(def, _), &Variants::Single { index }) => {
+                def.variants[index].fields[i].ident.to_string()
+            }
+            _ => format!("__{}", i),
+        };
+        let members = (0..layout.fields.count()).map(|i| {
+            let field = layout.field(self, i);
+            let operands = [
+                self.debug_string(&field_name(i)),
+                self.debug_type(field.ty),
+                file,
+                line,
+                col,
+                self.debug_u32(layout.fields.offset(i).bits() as u32),
+                self.debug_u32(field.size.bits() as u32),
+                no_flags,
+            ];
+            self.debug_global_inst(DebugInfoOp::DebugTypeMember, &operands)
+        });
+        let operands = vec![
+            name,
+            self.debug_u32(debuginfo::COMPOSITE_TAG_STRUCTURE),
+            file,
+            line,
+            col,
+            self.debug_compilation_unit(),
+            name,
+            self.debug_u32(layout.size.bits() as u32),
+            no_flags,
+        ]
+        .into_iter()
+        .chain(members)
+        .collect::<Vec<_>>();
+        self.debug_global_inst(DebugInfoOp::DebugTypeComposite, &operands)
+    }
+
+    fn debug_fn_type(&self, fn_abi: &FnAbi<'tcx, Ty<'tcx>>) -> Word {
+        let ret = match fn_abi.ret.mode {
+            PassMode::Ignore => SpirvType::Void.def(DUMMY_SP, self),
+            _ => self.debug_type(fn_abi.ret.layout.ty),
+        };
+        let operands = vec![self.debug_u32(0), ret]
+            .into_iter()
+            .chain(
+                fn_abi
+                    .args
+                    .iter()
+                    .filter(|arg| !matches!(arg.mode, PassMode::Ignore))
+                    .map(|arg| self.debug_type(arg.layout.ty)),
+            )
+            .collect::<Vec<_>>();
+        self.debug_global_inst(DebugInfoOp::DebugTypeFunction, &operands)
+    }
+
+    fn debug_inlined_at(&self, loc: DebugLoc) -> Word {
+        if let Some(&inlined_at) = self.debug_info.borrow().inlined_at.get(&loc) {
+            return inlined_at;
+        }
+        let operands = vec![self.debug_u32(loc.line), loc.scope]
+            .into_iter()
+            .chain(loc.inlined_at)
+            .collect::<Vec<_>>();
+        let inlined_at = self.debug_global_inst(DebugInfoOp::DebugInlinedAt, &operands);
+        self.debug_info
+            .borrow_mut()
+            .inlined_at
+            .insert(loc, inlined_at);
+        inlined_at
+    }
+
+    // NOTE: this mirrors `compute_mir_scopes` in `rustc_codegen_llvm`.
+    fn make_mir_scope(
+        &self,
+        instance: Instance<'tcx>,
+        mir: &Body<'tcx>,
+        fn_scope: Word,
+        has_variables: &HashSet<SourceScope>,
+        debug_context: &mut FunctionDebugContext<Word, DebugLoc>,
+        scope: SourceScope,
+    ) {
+        if debug_context.scopes[scope].dbg_scope.is_some() {
+            return;
+        }
+
+        let source_map = self.tcx.sess.source_map();
+        let scope_data = &mir.source_scopes[scope];
+        let parent_scope = if let Some(parent) = scope_data.parent_scope {
+            self.make_mir_scope(
+                instance,
+                mir,
+                fn_scope,
+                has_variables,
+                debug_context,
+                parent,
+            );
+            debug_context.scopes[parent]
+        } else {
+            // The root is the function itself.
+            let file = source_map.lookup_source_file(mir.span.lo());
+            debug_context.scopes[scope] = DebugScope {
+                dbg_scope: Some(fn_scope),
+                inlined_at: None,
+                file_start_pos: file.start_pos,
+                file_end_pos: file.end_pos,
+            };
+            return;
+        };
+
+        if !has_variables.contains(&scope) && scope_data.inlined.is_none() {
+            // Scopes without variables don't need a `DebugLexicalBlock` of their own.
+            debug_context.scopes[scope] = parent_scope;
+            return;
+        }
+
+        let dbg_scope = match scope_data.inlined {
+            Some((callee, _)) => {
+                let callee = self.tcx.subst_and_normalize_erasing_regions(
+                    instance.substs,
+                    ParamEnv::reveal_all(),
+                    callee,
+                );
+                let callee_fn_abi = FnAbi::of_instance(self, callee, &[]);
+                self.dbg_scope_fn(callee, &callee_fn_abi, None)
+            }
+            None => {
+                let (file, line, col) = self.debug_pos(scope_data.span.lo());
+                let operands = [
+                    file,
+                    self.debug_u32(line),
+                    self.debug_u32(col),
+                    parent_scope.dbg_scope.unwrap(),
+                ];
+                self.debug_global_inst(DebugInfoOp::DebugLexicalBlock, &operands)
+            }
+        };
+
+        let inlined_at = scope_data.inlined.map(|(_, callsite_span)| {
+            let callsite_scope = parent_scope.adjust_dbg_scope_for_span(self, callsite_span);
+            self.dbg_loc(callsite_scope, parent_scope.inlined_at, callsite_span)
+        });
+
+        let file = source_map.lookup_source_file(scope_data.span.lo());
+        debug_context.scopes[scope] = DebugScope {
+            dbg_scope: Some(dbg_scope),
+            inlined_at: inlined_at.or(parent_scope.inlined_at),
+            file_start_pos: file.start_pos,
+            file_end_pos: file.end_pos,
+        };
+    }
+}
+
+impl<'tcx> DebugInfoMethods<'tcx> for CodegenCx<'tcx> {
+    fn create_vtable_metadata(&self, _ty: Ty<'tcx>, _vtable: Self::Value) {
+        // Ignore.
+    }
+
+    fn create_function_debug_context(
+        &self,
+        instance: Instance<'tcx>,
+        fn_abi: &FnAbi<'tcx, Ty<'tcx>>,
+        llfn: Self::Function,
+        mir: &Body<'tcx>,
+    ) -> Option<FunctionDebugContext<Self::DIScope, Self::DILocation>> {
+        if self.tcx.sess.opts.debuginfo == DebugInfo::None {
+            return None;
+        }
+
+        let empty_scope = DebugScope {
+            dbg_scope: None,
+            inlined_at: None,
+            file_start_pos: BytePos(0),
+            file_end_pos: BytePos(0),
+        };
+        let mut debug_context = FunctionDebugContext {
+            scopes: mir.source_scopes.iter().map(|_| empty_scope).collect(),
+        };
+
+        // Only variables need scopes other than the function itself (see `make_mir_scope`).
+        let has_variables = if self.tcx.sess.opts.debuginfo == DebugInfo::Full {
+            mir.var_debug_info
+                .iter()
+                .map(|var| var.source_info.scope)
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        let fn_scope = self.dbg_scope_fn(instance, fn_abi, Some(llfn));
+        for scope in mir.source_scopes.indices() {
+            self.make_mir_scope(
+                instance,
+                mir,
+                fn_scope,
+                &has_variables,
+                &mut debug_context,
+                scope,
+            );
+        }
+        Some(debug_context)
+    }
+
+    fn dbg_scope_fn(
+        &self,
+        instance: Instance<'tcx>,
+        fn_abi: &FnAbi<'tcx, Ty<'tcx>>,
+        maybe_definition_llfn: Option<Self::Function>,
+    ) -> Self::DIScope {
+        if maybe_definition_llfn.is_none() {
+            if let Some(&debug_fn) = self.debug_info.borrow().functions.get(&instance) {
+                return debug_fn;
+            }
+        }
+
+        let def_id = instance.def_id();
+        let (file, line, col) = self.debug_pos(self.tcx.def_span(def_id).lo());
+        let line = self.debug_u32(line);
+        let operands = [
+            self.debug_string(&with_no_trimmed_paths(|| self.tcx.def_path_str(def_id))),
+            self.debug_fn_type(fn_abi),
+            file,
+            line,
+            self.debug_u32(col),
+            self.debug_compilation_unit(),
+            self.debug_string(self.tcx.symbol_name(instance).name),
+            self.debug_u32(if maybe_definition_llfn.is_some() {
+                flags::IS_DEFINITION
+            } else {
+                0
+            }),
+            line,
+        ];
+        let debug_fn = self.debug_global_inst(DebugInfoOp::DebugFunction, &operands);
+
+        let mut debug_info = self.debug_info.borrow_mut();
+        debug_info.functions.insert(instance, debug_fn);
+        if let Some(llfn) = maybe_definition_llfn {
+            debug_info
+                .pending_definitions
+                .insert(llfn.def_cx(self), debug_fn);
+        }
+        debug_fn
+    }
+
+    fn dbg_loc(
+        &self,
+        scope: Self::DIScope,
+        inlined_at: Option<Self::DILocation>,
+        span: Span,
+    ) -> Self::DILocation {
+        let (file, line, col) = self.src_loc(span.lo());
+        DebugLoc {
+            scope,
+            inlined_at: inlined_at.map(|loc| self.debug_inlined_at(loc)),
+            file,
+            line,
+            col,
+        }
+    }
+
+    fn extend_scope_to_file(
+        &self,
+        scope_metadata: Self::DIScope,
+        _file: &SourceFile,
+    ) -> Self::DIScope {
+        // The file is only needed by `OpLine`, which gets it from `DebugLoc` instead.
+        scope_metadata
+    }
+
+    fn debuginfo_finalize(&self) {
+        // Everything has already been emitted.
+    }
+
+    fn create_dbg_var(
+        &self,
+        variable_name: Symbol,
+        variable_type: Ty<'tcx>,
+        scope_metadata: Self::DIScope,
+        variable_kind: VariableKind,
+        span: Span,
+    ) -> Self::DIVariable {
+        let (file, line, col) = self.debug_pos(span.lo());
+        let mut operands = vec![
+            self.debug_string(&variable_name.as_str()),
+            self.debug_type(variable_type),
+            file,
+            self.debug_u32(line),
+            self.debug_u32(col),
+            scope_metadata,
+            self.debug_u32(flags::IS_LOCAL),
+        ];
+        if let VariableKind::ArgumentVariable(arg_index) = variable_kind {
+            operands.push(self.debug_u32(arg_index as u32));
+        }
+        self.debug_global_inst(DebugInfoOp::DebugLocalVariable, &operands)
+    }
+}
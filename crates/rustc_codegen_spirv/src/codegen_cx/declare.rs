@@ -1,7 +1,7 @@
 use super::CodegenCx;
 use crate::abi::ConvSpirvType;
 use crate::attr::AggregatedSpirvAttributes;
-use crate::builder_spirv::{SpirvConst, SpirvValue, SpirvValueExt};
+use crate::builder_spirv::{SpirvConst, SpirvValue, SpirvValueExt, SpirvValueKind};
 use crate::decorations::UnrollLoopsDecoration;
 use crate::spirv_type::SpirvType;
 use rspirv::spirv::{FunctionControl, LinkageType, StorageClass, Word};
@@ -108,6 +108,8 @@ impl<'tcx> CodegenCx<'tcx> {
             self.set_linkage(fn_id, symbol_name.to_owned(), linkage);
         }
 
+        self.set_src_loc(fn_id, self.tcx.sess.source_map().guess_head_span(span));
+
         let declared = fn_id.with_type(function_type);
 
         let attrs = AggregatedSpirvAttributes::parse(self, self.tcx.get_attrs(instance.def_id()));
@@ -119,10 +121,13 @@ impl<'tcx> CodegenCx<'tcx> {
                 .unwrap_or_else(|| instance.to_string());
             self.entry_stub(&instance, &fn_abi, declared, entry_name, entry)
         }
-        if attrs.unroll_loops.is_some() {
-            self.unroll_loops_decorations
-                .borrow_mut()
-                .insert(fn_id, UnrollLoopsDecoration {});
+        if let Some(unroll_loops) = attrs.unroll_loops {
+            self.unroll_loops_decorations.borrow_mut().insert(
+                fn_id,
+                UnrollLoopsDecoration {
+                    max_trip_count: unroll_loops.value,
+                },
+            );
         }
 
         let instance_def_id = instance.def_id();
@@ -241,8 +246,21 @@ impl<'tcx> PreDefineMethods<'tcx> for CodegenCx<'tcx> {
 }
 
 impl<'tcx> StaticMethods for CodegenCx<'tcx> {
-    fn static_addr_of(&self, cv: Self::Value, _align: Align, _kind: Option<&str>) -> Self::Value {
-        self.make_constant_pointer(DUMMY_SP, cv)
+    fn static_addr_of(&self, cv: Self::Value, _align: Align, kind: Option<&str>) -> Self::Value {
+        let ptr = self.make_constant_pointer(DUMMY_SP, cv);
+        if kind == Some("vtable") {
+            // `dyn Trait` vtables are never accessed through memory, instead their pointers are
+            // turned into indices (and their contents into constants) by `linker::devirtualize`.
+            let global_var = match ptr.kind {
+                SpirvValueKind::ConstantPointer { global_var, .. } => global_var,
+                _ => unreachable!(),
+            };
+            let ty = self.type_vtable_ptr(DUMMY_SP);
+            return self
+                .address_constant(ty, global_var, DUMMY_SP)
+                .with_type(ty);
+        }
+        ptr
     }
 
     fn codegen_static(&self, def_id: DefId, _is_mutable: bool) {
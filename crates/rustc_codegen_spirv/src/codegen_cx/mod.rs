@@ -1,37 +1,36 @@
 mod constant;
+mod debuginfo;
 mod declare;
 mod entry;
 mod type_;
 
+pub use debuginfo::{DebugInfoCache, DebugLoc};
+
 use crate::builder::{ExtInst, InstructionTable};
 use crate::builder_spirv::{BuilderCursor, BuilderSpirv, SpirvValue, SpirvValueKind};
 use crate::decorations::{
-    CustomDecoration, SerializedSpan, UnrollLoopsDecoration, ZombieDecoration,
+    DecorationEncoder, SerializedSpan, SrcLocDecoration, UnrollLoopsDecoration, ZombieDecoration,
 };
 use crate::spirv_type::{SpirvType, SpirvTypePrinter, TypeCache};
 use crate::symbols::Symbols;
 use rspirv::dr::{Module, Operand};
 use rspirv::spirv::{AddressingModel, Decoration, LinkageType, MemoryModel, StorageClass, Word};
-use rustc_codegen_ssa::mir::debuginfo::{FunctionDebugContext, VariableKind};
-use rustc_codegen_ssa::traits::{
-    AsmMethods, BackendTypes, CoverageInfoMethods, DebugInfoMethods, MiscMethods,
-};
+use rustc_codegen_ssa::traits::{AsmMethods, BackendTypes, CoverageInfoMethods, MiscMethods};
 use rustc_data_structures::fx::FxHashMap;
 use rustc_hir::GlobalAsm;
 use rustc_middle::mir::mono::CodegenUnit;
-use rustc_middle::mir::Body;
 use rustc_middle::ty::layout::{HasParamEnv, HasTyCtxt};
-use rustc_middle::ty::{Instance, ParamEnv, PolyExistentialTraitRef, Ty, TyCtxt, TyS};
+use rustc_middle::ty::{Instance, ParamEnv, PolyExistentialTraitRef, Ty, TyCtxt};
 use rustc_session::Session;
 use rustc_span::def_id::LOCAL_CRATE;
-use rustc_span::symbol::{sym, Symbol};
-use rustc_span::{SourceFile, Span, DUMMY_SP};
-use rustc_target::abi::call::FnAbi;
+use rustc_span::symbol::sym;
+use rustc_span::{Span, DUMMY_SP};
 use rustc_target::abi::{HasDataLayout, TargetDataLayout};
 use rustc_target::spec::{HasTargetSpec, Target};
 use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::iter::once;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::str::FromStr;
 
@@ -51,16 +50,22 @@ pub struct CodegenCx<'tcx> {
     /// Invalid spir-v IDs that should be stripped from the final binary,
     /// each with its own reason and span that should be used for reporting
     /// (in the event that the value is actually needed)
-    zombie_decorations: RefCell<HashMap<Word, ZombieDecoration>>,
+    // NOTE: the decoration maps are ordered, so they're emitted in the same order every time.
+    zombie_decorations: RefCell<BTreeMap<Word, ZombieDecoration>>,
     /// Functions that have `#[spirv(unroll_loops)]`, and therefore should
     /// get `LoopControl::UNROLL` applied to all of their loops' `OpLoopMerge`
     /// instructions, during structuralization.
-    unroll_loops_decorations: RefCell<HashMap<Word, UnrollLoopsDecoration>>,
+    unroll_loops_decorations: RefCell<BTreeMap<Word, UnrollLoopsDecoration>>,
+    /// Spans of function definitions, for errors only the linker can detect.
+    src_loc_decorations: RefCell<BTreeMap<Word, SrcLocDecoration>>,
+    /// Source-level debug info, only emitted with `-C debuginfo`.
+    pub debug_info: RefCell<DebugInfoCache<'tcx>>,
     pub kernel_mode: bool,
     /// Cache of all the builtin symbols we need
     pub sym: Rc<Symbols>,
     pub instruction_table: InstructionTable,
-    pub zombie_undefs_for_system_fn_addrs: RefCell<HashMap<Word, Word>>,
+    /// Map from function ID to the "address constant" of that function, see `address_constant`.
+    pub fn_addr_constants: RefCell<HashMap<Word, Word>>,
     pub libm_intrinsics: RefCell<HashMap<Word, super::builder::libm_intrinsics::LibmIntrinsic>>,
 
     /// Simple `panic!("...")` and builtin panics (from MIR `Assert`s) call `#[lang = "panic"]`.
@@ -102,6 +107,11 @@ impl<'tcx> CodegenCx<'tcx> {
                 memory_model = Some(MemoryModel::Vulkan);
             } else if feature == sym.glsl450 {
                 memory_model = Some(MemoryModel::GLSL450);
+            } else if feature == sym.emulate_int64
+                || feature == sym.narrow_float64
+                || feature == sym.promote_small_ints
+            {
+                // Handled by the linker (see `linker::Options`).
             } else {
                 tcx.sess.err(&format!("Unknown feature {}", feature));
             }
@@ -118,10 +128,12 @@ impl<'tcx> CodegenCx<'tcx> {
             ext_inst: Default::default(),
             zombie_decorations: Default::default(),
             unroll_loops_decorations: Default::default(),
+            src_loc_decorations: Default::default(),
+            debug_info: Default::default(),
             kernel_mode,
             sym,
             instruction_table: InstructionTable::new(),
-            zombie_undefs_for_system_fn_addrs: Default::default(),
+            fn_addr_constants: Default::default(),
             libm_intrinsics: Default::default(),
             panic_fn_id: Default::default(),
             panic_bounds_check_fn_id: Default::default(),
@@ -157,9 +169,10 @@ impl<'tcx> CodegenCx<'tcx> {
     /// Zombie system:
     /// When compiling libcore and other system libraries, if something unrepresentable is
     /// encountered, we don't want to fail the compilation. Instead, we emit something bogus
-    /// (usually it's fairly faithful, though, e.g. u128 emits `OpTypeInt 128`), and then mark the
-    /// resulting ID as a "zombie". We continue compiling the rest of the crate, then, at the very
-    /// end, anything that transtively references a zombie value is stripped from the binary.
+    /// (usually it's fairly faithful, though, e.g. u64 without `Int64` still emits `OpTypeInt 64`),
+    /// and then mark the resulting ID as a "zombie". We continue compiling the rest of the crate,
+    /// then, at the very end, anything that transtively references a zombie value is stripped from
+    /// the binary.
     ///
     /// If an exported function is stripped, then we emit a special "zombie export" item, which is
     /// consumed by the linker, which continues to infect other values that reference it.
@@ -186,6 +199,14 @@ impl<'tcx> CodegenCx<'tcx> {
         );
     }
 
+    pub fn set_src_loc(&self, word: Word, span: Span) {
+        if let Some(span) = SerializedSpan::from_rustc(span, self.tcx.sess.source_map()) {
+            self.src_loc_decorations
+                .borrow_mut()
+                .insert(word, SrcLocDecoration { span });
+        }
+    }
+
     pub fn is_system_crate(&self) -> bool {
         self.tcx
             .sess
@@ -196,6 +217,16 @@ impl<'tcx> CodegenCx<'tcx> {
             || self.tcx.crate_name(LOCAL_CRATE) == self.sym.num_traits
     }
 
+    pub fn addressing_model(&self) -> AddressingModel {
+        self.emit_global()
+            .module_ref()
+            .memory_model
+            .as_ref()
+            .map_or(AddressingModel::Logical, |inst| {
+                inst.operands[0].unwrap_addressing_model()
+            })
+    }
+
     // FIXME(eddyb) should this just be looking at `kernel_mode`?
     pub fn logical_addressing_model(&self) -> bool {
         self.emit_global()
@@ -209,18 +240,16 @@ impl<'tcx> CodegenCx<'tcx> {
 
     pub fn finalize_module(self) -> Module {
         let mut result = self.builder.finalize();
-        result.annotations.extend(
-            self.zombie_decorations
-                .into_inner()
-                .into_iter()
-                .map(|(id, zombie)| zombie.encode(id))
-                .chain(
-                    self.unroll_loops_decorations
-                        .into_inner()
-                        .into_iter()
-                        .map(|(id, unroll_loops)| unroll_loops.encode(id)),
-                ),
-        );
+        let mut encoder = DecorationEncoder::new(&mut result);
+        for (id, zombie) in self.zombie_decorations.into_inner() {
+            encoder.encode(id, &zombie);
+        }
+        for (id, unroll_loops) in self.unroll_loops_decorations.into_inner() {
+            encoder.encode(id, &unroll_loops);
+        }
+        for (id, src_loc) in self.src_loc_decorations.into_inner() {
+            encoder.encode(id, &src_loc);
+        }
         result
     }
 
@@ -232,6 +261,46 @@ impl<'tcx> CodegenCx<'tcx> {
         )
     }
 
+    /// Create a pointer constant of type `ty` referring to `target` (a function, or the global
+    /// variable of a vtable), for `linker::devirtualize` to replace with an index into the
+    /// (closed-world) set of such targets.
+    pub fn address_constant(&self, ty: Word, target: Word, span: Span) -> Word {
+        // HACK: SPIR-V doesn't have a way to take the address of a function (outside of
+        // `SPV_INTEL_function_pointers`, which `rspirv` can't load at module scope), so the
+        // constant is encoded as an `OpConstantComposite` with the target as its only field.
+        // We want a unique ID for these constants, so don't use the caching system.
+        let constant = self.emit_global().constant_composite(ty, once(target));
+        // That's invalid SPIR-V, so it's a zombie until `linker::devirtualize` replaces it (and
+        // otherwise, it's either unused, e.g. by direct calls, or its uses get reported).
+        self.zombie_even_in_user_code(
+            constant,
+            span,
+            "function pointers and `dyn Trait` vtables are only supported when devirtualized",
+        );
+        constant
+    }
+
+    /// The type of `dyn Trait` vtable pointers (see also `linker::devirtualize`).
+    pub fn type_vtable_ptr(&self, span: Span) -> Word {
+        SpirvType::Pointer {
+            pointee: SpirvType::Opaque {
+                name: crate::linker::VTABLE_TYPE_NAME.to_string(),
+            }
+            .def(span, self),
+        }
+        .def(span, self)
+    }
+
+    pub fn is_vtable_ptr_type(&self, ty: Word) -> bool {
+        match self.lookup_type(ty) {
+            SpirvType::Pointer { pointee } => matches!(
+                self.lookup_type(pointee),
+                SpirvType::Opaque { name } if name == crate::linker::VTABLE_TYPE_NAME
+            ),
+            _ => false,
+        }
+    }
+
     /// See note on `SpirvValueKind::ConstantPointer`
     pub fn make_constant_pointer(&self, span: Span, value: SpirvValue) -> SpirvValue {
         let ty = SpirvType::Pointer { pointee: value.ty }.def(span, self);
@@ -265,6 +334,19 @@ impl<'tcx> CodegenCx<'tcx> {
 
 pub struct CodegenArgs {
     pub module_output_type: ModuleOutputType,
+    /// Keep the source locations of functions (see `SrcLocDecoration`) in the final module.
+    pub keep_src_locs: bool,
+    /// The maximum size (in instructions) of functions to inline in optimized builds, besides
+    /// the `#[inline]`/`#[inline(always)]` ones, and those called only once (see `linker::inline`).
+    pub inline_threshold: usize,
+    /// The `spirv-opt` passes to run on the linked module(s), instead of the ones picked from
+    /// `-C opt-level` (see `link::do_spirv_opt`).
+    pub spirv_opt: Option<SpirvOptPasses>,
+    /// Options for `spirv-val`, also used by `spirv-opt` when validating its input.
+    pub spirv_val: SpirvValOptions,
+    /// Precompiled SPIR-V modules (e.g. hand-written, or compiled from GLSL) to link against, whose
+    /// exports can be called from Rust through `extern` declarations.
+    pub spirv_libraries: Vec<PathBuf>,
 }
 
 impl CodegenArgs {
@@ -284,13 +366,209 @@ impl CodegenArgs {
             "single output or multiple output",
             "[single|multiple]",
         );
+        opts.optflag(
+            "",
+            "keep-src-locs",
+            "keep the source locations of functions in the output, for tools which understand them",
+        );
+        opts.optopt(
+            "",
+            "inline-threshold",
+            "maximum size of functions to inline, 0 to only inline #[inline] ones",
+            "N",
+        );
+        opts.optopt(
+            "",
+            "spirv-opt",
+            "spirv-opt passes to run, instead of the ones picked from -C opt-level",
+            "[performance|size|legalization|none]",
+        );
+        opts.optmulti(
+            "",
+            "spirv-opt-pass",
+            "spirv-opt pass to run (in order), instead of the ones picked from -C opt-level",
+            "PASS",
+        );
+        opts.optflag(
+            "",
+            "relax-struct-store",
+            "spirv-val: relax struct store rules",
+        );
+        opts.optflag(
+            "",
+            "relax-logical-pointer",
+            "spirv-val: relax logical pointer rules",
+        );
+        opts.optflag(
+            "",
+            "relax-block-layout",
+            "spirv-val: use relaxed block layout rules",
+        );
+        opts.optflag(
+            "",
+            "uniform-buffer-standard-layout",
+            "spirv-val: use standard block layout rules for uniform buffers",
+        );
+        opts.optflag(
+            "",
+            "scalar-block-layout",
+            "spirv-val: use scalar block layout rules",
+        );
+        opts.optflag(
+            "",
+            "skip-block-layout",
+            "spirv-val: skip block layout validation",
+        );
+        opts.optflag(
+            "",
+            "before-legalization",
+            "spirv-val: relax the rules legalization is expected to fix",
+        );
+        opts.optmulti(
+            "",
+            "spirv-library",
+            "precompiled SPIR-V module to link against",
+            "PATH",
+        );
         let matches = opts.parse(args)?;
         let module_output_type =
             matches.opt_get_default("module-output", ModuleOutputType::Single)?;
-        Ok(Self { module_output_type })
+        let keep_src_locs = matches.opt_present("keep-src-locs");
+        let inline_threshold = match matches.opt_str("inline-threshold") {
+            Some(threshold) => threshold
+                .parse()
+                .map_err(|_| getopts::Fail::UnexpectedArgument(threshold))?,
+            None => 32,
+        };
+        let spirv_opt_passes = matches.opt_strs("spirv-opt-pass");
+        let spirv_opt = match matches.opt_str("spirv-opt") {
+            Some(_) if !spirv_opt_passes.is_empty() => {
+                return Err(getopts::Fail::OptionDuplicated("spirv-opt".to_string()))
+            }
+            Some(preset) => Some(preset.parse()?),
+            None if !spirv_opt_passes.is_empty() => Some(SpirvOptPasses::Custom(
+                spirv_opt_passes
+                    .iter()
+                    .map(|pass| parse_spirv_opt_pass(pass))
+                    .collect::<Result<_, _>>()?,
+            )),
+            None => None,
+        };
+        let spirv_val = SpirvValOptions {
+            relax_struct_store: matches.opt_present("relax-struct-store"),
+            relax_logical_pointer: matches.opt_present("relax-logical-pointer"),
+            relax_block_layout: matches.opt_present("relax-block-layout"),
+            uniform_buffer_standard_layout: matches.opt_present("uniform-buffer-standard-layout"),
+            scalar_block_layout: matches.opt_present("scalar-block-layout"),
+            skip_block_layout: matches.opt_present("skip-block-layout"),
+            before_legalization: matches.opt_present("before-legalization"),
+        };
+        let spirv_libraries = matches
+            .opt_strs("spirv-library")
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        Ok(Self {
+            module_output_type,
+            keep_src_locs,
+            inline_threshold,
+            spirv_opt,
+            spirv_val,
+            spirv_libraries,
+        })
     }
 }
 
+pub enum SpirvOptPasses {
+    Performance,
+    Size,
+    /// Only the passes needed to legalize the module, without optimizing it further.
+    Legalization,
+    /// Runs no passes, besides stripping debuginfo if it's off (which is done for all of these).
+    None,
+    /// Passes given by name (e.g. `--spirv-opt-pass=AggressiveDCE`), run in the given order.
+    Custom(Vec<spirv_tools::opt::Passes>),
+}
+
+impl FromStr for SpirvOptPasses {
+    type Err = rustc_session::getopts::Fail;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "performance" => Ok(Self::Performance),
+            "size" => Ok(Self::Size),
+            "legalization" => Ok(Self::Legalization),
+            "none" => Ok(Self::None),
+            v => Err(Self::Err::UnrecognizedOption(v.to_string())),
+        }
+    }
+}
+
+fn parse_spirv_opt_pass(
+    name: &str,
+) -> Result<spirv_tools::opt::Passes, rustc_session::getopts::Fail> {
+    use spirv_tools::opt::Passes;
+    const PASSES: &[Passes] = &[
+        Passes::AggressiveDCE,
+        Passes::BlockMerge,
+        Passes::CFGCleanup,
+        Passes::CodeSinking,
+        Passes::CombineAccessChains,
+        Passes::CompactIds,
+        Passes::ConditionalConstantPropagation,
+        Passes::CopyPropagateArrays,
+        Passes::DeadBranchElim,
+        Passes::DeadInsertElim,
+        Passes::DeadVariableElimination,
+        Passes::EliminateDeadConstant,
+        Passes::EliminateDeadFunctions,
+        Passes::FlattenDecoration,
+        Passes::FoldSpecConstantOpAndComposite,
+        Passes::FreezeSpecConstantValue,
+        Passes::IfConversion,
+        Passes::InlineExhaustive,
+        Passes::InlineOpaque,
+        Passes::InsertExtractElim,
+        Passes::LocalAccessChainConvert,
+        Passes::LocalMultiStoreElim,
+        Passes::LocalRedundancyElimination,
+        Passes::LocalSingleBlockLoadStoreElim,
+        Passes::LocalSingleStoreElim,
+        Passes::LoopInvariantCodeMotion,
+        Passes::LoopPeeling,
+        Passes::LoopUnswitch,
+        Passes::MergeReturn,
+        Passes::PrivateToLocal,
+        Passes::ReduceLoadSize,
+        Passes::RedundancyElimination,
+        Passes::RedundantLineInfoElim,
+        Passes::RemoveDuplicates,
+        Passes::Simplification,
+        Passes::SSARewrite,
+        Passes::StrengthReduction,
+        Passes::StripDebugInfo,
+        Passes::UnifyConstant,
+        Passes::VectorDCE,
+    ];
+    // NOTE: `Passes` has no `FromStr`, but its `Debug` output is the variant name.
+    PASSES
+        .iter()
+        .copied()
+        .find(|pass| format!("{:?}", pass) == name)
+        .ok_or_else(|| rustc_session::getopts::Fail::UnrecognizedOption(name.to_string()))
+}
+
+/// `spirv-val` options (see `spirv_tools::val::ValidatorOptions`).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SpirvValOptions {
+    pub relax_struct_store: bool,
+    pub relax_logical_pointer: bool,
+    pub relax_block_layout: bool,
+    pub uniform_buffer_standard_layout: bool,
+    pub scalar_block_layout: bool,
+    pub skip_block_layout: bool,
+    pub before_legalization: bool,
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ModuleOutputType {
     Single,
@@ -318,9 +596,11 @@ impl<'tcx> BackendTypes for CodegenCx<'tcx> {
     // https://doc.rust-lang.org/nightly/nightly-rustc/rustc_codegen_llvm/common/struct.Funclet.html
     type Funclet = ();
 
-    type DIScope = ();
-    type DILocation = ();
-    type DIVariable = ();
+    /// `DebugFunction` or `DebugLexicalBlock`.
+    type DIScope = Word;
+    type DILocation = DebugLoc;
+    /// `DebugLocalVariable`.
+    type DIVariable = Word;
 }
 
 impl<'tcx> HasTyCtxt<'tcx> for CodegenCx<'tcx> {
@@ -375,22 +655,16 @@ impl<'tcx> MiscMethods<'tcx> for CodegenCx<'tcx> {
         }
         .def(span, self);
 
-        if self.is_system_crate() {
-            // Create these undefs up front instead of on demand in SpirvValue::def because
-            // SpirvValue::def can't use cx.emit()
-            self.zombie_undefs_for_system_fn_addrs
-                .borrow_mut()
-                .entry(ty)
-                .or_insert_with(|| {
-                    // We want a unique ID for these undefs, so don't use the caching system.
-                    self.emit_global().undef(ty, None)
-                });
-        }
+        // Create these constants up front instead of on demand in SpirvValue::def because
+        // SpirvValue::def can't use cx.emit()
+        let function = function.def_cx(self);
+        self.fn_addr_constants
+            .borrow_mut()
+            .entry(function)
+            .or_insert_with(|| self.address_constant(ty, function, span));
 
         SpirvValue {
-            kind: SpirvValueKind::FnAddr {
-                function: function.def_cx(self),
-            },
+            kind: SpirvValueKind::FnAddr { function },
             ty,
         }
     }
@@ -428,67 +702,101 @@ impl<'tcx> MiscMethods<'tcx> for CodegenCx<'tcx> {
     }
 }
 
-impl<'tcx> DebugInfoMethods<'tcx> for CodegenCx<'tcx> {
-    fn create_vtable_metadata(&self, _ty: Ty<'tcx>, _vtable: Self::Value) {
-        // Ignore.
-    }
-
-    fn dbg_scope_fn(
-        &self,
-        _: rustc_middle::ty::Instance<'tcx>,
-        _: &FnAbi<'tcx, &'tcx TyS<'tcx>>,
-        _: Option<Self::Function>,
-    ) -> Self::DIScope {
+impl<'tcx> CoverageInfoMethods for CodegenCx<'tcx> {
+    fn coverageinfo_finalize(&self) {
         todo!()
     }
+}
 
-    fn dbg_loc(&self, _: Self::DIScope, _: Option<Self::DILocation>, _: Span) -> Self::DILocation {
+impl<'tcx> AsmMethods for CodegenCx<'tcx> {
+    fn codegen_global_asm(&self, _ga: &GlobalAsm) {
         todo!()
     }
+}
 
-    fn create_function_debug_context(
-        &self,
-        _instance: Instance<'tcx>,
-        _fn_abi: &FnAbi<'tcx, Ty<'tcx>>,
-        _llfn: Self::Function,
-        _mir: &Body<'_>,
-    ) -> Option<FunctionDebugContext<Self::DIScope, Self::DILocation>> {
-        // TODO: This is ignored. Do we want to implement this at some point?
-        None
-    }
+#[cfg(test)]
+mod test {
+    use super::{CodegenArgs, SpirvOptPasses, SpirvValOptions};
+    use rustc_session::getopts::Fail;
 
-    fn extend_scope_to_file(
-        &self,
-        _scope_metadata: Self::DIScope,
-        _file: &SourceFile,
-    ) -> Self::DIScope {
-        todo!()
+    fn parse(args: &[&str]) -> Result<CodegenArgs, Fail> {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        CodegenArgs::parse(&args)
     }
 
-    fn debuginfo_finalize(&self) {
-        todo!()
-    }
+    #[test]
+    fn spirv_opt_preset() {
+        let args = parse(&["--spirv-opt=size"]).unwrap();
+        assert!(matches!(args.spirv_opt, Some(SpirvOptPasses::Size)));
 
-    fn create_dbg_var(
-        &self,
-        _variable_name: Symbol,
-        _variable_type: Ty<'tcx>,
-        _scope_metadata: Self::DIScope,
-        _variable_kind: VariableKind,
-        _span: Span,
-    ) -> Self::DIVariable {
-        todo!()
+        let args = parse(&[]).unwrap();
+        assert!(args.spirv_opt.is_none());
+
+        assert_eq!(
+            parse(&["--spirv-opt=fast"]).err(),
+            Some(Fail::UnrecognizedOption("fast".to_string()))
+        );
     }
-}
 
-impl<'tcx> CoverageInfoMethods for CodegenCx<'tcx> {
-    fn coverageinfo_finalize(&self) {
-        todo!()
+    #[test]
+    fn spirv_opt_passes() {
+        let args = parse(&[
+            "--spirv-opt-pass=CFGCleanup",
+            "--spirv-opt-pass=AggressiveDCE",
+            "--spirv-opt-pass=CFGCleanup",
+        ])
+        .unwrap();
+        match args.spirv_opt {
+            Some(SpirvOptPasses::Custom(passes)) => assert_eq!(
+                format!("{:?}", passes),
+                "[CFGCleanup, AggressiveDCE, CFGCleanup]"
+            ),
+            _ => panic!("expected custom passes"),
+        }
+
+        assert_eq!(
+            parse(&["--spirv-opt-pass=NotAPass"]).err(),
+            Some(Fail::UnrecognizedOption("NotAPass".to_string()))
+        );
+        assert_eq!(
+            parse(&["--spirv-opt=size", "--spirv-opt-pass=AggressiveDCE"]).err(),
+            Some(Fail::OptionDuplicated("spirv-opt".to_string()))
+        );
     }
-}
 
-impl<'tcx> AsmMethods for CodegenCx<'tcx> {
-    fn codegen_global_asm(&self, _ga: &GlobalAsm) {
-        todo!()
+    #[test]
+    fn spirv_val_flags() {
+        let args = parse(&["--relax-block-layout", "--skip-block-layout"]).unwrap();
+        assert_eq!(
+            args.spirv_val,
+            SpirvValOptions {
+                relax_block_layout: true,
+                skip_block_layout: true,
+                ..Default::default()
+            }
+        );
+
+        let args = parse(&[
+            "--relax-struct-store",
+            "--relax-logical-pointer",
+            "--uniform-buffer-standard-layout",
+            "--scalar-block-layout",
+            "--before-legalization",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.spirv_val,
+            SpirvValOptions {
+                relax_struct_store: true,
+                relax_logical_pointer: true,
+                uniform_buffer_standard_layout: true,
+                scalar_block_layout: true,
+                before_legalization: true,
+                ..Default::default()
+            }
+        );
+
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.spirv_val, SpirvValOptions::default());
     }
 }
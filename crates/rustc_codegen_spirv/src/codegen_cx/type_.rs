@@ -166,9 +166,10 @@ impl<'tcx> BaseTypeMethods<'tcx> for CodegenCx<'tcx> {
                     .sess
                     .fatal(&format!("Invalid float width in type_kind: {}", other)),
             },
-            SpirvType::Adt { .. } | SpirvType::Opaque { .. } | SpirvType::InterfaceBlock { .. } => {
-                TypeKind::Struct
-            }
+            SpirvType::Adt { .. }
+            | SpirvType::Opaque { .. }
+            | SpirvType::InterfaceBlock { .. }
+            | SpirvType::Matrix { .. } => TypeKind::Struct,
             SpirvType::Vector { .. } => TypeKind::Vector,
             SpirvType::Array { .. } | SpirvType::RuntimeArray { .. } => TypeKind::Array,
             SpirvType::Pointer { .. } => TypeKind::Pointer,
@@ -0,0 +1,94 @@
+//! Source-level debug info, encoded using the `NonSemantic.Shader.DebugInfo.100` extended
+//! instruction set, produced during the original codegen of a crate (see
+//! `codegen_cx/debuginfo.rs`), and kept accurate by the `linker` passes which move code around.
+//!
+//! Note that all of these instructions take `<id>` operands (even for integers, which are always
+//! 32-bit `OpConstant`s), and have `OpTypeVoid` as their result type.
+//! <https://github.com/KhronosGroup/SPIRV-Registry/blob/main/nonsemantic/NonSemantic.Shader.DebugInfo.100.asciidoc>
+
+use rspirv::dr::{Instruction, Module, Operand};
+use rspirv::spirv::{Op, Word};
+use std::iter;
+
+pub const NON_SEMANTIC_DEBUG_INFO: &str = "NonSemantic.Shader.DebugInfo.100";
+
+/// The subset of `NonSemantic.Shader.DebugInfo.100` instructions we emit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebugInfoOp {
+    DebugInfoNone = 0,
+    DebugCompilationUnit = 1,
+    DebugTypeBasic = 2,
+    DebugTypePointer = 3,
+    DebugTypeArray = 5,
+    DebugTypeFunction = 8,
+    DebugTypeComposite = 10,
+    DebugTypeMember = 11,
+    DebugFunction = 20,
+    DebugLexicalBlock = 21,
+    DebugScope = 23,
+    DebugInlinedAt = 25,
+    DebugLocalVariable = 26,
+    DebugDeclare = 28,
+    DebugValue = 29,
+    DebugExpression = 31,
+    DebugSource = 35,
+    DebugFunctionDefinition = 101,
+}
+
+/// Values of the `Encoding` operand of `DebugTypeBasic`.
+pub mod encoding {
+    pub const BOOLEAN: u32 = 2;
+    pub const FLOAT: u32 = 3;
+    pub const SIGNED: u32 = 4;
+    pub const UNSIGNED: u32 = 6;
+}
+
+/// Bits of the `Flags` operand of various instructions.
+pub mod flags {
+    pub const IS_LOCAL: u32 = 1 << 2;
+    pub const IS_DEFINITION: u32 = 1 << 3;
+}
+
+/// The `Tag` operand of `DebugTypeComposite` for `struct`s (and anything laid out like one).
+pub const COMPOSITE_TAG_STRUCTURE: u32 = 1;
+
+/// The version of the instruction set, for `DebugCompilationUnit`.
+pub const VERSION: u32 = 100;
+pub const DWARF_VERSION: u32 = 4;
+
+/// Returns the `OpExtInstImport` for `NON_SEMANTIC_DEBUG_INFO`, if `module` contains debug info.
+pub fn find_import(module: &Module) -> Option<Word> {
+    module
+        .ext_inst_imports
+        .iter()
+        .find(|inst| inst.operands[0].unwrap_literal_string() == NON_SEMANTIC_DEBUG_INFO)
+        .map(|inst| inst.result_id.unwrap())
+}
+
+/// Whether `inst` is the `op` debug info instruction, given the result of `find_import`.
+pub fn is_debug_inst(inst: &Instruction, import: Option<Word>, op: DebugInfoOp) -> bool {
+    match (import, &inst.operands[..]) {
+        (Some(import), [Operand::IdRef(set), Operand::LiteralExtInstInteger(inst_op), ..]) => {
+            inst.class.opcode == Op::ExtInst && *set == import && *inst_op == op as u32
+        }
+        _ => false,
+    }
+}
+
+pub fn debug_inst(
+    import: Word,
+    void: Word,
+    result_id: Word,
+    op: DebugInfoOp,
+    operands: impl IntoIterator<Item = Word>,
+) -> Instruction {
+    Instruction::new(
+        Op::ExtInst,
+        Some(void),
+        Some(result_id),
+        iter::once(Operand::IdRef(import))
+            .chain(iter::once(Operand::LiteralExtInstInteger(op as u32)))
+            .chain(operands.into_iter().map(Operand::IdRef))
+            .collect(),
+    )
+}
@@ -2,175 +2,426 @@
 //! the original codegen of a crate, and consumed by the `linker`.
 
 use rspirv::dr::{Instruction, Module, Operand};
-use rspirv::spirv::{Decoration, Op, Word};
+use rspirv::spirv::{Op, Word};
 use rustc_span::{source_map::SourceMap, FileName, Pos, Span};
-use serde::{Deserialize, Serialize};
-use std::marker::PhantomData;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use std::{iter, slice};
 
-/// Decorations not native to SPIR-V require some form of encoding into existing
-/// SPIR-V constructs, for which we use `OpDecorateString` with decoration type
-/// `UserTypeGOOGLE` and a JSON-encoded Rust value as the decoration string.
+/// The name of the (private) non-semantic extended instruction set used to encode
+/// `CustomDecoration`s, see `SPV_KHR_non_semantic_info`.
+pub const CUSTOM_DECORATIONS: &str = "NonSemantic.RustGPU.Decorations";
+
+/// Decorations not native to SPIR-V are encoded as non-semantic extended instructions
+/// (which other tools processing the SPIR-V are required to ignore), of the form:
+/// `%_ = OpExtInst %void %CUSTOM_DECORATIONS OPCODE %target <payload ids>...`
+/// <https://htmlpreview.github.io/?https://github.com/KhronosGroup/SPIRV-Registry/blob/master/extensions/KHR/SPV_KHR_non_semantic_info.html>
 ///
-/// Each decoration type has to implement this trait, and use a different
-/// `ENCODING_PREFIX` from any other decoration type, to disambiguate them.
+/// Non-semantic instructions can only take `<id>` operands, so the payload of a
+/// decoration is made up of `OpString`s and 32-bit `OpConstant`s, which are shared
+/// between all the decorations of a module (see `DecorationEncoder`).
 ///
-/// Also, all decorations have to be stripped by the linker at some point,
-/// ideally as soon as they're no longer needed, because no other tools
-/// processing the SPIR-V would understand them correctly.
+/// Each decoration type has to implement this trait, and use a different
+/// `OPCODE` from any other decoration type, to disambiguate them.
 ///
-/// TODO: uses `non_semantic` instead of piggybacking off of `UserTypeGOOGLE`
-/// <https://htmlpreview.github.io/?https://github.com/KhronosGroup/SPIRV-Registry/blob/master/extensions/KHR/SPV_KHR_non_semantic_info.html>
-pub trait CustomDecoration: for<'de> Deserialize<'de> + Serialize {
-    const ENCODING_PREFIX: &'static str;
-
-    fn encode(self, id: Word) -> Instruction {
-        // FIXME(eddyb) this allocates twice, because there is no functionality
-        // in `serde_json` for writing to something that impls `fmt::Write`,
-        // only for `io::Write`, which would require performing redundant UTF-8
-        // (re)validation, or relying on `unsafe` code, to use with `String`.
-        let json = serde_json::to_string(&self).unwrap();
-        let encoded = [Self::ENCODING_PREFIX, &json].concat();
-
-        Instruction::new(
-            Op::DecorateString,
-            None,
-            None,
-            vec![
-                Operand::IdRef(id),
-                Operand::Decoration(Decoration::UserTypeGOOGLE),
-                Operand::LiteralString(encoded),
-            ],
-        )
+/// Also, decorations should be stripped by the linker as soon as they're no
+/// longer needed, unless they're explicitly kept around for other tooling
+/// (e.g. `SrcLocDecoration`, with `--keep-src-locs`).
+pub trait CustomDecoration: Sized {
+    const OPCODE: u32;
+
+    fn encode_payload(&self, encoder: &mut DecorationEncoder<'_>) -> Vec<Word>;
+
+    /// Returns `None` if `payload` wasn't produced by `encode_payload`.
+    fn decode_payload(decoder: &DecorationDecoder<'_>, payload: &[Operand]) -> Option<Self>;
+
+    fn decode_all(module: &Module) -> Vec<(Word, Self)> {
+        let decoder = DecorationDecoder::new(module);
+        decoder
+            .decorations(Self::OPCODE)
+            .filter_map(|(target, payload)| {
+                Some((target, Self::decode_payload(&decoder, payload)?))
+            })
+            .collect()
     }
 
-    fn try_decode(inst: &Instruction) -> Option<(Word, LazilyDeserialized<'_, Self>)> {
-        if inst.class.opcode == Op::DecorateString
-            && inst.operands[1].unwrap_decoration() == Decoration::UserTypeGOOGLE
-        {
-            let id = inst.operands[0].unwrap_id_ref();
-            let encoded = inst.operands[2].unwrap_literal_string();
-            let json = encoded.strip_prefix(Self::ENCODING_PREFIX)?;
+    fn remove_all(module: &mut Module) {
+        let import = find_import(module);
+        module
+            .types_global_values
+            .retain(|inst| !is_custom_decoration(inst, import, Some(Self::OPCODE)))
+    }
+}
+
+/// Returns the `OpExtInstImport` for `CUSTOM_DECORATIONS`, if `module` contains any decorations.
+pub fn find_import(module: &Module) -> Option<Word> {
+    module
+        .ext_inst_imports
+        .iter()
+        .find(|inst| inst.operands[0].unwrap_literal_string() == CUSTOM_DECORATIONS)
+        .map(|inst| inst.result_id.unwrap())
+}
 
-            Some((
-                id,
-                LazilyDeserialized {
-                    json,
-                    _marker: PhantomData,
-                },
-            ))
-        } else {
-            None
+/// Whether `inst` is a custom decoration (of type `opcode`, if specified), given the result
+/// of `find_import`.
+pub fn is_custom_decoration(inst: &Instruction, import: Option<Word>, opcode: Option<u32>) -> bool {
+    match (import, &inst.operands[..]) {
+        (Some(import), [Operand::IdRef(set), Operand::LiteralExtInstInteger(inst_op), ..]) => {
+            inst.class.opcode == Op::ExtInst
+                && *set == import
+                && opcode.map_or(true, |opcode| *inst_op == opcode)
         }
+        _ => false,
     }
+}
 
-    fn decode_all(module: &Module) -> DecodeAllIter<'_, Self> {
-        module
-            .annotations
+/// Returns the decorated ID, if `inst` is a custom decoration (see `is_custom_decoration`).
+pub fn custom_decoration_target(inst: &Instruction, import: Option<Word>) -> Option<Word> {
+    if is_custom_decoration(inst, import, None) {
+        Some(inst.operands[2].unwrap_id_ref())
+    } else {
+        None
+    }
+}
+
+/// Appends custom decorations to a module, allocating new IDs for them (and their payloads)
+/// from the module's `bound`, and adding the `CUSTOM_DECORATIONS` import if it's missing.
+pub struct DecorationEncoder<'a> {
+    module: &'a mut Module,
+    import: Option<Word>,
+    void: Option<Word>,
+    u32: Option<Word>,
+    strings: HashMap<String, Word>,
+    u32_constants: HashMap<u32, Word>,
+}
+
+impl<'a> DecorationEncoder<'a> {
+    pub fn new(module: &'a mut Module) -> Self {
+        Self {
+            module,
+            import: None,
+            void: None,
+            u32: None,
+            strings: HashMap::new(),
+            u32_constants: HashMap::new(),
+        }
+    }
+
+    fn id(&mut self) -> Word {
+        let header = self.module.header.as_mut().unwrap();
+        let id = header.bound;
+        header.bound += 1;
+        id
+    }
+
+    fn import(&mut self) -> Word {
+        if let Some(import) = self.import {
+            return import;
+        }
+        let import = match find_import(self.module) {
+            Some(import) => import,
+            None => {
+                let import = self.id();
+                self.module.ext_inst_imports.push(Instruction::new(
+                    Op::ExtInstImport,
+                    None,
+                    Some(import),
+                    vec![Operand::LiteralString(CUSTOM_DECORATIONS.to_string())],
+                ));
+                import
+            }
+        };
+        if !self
+            .module
+            .extensions
             .iter()
-            .filter_map(Self::try_decode as fn(_) -> _)
+            .any(|inst| inst.operands[0].unwrap_literal_string() == "SPV_KHR_non_semantic_info")
+        {
+            self.module.extensions.push(Instruction::new(
+                Op::Extension,
+                None,
+                None,
+                vec![Operand::LiteralString(
+                    "SPV_KHR_non_semantic_info".to_string(),
+                )],
+            ));
+        }
+        self.import = Some(import);
+        import
     }
 
-    fn remove_all(module: &mut Module) {
-        module
-            .annotations
-            .retain(|inst| Self::try_decode(inst).is_none())
+    /// Finds (or adds) the type defined by `opcode` and `operands`.
+    fn global_type(&mut self, opcode: Op, operands: Vec<Operand>) -> Word {
+        let existing = self.module.types_global_values.iter().find(|inst| {
+            inst.class.opcode == opcode && inst.result_type.is_none() && inst.operands == operands
+        });
+        if let Some(inst) = existing {
+            return inst.result_id.unwrap();
+        }
+        let id = self.id();
+        self.module
+            .types_global_values
+            .push(Instruction::new(opcode, None, Some(id), operands));
+        id
+    }
+
+    fn void(&mut self) -> Word {
+        if self.void.is_none() {
+            self.void = Some(self.global_type(Op::TypeVoid, vec![]));
+        }
+        self.void.unwrap()
+    }
+
+    pub fn string(&mut self, s: &str) -> Word {
+        if let Some(&id) = self.strings.get(s) {
+            return id;
+        }
+        let id = self.id();
+        self.module.debugs.push(Instruction::new(
+            Op::String,
+            None,
+            Some(id),
+            vec![Operand::LiteralString(s.to_string())],
+        ));
+        self.strings.insert(s.to_string(), id);
+        id
     }
-}
 
-// HACK(eddyb) return type of `CustomDecoration::decode_all`, in lieu of
-// `-> impl Iterator<Item = (Word, LazilyDeserialized<'_, Self>)` in the trait.
-type DecodeAllIter<'a, D> = iter::FilterMap<
-    slice::Iter<'a, Instruction>,
-    fn(&'a Instruction) -> Option<(Word, LazilyDeserialized<'a, D>)>,
->;
+    pub fn u32(&mut self, value: u32) -> Word {
+        if let Some(&id) = self.u32_constants.get(&value) {
+            return id;
+        }
+        if self.u32.is_none() {
+            self.u32 = Some(self.global_type(
+                Op::TypeInt,
+                vec![Operand::LiteralInt32(32), Operand::LiteralInt32(0)],
+            ));
+        }
+        let id = self.id();
+        self.module.types_global_values.push(Instruction::new(
+            Op::Constant,
+            self.u32,
+            Some(id),
+            vec![Operand::LiteralInt32(value)],
+        ));
+        self.u32_constants.insert(value, id);
+        id
+    }
+
+    pub fn encode<D: CustomDecoration>(&mut self, target: Word, decoration: &D) {
+        let payload = decoration.encode_payload(self);
+        let (import, void, id) = (self.import(), self.void(), self.id());
+        let operands = vec![
+            Operand::IdRef(import),
+            Operand::LiteralExtInstInteger(D::OPCODE),
+            Operand::IdRef(target),
+        ]
+        .into_iter()
+        .chain(payload.into_iter().map(Operand::IdRef))
+        .collect();
+        self.module.types_global_values.push(Instruction::new(
+            Op::ExtInst,
+            Some(void),
+            Some(id),
+            operands,
+        ));
+    }
+}
 
-/// Helper allowing full deserialization to be avoided where possible.
-#[derive(Copy, Clone)]
-pub struct LazilyDeserialized<'a, D> {
-    json: &'a str,
-    _marker: PhantomData<D>,
+/// Reads back the custom decorations (and their payloads) of a module.
+pub struct DecorationDecoder<'a> {
+    module: &'a Module,
+    import: Option<Word>,
+    strings: HashMap<Word, &'a str>,
+    u32_constants: HashMap<Word, u32>,
 }
 
-impl<'a, D: Deserialize<'a>> LazilyDeserialized<'a, D> {
-    pub fn deserialize(self) -> D {
-        serde_json::from_str(self.json).unwrap()
+impl<'a> DecorationDecoder<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        let strings = module
+            .debugs
+            .iter()
+            .filter(|inst| inst.class.opcode == Op::String)
+            .map(|inst| {
+                (
+                    inst.result_id.unwrap(),
+                    inst.operands[0].unwrap_literal_string(),
+                )
+            })
+            .collect();
+        // NOTE: there may be several equivalent `u32` types, if the module wasn't deduplicated.
+        let u32_types: HashSet<Word> = module
+            .types_global_values
+            .iter()
+            .filter(|inst| {
+                inst.class.opcode == Op::TypeInt
+                    && inst.operands == [Operand::LiteralInt32(32), Operand::LiteralInt32(0)]
+            })
+            .map(|inst| inst.result_id.unwrap())
+            .collect();
+        let u32_constants = module
+            .types_global_values
+            .iter()
+            .filter(|inst| {
+                inst.class.opcode == Op::Constant
+                    && inst.result_type.map_or(false, |ty| u32_types.contains(&ty))
+            })
+            .filter_map(|inst| match inst.operands[..] {
+                [Operand::LiteralInt32(value)] => Some((inst.result_id.unwrap(), value)),
+                _ => None,
+            })
+            .collect();
+        Self {
+            module,
+            import: find_import(module),
+            strings,
+            u32_constants,
+        }
+    }
+
+    /// Returns the target and payload of all the decorations with the given `opcode`.
+    fn decorations(&self, opcode: u32) -> impl Iterator<Item = (Word, &'a [Operand])> {
+        let import = self.import;
+        self.module
+            .types_global_values
+            .iter()
+            .filter(move |inst| is_custom_decoration(inst, import, Some(opcode)))
+            .map(|inst| (inst.operands[2].unwrap_id_ref(), &inst.operands[3..]))
+    }
+
+    pub fn string(&self, operand: &Operand) -> Option<&'a str> {
+        match operand {
+            Operand::IdRef(id) => self.strings.get(id).copied(),
+            _ => None,
+        }
+    }
+
+    pub fn u32(&self, operand: &Operand) -> Option<u32> {
+        match operand {
+            Operand::IdRef(id) => self.u32_constants.get(id).copied(),
+            _ => None,
+        }
     }
 }
 
 /// An `OpFunction` with `#[spirv(unroll_loops)]` on the Rust `fn` definition,
 /// which should get `LoopControl::UNROLL` applied to all of its loops'
-/// `OpLoopMerge` instructions, during structuralization.
-#[derive(Deserialize, Serialize)]
-pub struct UnrollLoopsDecoration {}
+/// `OpLoopMerge` instructions, during structuralization, and then have the
+/// loops which provably run at most `max_trip_count` times unrolled outright.
+///
+/// Payload: `%max_trip_count` (`u32` `OpConstant`), if explicitly specified.
+pub struct UnrollLoopsDecoration {
+    pub max_trip_count: Option<u32>,
+}
+
+impl UnrollLoopsDecoration {
+    /// The maximum trip count used for `#[spirv(unroll_loops)]` without `max = N`.
+    pub const DEFAULT_MAX_TRIP_COUNT: u32 = 16;
+
+    pub fn max_trip_count(&self) -> u32 {
+        self.max_trip_count.unwrap_or(Self::DEFAULT_MAX_TRIP_COUNT)
+    }
+}
 
 impl CustomDecoration for UnrollLoopsDecoration {
-    const ENCODING_PREFIX: &'static str = "U";
+    const OPCODE: u32 = 1;
+
+    fn encode_payload(&self, encoder: &mut DecorationEncoder<'_>) -> Vec<Word> {
+        self.max_trip_count
+            .iter()
+            .map(|&max_trip_count| encoder.u32(max_trip_count))
+            .collect()
+    }
+
+    fn decode_payload(decoder: &DecorationDecoder<'_>, payload: &[Operand]) -> Option<Self> {
+        let max_trip_count = match payload {
+            [] => None,
+            [max_trip_count] => Some(decoder.u32(max_trip_count)?),
+            _ => return None,
+        };
+        Some(Self { max_trip_count })
+    }
 }
 
-#[derive(Deserialize, Serialize)]
+/// Payload: `%reason` (`OpString`), optionally followed by a `SerializedSpan`.
 pub struct ZombieDecoration {
     pub reason: String,
-
-    #[serde(flatten)]
     pub span: Option<SerializedSpan>,
 }
 
 impl CustomDecoration for ZombieDecoration {
-    const ENCODING_PREFIX: &'static str = "Z";
+    const OPCODE: u32 = 2;
+
+    fn encode_payload(&self, encoder: &mut DecorationEncoder<'_>) -> Vec<Word> {
+        let mut payload = vec![encoder.string(&self.reason)];
+        if let Some(span) = &self.span {
+            payload.extend(span.encode(encoder));
+        }
+        payload
+    }
+
+    fn decode_payload(decoder: &DecorationDecoder<'_>, payload: &[Operand]) -> Option<Self> {
+        let (reason, span) = payload.split_first()?;
+        Some(Self {
+            reason: decoder.string(reason)?.to_string(),
+            span: if span.is_empty() {
+                None
+            } else {
+                Some(SerializedSpan::decode(decoder, span)?)
+            },
+        })
+    }
+}
+
+/// An `OpFunction` with the span of its Rust `fn` definition, so that errors
+/// found only by the linker (e.g. recursion) can still point at the source.
+///
+/// Payload: a `SerializedSpan`.
+pub struct SrcLocDecoration {
+    pub span: SerializedSpan,
+}
+
+impl CustomDecoration for SrcLocDecoration {
+    const OPCODE: u32 = 3;
+
+    fn encode_payload(&self, encoder: &mut DecorationEncoder<'_>) -> Vec<Word> {
+        self.span.encode(encoder).to_vec()
+    }
+
+    fn decode_payload(decoder: &DecorationDecoder<'_>, payload: &[Operand]) -> Option<Self> {
+        Some(Self {
+            span: SerializedSpan::decode(decoder, payload)?,
+        })
+    }
 }
 
 /// Representation of a `rustc` `Span` that can be turned into a `Span` again
 /// in another compilation, by reloading the file. However, note that this will
 /// fail if the file changed since, which is detected using the serialized `hash`.
-#[derive(Deserialize, Serialize)]
+///
+/// Encoded as `%file %hash %lo %hi`, with `OpString`s for `file` and `hash`
+/// (the latter in the `"{algorithm}:{hex digest}"` format, see `encode_hash`),
+/// and `u32` `OpConstant`s for `lo` and `hi`.
 pub struct SerializedSpan {
     file: PathBuf,
-    hash: serde_adapters::SourceFileHash,
+    hash: String,
     lo: u32,
     hi: u32,
 }
 
-// HACK(eddyb) `rustc_span` types implement only `rustc_serialize` traits, but
-// not `serde` traits, and the easiest workaround is to have our own types.
-mod serde_adapters {
-    use serde::{Deserialize, Serialize};
-
-    #[derive(Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
-    pub enum SourceFileHashAlgorithm {
-        Md5,
-        Sha1,
-        Sha256,
-    }
-
-    impl From<rustc_span::SourceFileHashAlgorithm> for SourceFileHashAlgorithm {
-        fn from(kind: rustc_span::SourceFileHashAlgorithm) -> Self {
-            match kind {
-                rustc_span::SourceFileHashAlgorithm::Md5 => Self::Md5,
-                rustc_span::SourceFileHashAlgorithm::Sha1 => Self::Sha1,
-                rustc_span::SourceFileHashAlgorithm::Sha256 => Self::Sha256,
-            }
-        }
-    }
-
-    #[derive(Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
-    pub struct SourceFileHash {
-        kind: SourceFileHashAlgorithm,
-        value: [u8; 32],
-    }
-
-    impl From<rustc_span::SourceFileHash> for SourceFileHash {
-        fn from(hash: rustc_span::SourceFileHash) -> Self {
-            let bytes = hash.hash_bytes();
-            let mut hash = Self {
-                kind: hash.kind.into(),
-                value: Default::default(),
-            };
-            hash.value[..bytes.len()].copy_from_slice(bytes);
-            hash
-        }
-    }
+// HACK(eddyb) `rustc_span::SourceFileHash` doesn't expose a way to
+// construct one from its parts, so we only ever compare the string form.
+fn encode_hash(hash: &rustc_span::SourceFileHash) -> String {
+    let kind = match hash.kind {
+        rustc_span::SourceFileHashAlgorithm::Md5 => "md5",
+        rustc_span::SourceFileHashAlgorithm::Sha1 => "sha1",
+        rustc_span::SourceFileHashAlgorithm::Sha256 => "sha256",
+    };
+    let digest: String = hash
+        .hash_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}:{}", kind, digest)
 }
 
 impl SerializedSpan {
@@ -200,7 +451,7 @@ impl SerializedSpan {
                 FileName::Real(real_name) => real_name.local_path().to_path_buf(),
                 _ => return None,
             },
-            hash: file.src_hash.into(),
+            hash: encode_hash(&file.src_hash),
             lo: (lo - file.start_pos).to_u32(),
             hi: (hi - file.start_pos).to_u32(),
         })
@@ -212,7 +463,7 @@ impl SerializedSpan {
         // If the file has changed since serializing, there's not much we can do,
         // other than avoid creating invalid/confusing `Span`s.
         // FIXME(eddyb) we could still indicate some of this to the user.
-        if self.hash != file.src_hash.into() {
+        if self.hash != encode_hash(&file.src_hash) {
             return None;
         }
 
@@ -225,4 +476,25 @@ impl SerializedSpan {
             file.start_pos + Pos::from_u32(self.hi),
         ))
     }
+
+    fn encode(&self, encoder: &mut DecorationEncoder<'_>) -> [Word; 4] {
+        [
+            encoder.string(&self.file.to_string_lossy()),
+            encoder.string(&self.hash),
+            encoder.u32(self.lo),
+            encoder.u32(self.hi),
+        ]
+    }
+
+    fn decode(decoder: &DecorationDecoder<'_>, payload: &[Operand]) -> Option<Self> {
+        match payload {
+            [file, hash, lo, hi] => Some(Self {
+                file: PathBuf::from(decoder.string(file)?),
+                hash: decoder.string(hash)?.to_string(),
+                lo: decoder.u32(lo)?,
+                hi: decoder.u32(hi)?,
+            }),
+            _ => None,
+        }
+    }
 }
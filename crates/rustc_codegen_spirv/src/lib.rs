@@ -110,6 +110,7 @@ mod attr;
 mod builder;
 mod builder_spirv;
 mod codegen_cx;
+mod debuginfo;
 mod decorations;
 mod link;
 mod linker;
@@ -223,6 +224,8 @@ fn target_options(env: Option<spirv_tools::TargetEnv>) -> Target {
             crt_static_allows_dylibs: true,
             dll_prefix: "".to_string(),
             dll_suffix: ".spv".to_string(),
+            // `staticlib`s are SPIR-V library modules (see `link.rs`), named `lib*.spv`.
+            staticlib_suffix: ".spv".to_string(),
             dynamic_linking: true,
             emit_debug_gdb_scripts: false,
             linker_flavor: LinkerFlavor::Ld,
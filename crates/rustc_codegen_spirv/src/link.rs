@@ -1,4 +1,8 @@
+use crate::codegen_cx::{CodegenArgs, SpirvOptPasses, SpirvValOptions};
 use crate::{linker, SpirvCodegenBackend, SpirvModuleBuffer, SpirvThinBuffer};
+use rspirv::binary::Assemble;
+use rspirv::dr::Module;
+use rspirv::spirv::{Decoration, LinkageType, Op, Word};
 use rustc_codegen_ssa::back::lto::{LtoModuleCodegen, SerializedModule, ThinModule, ThinShared};
 use rustc_codegen_ssa::back::write::CodegenContext;
 use rustc_codegen_ssa::{CodegenResults, NativeLib};
@@ -13,7 +17,9 @@ use rustc_session::config::{CrateType, DebugInfo, Lto, OptLevel, OutputFilenames
 use rustc_session::output::{check_file_is_writeable, invalid_output_for_target, out_filename};
 use rustc_session::utils::NativeLibKind;
 use rustc_session::Session;
-use std::collections::{HashMap, HashSet};
+use rustc_span::symbol::Symbol;
+use rustc_span::{BytePos, Span};
+use std::collections::{BTreeMap, HashSet};
 use std::env;
 use std::ffi::{CString, OsStr};
 use std::fs::File;
@@ -69,6 +75,18 @@ pub fn link<'a>(
                     legalize,
                     emit_multiple_modules,
                 ),
+                // `staticlib`s are SPIR-V library modules, for other SPIR-V linkers to consume.
+                CrateType::Staticlib if emit_multiple_modules => {
+                    sess.err("library modules can't be split into multiple modules")
+                }
+                CrateType::Staticlib => link_exe(
+                    sess,
+                    crate_type,
+                    &out_filename,
+                    codegen_results,
+                    legalize,
+                    false,
+                ),
                 other => sess.err(&format!("CrateType {:?} not supported yet", other)),
             }
         }
@@ -129,12 +147,20 @@ fn link_exe(
         codegen_results,
     );
 
-    let spv_binary = do_link(sess, &objects, &rlibs, legalize, emit_multiple_modules);
+    let codegen_args = CodegenArgs::from_session(sess);
+    let spv_binary = do_link(
+        sess,
+        &codegen_args,
+        &objects,
+        &rlibs,
+        legalize,
+        emit_multiple_modules,
+        crate_type == CrateType::Staticlib,
+    );
 
-    use rspirv::binary::Assemble;
     match spv_binary {
         linker::LinkResult::SingleModule(spv_binary) => {
-            post_link_single_module(sess, spv_binary.assemble(), out_filename);
+            post_link_single_module(sess, &codegen_args, &spv_binary, out_filename);
         }
         linker::LinkResult::MultipleModules(map) => {
             let mut root_file_name = out_filename.file_name().unwrap().to_owned();
@@ -143,20 +169,27 @@ fn link_exe(
             if !out_dir.is_dir() {
                 std::fs::create_dir_all(&out_dir).unwrap();
             }
-            let mut hashmap = HashMap::new();
+            let mut module_filenames = BTreeMap::new();
             for (name, spv_binary) in map {
                 let mut module_filename = out_dir.clone();
                 module_filename.push(sanitize_filename::sanitize(&name));
-                post_link_single_module(sess, spv_binary.assemble(), &module_filename);
-                hashmap.insert(name, module_filename);
+                post_link_single_module(sess, &codegen_args, &spv_binary, &module_filename);
+                module_filenames.insert(name, module_filename);
             }
             let file = File::create(out_filename).unwrap();
-            serde_json::to_writer(BufWriter::new(file), &hashmap).unwrap();
+            serde_json::to_writer(BufWriter::new(file), &module_filenames).unwrap();
         }
     }
 }
 
-fn post_link_single_module(sess: &Session, spv_binary: Vec<u32>, out_filename: &Path) {
+fn post_link_single_module(
+    sess: &Session,
+    codegen_args: &CodegenArgs,
+    module: &Module,
+    out_filename: &Path,
+) {
+    let spv_binary = module.assemble();
+
     if let Ok(ref path) = std::env::var("DUMP_POST_LINK") {
         File::create(path)
             .unwrap()
@@ -164,16 +197,22 @@ fn post_link_single_module(sess: &Session, spv_binary: Vec<u32>, out_filename: &
             .unwrap();
     }
 
-    let spv_binary = if sess.opts.optimize != OptLevel::No || sess.opts.debuginfo == DebugInfo::None
-    {
+    // NOTE: without debuginfo, `spirv-opt` is always run, to strip it (see `do_spirv_opt`).
+    let run_spirv_opt = sess.opts.debuginfo == DebugInfo::None
+        || match &codegen_args.spirv_opt {
+            Some(SpirvOptPasses::None) => false,
+            Some(_) => true,
+            None => sess.opts.optimize != OptLevel::No,
+        };
+    let spv_binary = if run_spirv_opt {
         let _timer = sess.timer("link_spirv_opt");
-        do_spirv_opt(sess, spv_binary, out_filename)
+        do_spirv_opt(sess, codegen_args, spv_binary, out_filename)
     } else {
         spv_binary
     };
 
     if env::var("NO_SPIRV_VAL").is_err() {
-        do_spirv_val(sess, &spv_binary, out_filename);
+        do_spirv_val(sess, codegen_args, module, &spv_binary, out_filename);
     }
 
     {
@@ -190,7 +229,14 @@ fn post_link_single_module(sess: &Session, spv_binary: Vec<u32>, out_filename: &
     }
 }
 
-fn do_spirv_opt(sess: &Session, spv_binary: Vec<u32>, filename: &Path) -> Vec<u32> {
+/// Runs the passes picked from `-C opt-level`, unless overridden by `--spirv-opt`/`--spirv-opt-pass`
+/// (see `CodegenArgs`), followed by stripping debuginfo if it's off (regardless of the passes).
+fn do_spirv_opt(
+    sess: &Session,
+    codegen_args: &CodegenArgs,
+    spv_binary: Vec<u32>,
+    filename: &Path,
+) -> Vec<u32> {
     use spirv_tools::{
         error,
         opt::{self, Optimizer},
@@ -198,14 +244,31 @@ fn do_spirv_opt(sess: &Session, spv_binary: Vec<u32>, filename: &Path) -> Vec<u3
 
     let mut optimizer = opt::create(None);
 
-    match sess.opts.optimize {
-        OptLevel::No => {}
-        OptLevel::Less | OptLevel::Default | OptLevel::Aggressive => {
+    match &codegen_args.spirv_opt {
+        None => match sess.opts.optimize {
+            OptLevel::No => {}
+            OptLevel::Less | OptLevel::Default | OptLevel::Aggressive => {
+                optimizer.register_performance_passes();
+            }
+            OptLevel::Size | OptLevel::SizeMin => {
+                optimizer.register_size_passes();
+            }
+        },
+        Some(SpirvOptPasses::Performance) => {
             optimizer.register_performance_passes();
         }
-        OptLevel::Size | OptLevel::SizeMin => {
+        Some(SpirvOptPasses::Size) => {
             optimizer.register_size_passes();
         }
+        Some(SpirvOptPasses::Legalization) => {
+            optimizer.register_hlsl_legalization_passes();
+        }
+        Some(SpirvOptPasses::None) => {}
+        Some(SpirvOptPasses::Custom(passes)) => {
+            for &pass in passes {
+                optimizer.register_pass(pass);
+            }
+        }
     }
 
     if sess.opts.debuginfo == DebugInfo::None {
@@ -233,7 +296,16 @@ fn do_spirv_opt(sess: &Session, spv_binary: Vec<u32>, filename: &Path) -> Vec<u3
         },
         // We currently run the validator separately after optimization or even
         // if we don't run optimization, the default options don't run the validator
-        None,
+        if codegen_args.spirv_val == SpirvValOptions::default() {
+            None
+        } else {
+            // NOTE: passing validator options makes `spirv-opt` validate its input, so that
+            // has to be done with the same (relaxed) rules as `spirv-val` uses afterwards.
+            Some(opt::Options {
+                validator_options: Some(validator_options(codegen_args)),
+                ..Default::default()
+            })
+        },
     );
 
     match result {
@@ -248,19 +320,131 @@ fn do_spirv_opt(sess: &Session, spv_binary: Vec<u32>, filename: &Path) -> Vec<u3
     }
 }
 
-fn do_spirv_val(sess: &Session, spv_binary: &[u32], filename: &Path) {
+/// `module` is the module before `spirv-opt`, i.e. with `OpLine`s even without debuginfo, which
+/// are used to point errors (through the IDs mentioned in them) at the Rust source.
+fn do_spirv_val(
+    sess: &Session,
+    codegen_args: &CodegenArgs,
+    module: &Module,
+    spv_binary: &[u32],
+    filename: &Path,
+) {
     use spirv_tools::val::{self, Validator};
 
     let validator = val::create(sess.target.options.env.parse().ok());
 
-    if let Err(e) = validator.validate(spv_binary, None) {
-        let mut err = sess.struct_err(&e.to_string());
+    if let Err(e) = validator.validate(spv_binary, Some(validator_options(codegen_args))) {
+        // NOTE: this is best-effort, as `spirv-opt` may have changed the instructions (or
+        // IDs) mentioned since `module`, in which case the location may be inaccurate.
+        let located = e.diagnostic.as_ref().and_then(|diag| {
+            let mut lines = diag.message.lines();
+            let headline = lines.next()?;
+            let span = mentioned_ids(headline)
+                .filter_map(|id| find_line(module, id))
+                .find_map(|(file, line, col)| span_of_line(sess, file, line, col))?;
+            Some((span, headline, lines))
+        });
+        let mut err = match located {
+            Some((span, headline, snippet)) => {
+                let mut err = sess.struct_span_err(span, headline);
+                let snippet = snippet
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .collect::<Vec<_>>();
+                if !snippet.is_empty() {
+                    err.note(&format!("failing instruction: `{}`", snippet.join("\n")));
+                }
+                err
+            }
+            None => sess.struct_err(&e.to_string()),
+        };
         err.note("spirv-val failed");
         err.note(&format!("module `{}`", filename.display()));
         err.emit();
     }
 }
 
+fn validator_options(codegen_args: &CodegenArgs) -> spirv_tools::val::ValidatorOptions {
+    let options = &codegen_args.spirv_val;
+    spirv_tools::val::ValidatorOptions {
+        relax_struct_store: options.relax_struct_store,
+        relax_logical_pointer: options.relax_logical_pointer,
+        before_legalization: options.before_legalization,
+        // NOTE: `None` leaves it up to the target environment (on for Vulkan 1.1 and later).
+        relax_block_layout: if options.relax_block_layout {
+            Some(true)
+        } else {
+            None
+        },
+        uniform_buffer_standard_layout: options.uniform_buffer_standard_layout,
+        scalar_block_layout: options.scalar_block_layout,
+        skip_block_layout: options.skip_block_layout,
+        ..Default::default()
+    }
+}
+
+/// The IDs mentioned in a `spirv-val` message, which are printed like `12[%foo]`.
+fn mentioned_ids(message: &str) -> impl Iterator<Item = Word> + '_ {
+    message.match_indices("[%").filter_map(move |(i, _)| {
+        let before = &message[..i];
+        let digits = &before[before.trim_end_matches(|c: char| c.is_ascii_digit()).len()..];
+        digits.parse().ok()
+    })
+}
+
+/// The file name, line and column of the `OpLine` applying to the instruction defining `id`
+/// (or, for functions, of their first `OpLine`).
+fn find_line(module: &Module, id: Word) -> Option<(&str, u32, u32)> {
+    let line = module.functions.iter().find_map(|func| {
+        let mut first_line = None;
+        for block in &func.blocks {
+            // `OpLine`s only apply until the end of their block.
+            let mut current_line = None;
+            for inst in &block.instructions {
+                match inst.class.opcode {
+                    Op::Line => {
+                        current_line = Some(inst);
+                        first_line = first_line.or(current_line);
+                    }
+                    Op::NoLine => current_line = None,
+                    _ if inst.result_id == Some(id) => return current_line,
+                    _ => {}
+                }
+            }
+        }
+        let is_func_or_param = func.def_id() == Some(id)
+            || func
+                .parameters
+                .iter()
+                .any(|param| param.result_id == Some(id));
+        if is_func_or_param {
+            first_line
+        } else {
+            None
+        }
+    })?;
+    let file = line.operands[0].unwrap_id_ref();
+    let file = module
+        .debugs
+        .iter()
+        .find(|inst| inst.class.opcode == Op::String && inst.result_id == Some(file))?;
+    Some((
+        file.operands[0].unwrap_literal_string(),
+        line.operands[1].unwrap_literal_int32(),
+        line.operands[2].unwrap_literal_int32(),
+    ))
+}
+
+/// Turns an `OpLine` (as emitted by `CodegenCx::src_loc`) back into a `Span`, if the file can
+/// still be loaded.
+fn span_of_line(sess: &Session, file: &str, line: u32, col: u32) -> Option<Span> {
+    let file = sess.source_map().load_file(Path::new(file)).ok()?;
+    let line_start = *file.lines.get(line.checked_sub(1)? as usize)?;
+    // FIXME: the column counts `char`s, not bytes, so this is off for non-ASCII lines.
+    let lo = (line_start + BytePos(col.saturating_sub(1))).min(file.end_pos);
+    Some(Span::with_root_ctxt(lo, lo))
+}
+
 fn link_local_crate_native_libs_and_dependent_crate_libs<'a>(
     rlibs: &mut Vec<PathBuf>,
     sess: &'a Session,
@@ -413,10 +597,12 @@ pub fn read_metadata(rlib: &Path) -> Result<MetadataRef, String> {
 /// shenanigans to collect all the object files we need to link.
 fn do_link(
     sess: &Session,
+    codegen_args: &CodegenArgs,
     objects: &[PathBuf],
     rlibs: &[PathBuf],
     legalize: bool,
     emit_multiple_modules: bool,
+    library: bool,
 ) -> linker::LinkResult {
     fn load(bytes: &[u8]) -> rspirv::dr::Module {
         let mut loader = rspirv::dr::Loader::new();
@@ -432,6 +618,11 @@ fn do_link(
         File::open(obj).unwrap().read_to_end(&mut bytes).unwrap();
         modules.push(load(&bytes));
     }
+    let library_exports = if library {
+        Some(library_exports(&modules))
+    } else {
+        None
+    };
     // `rlibs` are archive files we've created in `create_archive`, usually produced by crates that are being
     // referenced. We need to unpack them and add the modules inside.
     for rlib in rlibs {
@@ -444,9 +635,21 @@ fn do_link(
             }
         }
     }
+    // `--spirv-library`s are precompiled SPIR-V libraries (e.g. hand-written, or compiled from GLSL), whose
+    // exports can be called from Rust through `extern` declarations.
+    for path in &codegen_args.spirv_libraries {
+        match load_spirv_library(path) {
+            Ok(module) => modules.push(module),
+            Err(err) => sess.err(&format!(
+                "failed to load SPIR-V library `{}`: {}",
+                path.display(),
+                err
+            )),
+        }
+    }
+    sess.abort_if_errors();
 
     if let Ok(ref path) = env::var("DUMP_PRE_LINK") {
-        use rspirv::binary::Assemble;
         let path = Path::new(path);
         if path.is_file() {
             std::fs::remove_file(path).unwrap();
@@ -466,10 +669,26 @@ fn do_link(
         dce: env::var("NO_DCE").is_err(),
         compact_ids: env::var("NO_COMPACT_IDS").is_err(),
         inline: legalize,
+        inline_threshold: if sess.opts.optimize == OptLevel::No {
+            0
+        } else {
+            codegen_args.inline_threshold
+        },
         mem2reg: legalize,
+        opt: legalize && sess.opts.optimize != OptLevel::No,
         structurize: env::var("NO_STRUCTURIZE").is_err(),
-        use_new_structurizer: env::var("OLD_STRUCTURIZER").is_err(),
         emit_multiple_modules,
+        emulate_int64: sess
+            .target_features
+            .contains(&Symbol::intern("emulate-int64")),
+        narrow_float64: sess
+            .target_features
+            .contains(&Symbol::intern("narrow-float64")),
+        promote_small_ints: sess
+            .target_features
+            .contains(&Symbol::intern("promote-small-ints")),
+        keep_src_locs: codegen_args.keep_src_locs,
+        library_exports,
     };
 
     let link_result = linker::link(sess, modules, &options);
@@ -483,6 +702,37 @@ fn do_link(
     }
 }
 
+/// The symbols exported by a library module, i.e. the `#[no_mangle]` (or `#[export_name]`) items of the crate being
+/// linked (given its own `modules`). The other exports only link codegen units and crates together, and so have
+/// mangled names (dependencies' `#[no_mangle]` items, e.g. `memcmp` in `spirv-std`, aren't part of the library).
+fn library_exports(modules: &[Module]) -> HashSet<String> {
+    modules
+        .iter()
+        .flat_map(|module| &module.annotations)
+        .filter(|inst| {
+            inst.class.opcode == Op::Decorate
+                && inst.operands[1].unwrap_decoration() == Decoration::LinkageAttributes
+                && inst.operands[3].unwrap_linkage_type() == LinkageType::Export
+        })
+        .map(|inst| inst.operands[2].unwrap_literal_string())
+        .filter(|name| rustc_demangle::try_demangle(name).is_err())
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Loads an external SPIR-V module to link against. Only its exports are of interest, so its memory model, entry
+/// points and execution modes are dropped (they'd otherwise clash with the ones of the crate being linked).
+fn load_spirv_library(path: &Path) -> Result<Module, String> {
+    let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+    let mut loader = rspirv::dr::Loader::new();
+    rspirv::binary::parse_bytes(&bytes, &mut loader).map_err(|err| format!("{:?}", err))?;
+    let mut module = loader.module();
+    module.memory_model = None;
+    module.entry_points.clear();
+    module.execution_modes.clear();
+    Ok(module)
+}
+
 /// As of right now, this is essentially a no-op, just plumbing through all the files.
 // TODO: WorkProduct impl
 #[allow(clippy::unnecessary_wraps)]
@@ -533,3 +783,69 @@ pub(crate) fn run_thin(
 
     Ok((opt_jobs, vec![]))
 }
+
+#[cfg(test)]
+mod test {
+    use super::{find_line, mentioned_ids};
+    use rspirv::dr::{Loader, Module};
+    use rspirv::spirv::{Op, Word};
+
+    fn assemble(spirv: &str) -> Module {
+        use spirv_tools::assembler::{self, Assembler};
+
+        let spv_binary = assembler::create(None)
+            .assemble(spirv, assembler::AssemblerOptions::default())
+            .expect("Failed to assemble test spir-v");
+        let bytes: &[u8] = spv_binary.as_ref();
+        let mut loader = Loader::new();
+        rspirv::binary::parse_bytes(bytes, &mut loader).unwrap();
+        loader.module()
+    }
+
+    #[test]
+    fn ids_in_message() {
+        let message = "ID 12[%foo] has not been defined\n  %13 = OpLoad %float 7[%7]";
+        assert_eq!(mentioned_ids(message).collect::<Vec<_>>(), [12, 7]);
+    }
+
+    #[test]
+    fn line_of_instruction() {
+        let module = assemble(
+            r#"OpCapability Shader
+            OpMemoryModel Logical Simple
+            %file = OpString "src/lib.rs"
+            %void = OpTypeVoid
+            %float = OpTypeFloat 32
+            %fn_ty = OpTypeFunction %void %float
+            %func = OpFunction %void None %fn_ty
+            %param = OpFunctionParameter %float
+            %entry = OpLabel
+            %before = OpFAdd %float %param %param
+            OpLine %file 3 5
+            %at = OpFMul %float %param %param
+            OpNoLine
+            %after = OpFSub %float %param %param
+            OpReturn
+            OpFunctionEnd"#,
+        );
+        let func = &module.functions[0];
+        let result_of = |opcode: Op| -> Word {
+            func.blocks[0]
+                .instructions
+                .iter()
+                .find(|inst| inst.class.opcode == opcode)
+                .unwrap()
+                .result_id
+                .unwrap()
+        };
+
+        let line = Some(("src/lib.rs", 3, 5));
+        assert_eq!(find_line(&module, result_of(Op::FAdd)), None);
+        assert_eq!(find_line(&module, result_of(Op::FMul)), line);
+        assert_eq!(find_line(&module, result_of(Op::FSub)), None);
+        // Functions and their parameters get the first line in the function.
+        assert_eq!(find_line(&module, func.def_id().unwrap()), line);
+        let param = func.parameters[0].result_id.unwrap();
+        assert_eq!(find_line(&module, param), line);
+    }
+}
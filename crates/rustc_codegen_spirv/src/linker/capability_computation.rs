@@ -1,5 +1,9 @@
+use super::Result;
+use indexmap::IndexSet;
 use rspirv::dr::{Instruction, Module, Operand};
-use rspirv::spirv::{Capability, Dim, ExecutionModel, Op};
+use rspirv::spirv::{Capability, Dim, ExecutionModel, Op, StorageClass, Word};
+use rustc_errors::ErrorReported;
+use rustc_session::Session;
 use std::collections::HashSet;
 
 pub fn remove_extra_capabilities(module: &mut Module) {
@@ -24,6 +28,31 @@ pub fn remove_extra_capabilities(module: &mut Module) {
     remove_capabilities(module, &to_remove);
 }
 
+/// Errors if the module still uses any of `unavailable` capabilities (e.g. `Int64` when 64-bit
+/// integers were supposed to be emulated), as the target wouldn't be able to run it.
+pub fn check_unavailable_capabilities(
+    sess: &Session,
+    module: &Module,
+    unavailable: &[Capability],
+) -> Result<()> {
+    let used_capabilities = used_capabilities(module);
+    let mut has_err = false;
+    for capability in unavailable {
+        if used_capabilities.contains(capability) {
+            sess.err(&format!(
+                "module requires the {:?} capability, which the target doesn't support",
+                capability
+            ));
+            has_err = true;
+        }
+    }
+    if has_err {
+        Err(ErrorReported)
+    } else {
+        Ok(())
+    }
+}
+
 fn used_capabilities(module: &Module) -> HashSet<Capability> {
     let mut set = HashSet::new();
     for inst in module.all_inst_iter() {
@@ -63,7 +92,9 @@ fn remove_capabilities(module: &mut Module, set: &HashSet<Capability>) {
 }
 
 pub fn add_required_capabilities(module: &mut Module) {
-    let mut required_capabilities: HashSet<Capability> = module
+    // NOTE: this keeps the existing capabilities in their original order, and sorts the added
+    // ones, so that they're always emitted in the same order.
+    let mut required_capabilities: IndexSet<Capability> = module
         .capabilities
         .drain(..)
         .filter(|inst| inst.class.opcode == Op::Capability)
@@ -86,6 +117,33 @@ pub fn add_required_capabilities(module: &mut Module) {
             _ => {}
         });
 
+    for &(width, extension, min_version) in &[
+        (8, "SPV_KHR_8bit_storage", (1, 5)),
+        (16, "SPV_KHR_16bit_storage", (1, 3)),
+    ] {
+        let storage_capabilities = small_storage_capabilities(module, width);
+        if storage_capabilities.is_empty() {
+            continue;
+        }
+        let version = module.header.as_ref().unwrap().version();
+        if version < min_version
+            && !module
+                .extensions
+                .iter()
+                .any(|inst| inst.operands[0].unwrap_literal_string() == extension)
+        {
+            module.extensions.push(Instruction::new(
+                Op::Extension,
+                None,
+                None,
+                vec![Operand::LiteralString(extension.to_string())],
+            ));
+        }
+        let mut storage_capabilities = storage_capabilities.into_iter().collect::<Vec<_>>();
+        storage_capabilities.sort_by_key(|&capability| capability as u32);
+        required_capabilities.extend(storage_capabilities);
+    }
+
     for capability in required_capabilities {
         module.capabilities.push(Instruction::new(
             Op::Capability,
@@ -96,6 +154,61 @@ pub fn add_required_capabilities(module: &mut Module) {
     }
 }
 
+/// 8-bit and 16-bit types (e.g. `u8`, `u16` or `f16`) can't be loaded from/stored to interface
+/// storage classes without the `*8BitAccess`/`*16BitAccess` (or `*8`/`*16`) capabilities, even if
+/// `Int8`/`Int16`/`Float16` are present.
+fn small_storage_capabilities(module: &Module, width: u32) -> HashSet<Capability> {
+    let mut types_containing_small = HashSet::<Word>::new();
+    let mut capabilities = HashSet::new();
+    for inst in &module.types_global_values {
+        let contains_small = match inst.class.opcode {
+            Op::TypeInt | Op::TypeFloat => inst.operands[0].unwrap_literal_int32() == width,
+            Op::TypeVector
+            | Op::TypeMatrix
+            | Op::TypeArray
+            | Op::TypeRuntimeArray
+            | Op::TypeStruct => inst
+                .operands
+                .iter()
+                .filter_map(|op| op.id_ref_any())
+                .any(|id| types_containing_small.contains(&id)),
+            Op::TypePointer => {
+                if types_containing_small.contains(&inst.operands[1].unwrap_id_ref()) {
+                    let capability = match (inst.operands[0].unwrap_storage_class(), width) {
+                        (StorageClass::StorageBuffer, 8)
+                        | (StorageClass::PhysicalStorageBuffer, 8) => {
+                            Some(Capability::StorageBuffer8BitAccess)
+                        }
+                        (StorageClass::Uniform, 8) => {
+                            Some(Capability::UniformAndStorageBuffer8BitAccess)
+                        }
+                        (StorageClass::PushConstant, 8) => Some(Capability::StoragePushConstant8),
+                        (StorageClass::StorageBuffer, _)
+                        | (StorageClass::PhysicalStorageBuffer, _) => {
+                            Some(Capability::StorageBuffer16BitAccess)
+                        }
+                        (StorageClass::Uniform, _) => {
+                            Some(Capability::UniformAndStorageBuffer16BitAccess)
+                        }
+                        (StorageClass::PushConstant, _) => Some(Capability::StoragePushConstant16),
+                        (StorageClass::Input, 16) | (StorageClass::Output, 16) => {
+                            Some(Capability::StorageInputOutput16)
+                        }
+                        _ => None,
+                    };
+                    capabilities.extend(capability);
+                }
+                false
+            }
+            _ => false,
+        };
+        if contains_small {
+            types_containing_small.insert(inst.result_id.unwrap());
+        }
+    }
+    capabilities
+}
+
 // rspirv pulls its spec information from the latest version. However, we might not be compiling for
 // the latest version.
 // For example, we might run into this situation:
@@ -115,6 +228,13 @@ fn additional_extensions(module: &Module, inst: &Instruction) -> &'static [&'sta
             }
             _ => &[],
         }
+    } else if inst.class.opcode == Op::ExtInstImport
+        && inst.operands[0]
+            .unwrap_literal_string()
+            .starts_with("NonSemantic.")
+    {
+        // Non-semantic instruction sets (e.g. debug info) aren't known to rspirv at all.
+        &["SPV_KHR_non_semantic_info"]
     } else {
         &[]
     }
@@ -1,14 +1,17 @@
 //! Dead code elimination
 //!
 //! This pass removes any instruction that doesn't affect the module. It does so by considering all
-//! `OpEntryPoint` instructions to be "rooted", and then everything a rooted instruction touches is
+//! `OpEntryPoint` instructions (and, in library modules, the exported functions and variables) to
+//! be "rooted", and then everything a rooted instruction touches is
 //! also rooted (done transitively). Then, any instruction not rooted is removed. It gets a little
 //! weird with things like `OpDecorate`, where the reference is reversed - an `OpDecorate` that
 //! *references* a rooted thing is also rooted, not the other way around - but that's the basic
-//! concept.
+//! concept. Custom decorations (see `crate::decorations`) are treated the same way, even though
+//! they're `OpExtInst`s with their own result IDs.
 
+use crate::decorations;
 use rspirv::dr::{Function, Instruction, Module};
-use rspirv::spirv::{Op, Word};
+use rspirv::spirv::{Decoration, LinkageType, Op, Word};
 use std::collections::HashSet;
 
 pub fn dce(module: &mut Module) {
@@ -22,13 +25,41 @@ pub fn collect_roots(module: &Module) -> HashSet<Word> {
     for inst in &module.entry_points {
         root(inst, &mut rooted);
     }
+    for (id, _) in exports(module) {
+        rooted.insert(id);
+    }
     rooted
 }
 
+/// The IDs and names of everything with `Export` linkage, which is only left in library modules
+/// by the time this is needed (see `import_export_link`).
+pub fn exports(module: &Module) -> impl Iterator<Item = (Word, &str)> {
+    module.annotations.iter().filter_map(|inst| {
+        if inst.class.opcode == Op::Decorate
+            && inst.operands[1].unwrap_decoration() == Decoration::LinkageAttributes
+            && inst.operands[3].unwrap_linkage_type() == LinkageType::Export
+        {
+            Some((
+                inst.operands[0].unwrap_id_ref(),
+                inst.operands[2].unwrap_literal_string(),
+            ))
+        } else {
+            None
+        }
+    })
+}
+
 fn spread_roots(module: &Module, rooted: &mut HashSet<Word>) -> bool {
     let mut any = false;
+    let custom_decorations_import = decorations::find_import(module);
     for inst in module.global_inst_iter() {
         if let Some(id) = inst.result_id {
+            let decorates_rooted =
+                decorations::custom_decoration_target(inst, custom_decorations_import)
+                    .map_or(false, |target| rooted.contains(&target));
+            if decorates_rooted {
+                any |= rooted.insert(id);
+            }
             if rooted.contains(&id) {
                 any |= root(inst, rooted);
             }
@@ -0,0 +1,876 @@
+//! Devirtualization of indirect calls, through `fn` pointers and `dyn Trait` vtables.
+//!
+//! SPIR-V has no function pointers (outside of `SPV_INTEL_function_pointers`), so codegen emits:
+//! * `fn` pointers as "address constants" of their functions, and `dyn Trait` vtable pointers as
+//!   "address constants" of the global variables holding the vtables (see
+//!   `CodegenCx::address_constant`), which are zombies until replaced here
+//! * reads from vtables as `OpLoad`s from an `OpInBoundsPtrAccessChain` into an `OpBitcast` of a
+//!   vtable pointer
+//! * indirect calls as `OpFunctionPointerCallINTEL`
+//!
+//! As the linker sees the whole program, only a finite (and usually small) set of functions (and
+//! vtables) can ever have their address taken, which allows replacing:
+//! * `fn` and vtable pointers with (32-bit integer) indices into those sets, with `0` as `null`
+//! * reads from vtables with the constant read (or an `OpSelect` chain over all the constants
+//!   that could be read, when the vtable isn't known)
+//! * indirect calls with direct ones (or an `OpSwitch` over direct calls to all the functions that
+//!   could be called, when the callee isn't known)
+//!
+//! To know the vtable (or callee) in more cases, functions taking `fn` or vtable pointers are
+//! first inlined into their callers.
+//!
+//! The receivers of `dyn Trait` methods are type-erased (by an `OpBitcast` to a pointer to the
+//! `dyn Trait` itself), so the direct calls have to undo the cast, which is only possible when it
+//! can be seen from the call. Those casts are zombies, so any uses left over get reported.
+
+use super::simple_passes::strongly_connected_components;
+use super::{apply_rewrite_rules, duplicates, id, inline};
+use crate::decorations::{
+    custom_decoration_target, find_import, is_custom_decoration, CustomDecoration,
+    DecorationEncoder, ZombieDecoration,
+};
+use indexmap::IndexSet;
+use rspirv::dr::{Block, Function, Instruction, Module, ModuleHeader, Operand};
+use rspirv::spirv::{Op, Word};
+use std::collections::{HashMap, HashSet};
+use std::iter::once;
+
+/// Name of the `OpTypeOpaque` pointed to by `dyn Trait` vtable pointers.
+pub const VTABLE_TYPE_NAME: &str = "rust_gpu::vtable";
+
+pub fn devirtualize(module: &mut Module) {
+    let mut function_types = HashSet::new();
+    let mut vtable_types = HashSet::new();
+    let mut fn_ptr_types = HashMap::new();
+    let mut vtable_ptr_types = HashSet::new();
+    for inst in &module.types_global_values {
+        let result_id = match inst.result_id {
+            Some(result_id) => result_id,
+            None => continue,
+        };
+        match inst.class.opcode {
+            Op::TypeFunction => {
+                function_types.insert(result_id);
+            }
+            Op::TypeOpaque if inst.operands[0].unwrap_literal_string() == VTABLE_TYPE_NAME => {
+                vtable_types.insert(result_id);
+            }
+            Op::TypePointer => {
+                let pointee = inst.operands[1].unwrap_id_ref();
+                if function_types.contains(&pointee) {
+                    fn_ptr_types.insert(result_id, pointee);
+                } else if vtable_types.contains(&pointee) {
+                    vtable_ptr_types.insert(result_id);
+                }
+            }
+            _ => {}
+        }
+    }
+    if fn_ptr_types.is_empty() && vtable_ptr_types.is_empty() {
+        return;
+    }
+
+    // Where a `fn` (or vtable) pointer comes from is usually only visible in the caller of the
+    // function taking it as an argument. Recursive functions would never stop being inlined into
+    // themselves, so they're left alone (to be reported by `recursion.rs`).
+    let recursive = recursive_functions(module);
+    inline::inline_functions(module, |func| {
+        !recursive.contains(&func.def_id().unwrap())
+            && func.parameters.iter().any(|param| {
+                let ty = param.result_type.unwrap();
+                fn_ptr_types.contains_key(&ty) || vtable_ptr_types.contains(&ty)
+            })
+    });
+
+    let mut address_constants = HashMap::new();
+    let mut functions = IndexSet::new();
+    let mut vtables = IndexSet::new();
+    let mut int_literals = HashMap::new();
+    let mut null_constants = HashSet::new();
+    let mut global_types = HashMap::new();
+    for inst in &module.types_global_values {
+        let result_id = match inst.result_id {
+            Some(result_id) => result_id,
+            None => continue,
+        };
+        if let Some(result_type) = inst.result_type {
+            global_types.insert(result_id, result_type);
+        }
+        match inst.class.opcode {
+            Op::ConstantComposite if inst.operands.len() == 1 => {
+                let ty = inst.result_type.unwrap();
+                let target = inst.operands[0].unwrap_id_ref();
+                if fn_ptr_types.contains_key(&ty) {
+                    functions.insert(target);
+                } else if vtable_ptr_types.contains(&ty) {
+                    vtables.insert(target);
+                } else {
+                    continue;
+                }
+                address_constants.insert(result_id, target);
+            }
+            Op::Constant => {
+                if let Operand::LiteralInt32(value) = inst.operands[0] {
+                    int_literals.insert(result_id, value);
+                }
+            }
+            Op::ConstantNull => {
+                null_constants.insert(result_id);
+            }
+            _ => {}
+        }
+    }
+
+    // The fields of each vtable, i.e. the values that can be read from its slots.
+    let vtable_slots = vtables
+        .iter()
+        .map(|&vtable| {
+            let initializer = module
+                .types_global_values
+                .iter()
+                .find(|inst| inst.result_id == Some(vtable))
+                .and_then(|var| var.operands.get(1))
+                .map(|initializer| initializer.unwrap_id_ref());
+            module
+                .types_global_values
+                .iter()
+                .find(|inst| {
+                    inst.class.opcode == Op::ConstantComposite && inst.result_id == initializer
+                })
+                .map_or(Vec::new(), |composite| {
+                    composite
+                        .operands
+                        .iter()
+                        .map(|field| field.unwrap_id_ref())
+                        .collect()
+                })
+        })
+        .collect();
+
+    let signatures = module
+        .functions
+        .iter()
+        .map(|func| {
+            let def = func.def.as_ref().unwrap();
+            let signature = Signature {
+                return_type: def.result_type.unwrap(),
+                function_type: def.operands[1].unwrap_id_ref(),
+                params: func
+                    .parameters
+                    .iter()
+                    .map(|param| param.result_type.unwrap())
+                    .collect(),
+            };
+            (def.result_id.unwrap(), signature)
+        })
+        .collect();
+
+    let find_type = |opcode| {
+        module
+            .types_global_values
+            .iter()
+            .find(|inst| inst.class.opcode == opcode)
+            .map(|inst| inst.result_id.unwrap())
+    };
+    let mut devirtualizer = Devirtualizer {
+        header: module.header.as_mut().unwrap(),
+        fn_ptr_types: &fn_ptr_types,
+        vtable_ptr_types: &vtable_ptr_types,
+        address_constants,
+        functions,
+        vtables,
+        vtable_slots,
+        int_literals,
+        null_constants,
+        global_types,
+        signatures,
+        void: find_type(Op::TypeVoid),
+        bool_type: find_type(Op::TypeBool),
+        int_constants: HashMap::new(),
+        new_globals: Vec::new(),
+        zombies: Vec::new(),
+    };
+    for func in &mut module.functions {
+        devirtualizer.devirtualize_function(func);
+    }
+    let Devirtualizer {
+        address_constants,
+        functions,
+        vtables,
+        new_globals,
+        zombies,
+        ..
+    } = devirtualizer;
+
+    // Finally, turn `fn` and vtable pointers into indices.
+    let is_index_type = |ty| fn_ptr_types.contains_key(&ty) || vtable_ptr_types.contains(&ty);
+    module.types_global_values.retain(|inst| {
+        inst.class.opcode != Op::TypeForwardPointer
+            || !is_index_type(inst.operands[0].unwrap_id_ref())
+    });
+    for inst in &mut module.types_global_values {
+        let result_id = match inst.result_id {
+            Some(result_id) => result_id,
+            None => continue,
+        };
+        if inst.class.opcode == Op::TypePointer && is_index_type(result_id) {
+            *inst = Instruction::new(
+                Op::TypeInt,
+                None,
+                Some(result_id),
+                vec![Operand::LiteralInt32(32), Operand::LiteralInt32(0)],
+            );
+        } else if let Some(target) = address_constants.get(&result_id) {
+            let index = functions
+                .get_index_of(target)
+                .or_else(|| vtables.get_index_of(target))
+                .unwrap();
+            *inst = Instruction::new(
+                Op::Constant,
+                inst.result_type,
+                Some(result_id),
+                vec![Operand::LiteralInt32(index as u32 + 1)],
+            );
+        }
+    }
+    module.types_global_values.extend(new_globals);
+    // The address constants were zombies (see `CodegenCx::address_constant`), but are all valid
+    // indices now.
+    let import = find_import(module);
+    module.types_global_values.retain(|inst| {
+        !is_custom_decoration(inst, import, Some(ZombieDecoration::OPCODE))
+            || !address_constants.contains_key(&custom_decoration_target(inst, import).unwrap())
+    });
+    let mut encoder = DecorationEncoder::new(module);
+    for (id, zombie) in zombies {
+        encoder.encode(id, &zombie);
+    }
+
+    // All the `fn` and vtable pointer types are now duplicates of `u32`.
+    duplicates::remove_duplicate_types(module);
+}
+
+/// The functions in cycles of (direct) calls, i.e. calling themselves, directly or indirectly.
+fn recursive_functions(module: &Module) -> HashSet<Word> {
+    let callees = module
+        .functions
+        .iter()
+        .map(|func| {
+            let callees = func
+                .all_inst_iter()
+                .filter(|inst| inst.class.opcode == Op::FunctionCall)
+                .map(|inst| inst.operands[0].unwrap_id_ref())
+                .collect::<HashSet<_>>();
+            (func.def_id().unwrap(), callees)
+        })
+        .collect::<HashMap<_, _>>();
+    let functions = module
+        .functions
+        .iter()
+        .map(|func| func.def_id().unwrap())
+        .collect::<Vec<_>>();
+    strongly_connected_components(&functions, |func| {
+        // Calls to imported functions that were never defined have nothing to recurse into.
+        callees[&func]
+            .iter()
+            .copied()
+            .filter(|callee| callees.contains_key(callee))
+            .collect::<Vec<_>>()
+    })
+    .into_iter()
+    .filter(|scc| scc.len() > 1 || callees[&scc[0]].contains(&scc[0]))
+    .flatten()
+    .collect()
+}
+
+struct Signature {
+    return_type: Word,
+    function_type: Word,
+    params: Vec<Word>,
+}
+
+struct Devirtualizer<'a> {
+    header: &'a mut ModuleHeader,
+    /// Map from `fn` pointer types to the function types they point to.
+    fn_ptr_types: &'a HashMap<Word, Word>,
+    vtable_ptr_types: &'a HashSet<Word>,
+    /// Map from address constants to their targets (functions, or vtables' global variables).
+    address_constants: HashMap<Word, Word>,
+    /// The functions whose address is taken, with `fn` pointers becoming their index (plus `1`).
+    functions: IndexSet<Word>,
+    /// The vtables, with vtable pointers becoming their index (plus `1`).
+    vtables: IndexSet<Word>,
+    vtable_slots: Vec<Vec<Word>>,
+    /// The values of all (32-bit) integer constants, including the ones created by this pass.
+    int_literals: HashMap<Word, u32>,
+    null_constants: HashSet<Word>,
+    global_types: HashMap<Word, Word>,
+    signatures: HashMap<Word, Signature>,
+    void: Option<Word>,
+    bool_type: Option<Word>,
+    /// Cache for `int_constant`.
+    int_constants: HashMap<(Word, u32), Word>,
+    new_globals: Vec<Instruction>,
+    zombies: Vec<(Word, ZombieDecoration)>,
+}
+
+impl Devirtualizer<'_> {
+    fn id(&mut self) -> Word {
+        id(self.header)
+    }
+
+    fn int_constant(&mut self, ty: Word, value: u32) -> Word {
+        if let Some(&constant) = self.int_constants.get(&(ty, value)) {
+            return constant;
+        }
+        let constant = self.id();
+        self.new_globals.push(Instruction::new(
+            Op::Constant,
+            Some(ty),
+            Some(constant),
+            vec![Operand::LiteralInt32(value)],
+        ));
+        self.int_constants.insert((ty, value), constant);
+        self.int_literals.insert(constant, value);
+        constant
+    }
+
+    fn bool_type(&mut self) -> Word {
+        if let Some(bool_type) = self.bool_type {
+            return bool_type;
+        }
+        let bool_type = self.id();
+        self.new_globals.push(Instruction::new(
+            Op::TypeBool,
+            None,
+            Some(bool_type),
+            vec![],
+        ));
+        self.bool_type = Some(bool_type);
+        bool_type
+    }
+
+    fn zombie(&mut self, id: Word, reason: &str) {
+        self.zombies.push((
+            id,
+            ZombieDecoration {
+                reason: reason.to_string(),
+                span: None,
+            },
+        ));
+    }
+
+    fn devirtualize_function(&mut self, func: &mut Function) {
+        // The vtable pointers and `OpBitcast`s/access chains that may no longer be needed.
+        let mut maybe_dead = HashSet::new();
+        let known_callees = self.resolve_vtable_reads(func, &mut maybe_dead);
+        self.resolve_indirect_calls(func, &known_callees, &mut maybe_dead);
+        remove_dead_instructions(func, &maybe_dead);
+    }
+
+    /// Replace reads from vtables with the values read, returning the functions each `fn`
+    /// pointer read could be pointing to (as indices into `self.functions`).
+    fn resolve_vtable_reads(
+        &mut self,
+        func: &mut Function,
+        maybe_dead: &mut HashSet<Word>,
+    ) -> HashMap<Word, Vec<usize>> {
+        let defs = FunctionDefs::new(func);
+        let mut known_callees = HashMap::new();
+        let mut rewrite_rules = HashMap::new();
+        for block in &mut func.blocks {
+            let mut inst_idx = 0;
+            while inst_idx < block.instructions.len() {
+                let inst = &block.instructions[inst_idx];
+                let read = if inst.class.opcode == Op::Load {
+                    self.vtable_read(&defs, inst.operands[0].unwrap_id_ref())
+                } else {
+                    None
+                };
+                let (vtable_ptr, slot, chain) = match read {
+                    Some(read) => read,
+                    None => {
+                        inst_idx += 1;
+                        continue;
+                    }
+                };
+                let ty = inst.result_type.unwrap();
+                let result_id = inst.result_id.unwrap();
+                maybe_dead.extend(chain);
+
+                let slot = match slot {
+                    Some(slot) => slot as usize,
+                    None => {
+                        self.zombie(result_id, "Cannot read from a vtable with a dynamic index");
+                        inst_idx += 1;
+                        continue;
+                    }
+                };
+                let vtables = self
+                    .possible_targets(&defs, &HashMap::new(), vtable_ptr)
+                    .unwrap_or_else(|| (0..self.vtables.len()).collect());
+
+                let fields = vtables
+                    .iter()
+                    .map(|&vtable| self.vtable_slots[vtable].get(slot).copied())
+                    .collect::<Vec<_>>();
+                if self.fn_ptr_types.contains_key(&ty) {
+                    let mut callees = fields
+                        .iter()
+                        .filter_map(|&field| self.address_constants.get(&field?))
+                        .filter_map(|target| self.functions.get_index_of(target))
+                        .collect::<Vec<_>>();
+                    callees.sort_unstable();
+                    callees.dedup();
+                    known_callees.insert(result_id, callees);
+                }
+                let values = fields
+                    .into_iter()
+                    .map(|field| {
+                        let value = field.map_or(0, |field| self.slot_value(field));
+                        self.int_constant(ty, value)
+                    })
+                    .collect::<Vec<_>>();
+
+                if values.windows(2).all(|w| w[0] == w[1]) {
+                    let value = match values.first() {
+                        Some(&value) => value,
+                        None => self.int_constant(ty, 0),
+                    };
+                    rewrite_rules.insert(result_id, value);
+                    block.instructions.remove(inst_idx);
+                    continue;
+                }
+
+                // Select the value read from the right vtable, based on the vtable pointer.
+                let bool_type = self.bool_type();
+                let vtable_ptr_type = defs.type_of(vtable_ptr, &self.global_types).unwrap();
+                let mut insts = Vec::new();
+                let mut value = values[0];
+                for (i, (&vtable, &vtable_value)) in vtables.iter().zip(&values).enumerate().skip(1)
+                {
+                    let index = self.int_constant(vtable_ptr_type, vtable as u32 + 1);
+                    let is_vtable = self.id();
+                    insts.push(Instruction::new(
+                        Op::IEqual,
+                        Some(bool_type),
+                        Some(is_vtable),
+                        vec![Operand::IdRef(vtable_ptr), Operand::IdRef(index)],
+                    ));
+                    let select = if i == values.len() - 1 {
+                        result_id
+                    } else {
+                        self.id()
+                    };
+                    insts.push(Instruction::new(
+                        Op::Select,
+                        Some(ty),
+                        Some(select),
+                        vec![
+                            Operand::IdRef(is_vtable),
+                            Operand::IdRef(vtable_value),
+                            Operand::IdRef(value),
+                        ],
+                    ));
+                    value = select;
+                }
+                let inserted = insts.len();
+                block.instructions.splice(inst_idx..=inst_idx, insts);
+                inst_idx += inserted;
+            }
+        }
+        apply_rewrite_rules(&rewrite_rules, &mut func.blocks);
+        known_callees
+    }
+
+    /// If `ptr` points into a vtable, returns the vtable pointer, the slot being read (if it's
+    /// constant), and the instructions computing `ptr` from the vtable pointer.
+    fn vtable_read(
+        &self,
+        defs: &FunctionDefs,
+        mut ptr: Word,
+    ) -> Option<(Word, Option<u32>, Vec<Word>)> {
+        let mut chain = Vec::new();
+        let mut slot = Some(0);
+        if let Some(inst) = defs.insts.get(&ptr) {
+            match inst.class.opcode {
+                Op::PtrAccessChain | Op::InBoundsPtrAccessChain if inst.operands.len() == 2 => {
+                    chain.push(ptr);
+                    slot = self
+                        .int_literals
+                        .get(&inst.operands[1].unwrap_id_ref())
+                        .copied();
+                    ptr = inst.operands[0].unwrap_id_ref();
+                }
+                Op::AccessChain | Op::InBoundsAccessChain if inst.operands.len() == 1 => {
+                    chain.push(ptr);
+                    ptr = inst.operands[0].unwrap_id_ref();
+                }
+                _ => {}
+            }
+        }
+        let bitcast = defs.insts.get(&ptr)?;
+        if bitcast.class.opcode != Op::Bitcast {
+            return None;
+        }
+        let vtable_ptr = bitcast.operands[0].unwrap_id_ref();
+        let vtable_ptr_type = defs.type_of(vtable_ptr, &self.global_types)?;
+        if !self.vtable_ptr_types.contains(&vtable_ptr_type) {
+            return None;
+        }
+        chain.push(ptr);
+        Some((vtable_ptr, slot, chain))
+    }
+
+    /// The index (plus `1`) of a function (or the integer) a vtable field holds.
+    fn slot_value(&self, field: Word) -> u32 {
+        match self.address_constants.get(&field) {
+            Some(target) => self
+                .functions
+                .get_index_of(target)
+                .map_or(0, |index| index as u32 + 1),
+            None => self.int_literals.get(&field).copied().unwrap_or(0),
+        }
+    }
+
+    /// All the functions (or vtables) `value` could be pointing to, as indices into
+    /// `self.functions` (or `self.vtables`), or `None` if they can't be determined.
+    fn possible_targets(
+        &self,
+        defs: &FunctionDefs,
+        known: &HashMap<Word, Vec<usize>>,
+        value: Word,
+    ) -> Option<Vec<usize>> {
+        let mut targets = Vec::new();
+        let mut visited = HashSet::new();
+        let mut worklist = vec![value];
+        while let Some(value) = worklist.pop() {
+            if !visited.insert(value) {
+                continue;
+            }
+            if let Some(target) = self.address_constants.get(&value) {
+                targets.extend(
+                    self.functions
+                        .get_index_of(target)
+                        .or_else(|| self.vtables.get_index_of(target)),
+                );
+            } else if let Some(&index) = self.int_literals.get(&value) {
+                if index > 0 {
+                    targets.push(index as usize - 1);
+                }
+            } else if let Some(known) = known.get(&value) {
+                targets.extend(known);
+            } else if !self.null_constants.contains(&value) {
+                let inst = defs.insts.get(&value)?;
+                match inst.class.opcode {
+                    Op::Phi => worklist.extend(
+                        inst.operands
+                            .iter()
+                            .step_by(2)
+                            .map(|operand| operand.unwrap_id_ref()),
+                    ),
+                    Op::Select => worklist.extend(
+                        inst.operands[1..]
+                            .iter()
+                            .map(|operand| operand.unwrap_id_ref()),
+                    ),
+                    Op::CopyObject => worklist.push(inst.operands[0].unwrap_id_ref()),
+                    _ => return None,
+                }
+            }
+        }
+        targets.sort_unstable();
+        targets.dedup();
+        Some(targets)
+    }
+
+    fn resolve_indirect_calls(
+        &mut self,
+        func: &mut Function,
+        known_callees: &HashMap<Word, Vec<usize>>,
+        maybe_dead: &mut HashSet<Word>,
+    ) {
+        let defs = FunctionDefs::new(func);
+        let mut block_idx = 0;
+        let mut inst_idx = 0;
+        while block_idx < func.blocks.len() {
+            let call_idx = func.blocks[block_idx].instructions[inst_idx..]
+                .iter()
+                .position(|inst| inst.class.opcode == Op::FunctionPointerCallINTEL);
+            let call_idx = match call_idx {
+                Some(i) => inst_idx + i,
+                None => {
+                    block_idx += 1;
+                    inst_idx = 0;
+                    continue;
+                }
+            };
+            let call = &func.blocks[block_idx].instructions[call_idx];
+            let result_type = call.result_type.unwrap();
+            let result_id = call.result_id.unwrap();
+            let callee = call.operands[0].unwrap_id_ref();
+            let args = call.operands[1..]
+                .iter()
+                .map(|operand| operand.unwrap_id_ref())
+                .collect::<Vec<_>>();
+
+            let candidates = match self.possible_targets(&defs, known_callees, callee) {
+                // The callee is known to be one of these functions, but (for `dyn Trait`
+                // methods) not necessarily of the exact same type.
+                Some(indices) => indices
+                    .into_iter()
+                    .map(|index| self.functions[index])
+                    .filter(|f| self.is_possible_callee(&defs, *f, result_type, &args))
+                    .collect::<Vec<_>>(),
+                None => match defs
+                    .type_of(callee, &self.global_types)
+                    .and_then(|ty| self.fn_ptr_types.get(&ty))
+                {
+                    Some(&function_type) => self
+                        .functions
+                        .iter()
+                        .copied()
+                        .filter(|f| {
+                            self.signatures
+                                .get(f)
+                                .map_or(false, |sig| sig.function_type == function_type)
+                        })
+                        .collect(),
+                    None => Vec::new(),
+                },
+            };
+
+            match candidates[..] {
+                [] => {
+                    self.zombie(
+                        result_id,
+                        "Cannot call a function pointer that can't point to any function",
+                    );
+                    inst_idx = call_idx + 1;
+                }
+                [f] => {
+                    let (mut insts, args) = self.call_args(&defs, f, &args, maybe_dead);
+                    insts.push(Instruction::new(
+                        Op::FunctionCall,
+                        Some(result_type),
+                        Some(result_id),
+                        once(f).chain(args).map(Operand::IdRef).collect(),
+                    ));
+                    let inserted = insts.len();
+                    func.blocks[block_idx]
+                        .instructions
+                        .splice(call_idx..=call_idx, insts);
+                    inst_idx = call_idx + inserted;
+                }
+                _ => {
+                    // Split the block around the call, and switch between direct calls.
+                    let block = &mut func.blocks[block_idx];
+                    let block_label = block.label_id().unwrap();
+                    let mut rest = block.instructions.split_off(call_idx + 1);
+                    block.instructions.pop();
+
+                    let merge_label = self.id();
+                    let case_labels = candidates.iter().map(|_| self.id()).collect::<Vec<_>>();
+                    let mut switch_operands =
+                        vec![Operand::IdRef(callee), Operand::IdRef(case_labels[0])];
+                    let mut phi_operands = Vec::new();
+                    let mut case_blocks = Vec::new();
+                    for (&f, &label) in candidates.iter().zip(&case_labels) {
+                        let (mut insts, args) = self.call_args(&defs, f, &args, maybe_dead);
+                        let case_result_id = self.id();
+                        insts.push(Instruction::new(
+                            Op::FunctionCall,
+                            Some(result_type),
+                            Some(case_result_id),
+                            once(f).chain(args).map(Operand::IdRef).collect(),
+                        ));
+                        insts.push(Instruction::new(
+                            Op::Branch,
+                            None,
+                            None,
+                            vec![Operand::IdRef(merge_label)],
+                        ));
+                        let index = self.functions.get_index_of(&f).unwrap() as u32 + 1;
+                        switch_operands.push(Operand::LiteralInt32(index));
+                        switch_operands.push(Operand::IdRef(label));
+                        phi_operands.push(Operand::IdRef(case_result_id));
+                        phi_operands.push(Operand::IdRef(label));
+                        case_blocks.push(Block {
+                            label: Some(Instruction::new(Op::Label, None, Some(label), vec![])),
+                            instructions: insts,
+                        });
+                    }
+                    func.blocks[block_idx].instructions.push(Instruction::new(
+                        Op::Switch,
+                        None,
+                        None,
+                        switch_operands,
+                    ));
+
+                    // The successors of the original block are now reached from the merge block.
+                    for inst in func.blocks.iter_mut().flat_map(|b| &mut b.instructions) {
+                        if inst.class.opcode == Op::Phi {
+                            for pred in inst.operands.iter_mut().skip(1).step_by(2) {
+                                if *pred == Operand::IdRef(block_label) {
+                                    *pred = Operand::IdRef(merge_label);
+                                }
+                            }
+                        }
+                    }
+                    if Some(result_type) != self.void {
+                        rest.insert(
+                            0,
+                            Instruction::new(
+                                Op::Phi,
+                                Some(result_type),
+                                Some(result_id),
+                                phi_operands,
+                            ),
+                        );
+                    }
+                    let merge_block = Block {
+                        label: Some(Instruction::new(Op::Label, None, Some(merge_label), vec![])),
+                        instructions: rest,
+                    };
+                    let inserted = case_blocks.len() + 1;
+                    func.blocks.splice(
+                        block_idx + 1..block_idx + 1,
+                        case_blocks.into_iter().chain(once(merge_block)),
+                    );
+                    block_idx += inserted;
+                    inst_idx = 0;
+                }
+            }
+        }
+    }
+
+    /// Whether `f` could be the callee of an indirect call, with a `result_type` result, and
+    /// `args` arguments, which can't be type-erased `dyn Trait` receivers of another type.
+    fn is_possible_callee(
+        &self,
+        defs: &FunctionDefs,
+        f: Word,
+        result_type: Word,
+        args: &[Word],
+    ) -> bool {
+        let signature = match self.signatures.get(&f) {
+            Some(signature) => signature,
+            None => return false,
+        };
+        signature.return_type == result_type
+            && signature.params.len() == args.len()
+            && args
+                .iter()
+                .zip(&signature.params)
+                .all(|(&arg, &param_type)| {
+                    defs.type_of(arg, &self.global_types) == Some(param_type)
+                        || defs.bitcast_source(arg).map_or(true, |source| {
+                            defs.type_of(source, &self.global_types) == Some(param_type)
+                        })
+                })
+    }
+
+    /// The arguments for a direct call to `f`, undoing the type erasure of `dyn Trait`
+    /// receivers, along with any instructions needed to compute them.
+    fn call_args(
+        &mut self,
+        defs: &FunctionDefs,
+        f: Word,
+        args: &[Word],
+        maybe_dead: &mut HashSet<Word>,
+    ) -> (Vec<Instruction>, Vec<Word>) {
+        let mut insts = Vec::new();
+        let params = self.signatures[&f].params.clone();
+        let args = args
+            .iter()
+            .zip(params)
+            .map(|(&arg, param_type)| {
+                if defs.type_of(arg, &self.global_types) == Some(param_type) {
+                    return arg;
+                }
+                if let Some(source) = defs.bitcast_source(arg) {
+                    if defs.type_of(source, &self.global_types) == Some(param_type) {
+                        maybe_dead.insert(arg);
+                        return source;
+                    }
+                }
+                let cast = self.id();
+                insts.push(Instruction::new(
+                    Op::Bitcast,
+                    Some(param_type),
+                    Some(cast),
+                    vec![Operand::IdRef(arg)],
+                ));
+                self.zombie(
+                    cast,
+                    "Cannot call a `dyn Trait` method without knowing the type it was created from",
+                );
+                cast
+            })
+            .collect();
+        (insts, args)
+    }
+}
+
+/// The instructions (and parameters) defined in a function, by their result IDs.
+struct FunctionDefs {
+    insts: HashMap<Word, Instruction>,
+    types: HashMap<Word, Word>,
+}
+
+impl FunctionDefs {
+    fn new(func: &Function) -> Self {
+        let insts = func
+            .all_inst_iter()
+            .filter_map(|inst| Some((inst.result_id?, inst.clone())))
+            .collect::<HashMap<_, _>>();
+        let types = insts
+            .values()
+            .filter_map(|inst| Some((inst.result_id?, inst.result_type?)))
+            .collect();
+        Self { insts, types }
+    }
+
+    fn type_of(&self, id: Word, global_types: &HashMap<Word, Word>) -> Option<Word> {
+        self.types
+            .get(&id)
+            .or_else(|| global_types.get(&id))
+            .copied()
+    }
+
+    fn bitcast_source(&self, id: Word) -> Option<Word> {
+        let inst = self.insts.get(&id)?;
+        if inst.class.opcode == Op::Bitcast {
+            Some(inst.operands[0].unwrap_id_ref())
+        } else {
+            None
+        }
+    }
+}
+
+/// Remove the instructions in `maybe_dead` that are no longer used (transitively).
+fn remove_dead_instructions(func: &mut Function, maybe_dead: &HashSet<Word>) {
+    loop {
+        let used = func
+            .all_inst_iter()
+            .flat_map(|inst| {
+                inst.result_type
+                    .into_iter()
+                    .chain(inst.operands.iter().filter_map(|op| op.id_ref_any()))
+            })
+            .collect::<HashSet<_>>();
+        let mut changed = false;
+        for block in &mut func.blocks {
+            block.instructions.retain(|inst| {
+                let dead = inst
+                    .result_id
+                    .map_or(false, |id| maybe_dead.contains(&id) && !used.contains(&id));
+                changed |= dead;
+                !dead
+            });
+        }
+        if !changed {
+            break;
+        }
+    }
+}
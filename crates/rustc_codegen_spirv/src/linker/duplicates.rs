@@ -1,9 +1,14 @@
+use super::Result;
 use crate::decorations::{CustomDecoration, ZombieDecoration};
 use rspirv::binary::Assemble;
 use rspirv::dr::{Instruction, Module, Operand};
 use rspirv::spirv::{Op, Word};
+use rustc_errors::ErrorReported;
 use rustc_middle::bug;
+use rustc_session::Session;
 use std::collections::{hash_map, HashMap, HashSet};
+use std::iter;
+use std::mem::replace;
 
 pub fn remove_duplicate_extensions(module: &mut Module) {
     let mut set = HashSet::new();
@@ -58,6 +63,95 @@ pub fn remove_duplicate_ext_inst_imports(module: &mut Module) {
     }
 }
 
+/// Replaces `OpGroupDecorate`s (and `OpGroupMemberDecorate`s) with copies of the decorations of
+/// their `OpDecorationGroup` for each of their targets, and removes the groups, so that the rest
+/// of the linker only has to look at plain decorations (e.g. when deduplicating types).
+pub fn flatten_decoration_groups(sess: &Session, module: &mut Module) -> Result<()> {
+    let groups = module
+        .annotations
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::DecorationGroup)
+        .map(|inst| inst.result_id.unwrap())
+        .collect::<HashSet<_>>();
+    if groups.is_empty() {
+        return Ok(());
+    }
+    let is_group_decoration = |inst: &Instruction| {
+        matches!(
+            inst.class.opcode,
+            Op::Decorate | Op::DecorateId | Op::DecorateString
+        ) && groups.contains(&inst.operands[0].unwrap_id_ref())
+    };
+
+    let mut group_decorations = HashMap::<Word, Vec<Instruction>>::new();
+    for inst in &module.annotations {
+        if is_group_decoration(inst) {
+            group_decorations
+                .entry(inst.operands[0].unwrap_id_ref())
+                .or_default()
+                .push(inst.clone());
+        }
+    }
+
+    let annotations = replace(&mut module.annotations, Vec::new());
+    for inst in annotations {
+        match inst.class.opcode {
+            Op::DecorationGroup => {}
+            _ if is_group_decoration(&inst) => {}
+            Op::GroupDecorate => {
+                // NOTE: a group can be applied without having any decorations of its own.
+                let decorations = group_decorations
+                    .get(&inst.operands[0].unwrap_id_ref())
+                    .map_or(&[][..], |d| &d[..]);
+                for target in &inst.operands[1..] {
+                    for decoration in decorations {
+                        let mut decoration = decoration.clone();
+                        decoration.operands[0] = target.clone();
+                        module.annotations.push(decoration);
+                    }
+                }
+            }
+            Op::GroupMemberDecorate => {
+                let decorations = group_decorations
+                    .get(&inst.operands[0].unwrap_id_ref())
+                    .map_or(&[][..], |d| &d[..]);
+                for target_and_member in inst.operands[1..].chunks(2) {
+                    for decoration in decorations {
+                        let opcode = match decoration.class.opcode {
+                            Op::Decorate => Op::MemberDecorate,
+                            Op::DecorateString => Op::MemberDecorateString,
+                            // `OpDecorateId` has no member equivalent, so it can't be in a
+                            // group applied to members.
+                            opcode => {
+                                sess.err(&format!(
+                                    "decoration group with `Op{:?}` applied to struct members",
+                                    opcode
+                                ));
+                                return Err(ErrorReported);
+                            }
+                        };
+                        let operands = target_and_member
+                            .iter()
+                            .chain(&decoration.operands[1..])
+                            .cloned()
+                            .collect();
+                        module
+                            .annotations
+                            .push(Instruction::new(opcode, None, None, operands));
+                    }
+                }
+            }
+            _ => module.annotations.push(inst),
+        }
+    }
+
+    // Groups can only have names besides their decorations.
+    module.debugs.retain(|inst| {
+        inst.class.opcode != Op::Name || !groups.contains(&inst.operands[0].unwrap_id_ref())
+    });
+    Ok(())
+}
+
 fn make_annotation_key(inst: &Instruction) -> Vec<u32> {
     let mut data = vec![inst.class.opcode as u32];
 
@@ -72,7 +166,10 @@ fn make_annotation_key(inst: &Instruction) -> Vec<u32> {
 fn gather_annotations(annotations: &[Instruction]) -> HashMap<Word, Vec<u32>> {
     let mut map = HashMap::new();
     for inst in annotations {
-        if inst.class.opcode == Op::Decorate || inst.class.opcode == Op::MemberDecorate {
+        if matches!(
+            inst.class.opcode,
+            Op::Decorate | Op::DecorateString | Op::MemberDecorate | Op::MemberDecorateString
+        ) {
             match map.entry(inst.operands[0].id_ref_any().unwrap()) {
                 hash_map::Entry::Vacant(entry) => {
                     entry.insert(vec![make_annotation_key(inst)]);
@@ -185,6 +282,7 @@ pub fn remove_duplicate_types(module: &mut Module) {
 
     // Include zombies in the key to not merge zombies with non-zombies
     let zombies: HashSet<Word> = ZombieDecoration::decode_all(module)
+        .into_iter()
         .map(|(z, _)| z)
         .collect();
 
@@ -259,6 +357,11 @@ pub fn remove_duplicate_types(module: &mut Module) {
         rewrite_inst_with_rules(inst, &rewrite_rules);
     }
 
+    remove_duplicate_annotations(module);
+    remove_duplicate_names(module);
+}
+
+fn remove_duplicate_annotations(module: &mut Module) {
     // The same decorations for duplicated types will cause those different types to merge
     // together. So, we need to deduplicate the annotations as well. (Note we *do* care about the
     // ID of the type being applied to here, unlike `gather_annotations`)
@@ -266,9 +369,19 @@ pub fn remove_duplicate_types(module: &mut Module) {
     module
         .annotations
         .retain(|inst| anno_set.insert(inst.assemble()));
-    // Same thing with OpName
+}
+
+fn remove_duplicate_names(module: &mut Module) {
+    // Merged types also end up with multiple OpNames (and OpMemberNames), of which only the
+    // first one for each ID (or member) is kept, even if the merged types had different names.
     let mut name_ids = HashSet::new();
-    module.debugs.retain(|inst| {
-        inst.class.opcode != Op::Name || name_ids.insert(inst.operands[0].unwrap_id_ref())
+    let mut member_name_ids = HashSet::new();
+    module.debugs.retain(|inst| match inst.class.opcode {
+        Op::Name => name_ids.insert(inst.operands[0].unwrap_id_ref()),
+        Op::MemberName => member_name_ids.insert((
+            inst.operands[0].unwrap_id_ref(),
+            inst.operands[1].unwrap_literal_int32(),
+        )),
+        _ => true,
     });
 }
@@ -0,0 +1,143 @@
+//! Narrowing of 64-bit floats to 32-bit ones, for targets lacking the `Float64` capability.
+//!
+//! Unlike 64-bit integers (see `int_lowering`), there's no reasonable way to emulate `f64`, so
+//! when the `narrow-float64` target feature is enabled, `f64` is simply computed with `f32`
+//! precision instead. Only reinterpreting its bits can't be supported this way, nor can `f64`s
+//! in memory whose layout is observable outside of the shader (e.g. storage buffers), which the
+//! host would have written (or would read) as actual 64-bit floats.
+
+use super::small_int_promotion::is_external;
+use super::{duplicates, Result};
+use rspirv::dr::{Instruction, Module, Operand};
+use rspirv::spirv::{Op, Word};
+use rustc_errors::ErrorReported;
+use rustc_session::Session;
+use std::collections::{HashMap, HashSet};
+use std::mem::take;
+
+pub fn narrow_float64(sess: &Session, module: &mut Module) -> Result<()> {
+    let mut float_widths = HashMap::new();
+    // Types which are, or (in their layout) contain, 64-bit floats.
+    let mut contains_f64 = HashSet::new();
+    let mut has_err = false;
+    for inst in &module.types_global_values {
+        let result_id = match inst.result_id {
+            Some(result_id) => result_id,
+            None => continue,
+        };
+        match inst.class.opcode {
+            Op::TypeFloat => {
+                let width = inst.operands[0].unwrap_literal_int32();
+                float_widths.insert(result_id, width);
+                if width == 64 {
+                    contains_f64.insert(result_id);
+                }
+            }
+            Op::TypeVector
+            | Op::TypeMatrix
+            | Op::TypeArray
+            | Op::TypeRuntimeArray
+            | Op::TypeStruct => {
+                let contains = inst.operands.iter().any(|operand| {
+                    operand
+                        .id_ref_any()
+                        .map_or(false, |id| contains_f64.contains(&id))
+                });
+                if contains {
+                    contains_f64.insert(result_id);
+                }
+            }
+            Op::TypePointer => {
+                let storage_class = inst.operands[0].unwrap_storage_class();
+                if is_external(storage_class)
+                    && contains_f64.contains(&inst.operands[1].unwrap_id_ref())
+                {
+                    sess.err(&format!(
+                        "64-bit floats in `{:?}` storage cannot be narrowed to 32 bits, as their \
+                         layout is observable outside of the shader",
+                        storage_class
+                    ));
+                    has_err = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    if has_err {
+        return Err(ErrorReported);
+    }
+
+    for inst in &mut module.types_global_values {
+        match inst.class.opcode {
+            Op::TypeFloat => {
+                if inst.operands[0].unwrap_literal_int32() == 64 {
+                    inst.operands[0] = Operand::LiteralInt32(32);
+                }
+            }
+            Op::Constant => {
+                if let Operand::LiteralFloat64(value) = inst.operands[0] {
+                    inst.operands[0] = Operand::LiteralFloat32(value as f32);
+                }
+            }
+            _ => {}
+        }
+    }
+    let f64s = float_widths
+        .iter()
+        .filter(|&(_, &width)| width == 64)
+        .map(|(&ty, _)| ty)
+        .collect::<HashSet<Word>>();
+    if f64s.is_empty() {
+        return Ok(());
+    }
+
+    let value_types = module
+        .all_inst_iter()
+        .filter_map(|inst| Some((inst.result_id?, inst.result_type?)))
+        .collect::<HashMap<_, _>>();
+    let narrowed_width = |ty: Word| match float_widths.get(&ty) {
+        Some(64) => Some(32),
+        width => width.copied(),
+    };
+
+    for inst in module
+        .functions
+        .iter_mut()
+        .flat_map(|func| &mut func.blocks)
+        .flat_map(|block| &mut block.instructions)
+    {
+        let operand_type = inst
+            .operands
+            .first()
+            .and_then(|operand| operand.id_ref_any())
+            .and_then(|operand| value_types.get(&operand).copied());
+        match inst.class.opcode {
+            Op::FConvert => {
+                let from = operand_type.and_then(narrowed_width);
+                if from.is_some() && from == inst.result_type.and_then(narrowed_width) {
+                    *inst = Instruction::new(
+                        Op::CopyObject,
+                        inst.result_type,
+                        inst.result_id,
+                        take(&mut inst.operands),
+                    );
+                }
+            }
+            Op::Bitcast => {
+                let involves_f64 = |ty: Option<Word>| ty.map_or(false, |ty| f64s.contains(&ty));
+                if involves_f64(inst.result_type) || involves_f64(operand_type) {
+                    sess.err("cannot reinterpret the bits of a 64-bit float narrowed to 32 bits");
+                    has_err = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    if has_err {
+        return Err(ErrorReported);
+    }
+
+    // `f64` is now a duplicate of `f32` (as are any types or constants built from both).
+    duplicates::remove_duplicate_types(module);
+    Ok(())
+}
@@ -1,14 +1,20 @@
 use super::Result;
-use rspirv::dr::{Instruction, Module};
+use rspirv::dr::{Instruction, Module, Operand};
 use rspirv::spirv::{Capability, Decoration, LinkageType, Op, Word};
 use rustc_errors::ErrorReported;
 use rustc_session::Session;
 use std::collections::{HashMap, HashSet};
 
-pub fn run(sess: &Session, module: &mut Module) -> Result<()> {
+/// Links imports to their exports, removing all linkage information, except for the exports in
+/// `library_exports` (along with the `Linkage` capability), when producing a library module.
+pub fn run(
+    sess: &Session,
+    module: &mut Module,
+    library_exports: Option<&HashSet<String>>,
+) -> Result<()> {
     let (rewrite_rules, killed_parameters) =
         find_import_export_pairs_and_killed_params(sess, module)?;
-    kill_linkage_instructions(module, &rewrite_rules);
+    kill_linkage_instructions(module, &rewrite_rules, library_exports);
     import_kill_annotations_and_debug(module, &rewrite_rules, &killed_parameters);
     replace_all_uses_with(module, &rewrite_rules);
     Ok(())
@@ -56,7 +62,7 @@ fn find_import_export_pairs_and_killed_params(
         };
         let import_type = *type_map.get(&import_id).expect("Unexpected op");
         // Make sure the import/export pair has the same type.
-        check_tys_equal(sess, name, import_type, export_type)?;
+        check_tys_equal(sess, module, name, import_type, export_type)?;
         rewrite_rules.insert(import_id, export_id);
         if let Some(params) = fn_parameters.get(&import_id) {
             for &param in params {
@@ -108,15 +114,115 @@ fn fn_parameters(module: &Module) -> HashMap<Word, Vec<Word>> {
         .collect()
 }
 
-fn check_tys_equal(sess: &Session, name: &str, import_type: Word, export_type: Word) -> Result<()> {
+fn check_tys_equal(
+    sess: &Session,
+    module: &Module,
+    name: &str,
+    import_type: Word,
+    export_type: Word,
+) -> Result<()> {
     if import_type == export_type {
         Ok(())
     } else {
-        sess.err(&format!("Types mismatch for {:?}", name));
+        let cx = TypeFormatter::new(module);
+        sess.err(&format!(
+            "Types mismatch for {:?}: imported as `{}`, exported as `{}`",
+            name,
+            cx.format(import_type),
+            cx.format(export_type)
+        ));
         Err(ErrorReported)
     }
 }
 
+/// Formats SPIR-V types in a Rust-like syntax, for error messages (e.g. about mismatched signatures between
+/// Rust `extern` declarations and the functions exported by an external SPIR-V library).
+struct TypeFormatter<'a> {
+    defs: HashMap<Word, &'a Instruction>,
+    names: HashMap<Word, &'a str>,
+}
+
+impl<'a> TypeFormatter<'a> {
+    fn new(module: &'a Module) -> Self {
+        let defs = module
+            .types_global_values
+            .iter()
+            .filter_map(|inst| Some((inst.result_id?, inst)))
+            .collect();
+        let names = module
+            .debugs
+            .iter()
+            .filter(|inst| inst.class.opcode == Op::Name)
+            .map(|inst| {
+                (
+                    inst.operands[0].unwrap_id_ref(),
+                    inst.operands[1].unwrap_literal_string(),
+                )
+            })
+            .collect();
+        Self { defs, names }
+    }
+
+    fn format(&self, id: Word) -> String {
+        let inst = match self.defs.get(&id) {
+            Some(inst) => inst,
+            None => return format!("%{}", id),
+        };
+        let ty = |operand: &Operand| self.format(operand.unwrap_id_ref());
+        let ops = &inst.operands;
+        match inst.class.opcode {
+            Op::TypeVoid => "()".to_string(),
+            Op::TypeBool => "bool".to_string(),
+            Op::TypeInt => {
+                let signed = ops[1].unwrap_literal_int32() != 0;
+                format!(
+                    "{}{}",
+                    if signed { "i" } else { "u" },
+                    ops[0].unwrap_literal_int32()
+                )
+            }
+            Op::TypeFloat => format!("f{}", ops[0].unwrap_literal_int32()),
+            Op::TypeVector | Op::TypeMatrix => format!(
+                "{}<{}, {}>",
+                if inst.class.opcode == Op::TypeVector {
+                    "vector"
+                } else {
+                    "matrix"
+                },
+                ty(&ops[0]),
+                ops[1].unwrap_literal_int32()
+            ),
+            Op::TypeArray => {
+                let len = ops[1].unwrap_id_ref();
+                let len = match self.defs.get(&len) {
+                    Some(inst) if inst.class.opcode == Op::Constant => match inst.operands[0] {
+                        Operand::LiteralInt32(len) => len.to_string(),
+                        Operand::LiteralInt64(len) => len.to_string(),
+                        _ => format!("%{}", len),
+                    },
+                    _ => format!("%{}", len),
+                };
+                format!("[{}; {}]", ty(&ops[0]), len)
+            }
+            Op::TypeRuntimeArray => format!("[{}]", ty(&ops[0])),
+            Op::TypeStruct => match self.names.get(&id) {
+                Some(name) => name.to_string(),
+                None => format!(
+                    "struct {{ {} }}",
+                    ops.iter().map(ty).collect::<Vec<_>>().join(", ")
+                ),
+            },
+            Op::TypePointer => format!("*{:?} {}", ops[0].unwrap_storage_class(), ty(&ops[1])),
+            Op::TypeFunction => format!(
+                "fn({}) -> {}",
+                ops[1..].iter().map(ty).collect::<Vec<_>>().join(", "),
+                ty(&ops[0])
+            ),
+            _ => format!("{:?} %{}", inst.class.opcode, id),
+        }
+    }
+}
+
 fn replace_all_uses_with(module: &mut Module, rules: &HashMap<u32, u32>) {
     module.all_inst_iter_mut().for_each(|inst| {
         if let Some(ref mut result_type) = &mut inst.result_type {
@@ -135,7 +241,11 @@ fn replace_all_uses_with(module: &mut Module, rules: &HashMap<u32, u32>) {
     });
 }
 
-fn kill_linkage_instructions(module: &mut Module, rewrite_rules: &HashMap<u32, u32>) {
+fn kill_linkage_instructions(
+    module: &mut Module,
+    rewrite_rules: &HashMap<u32, u32>,
+    library_exports: Option<&HashSet<String>>,
+) {
     // drop imported functions
     module
         .functions
@@ -147,16 +257,23 @@ fn kill_linkage_instructions(module: &mut Module, rewrite_rules: &HashMap<u32, u
             .map_or(true, |v| !rewrite_rules.contains_key(&v))
     });
 
-    module.annotations.retain(|inst| {
-        inst.class.opcode != Op::Decorate
-            || inst.operands[1].unwrap_decoration() != Decoration::LinkageAttributes
-    });
+    module
+        .annotations
+        .retain(|inst| match get_linkage_inst(inst) {
+            Some((_, name, LinkageType::Export)) => {
+                library_exports.map_or(false, |exports| exports.contains(name))
+            }
+            Some(_) => false,
+            None => true,
+        });
 
-    // drop OpCapability Linkage
-    module.capabilities.retain(|inst| {
-        inst.class.opcode != Op::Capability
-            || inst.operands[0].unwrap_capability() != Capability::Linkage
-    })
+    // drop OpCapability Linkage, unless it's still needed by a library module
+    if library_exports.is_none() {
+        module.capabilities.retain(|inst| {
+            inst.class.opcode != Op::Capability
+                || inst.operands[0].unwrap_capability() != Capability::Linkage
+        })
+    }
 }
 
 fn import_kill_annotations_and_debug(
@@ -176,14 +293,5 @@ fn import_kill_annotations_and_debug(
                 !rewrite_rules.contains_key(&id) && !killed_parameters.contains(&id)
             })
     });
-    // need to remove OpGroupDecorate members that mention this id
-    for inst in &mut module.annotations {
-        if inst.class.opcode == Op::GroupDecorate {
-            inst.operands.retain(|op| {
-                op.id_ref_any().map_or(true, |id| {
-                    !rewrite_rules.contains_key(&id) && !killed_parameters.contains(&id)
-                })
-            })
-        }
-    }
+    // NOTE: there are no `OpGroupDecorate`s to update, `flatten_decoration_groups` removed them.
 }
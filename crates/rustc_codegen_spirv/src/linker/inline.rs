@@ -1,25 +1,47 @@
-//! This algorithm is not intended to be an optimization, it is rather for legalization.
-//! Specifically, spir-v disallows things like a `StorageClass::Function` pointer to a
+//! Inlining of function calls, both for legalization, and (optionally) as an optimization.
+//!
+//! Legalization: spir-v disallows things like a `StorageClass::Function` pointer to a
 //! `StorageClass::Input` pointer. Our frontend definitely allows it, though, this is like taking a
 //! `&Input<T>` in a function! So, we inline all functions that take these "illegal" pointers, then
 //! run mem2reg (see mem2reg.rs) on the result to "unwrap" the Function pointer.
+//!
+//! Optimization: functions are also inlined based on their `FunctionControl` (i.e. `#[inline]`,
+//! `#[inline(always)]` and `#[inline(never)]`, see `attrs_to_spirv`), and otherwise on their size
+//! (and on being called only once), see `should_inline`.
 
 use super::apply_rewrite_rules;
-use super::simple_passes::outgoing_edges;
+use super::simple_passes::{self, outgoing_edges};
+use crate::debuginfo::{self, DebugInfoOp};
 use rspirv::dr::{Block, Function, Instruction, Module, ModuleHeader, Operand};
-use rspirv::spirv::{FunctionControl, Op, StorageClass, Word};
+use rspirv::spirv::{Decoration, FunctionControl, Op, StorageClass, Word};
 use std::collections::{HashMap, HashSet};
+use std::iter;
 use std::mem::replace;
 
 type FunctionMap = HashMap<Word, Function>;
 
-pub fn inline(module: &mut Module) {
+/// `threshold` is the maximum size (in instructions) of functions to inline without them being
+/// marked `FunctionControl::INLINE`, with `0` disabling all inlining that isn't either requested
+/// or needed for legalization.
+pub fn inline(module: &mut Module, threshold: usize) {
+    let disallowed_argument_types = compute_disallowed_argument_types(module);
+    let heuristics = if threshold > 0 {
+        Some(InlineHeuristics::new(module, threshold))
+    } else {
+        None
+    };
+    inline_functions(module, |f| {
+        should_inline(&disallowed_argument_types, heuristics.as_ref(), f)
+    });
+}
+
+/// Inline all calls to the functions `should_inline` returns `true` for, and remove them.
+pub fn inline_functions(module: &mut Module, should_inline: impl Fn(&Function) -> bool) {
     let functions = module
         .functions
         .iter()
         .map(|f| (f.def_id().unwrap(), f.clone()))
         .collect();
-    let disallowed_argument_types = compute_disallowed_argument_types(module);
     let void = module
         .types_global_values
         .iter()
@@ -30,7 +52,7 @@ pub fn inline(module: &mut Module) {
     // inlines in functions that will get inlined)
     let mut dropped_ids = HashSet::new();
     module.functions.retain(|f| {
-        if should_inline(&disallowed_argument_types, f) {
+        if should_inline(f) {
             // TODO: We should insert all defined IDs in this function.
             dropped_ids.insert(f.def_id().unwrap());
             false
@@ -45,12 +67,14 @@ pub fn inline(module: &mut Module) {
                 .map_or(false, |id| dropped_ids.contains(&id))
         })
     });
+    let debug_info_import = debuginfo::find_import(module);
     let mut inliner = Inliner {
         header: &mut module.header.as_mut().unwrap(),
         types_global_values: &mut module.types_global_values,
         void,
+        debug_info_import,
         functions: &functions,
-        disallowed_argument_types: &disallowed_argument_types,
+        inlined_functions: &dropped_ids,
     };
     for function in &mut module.functions {
         inliner.inline_fn(function);
@@ -115,14 +139,100 @@ fn compute_disallowed_argument_types(module: &Module) -> HashSet<Word> {
     disallowed_argument_types
 }
 
-fn should_inline(disallowed_argument_types: &HashSet<Word>, function: &Function) -> bool {
+/// What's needed to decide whether to inline functions not marked `FunctionControl::INLINE`.
+struct InlineHeuristics {
+    threshold: usize,
+    debug_info_import: Option<Word>,
+    /// Functions which can't be removed, even if all their calls are inlined (i.e. entry points
+    /// and exports).
+    roots: HashSet<Word>,
+    call_counts: HashMap<Word, usize>,
+}
+
+impl InlineHeuristics {
+    fn new(module: &Module, threshold: usize) -> Self {
+        let roots = module
+            .entry_points
+            .iter()
+            .map(|inst| inst.operands[1].unwrap_id_ref())
+            .chain(module.annotations.iter().filter_map(|inst| {
+                if inst.class.opcode == Op::Decorate
+                    && inst.operands[1].unwrap_decoration() == Decoration::LinkageAttributes
+                {
+                    Some(inst.operands[0].unwrap_id_ref())
+                } else {
+                    None
+                }
+            }))
+            .collect();
+        let mut call_counts = HashMap::new();
+        for inst in module.all_inst_iter() {
+            if inst.class.opcode == Op::FunctionCall {
+                *call_counts
+                    .entry(inst.operands[0].unwrap_id_ref())
+                    .or_insert(0) += 1;
+            }
+        }
+        Self {
+            threshold,
+            debug_info_import: debuginfo::find_import(module),
+            roots,
+            call_counts,
+        }
+    }
+
+    /// The size of `function`, ignoring debuginfo (which doesn't end up in the machine code).
+    fn cost(&self, function: &Function) -> usize {
+        function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter(|inst| match inst.class.opcode {
+                Op::Line | Op::NoLine => false,
+                Op::ExtInst => Some(inst.operands[0].unwrap_id_ref()) != self.debug_info_import,
+                _ => true,
+            })
+            .count()
+    }
+
+    fn should_inline(&self, function: &Function) -> bool {
+        let id = function.def_id().unwrap();
+        if self.roots.contains(&id) {
+            return false;
+        }
+        // Only called functions can be inlined, and inlining the only call of a function never
+        // duplicates its body, no matter how large it is.
+        match self.call_counts.get(&id).copied().unwrap_or(0) {
+            0 => false,
+            1 => true,
+            _ => self.cost(function) <= self.threshold,
+        }
+    }
+}
+
+fn should_inline(
+    disallowed_argument_types: &HashSet<Word>,
+    heuristics: Option<&InlineHeuristics>,
+    function: &Function,
+) -> bool {
+    // NOTE: legalization takes precedence over `#[inline(never)]`, as the function would be
+    // rejected by the validator otherwise.
+    if function
+        .parameters
+        .iter()
+        .any(|inst| disallowed_argument_types.contains(inst.result_type.as_ref().unwrap()))
+    {
+        return true;
+    }
     let def = function.def.as_ref().unwrap();
     let control = def.operands[0].unwrap_function_control();
-    control.contains(FunctionControl::INLINE)
-        || function
-            .parameters
-            .iter()
-            .any(|inst| disallowed_argument_types.contains(inst.result_type.as_ref().unwrap()))
+    if control.contains(FunctionControl::DONT_INLINE) {
+        false
+    } else if control.contains(FunctionControl::INLINE) {
+        true
+    } else {
+        heuristics.map_or(false, |heuristics| heuristics.should_inline(function))
+    }
 }
 
 // Steps:
@@ -135,8 +245,9 @@ struct Inliner<'m, 'map> {
     header: &'m mut ModuleHeader,
     types_global_values: &'m mut Vec<Instruction>,
     void: Word,
+    debug_info_import: Option<Word>,
     functions: &'map FunctionMap,
-    disallowed_argument_types: &'map HashSet<Word>,
+    inlined_functions: &'map HashSet<Word>,
     // rewrite_rules: HashMap<Word, Word>,
 }
 
@@ -170,6 +281,43 @@ impl Inliner<'_, '_> {
         inst_id
     }
 
+    fn u32_constant(&mut self, value: u32) -> Word {
+        let u32_type = simple_passes::u32_type(self.header, self.types_global_values);
+        simple_passes::u32_constant(self.header, self.types_global_values, u32_type, value)
+    }
+
+    /// The debug location a call was made from, i.e. the last `DebugScope` and `OpLine` before
+    /// the call, as a `DebugInlinedAt` (to attribute the inlined callee's scopes to), and the
+    /// `DebugScope` itself (to restore after the inlined callee).
+    fn debug_call_site(&mut self, caller_insts: &[Instruction]) -> Option<(Word, Instruction)> {
+        let import = self.debug_info_import?;
+        let scope = caller_insts
+            .iter()
+            .rev()
+            .find(|inst| debuginfo::is_debug_inst(inst, Some(import), DebugInfoOp::DebugScope))?
+            .clone();
+        let line = caller_insts
+            .iter()
+            .rev()
+            .find(|inst| inst.class.opcode == Op::Line)
+            .map_or(0, |inst| inst.operands[1].unwrap_literal_int32());
+        let line = self.u32_constant(line);
+        // `DebugScope`'s operands are `Scope [InlinedAt]`, which is also how `DebugInlinedAt`
+        // continues after `Line`, so an already inlined call site turns into a chain.
+        let operands = iter::once(line)
+            .chain(scope.operands[2..].iter().map(|op| op.unwrap_id_ref()))
+            .collect::<Vec<_>>();
+        let inlined_at = self.id();
+        self.types_global_values.push(debuginfo::debug_inst(
+            import,
+            self.void,
+            inlined_at,
+            DebugInfoOp::DebugInlinedAt,
+            operands,
+        ));
+        Some((inlined_at, scope))
+    }
+
     fn inline_fn(&mut self, function: &mut Function) {
         let mut block_idx = 0;
         while block_idx < function.blocks.len() {
@@ -198,7 +346,7 @@ impl Inliner<'_, '_> {
                         .unwrap(),
                 )
             })
-            .find(|(_, _, f)| should_inline(self.disallowed_argument_types, f));
+            .find(|(_, _, f)| self.inlined_functions.contains(&f.def_id().unwrap()));
         let (call_index, call_inst, callee) = match call {
             None => return false,
             Some(call) => call,
@@ -230,8 +378,13 @@ impl Inliner<'_, '_> {
             None
         };
         let return_jump = self.id();
+        let call_site = self.debug_call_site(&caller.blocks[block_idx].instructions[..call_index]);
         // Rewrite OpReturns of the callee.
         let mut inlined_blocks = get_inlined_blocks(callee, return_variable, return_jump);
+        if let Some(import) = self.debug_info_import {
+            let inlined_at = call_site.as_ref().map(|&(inlined_at, _)| inlined_at);
+            attach_debug_info_to_inlined_blocks(&mut inlined_blocks, import, inlined_at);
+        }
         // Clone the IDs of the callee, because otherwise they'd be defined multiple times if the
         // fn is inlined multiple times.
         self.add_clone_id_rules(&mut rewrite_rules, &inlined_blocks);
@@ -257,17 +410,23 @@ impl Inliner<'_, '_> {
 
         // Fuse the first block of the callee into the block of the caller. This is okay because
         // it's illegal to branch to the first BB in a function.
-        let mut callee_header = inlined_blocks.remove(0).instructions;
-        // TODO: OpLine handling
-        let num_variables = callee_header
-            .iter()
-            .position(|inst| inst.class.opcode != Op::Variable)
-            .unwrap_or(callee_header.len());
+        // Move the OpVariables of the callee to the caller, and append everything else (including
+        // any OpLines or debug instructions mixed in with them) to the caller's block.
+        let (callee_variables, mut callee_header): (Vec<_>, Vec<_>) = inlined_blocks
+            .remove(0)
+            .instructions
+            .into_iter()
+            .partition(|inst| inst.class.opcode == Op::Variable);
         caller.blocks[block_idx]
             .instructions
-            .append(&mut callee_header.split_off(num_variables));
-        // Move the OpVariables of the callee to the caller.
-        insert_opvariables(&mut caller.blocks[0], callee_header);
+            .append(&mut callee_header);
+        insert_opvariables(&mut caller.blocks[0], callee_variables);
+
+        // The callee's debug location is still active at this point, so restore the caller's.
+        if let Some((_, mut scope)) = call_site {
+            scope.result_id = Some(self.id());
+            post_call_block_insts.insert(0, scope);
+        }
 
         if let Some(call_result_type) = call_result_type {
             // Add the load of the result value after the inlined function. Note there's guaranteed no
@@ -344,6 +503,31 @@ fn get_inlined_blocks(
     blocks
 }
 
+/// Drops the `DebugFunctionDefinition` of the callee (it doesn't own these blocks anymore), and
+/// marks its `DebugScope`s as inlined at the call site.
+fn attach_debug_info_to_inlined_blocks(
+    blocks: &mut [Block],
+    import: Word,
+    inlined_at: Option<Word>,
+) {
+    for block in blocks {
+        block.instructions.retain(|inst| {
+            !debuginfo::is_debug_inst(inst, Some(import), DebugInfoOp::DebugFunctionDefinition)
+        });
+        if let Some(inlined_at) = inlined_at {
+            for inst in &mut block.instructions {
+                // `DebugScope`s with an `InlinedAt` operand already were inlined, and are
+                // attributed to the call site through it.
+                if debuginfo::is_debug_inst(inst, Some(import), DebugInfoOp::DebugScope)
+                    && inst.operands.len() == 3
+                {
+                    inst.operands.push(Operand::IdRef(inlined_at));
+                }
+            }
+        }
+    }
+}
+
 fn insert_opvariable(block: &mut Block, ptr_ty: Word, result_id: Word) {
     let index = block
         .instructions
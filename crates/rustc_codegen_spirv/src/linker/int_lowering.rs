@@ -0,0 +1,1164 @@
+//! Lowering of integer types wider than SPIR-V (or the target) supports.
+//!
+//! Codegen emits `u128`/`i128` as `OpTypeInt 128` (with constants as `OpConstantComposite`s of
+//! their 32-bit words, least significant first), which isn't valid SPIR-V. Likewise, when the
+//! `emulate-int64` target feature is enabled, 64-bit integers are emitted as usual, but mustn't
+//! end up in the output, as the target lacks the `Int64` capability. This pass turns every such
+//! type into a vector of 32-bit words, and every instruction computing on one into the equivalent
+//! sequence of 32-bit operations. Division and remainder need a loop, so they're expanded into
+//! calls to a generated long division function instead.
+
+use super::{apply_rewrite_rules, duplicates, id, simple_passes, Result};
+use crate::debuginfo;
+use rspirv::binary::Assemble;
+use rspirv::dr::{Block, Function, Instruction, Module, ModuleHeader, Operand};
+use rspirv::spirv::{FunctionControl, LoopControl, Op, Word};
+use rustc_errors::ErrorReported;
+use rustc_session::Session;
+use std::collections::HashMap;
+use std::mem::take;
+
+/// Bit width of the words that wide integers are split into.
+const WORD_BITS: u32 = 32;
+
+pub fn lower_wide_integers(sess: &Session, module: &mut Module, emulate_int64: bool) -> Result<()> {
+    // Map from each wide integer type to the number of words it's made of.
+    let mut wide_types = HashMap::new();
+    // Map from each native integer or float type to its width, used by conversions.
+    let mut int_widths = HashMap::new();
+    let mut float_widths = HashMap::new();
+    for inst in &module.types_global_values {
+        match inst.class.opcode {
+            Op::TypeInt => {
+                let width = inst.operands[0].unwrap_literal_int32();
+                if width > 64 || (emulate_int64 && width == 64) {
+                    wide_types.insert(inst.result_id.unwrap(), width / WORD_BITS);
+                } else {
+                    int_widths.insert(inst.result_id.unwrap(), width);
+                }
+            }
+            Op::TypeFloat => {
+                let width = inst.operands[0].unwrap_literal_int32();
+                float_widths.insert(inst.result_id.unwrap(), width);
+            }
+            _ => {}
+        }
+    }
+    if wide_types.is_empty() {
+        return Ok(());
+    }
+
+    let value_types = module
+        .types_global_values
+        .iter()
+        .chain(module.functions.iter().flat_map(|func| {
+            func.parameters
+                .iter()
+                .chain(func.blocks.iter().flat_map(|block| &block.instructions))
+        }))
+        .filter_map(|inst| Some((inst.result_id?, inst.result_type?)))
+        .collect::<HashMap<_, _>>();
+
+    let debug_info_import = debuginfo::find_import(module);
+    let mut functions = take(&mut module.functions);
+    let mut cx = LoweringCx::new(
+        sess,
+        module.header.as_mut().unwrap(),
+        &mut module.types_global_values,
+        wide_types,
+        int_widths,
+        float_widths,
+        debug_info_import,
+    );
+    for func in &mut functions {
+        cx.lower_function(func, &value_types);
+    }
+    let u32_constants = take(&mut cx.u32_constants);
+    let insert_point = cx.constants_insert_point;
+    cx.types_global_values
+        .splice(insert_point..insert_point, u32_constants);
+    functions.append(&mut cx.new_functions);
+    module.debugs.append(&mut cx.new_names);
+    let has_err = cx.has_err;
+    module.functions = functions;
+
+    if has_err {
+        return Err(ErrorReported);
+    }
+
+    // Signed and unsigned wide integers (and anything built from them, like pointers) now lower
+    // to the same types, which SPIR-V doesn't allow to be declared twice.
+    duplicates::remove_duplicate_types(module);
+    Ok(())
+}
+
+struct LoweringCx<'a> {
+    sess: &'a Session,
+    header: &'a mut ModuleHeader,
+    types_global_values: &'a mut Vec<Instruction>,
+    /// New constants are inserted at this index in `types_global_values`, right after all the
+    /// scalar types (which are moved to the very start), so they're defined before any use.
+    constants_insert_point: usize,
+
+    wide_types: HashMap<Word, u32>,
+    int_widths: HashMap<Word, u32>,
+    float_widths: HashMap<Word, u32>,
+    /// One of the (now vector) wide types for each word count, used by generated code.
+    vector_types: HashMap<u32, Word>,
+    u32: Word,
+    bool: Word,
+    debug_info_import: Option<Word>,
+
+    /// `u32` constants created by this pass, inserted at `constants_insert_point` at the end.
+    u32_constants: Vec<Instruction>,
+    float_constants: HashMap<(Word, u64), Word>,
+    null_constants: HashMap<Word, Word>,
+    /// Types created by this pass, keyed by their opcode and operands.
+    new_types: HashMap<Vec<u32>, Word>,
+    /// Long division function for each word count (see `udivmod`).
+    udivmod_functions: HashMap<u32, Word>,
+    new_functions: Vec<Function>,
+    new_names: Vec<Instruction>,
+
+    has_err: bool,
+}
+
+impl<'a> LoweringCx<'a> {
+    fn new(
+        sess: &'a Session,
+        header: &'a mut ModuleHeader,
+        types_global_values: &'a mut Vec<Instruction>,
+        wide_types: HashMap<Word, u32>,
+        int_widths: HashMap<Word, u32>,
+        float_widths: HashMap<Word, u32>,
+        debug_info_import: Option<Word>,
+    ) -> Self {
+        // Scalar types have no dependencies, so they can all be moved to the very start, where
+        // everything created by this pass can refer to them.
+        let (mut scalar_types, other_types): (Vec<_>, Vec<_>) =
+            take(types_global_values).into_iter().partition(|inst| {
+                matches!(
+                    inst.class.opcode,
+                    Op::TypeInt | Op::TypeFloat | Op::TypeBool
+                ) && !wide_types.contains_key(&inst.result_id.unwrap())
+            });
+        let mut find_or_add_scalar_type = |opcode: Op, operands: Vec<Operand>| {
+            let existing = scalar_types
+                .iter()
+                .find(|inst| inst.class.opcode == opcode && inst.operands == operands);
+            match existing {
+                Some(existing) => existing.result_id.unwrap(),
+                None => {
+                    let ty = id(header);
+                    scalar_types.push(Instruction::new(opcode, None, Some(ty), operands));
+                    ty
+                }
+            }
+        };
+        let u32 = find_or_add_scalar_type(
+            Op::TypeInt,
+            vec![Operand::LiteralInt32(32), Operand::LiteralInt32(0)],
+        );
+        let bool = find_or_add_scalar_type(Op::TypeBool, vec![]);
+        let constants_insert_point = scalar_types.len();
+        *types_global_values = scalar_types;
+        types_global_values.extend(other_types);
+
+        let mut cx = Self {
+            sess,
+            header,
+            types_global_values,
+            constants_insert_point,
+            wide_types,
+            int_widths,
+            float_widths,
+            vector_types: HashMap::new(),
+            u32,
+            bool,
+            debug_info_import,
+            u32_constants: Vec::new(),
+            float_constants: HashMap::new(),
+            null_constants: HashMap::new(),
+            new_types: HashMap::new(),
+            udivmod_functions: HashMap::new(),
+            new_functions: Vec::new(),
+            new_names: Vec::new(),
+            has_err: false,
+        };
+
+        let mut index = cx.constants_insert_point;
+        while index < cx.types_global_values.len() {
+            let inst = &mut cx.types_global_values[index];
+            let count = match (inst.class.opcode, inst.result_id, inst.result_type) {
+                (Op::TypeVector, _, _) => cx
+                    .wide_types
+                    .get(&inst.operands[0].unwrap_id_ref())
+                    .copied(),
+                (Op::TypeInt, Some(ty), _) | (_, _, Some(ty)) => cx.wide_types.get(&ty).copied(),
+                _ => None,
+            };
+            let count = match count {
+                Some(count) => count,
+                None => {
+                    index += 1;
+                    continue;
+                }
+            };
+            match inst.class.opcode {
+                Op::TypeInt => {
+                    *inst = Instruction::new(
+                        Op::TypeVector,
+                        None,
+                        inst.result_id,
+                        vec![Operand::IdRef(u32), Operand::LiteralInt32(count)],
+                    );
+                    cx.vector_types
+                        .entry(count)
+                        .or_insert_with(|| inst.result_id.unwrap());
+                }
+                Op::Constant => {
+                    let mut literal = Vec::new();
+                    for operand in &inst.operands {
+                        operand.assemble_into(&mut literal);
+                    }
+                    let words = literal
+                        .into_iter()
+                        .map(|word| Operand::IdRef(cx.u32_constant(word)))
+                        .collect();
+                    let inst = &mut cx.types_global_values[index];
+                    *inst = Instruction::new(
+                        Op::ConstantComposite,
+                        inst.result_type,
+                        inst.result_id,
+                        words,
+                    );
+                }
+                Op::ConstantComposite | Op::ConstantNull | Op::Undef | Op::Variable => {}
+                opcode => cx.unsupported(opcode, count),
+            }
+            index += 1;
+        }
+        cx
+    }
+
+    fn unsupported(&mut self, opcode: Op, count: u32) {
+        self.sess.err(&format!(
+            "unsupported operation on {}-bit integers: Op{:?}",
+            count * WORD_BITS,
+            opcode
+        ));
+        self.has_err = true;
+    }
+
+    fn u32_constant(&mut self, value: u32) -> Word {
+        simple_passes::u32_constant(self.header, &mut self.u32_constants, self.u32, value)
+    }
+
+    fn float_constant(&mut self, ty: Word, value: f64) -> Option<Word> {
+        let literal = match self.float_widths.get(&ty)? {
+            32 => Operand::LiteralFloat32(value as f32),
+            64 => Operand::LiteralFloat64(value),
+            _ => return None,
+        };
+        if let Some(&constant) = self.float_constants.get(&(ty, value.to_bits())) {
+            return Some(constant);
+        }
+        let constant = id(self.header);
+        self.types_global_values.insert(
+            self.constants_insert_point,
+            Instruction::new(Op::Constant, Some(ty), Some(constant), vec![literal]),
+        );
+        self.constants_insert_point += 1;
+        self.float_constants.insert((ty, value.to_bits()), constant);
+        Some(constant)
+    }
+
+    fn null_constant(&mut self, ty: Word) -> Word {
+        if let Some(&constant) = self.null_constants.get(&ty) {
+            return constant;
+        }
+        let constant = id(self.header);
+        self.types_global_values.push(Instruction::new(
+            Op::ConstantNull,
+            Some(ty),
+            Some(constant),
+            vec![],
+        ));
+        self.null_constants.insert(ty, constant);
+        constant
+    }
+
+    /// Types created here are appended to the end of `types_global_values`, so they may depend on
+    /// anything already declared (duplicates of existing types get removed afterwards).
+    fn def_type(&mut self, opcode: Op, operands: Vec<Operand>) -> Word {
+        let mut key = vec![opcode as u32];
+        for operand in &operands {
+            operand.assemble_into(&mut key);
+        }
+        if let Some(&ty) = self.new_types.get(&key) {
+            return ty;
+        }
+        let ty = id(self.header);
+        self.types_global_values
+            .push(Instruction::new(opcode, None, Some(ty), operands));
+        self.new_types.insert(key, ty);
+        ty
+    }
+
+    fn type_vector(&mut self, element: Word, count: u32) -> Word {
+        self.def_type(
+            Op::TypeVector,
+            vec![Operand::IdRef(element), Operand::LiteralInt32(count)],
+        )
+    }
+
+    fn type_struct(&mut self, members: &[Word]) -> Word {
+        self.def_type(
+            Op::TypeStruct,
+            members.iter().copied().map(Operand::IdRef).collect(),
+        )
+    }
+
+    /// The `{ u32, u32 }` result of `OpIAddCarry`, `OpISubBorrow` and `OpUMulExtended`.
+    fn type_word_pair(&mut self) -> Word {
+        let u32 = self.u32;
+        self.type_struct(&[u32, u32])
+    }
+
+    fn lower_function(&mut self, func: &mut Function, value_types: &HashMap<Word, Word>) {
+        let mut rewrite_rules = HashMap::new();
+        for block in &mut func.blocks {
+            let mut emitter = Emitter {
+                cx: self,
+                insts: Vec::with_capacity(block.instructions.len()),
+            };
+            for inst in take(&mut block.instructions) {
+                if let Some((original, lowered)) = emitter.lower_inst(inst, value_types) {
+                    rewrite_rules.insert(original, lowered);
+                }
+            }
+            block.instructions = emitter.insts;
+        }
+        apply_rewrite_rules(&rewrite_rules, &mut func.blocks);
+    }
+
+    /// Returns a function taking a numerator and a denominator, both wide unsigned integers of
+    /// `count` words, and returning a struct of their quotient and remainder. It implements plain
+    /// binary long division, one bit per loop iteration.
+    fn udivmod(&mut self, count: u32) -> Word {
+        if let Some(&function) = self.udivmod_functions.get(&count) {
+            return function;
+        }
+        let (u32, bool) = (self.u32, self.bool);
+        let vector = self.vector_types[&count];
+        let pair = self.type_struct(&[vector, vector]);
+        let function_type = self.def_type(
+            Op::TypeFunction,
+            vec![
+                Operand::IdRef(pair),
+                Operand::IdRef(vector),
+                Operand::IdRef(vector),
+            ],
+        );
+        let zero_vector = self.null_constant(vector);
+        let zero = self.u32_constant(0);
+        let one = self.u32_constant(1);
+        let bit_count = self.u32_constant(count * WORD_BITS);
+        let top_bit_shift = self.u32_constant(WORD_BITS - 1);
+
+        let function = id(self.header);
+        let [numerator, denominator] = [id(self.header), id(self.header)];
+        let [entry, header, body, continue_target, merge] = [
+            id(self.header),
+            id(self.header),
+            id(self.header),
+            id(self.header),
+            id(self.header),
+        ];
+        // Loop state: iteration, remaining numerator bits, partial remainder and quotient.
+        let [i, num, rem, quo] = [
+            id(self.header),
+            id(self.header),
+            id(self.header),
+            id(self.header),
+        ];
+
+        let mut emitter = Emitter::new(self);
+        let den_words = emitter.words(denominator, count);
+        emitter.emit(Op::Branch, None, vec![Operand::IdRef(header)]);
+        let entry_insts = emitter.insts;
+
+        let mut emitter = Emitter::new(self);
+        let num_words = emitter.words(num, count);
+        let rem_words = emitter.words(rem, count);
+        let quo_words = emitter.words(quo, count);
+        // Shift the top bit of the numerator into the remainder.
+        let top_bit = emitter.op2(
+            Op::ShiftRightLogical,
+            u32,
+            num_words[count as usize - 1],
+            top_bit_shift,
+        );
+        let mut rem_shifted = emitter.shl1(&rem_words);
+        rem_shifted[0] = emitter.op2(Op::BitwiseOr, u32, rem_shifted[0], top_bit);
+        let num_shifted = emitter.shl1(&num_words);
+        // Subtract the denominator if it fits, which sets the next quotient bit.
+        let rem_lt_den = emitter.less_than(&rem_shifted, &den_words, false);
+        let fits = emitter.op1(Op::LogicalNot, bool, rem_lt_den);
+        let rem_minus_den = emitter.add_or_sub(Op::ISub, &rem_shifted, &den_words);
+        let rem_next = emitter.select_words(fits, &rem_minus_den, &rem_shifted);
+        let mut quo_next = emitter.shl1(&quo_words);
+        let quo_bit = emitter.op3(Op::Select, u32, fits, one, zero);
+        quo_next[0] = emitter.op2(Op::BitwiseOr, u32, quo_next[0], quo_bit);
+        let num_next = emitter.construct(vector, &num_shifted);
+        let rem_next = emitter.construct(vector, &rem_next);
+        let quo_next = emitter.construct(vector, &quo_next);
+        emitter.emit(Op::Branch, None, vec![Operand::IdRef(continue_target)]);
+        let body_insts = emitter.insts;
+
+        let mut emitter = Emitter::new(self);
+        let i_next = emitter.op2(Op::IAdd, u32, i, one);
+        emitter.emit(Op::Branch, None, vec![Operand::IdRef(header)]);
+        let continue_insts = emitter.insts;
+
+        let mut emitter = Emitter::new(self);
+        for &(phi, ty, initial, next) in &[
+            (i, u32, zero, i_next),
+            (num, vector, numerator, num_next),
+            (rem, vector, zero_vector, rem_next),
+            (quo, vector, zero_vector, quo_next),
+        ] {
+            emitter.insts.push(Instruction::new(
+                Op::Phi,
+                Some(ty),
+                Some(phi),
+                vec![
+                    Operand::IdRef(initial),
+                    Operand::IdRef(entry),
+                    Operand::IdRef(next),
+                    Operand::IdRef(continue_target),
+                ],
+            ));
+        }
+        let cond = emitter.op2(Op::ULessThan, bool, i, bit_count);
+        emitter.emit(
+            Op::LoopMerge,
+            None,
+            vec![
+                Operand::IdRef(merge),
+                Operand::IdRef(continue_target),
+                Operand::LoopControl(LoopControl::NONE),
+            ],
+        );
+        emitter.emit(
+            Op::BranchConditional,
+            None,
+            vec![
+                Operand::IdRef(cond),
+                Operand::IdRef(body),
+                Operand::IdRef(merge),
+            ],
+        );
+        let header_insts = emitter.insts;
+
+        let mut emitter = Emitter::new(self);
+        let result = emitter.construct(pair, &[quo, rem]);
+        emitter.emit(Op::ReturnValue, None, vec![Operand::IdRef(result)]);
+        let merge_insts = emitter.insts;
+
+        let block = |label, instructions| Block {
+            label: Some(Instruction::new(Op::Label, None, Some(label), vec![])),
+            instructions,
+        };
+        let mut func = Function::new();
+        func.def = Some(Instruction::new(
+            Op::Function,
+            Some(pair),
+            Some(function),
+            vec![
+                Operand::FunctionControl(FunctionControl::NONE),
+                Operand::IdRef(function_type),
+            ],
+        ));
+        func.parameters = vec![
+            Instruction::new(Op::FunctionParameter, Some(vector), Some(numerator), vec![]),
+            Instruction::new(
+                Op::FunctionParameter,
+                Some(vector),
+                Some(denominator),
+                vec![],
+            ),
+        ];
+        func.blocks = vec![
+            block(entry, entry_insts),
+            block(header, header_insts),
+            block(body, body_insts),
+            block(continue_target, continue_insts),
+            block(merge, merge_insts),
+        ];
+        func.end = Some(Instruction::new(Op::FunctionEnd, None, None, vec![]));
+        self.new_functions.push(func);
+        self.new_names.push(Instruction::new(
+            Op::Name,
+            None,
+            None,
+            vec![
+                Operand::IdRef(function),
+                Operand::LiteralString(format!("udivmod_u{}", count * WORD_BITS)),
+            ],
+        ));
+        self.udivmod_functions.insert(count, function);
+        function
+    }
+}
+
+/// Accumulates the 32-bit instructions that a wide integer instruction is lowered to.
+struct Emitter<'a, 'b> {
+    cx: &'a mut LoweringCx<'b>,
+    insts: Vec<Instruction>,
+}
+
+impl<'a, 'b> Emitter<'a, 'b> {
+    fn new(cx: &'a mut LoweringCx<'b>) -> Self {
+        Self {
+            cx,
+            insts: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self, opcode: Op, ty: Option<Word>, operands: Vec<Operand>) -> Word {
+        let result = ty.map(|_| id(self.cx.header));
+        self.insts
+            .push(Instruction::new(opcode, ty, result, operands));
+        result.unwrap_or(0)
+    }
+
+    fn op1(&mut self, opcode: Op, ty: Word, a: Word) -> Word {
+        self.emit(opcode, Some(ty), vec![Operand::IdRef(a)])
+    }
+
+    fn op2(&mut self, opcode: Op, ty: Word, a: Word, b: Word) -> Word {
+        self.emit(opcode, Some(ty), vec![Operand::IdRef(a), Operand::IdRef(b)])
+    }
+
+    fn op3(&mut self, opcode: Op, ty: Word, a: Word, b: Word, c: Word) -> Word {
+        self.emit(
+            opcode,
+            Some(ty),
+            vec![Operand::IdRef(a), Operand::IdRef(b), Operand::IdRef(c)],
+        )
+    }
+
+    fn extract(&mut self, ty: Word, composite: Word, index: u32) -> Word {
+        self.emit(
+            Op::CompositeExtract,
+            Some(ty),
+            vec![Operand::IdRef(composite), Operand::LiteralInt32(index)],
+        )
+    }
+
+    fn construct(&mut self, ty: Word, parts: &[Word]) -> Word {
+        self.emit(
+            Op::CompositeConstruct,
+            Some(ty),
+            parts.iter().copied().map(Operand::IdRef).collect(),
+        )
+    }
+
+    fn words(&mut self, value: Word, count: u32) -> Vec<Word> {
+        let u32 = self.cx.u32;
+        (0..count).map(|i| self.extract(u32, value, i)).collect()
+    }
+
+    /// Converts `value` (of any integer type) to exactly `count` words, truncating it or
+    /// extending it (with its sign if `signed`).
+    fn to_words(&mut self, value: Word, ty: Word, signed: bool, count: u32) -> Option<Vec<Word>> {
+        let u32 = self.cx.u32;
+        let mut words = if let Some(&value_count) = self.cx.wide_types.get(&ty) {
+            self.words(value, value_count)
+        } else {
+            match self.cx.int_widths.get(&ty).copied()? {
+                32 if ty == u32 => vec![value],
+                32 => vec![self.op1(Op::Bitcast, u32, value)],
+                64 => {
+                    let u32x2 = self.cx.type_vector(u32, 2);
+                    let pair = self.op1(Op::Bitcast, u32x2, value);
+                    self.words(pair, 2)
+                }
+                _ => vec![self.op1(if signed { Op::SConvert } else { Op::UConvert }, u32, value)],
+            }
+        };
+        if words.len() < count as usize {
+            let fill = if signed {
+                let top_bit_shift = self.cx.u32_constant(WORD_BITS - 1);
+                self.op2(
+                    Op::ShiftRightArithmetic,
+                    u32,
+                    *words.last().unwrap(),
+                    top_bit_shift,
+                )
+            } else {
+                self.cx.u32_constant(0)
+            };
+            words.resize(count as usize, fill);
+        }
+        words.truncate(count as usize);
+        Some(words)
+    }
+
+    /// Converts the low words of a wide integer to the native integer type `ty`.
+    fn from_words(&mut self, words: &[Word], ty: Word) -> Option<Word> {
+        let u32 = self.cx.u32;
+        Some(match self.cx.int_widths.get(&ty).copied()? {
+            32 if ty == u32 => words[0],
+            32 => self.op1(Op::Bitcast, ty, words[0]),
+            64 => {
+                let u32x2 = self.cx.type_vector(u32, 2);
+                let pair = self.construct(u32x2, &words[..2]);
+                self.op1(Op::Bitcast, ty, pair)
+            }
+            _ => self.op1(Op::UConvert, ty, words[0]),
+        })
+    }
+
+    /// Converts a wide integer to the float type `ty`, one word at a time, starting from the most
+    /// significant one.
+    fn words_to_float(&mut self, words: &[Word], ty: Word, signed: bool) -> Option<Word> {
+        let bool = self.cx.bool;
+        let word_scale = self.cx.float_constant(ty, 2f64.powi(WORD_BITS as i32))?;
+        let mut negative = None;
+        let mut words = words.to_vec();
+        if signed {
+            let zero = self.cx.u32_constant(0);
+            let is_negative = self.op2(Op::SLessThan, bool, words[words.len() - 1], zero);
+            let negated = self.neg(&words);
+            words = self.select_words(is_negative, &negated, &words);
+            negative = Some(is_negative);
+        }
+        let mut result = self.op1(Op::ConvertUToF, ty, words[words.len() - 1]);
+        for &word in words.iter().rev().skip(1) {
+            let scaled = self.op2(Op::FMul, ty, result, word_scale);
+            let word = self.op1(Op::ConvertUToF, ty, word);
+            result = self.op2(Op::FAdd, ty, scaled, word);
+        }
+        if let Some(negative) = negative {
+            let negated = self.op1(Op::FNegate, ty, result);
+            result = self.op3(Op::Select, ty, negative, negated, result);
+        }
+        Some(result)
+    }
+
+    /// Converts a float of type `ty` to a wide integer of `count` words, one word at a time,
+    /// starting from the most significant one. Out of range values aren't handled, as Rust's
+    /// saturating casts already clamp the float before converting it.
+    fn float_to_words(
+        &mut self,
+        value: Word,
+        ty: Word,
+        signed: bool,
+        count: u32,
+    ) -> Option<Vec<Word>> {
+        let (u32, bool) = (self.cx.u32, self.cx.bool);
+        let mut negative = None;
+        let mut remaining = value;
+        if signed {
+            let zero = self.cx.float_constant(ty, 0.0)?;
+            let is_negative = self.op2(Op::FOrdLessThan, bool, value, zero);
+            let negated = self.op1(Op::FNegate, ty, value);
+            remaining = self.op3(Op::Select, ty, is_negative, negated, value);
+            negative = Some(is_negative);
+        }
+        let mut words = vec![0; count as usize];
+        for i in (0..count as usize).rev() {
+            let shift = (i as u32 * WORD_BITS) as i32;
+            let inverse_scale = self.cx.float_constant(ty, 2f64.powi(-shift))?;
+            let scaled = self.op2(Op::FMul, ty, remaining, inverse_scale);
+            words[i] = self.op1(Op::ConvertFToU, u32, scaled);
+            if i > 0 {
+                // Both the word and the remainder are exactly representable, as they're just
+                // splitting up the bits of the original float.
+                let scale = self.cx.float_constant(ty, 2f64.powi(shift))?;
+                let word = self.op1(Op::ConvertUToF, ty, words[i]);
+                let word = self.op2(Op::FMul, ty, word, scale);
+                remaining = self.op2(Op::FSub, ty, remaining, word);
+            }
+        }
+        if let Some(negative) = negative {
+            let negated = self.neg(&words);
+            words = self.select_words(negative, &negated, &words);
+        }
+        Some(words)
+    }
+
+    fn with_carry(&mut self, opcode: Op, a: Word, b: Word) -> (Word, Word) {
+        let u32 = self.cx.u32;
+        let pair = self.cx.type_word_pair();
+        let result = self.op2(opcode, pair, a, b);
+        (self.extract(u32, result, 0), self.extract(u32, result, 1))
+    }
+
+    /// `opcode` is either `OpIAdd` or `OpISub`, with the carry (or borrow) propagated from each
+    /// word to the next one.
+    fn add_or_sub(&mut self, opcode: Op, a: &[Word], b: &[Word]) -> Vec<Word> {
+        let u32 = self.cx.u32;
+        let opcode_with_carry = match opcode {
+            Op::IAdd => Op::IAddCarry,
+            Op::ISub => Op::ISubBorrow,
+            _ => unreachable!(),
+        };
+        let mut result = Vec::with_capacity(a.len());
+        let mut carry = None;
+        for (i, (&a_word, &b_word)) in a.iter().zip(b).enumerate() {
+            // The carry out of the most significant word is discarded.
+            if i == a.len() - 1 {
+                let word = self.op2(opcode, u32, a_word, b_word);
+                result.push(match carry {
+                    Some(carry) => self.op2(opcode, u32, word, carry),
+                    None => word,
+                });
+                break;
+            }
+            let (word, word_carry) = self.with_carry(opcode_with_carry, a_word, b_word);
+            result.push(match carry {
+                Some(carry_in) => {
+                    // At most one of these can carry, so OR-ing them is enough.
+                    let (word, carry_from_carry) =
+                        self.with_carry(opcode_with_carry, word, carry_in);
+                    carry = Some(self.op2(Op::BitwiseOr, u32, word_carry, carry_from_carry));
+                    word
+                }
+                None => {
+                    carry = Some(word_carry);
+                    word
+                }
+            });
+        }
+        result
+    }
+
+    fn neg(&mut self, a: &[Word]) -> Vec<Word> {
+        let zero = self.cx.u32_constant(0);
+        let zeros = vec![zero; a.len()];
+        self.add_or_sub(Op::ISub, &zeros, a)
+    }
+
+    /// Schoolbook multiplication, keeping only the low `a.len()` words of the product.
+    fn mul(&mut self, a: &[Word], b: &[Word]) -> Vec<Word> {
+        let u32 = self.cx.u32;
+        let count = a.len();
+        let mut result: Vec<Option<Word>> = vec![None; count];
+        for (i, &a_word) in a.iter().enumerate() {
+            let mut carry = None;
+            for (j, &b_word) in b.iter().enumerate().take(count - i) {
+                let k = i + j;
+                let add = |this: &mut Self, acc: Option<Word>, x: Word| match acc {
+                    Some(acc) => this.op2(Op::IAdd, u32, acc, x),
+                    None => x,
+                };
+                // Only the low bits of the most significant word are needed.
+                if k == count - 1 {
+                    let low = self.op2(Op::IMul, u32, a_word, b_word);
+                    let word = add(self, result[k], low);
+                    result[k] = Some(add(self, carry, word));
+                    continue;
+                }
+                let pair = self.cx.type_word_pair();
+                let product = self.op2(Op::UMulExtended, pair, a_word, b_word);
+                let low = self.extract(u32, product, 0);
+                let mut high = self.extract(u32, product, 1);
+                let mut word = low;
+                // NOTE: `high + carries` can't overflow, as `a_word * b_word + result[k] + carry`
+                // always fits in two words.
+                for &x in result[k].iter().chain(&carry) {
+                    let (sum, sum_carry) = self.with_carry(Op::IAddCarry, word, x);
+                    word = sum;
+                    high = self.op2(Op::IAdd, u32, high, sum_carry);
+                }
+                result[k] = Some(word);
+                carry = Some(high);
+            }
+        }
+        result.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Compares word by word, starting from the least significant one, with each word deciding
+    /// the result unless it's equal. Only the most significant word is compared as signed for
+    /// signed comparisons.
+    fn less_than(&mut self, a: &[Word], b: &[Word], signed: bool) -> Word {
+        let bool = self.cx.bool;
+        let mut result = self.op2(Op::ULessThan, bool, a[0], b[0]);
+        for (i, (&a_word, &b_word)) in a.iter().zip(b).enumerate().skip(1) {
+            let less_than_op = if signed && i == a.len() - 1 {
+                Op::SLessThan
+            } else {
+                Op::ULessThan
+            };
+            let word_less_than = self.op2(less_than_op, bool, a_word, b_word);
+            let word_equal = self.op2(Op::IEqual, bool, a_word, b_word);
+            let equal_and_less_than = self.op2(Op::LogicalAnd, bool, word_equal, result);
+            result = self.op2(Op::LogicalOr, bool, word_less_than, equal_and_less_than);
+        }
+        result
+    }
+
+    fn select_words(&mut self, cond: Word, a: &[Word], b: &[Word]) -> Vec<Word> {
+        let u32 = self.cx.u32;
+        a.iter()
+            .zip(b)
+            .map(|(&a, &b)| self.op3(Op::Select, u32, cond, a, b))
+            .collect()
+    }
+
+    fn shl1(&mut self, a: &[Word]) -> Vec<Word> {
+        let u32 = self.cx.u32;
+        let one = self.cx.u32_constant(1);
+        let top_bit_shift = self.cx.u32_constant(WORD_BITS - 1);
+        (0..a.len())
+            .map(|i| {
+                let word = self.op2(Op::ShiftLeftLogical, u32, a[i], one);
+                if i == 0 {
+                    return word;
+                }
+                let carried = self.op2(Op::ShiftRightLogical, u32, a[i - 1], top_bit_shift);
+                self.op2(Op::BitwiseOr, u32, word, carried)
+            })
+            .collect()
+    }
+
+    /// Shifts by a dynamic amount, first by whole words (selecting between every possible word
+    /// offset), then by the remaining bits (merging in the bits shifted out of the neighbour).
+    fn shift(&mut self, opcode: Op, a: &[Word], amount: Word) -> Vec<Word> {
+        let (u32, bool) = (self.cx.u32, self.cx.bool);
+        let count = a.len();
+        let zero = self.cx.u32_constant(0);
+        let word_shift_bits = self.cx.u32_constant(WORD_BITS.trailing_zeros());
+        let bit_mask = self.cx.u32_constant(WORD_BITS - 1);
+        let word_bits = self.cx.u32_constant(WORD_BITS);
+        let word_shift = self.op2(Op::ShiftRightLogical, u32, amount, word_shift_bits);
+        let bit_shift = self.op2(Op::BitwiseAnd, u32, amount, bit_mask);
+        let no_bit_shift = self.op2(Op::IEqual, bool, bit_shift, zero);
+        let inverse_bit_shift = self.op2(Op::ISub, u32, word_bits, bit_shift);
+
+        let left = opcode == Op::ShiftLeftLogical;
+        let fill = if opcode == Op::ShiftRightArithmetic {
+            let top_bit_shift = self.cx.u32_constant(WORD_BITS - 1);
+            self.op2(Op::ShiftRightArithmetic, u32, a[count - 1], top_bit_shift)
+        } else {
+            zero
+        };
+
+        // Word `k` of the result comes from word `k - offset` (or `k + offset` when shifting
+        // right) of `a`, or is `fill` if that's out of bounds.
+        let mut shifted_words = Vec::with_capacity(count + 1);
+        for k in 0..count {
+            let mut word = fill;
+            for offset in 0..count {
+                let source = if left {
+                    k.checked_sub(offset)
+                } else {
+                    Some(k + offset).filter(|&source| source < count)
+                };
+                if let Some(source) = source {
+                    let offset = self.cx.u32_constant(offset as u32);
+                    let is_offset = self.op2(Op::IEqual, bool, word_shift, offset);
+                    word = self.op3(Op::Select, u32, is_offset, a[source], word);
+                }
+            }
+            shifted_words.push(word);
+        }
+
+        let (shift_op, inverse_shift_op) = if left {
+            (Op::ShiftLeftLogical, Op::ShiftRightLogical)
+        } else {
+            (Op::ShiftRightLogical, Op::ShiftLeftLogical)
+        };
+        (0..count)
+            .map(|k| {
+                let word = self.op2(shift_op, u32, shifted_words[k], bit_shift);
+                let neighbour = if left {
+                    k.checked_sub(1).map(|k| shifted_words[k])
+                } else if k + 1 < count {
+                    Some(shifted_words[k + 1])
+                } else {
+                    Some(fill).filter(|&fill| fill != zero)
+                };
+                match neighbour {
+                    Some(neighbour) => {
+                        // Shifting by the full word width isn't defined, hence the select.
+                        let carried = self.op2(inverse_shift_op, u32, neighbour, inverse_bit_shift);
+                        let carried = self.op3(Op::Select, u32, no_bit_shift, zero, carried);
+                        self.op2(Op::BitwiseOr, u32, word, carried)
+                    }
+                    None => word,
+                }
+            })
+            .collect()
+    }
+
+    /// Unsigned or signed division and remainder, through `LoweringCx::udivmod`.
+    fn div_rem(&mut self, a: &[Word], b: &[Word], signed: bool) -> (Vec<Word>, Vec<Word>) {
+        let bool = self.cx.bool;
+        let count = a.len() as u32;
+        let vector = self.cx.vector_types[&count];
+        let udivmod = self.cx.udivmod(count);
+        let pair = self.cx.type_struct(&[vector, vector]);
+        let zero = self.cx.u32_constant(0);
+
+        let mut a_negative = None;
+        let mut b_negative = None;
+        let abs = |this: &mut Self, words: &[Word], negative: &mut Option<Word>| {
+            if !signed {
+                return this.construct(vector, words);
+            }
+            let is_negative = this.op2(Op::SLessThan, bool, words[words.len() - 1], zero);
+            *negative = Some(is_negative);
+            let negated = this.neg(words);
+            let abs = this.select_words(is_negative, &negated, words);
+            this.construct(vector, &abs)
+        };
+        let a_abs = abs(self, a, &mut a_negative);
+        let b_abs = abs(self, b, &mut b_negative);
+
+        let result = self.op3(Op::FunctionCall, pair, udivmod, a_abs, b_abs);
+        let quotient = self.extract(vector, result, 0);
+        let remainder = self.extract(vector, result, 1);
+        let mut quotient = self.words(quotient, count);
+        let mut remainder = self.words(remainder, count);
+
+        if let (Some(a_negative), Some(b_negative)) = (a_negative, b_negative) {
+            // The quotient is negative if exactly one side is, the remainder takes the sign of
+            // the dividend (i.e. division truncates towards zero).
+            let quotient_negative = self.op2(Op::LogicalNotEqual, bool, a_negative, b_negative);
+            let negated = self.neg(&quotient);
+            quotient = self.select_words(quotient_negative, &negated, &quotient);
+            let negated = self.neg(&remainder);
+            remainder = self.select_words(a_negative, &negated, &remainder);
+        }
+        (quotient, remainder)
+    }
+
+    /// Lowers `inst` if it involves wide integers (otherwise it's kept as-is), returning the
+    /// replacement for its result, if it was lowered.
+    fn lower_inst(
+        &mut self,
+        inst: Instruction,
+        value_types: &HashMap<Word, Word>,
+    ) -> Option<(Word, Word)> {
+        let result_count = self.wide_count(inst.result_type);
+        let operand_count = inst
+            .operands
+            .iter()
+            .find_map(|operand| self.wide_count(operand_type(value_types, operand)));
+        let count = match result_count.or(operand_count) {
+            Some(count) => count,
+            None => {
+                self.insts.push(inst);
+                return None;
+            }
+        };
+
+        if matches!(
+            inst.class.opcode,
+            Op::AccessChain
+                | Op::InBoundsAccessChain
+                | Op::PtrAccessChain
+                | Op::InBoundsPtrAccessChain
+                | Op::VectorExtractDynamic
+                | Op::VectorInsertDynamic
+        ) {
+            // Indices can't be out of `u32` range anyway, so only keep their low word.
+            let u32 = self.cx.u32;
+            let mut inst = inst;
+            for operand in &mut inst.operands {
+                if self
+                    .wide_count(operand_type(value_types, operand))
+                    .is_some()
+                {
+                    let index = operand.unwrap_id_ref();
+                    *operand = Operand::IdRef(self.extract(u32, index, 0));
+                }
+            }
+            self.insts.push(inst);
+            return None;
+        }
+
+        let type_agnostic = match inst.class.opcode {
+            // These don't care about the type of their operands (and bitwise operations work the
+            // same on vectors).
+            Op::Load
+            | Op::Store
+            | Op::Phi
+            | Op::FunctionCall
+            | Op::ReturnValue
+            | Op::CompositeConstruct
+            | Op::CompositeExtract
+            | Op::CompositeInsert
+            | Op::CopyObject
+            | Op::Undef
+            | Op::BitwiseAnd
+            | Op::BitwiseOr
+            | Op::BitwiseXor
+            | Op::Not => true,
+            // Debug info (e.g. `DebugValue`) only refers to values, whatever their type is.
+            Op::ExtInst => Some(inst.operands[0].unwrap_id_ref()) == self.cx.debug_info_import,
+            // Unless both sides are wide integers, this is a bitcast between vectors.
+            Op::Bitcast => result_count.is_none() || result_count != operand_count,
+            _ => false,
+        };
+        if type_agnostic {
+            self.insts.push(inst);
+            return None;
+        }
+
+        match self.lower_wide_inst(&inst, result_count, count, value_types) {
+            Some(lowered) => Some((inst.result_id.unwrap(), lowered)),
+            None => {
+                self.cx.unsupported(inst.class.opcode, count);
+                self.insts.push(inst);
+                None
+            }
+        }
+    }
+
+    fn wide_count(&self, ty: Option<Word>) -> Option<u32> {
+        self.cx.wide_types.get(&ty?).copied()
+    }
+
+    fn lower_wide_inst(
+        &mut self,
+        inst: &Instruction,
+        result_count: Option<u32>,
+        count: u32,
+        value_types: &HashMap<Word, Word>,
+    ) -> Option<Word> {
+        let opcode = inst.class.opcode;
+        let result_type = inst.result_type?;
+        let operand = |i: usize| inst.operands[i].unwrap_id_ref();
+        Some(match opcode {
+            // Both sides are the same size, so this just changes signedness.
+            Op::Bitcast => self.op1(Op::CopyObject, result_type, operand(0)),
+
+            Op::Select => {
+                let bool = self.cx.bool;
+                let bool_vector = self.cx.type_vector(bool, count);
+                let cond = self.construct(bool_vector, &vec![operand(0); count as usize]);
+                self.op3(Op::Select, result_type, cond, operand(1), operand(2))
+            }
+
+            Op::IAdd | Op::ISub | Op::IMul => {
+                let a = self.words(operand(0), count);
+                let b = self.words(operand(1), count);
+                let words = if opcode == Op::IMul {
+                    self.mul(&a, &b)
+                } else {
+                    self.add_or_sub(opcode, &a, &b)
+                };
+                self.construct(result_type, &words)
+            }
+            Op::SNegate => {
+                let a = self.words(operand(0), count);
+                let words = self.neg(&a);
+                self.construct(result_type, &words)
+            }
+            Op::UDiv | Op::SDiv | Op::UMod | Op::SRem => {
+                let a = self.words(operand(0), count);
+                let b = self.words(operand(1), count);
+                let signed = matches!(opcode, Op::SDiv | Op::SRem);
+                let (quotient, remainder) = self.div_rem(&a, &b, signed);
+                let words = if matches!(opcode, Op::UDiv | Op::SDiv) {
+                    quotient
+                } else {
+                    remainder
+                };
+                self.construct(result_type, &words)
+            }
+
+            Op::ShiftLeftLogical | Op::ShiftRightLogical | Op::ShiftRightArithmetic => {
+                // Only the low word of the amount matters, as shifting by more than the width
+                // isn't defined anyway.
+                let amount_type = operand_type(value_types, &inst.operands[1])?;
+                let amount = self.to_words(operand(1), amount_type, false, 1)?[0];
+                match result_count {
+                    Some(count) => {
+                        let a = self.words(operand(0), count);
+                        let words = self.shift(opcode, &a, amount);
+                        self.construct(result_type, &words)
+                    }
+                    // Only the amount is wide.
+                    None => self.op2(opcode, result_type, operand(0), amount),
+                }
+            }
+
+            Op::IEqual | Op::INotEqual => {
+                let bool = self.cx.bool;
+                let bool_vector = self.cx.type_vector(bool, count);
+                let words_equal = self.op2(opcode, bool_vector, operand(0), operand(1));
+                let reduce = if opcode == Op::IEqual {
+                    Op::All
+                } else {
+                    Op::Any
+                };
+                self.op1(reduce, bool, words_equal)
+            }
+            Op::ULessThan
+            | Op::ULessThanEqual
+            | Op::UGreaterThan
+            | Op::UGreaterThanEqual
+            | Op::SLessThan
+            | Op::SLessThanEqual
+            | Op::SGreaterThan
+            | Op::SGreaterThanEqual => {
+                let bool = self.cx.bool;
+                let a = self.words(operand(0), count);
+                let b = self.words(operand(1), count);
+                let signed = matches!(
+                    opcode,
+                    Op::SLessThan | Op::SLessThanEqual | Op::SGreaterThan | Op::SGreaterThanEqual
+                );
+                // Everything is expressed as `a < b`, `b < a`, or their negation.
+                match opcode {
+                    Op::ULessThan | Op::SLessThan => self.less_than(&a, &b, signed),
+                    Op::UGreaterThan | Op::SGreaterThan => self.less_than(&b, &a, signed),
+                    Op::ULessThanEqual | Op::SLessThanEqual => {
+                        let greater_than = self.less_than(&b, &a, signed);
+                        self.op1(Op::LogicalNot, bool, greater_than)
+                    }
+                    _ => {
+                        let less_than = self.less_than(&a, &b, signed);
+                        self.op1(Op::LogicalNot, bool, less_than)
+                    }
+                }
+            }
+
+            Op::ConvertUToF | Op::ConvertSToF => {
+                let words = self.words(operand(0), count);
+                self.words_to_float(&words, result_type, opcode == Op::ConvertSToF)?
+            }
+            Op::ConvertFToU | Op::ConvertFToS => {
+                let from_type = operand_type(value_types, &inst.operands[0])?;
+                let signed = opcode == Op::ConvertFToS;
+                let words = self.float_to_words(operand(0), from_type, signed, count)?;
+                self.construct(result_type, &words)
+            }
+
+            Op::UConvert | Op::SConvert => match result_count {
+                Some(count) => {
+                    let from_type = operand_type(value_types, &inst.operands[0])?;
+                    let signed = opcode == Op::SConvert;
+                    let words = self.to_words(operand(0), from_type, signed, count)?;
+                    self.construct(result_type, &words)
+                }
+                None => {
+                    let words = self.words(operand(0), count);
+                    self.from_words(&words, result_type)?
+                }
+            },
+
+            _ => return None,
+        })
+    }
+}
+
+fn operand_type(value_types: &HashMap<Word, Word>, operand: &Operand) -> Option<Word> {
+    value_types.get(&operand.id_ref_any()?).copied()
+}
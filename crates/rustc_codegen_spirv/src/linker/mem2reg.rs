@@ -11,6 +11,7 @@
 
 use super::simple_passes::outgoing_edges;
 use super::{apply_rewrite_rules, id};
+use crate::debuginfo::{self, DebugInfoOp};
 use rspirv::dr::{Block, Function, Instruction, ModuleHeader, Operand};
 use rspirv::spirv::{Op, Word};
 use std::collections::{hash_map, HashMap, HashSet};
@@ -20,6 +21,7 @@ pub fn mem2reg(
     types_global_values: &mut Vec<Instruction>,
     pointer_to_pointee: &HashMap<Word, Word>,
     constants: &HashMap<Word, u32>,
+    debug_info_import: Option<Word>,
     func: &mut Function,
 ) {
     let reachable = compute_reachable(&func.blocks);
@@ -31,6 +33,7 @@ pub fn mem2reg(
         types_global_values,
         pointer_to_pointee,
         constants,
+        debug_info_import,
         &mut func.blocks,
         dominance_frontier,
     );
@@ -147,6 +150,7 @@ fn insert_phis_all(
     types_global_values: &mut Vec<Instruction>,
     pointer_to_pointee: &HashMap<Word, Word>,
     constants: &HashMap<Word, u32>,
+    debug_info_import: Option<Word>,
     blocks: &mut [Block],
     dominance_frontier: Vec<HashSet<usize>>,
 ) {
@@ -158,13 +162,28 @@ fn insert_phis_all(
             let var = inst.result_id.unwrap();
             let var_ty = *pointer_to_pointee.get(&inst.result_type.unwrap()).unwrap();
             Some((
-                collect_access_chains(pointer_to_pointee, constants, blocks, var, var_ty)?,
+                collect_access_chains(
+                    pointer_to_pointee,
+                    constants,
+                    debug_info_import,
+                    blocks,
+                    var,
+                    var_ty,
+                )?,
                 var_ty,
             ))
         })
         .collect::<Vec<_>>();
     for &(ref var_map, base_var_type) in &thing {
         let blocks_with_phi = insert_phis(blocks, &dominance_frontier, var_map);
+        let debug_declare = blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .find(|inst| {
+                debuginfo::is_debug_inst(inst, debug_info_import, DebugInfoOp::DebugDeclare)
+                    && var_map.contains_key(&inst.operands[3].unwrap_id_ref())
+            })
+            .cloned();
         let mut renamer = Renamer {
             header,
             types_global_values,
@@ -172,6 +191,8 @@ fn insert_phis_all(
             blocks_with_phi,
             base_var_type,
             var_map,
+            debug_info_import,
+            debug_declare,
             phi_defs: HashSet::new(),
             visited: HashSet::new(),
             stack: Vec::new(),
@@ -195,6 +216,7 @@ struct VarInfo {
 fn collect_access_chains(
     pointer_to_pointee: &HashMap<Word, Word>,
     constants: &HashMap<Word, u32>,
+    debug_info_import: Option<Word>,
     blocks: &[Block],
     base_var: Word,
     base_var_ty: Word,
@@ -236,6 +258,15 @@ fn collect_access_chains(
                             // Only allow store if pointer is the lhs, not rhs
                             Op::Store if index == 0 => {}
                             Op::Load | Op::AccessChain | Op::InBoundsAccessChain => {}
+                            // A `DebugDeclare` of the whole variable becomes `DebugValue`s.
+                            Op::ExtInst
+                                if index == 3
+                                    && *id == base_var
+                                    && debuginfo::is_debug_inst(
+                                        inst,
+                                        debug_info_import,
+                                        DebugInfoOp::DebugDeclare,
+                                    ) => {}
                             _ => return None,
                         }
                     }
@@ -336,6 +367,9 @@ struct Renamer<'a> {
     blocks_with_phi: HashSet<usize>,
     base_var_type: Word,
     var_map: &'a HashMap<Word, VarInfo>,
+    debug_info_import: Option<Word>,
+    // The `DebugDeclare` of the variable, replaced by a `DebugValue` after every store to it.
+    debug_declare: Option<Instruction>,
     phi_defs: HashSet<Word>,
     visited: HashSet<usize>,
     stack: Vec<Word>,
@@ -398,7 +432,8 @@ impl Renamer<'_> {
             return;
         }
 
-        for inst in &mut self.blocks[block].instructions {
+        let mut debug_values = Vec::new();
+        for (inst_index, inst) in self.blocks[block].instructions.iter_mut().enumerate() {
             if inst.class.opcode == Op::Variable && inst.operands.len() > 1 {
                 let ptr = inst.result_id.unwrap();
                 let val = inst.operands[1].id_ref_any().unwrap();
@@ -432,7 +467,17 @@ impl Renamer<'_> {
                         );
                         self.stack.push(new_id);
                     }
+                    if self.debug_declare.is_some() {
+                        debug_values.push((inst_index + 1, *self.stack.last().unwrap()));
+                    }
                 }
+            } else if debuginfo::is_debug_inst(
+                inst,
+                self.debug_info_import,
+                DebugInfoOp::DebugDeclare,
+            ) && self.var_map.contains_key(&inst.operands[3].unwrap_id_ref())
+            {
+                *inst = Instruction::new(Op::Nop, None, None, vec![]);
             } else if inst.class.opcode == Op::Load {
                 let ptr = inst.operands[0].id_ref_any().unwrap();
                 if let Some(var_info) = self.var_map.get(&ptr) {
@@ -463,6 +508,25 @@ impl Renamer<'_> {
                 }
             }
         }
+        if let Some(debug_declare) = &self.debug_declare {
+            // Insert back to front, so earlier indices stay valid.
+            for (inst_index, value) in debug_values.into_iter().rev() {
+                let debug_value = debuginfo::debug_inst(
+                    debug_declare.operands[0].unwrap_id_ref(),
+                    debug_declare.result_type.unwrap(),
+                    id(self.header),
+                    DebugInfoOp::DebugValue,
+                    vec![
+                        debug_declare.operands[2].unwrap_id_ref(),
+                        value,
+                        debug_declare.operands[4].unwrap_id_ref(),
+                    ],
+                );
+                self.blocks[block]
+                    .instructions
+                    .insert(inst_index, debug_value);
+            }
+        }
 
         for dest_id in outgoing_edges(&self.blocks[block]).collect::<Vec<_>>() {
             // TODO: Don't do this find
@@ -3,23 +3,34 @@ mod test;
 
 mod capability_computation;
 mod dce;
+mod devirtualize;
 mod duplicates;
+mod float_narrowing;
 mod import_export_link;
 mod inline;
+mod int_lowering;
 mod mem2reg;
-mod new_structurizer;
+mod opt;
+mod physical_storage_buffer;
+mod recursion;
 mod simple_passes;
+mod small_int_promotion;
 mod specializer;
 mod structurizer;
+mod unroll;
+mod volatile;
 mod zombies;
 
-use crate::decorations::{CustomDecoration, UnrollLoopsDecoration};
+use crate::debuginfo;
+use crate::decorations::{CustomDecoration, SrcLocDecoration, UnrollLoopsDecoration};
 use rspirv::binary::Consumer;
 use rspirv::dr::{Block, Instruction, Loader, Module, ModuleHeader, Operand};
-use rspirv::spirv::{Op, StorageClass, Word};
+use rspirv::spirv::{Capability, Op, StorageClass, Word};
 use rustc_errors::ErrorReported;
 use rustc_session::Session;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+pub use devirtualize::VTABLE_TYPE_NAME;
 
 pub type Result<T> = std::result::Result<T, ErrorReported>;
 
@@ -27,15 +38,33 @@ pub struct Options {
     pub compact_ids: bool,
     pub dce: bool,
     pub inline: bool,
+    /// The maximum size of functions for `inline` to inline (unless `#[inline(never)]`), on top
+    /// of the ones that have to be (for legalization) or were asked to be (`#[inline]`).
+    /// `0` disables this (see `inline::inline`).
+    pub inline_threshold: usize,
     pub mem2reg: bool,
+    /// Run our own optimization passes (see `opt.rs`), after `mem2reg`.
+    pub opt: bool,
     pub structurize: bool,
-    pub use_new_structurizer: bool,
     pub emit_multiple_modules: bool,
+    /// Lower 64-bit integers to pairs of 32-bit ones, for targets without `Int64`.
+    pub emulate_int64: bool,
+    /// Compute 64-bit floats as 32-bit ones, for targets without `Float64`.
+    pub narrow_float64: bool,
+    /// Promote 8-bit and 16-bit integers to 32-bit ones, for targets without `Int8`/`Int16`.
+    pub promote_small_ints: bool,
+    /// Keep the `SrcLocDecoration`s of functions in the output, for tools that can map
+    /// functions back to their Rust source (other tools will ignore them).
+    pub keep_src_locs: bool,
+    /// Produce a library module instead, which keeps the `Linkage` capability, and the `Export`
+    /// linkage of these symbols (which, like entry points, are kept alive by `dce`).
+    pub library_exports: Option<HashSet<String>>,
 }
 
 pub enum LinkResult {
     SingleModule(Module),
-    MultipleModules(HashMap<String, Module>),
+    /// By entry point name (ordered, so they're always processed in the same order).
+    MultipleModules(BTreeMap<String, Module>),
 }
 
 fn id(header: &mut ModuleHeader) -> Word {
@@ -87,11 +116,13 @@ pub fn link(sess: &Session, mut inputs: Vec<Module>, opts: &Options) -> Result<L
             simple_passes::shift_ids(&mut module, bound);
             bound += module.header.as_ref().unwrap().bound - 1;
             let this_version = module.header.as_ref().unwrap().version();
-            if version != this_version {
-                sess.fatal(&format!(
-                    "cannot link two modules with different SPIR-V versions: v{}.{} and v{}.{}",
-                    version.0, version.1, this_version.0, this_version.1
-                ))
+            // NOTE: modules of older versions (e.g. external SPIR-V libraries) are also valid in newer ones.
+            if this_version > version {
+                sess.err(&format!(
+                    "cannot link a SPIR-V v{}.{} module into a v{}.{} one",
+                    this_version.0, this_version.1, version.0, version.1
+                ));
+                return Err(ErrorReported);
             }
         }
 
@@ -128,14 +159,26 @@ pub fn link(sess: &Session, mut inputs: Vec<Module>, opts: &Options) -> Result<L
         duplicates::remove_duplicate_extensions(&mut output);
         duplicates::remove_duplicate_capablities(&mut output);
         duplicates::remove_duplicate_ext_inst_imports(&mut output);
+        duplicates::flatten_decoration_groups(sess, &mut output)?;
         duplicates::remove_duplicate_types(&mut output);
-        // jb-todo: strip identical OpDecoration / OpDecorationGroups
     }
 
     // find import / export pairs
     {
         let _timer = sess.timer("link_find_pairs");
-        import_export_link::run(sess, &mut output)?;
+        import_export_link::run(sess, &mut output, opts.library_exports.as_ref())?;
+    }
+
+    // NOTE: this has to happen before `devirtualize`, which inlines functions taking `fn` or vtable
+    // pointers, and would never stop inlining such a function into itself.
+    {
+        let _timer = sess.timer("link_report_recursion");
+        recursion::report_recursion(sess, &mut output)?;
+    }
+
+    {
+        let _timer = sess.timer("link_devirtualize");
+        devirtualize::devirtualize(&mut output);
     }
 
     {
@@ -173,9 +216,23 @@ pub fn link(sess: &Session, mut inputs: Vec<Module>, opts: &Options) -> Result<L
         );
     }
 
+    {
+        let _timer = sess.timer("link_make_volatile_accesses_coherent");
+        volatile::make_volatile_accesses_coherent(&mut output);
+    }
+
+    // NOTE: devirtualized calls can only now be seen to recurse (e.g. through `dyn Trait` methods).
+    {
+        let _timer = sess.timer("link_report_recursion");
+        recursion::report_recursion(sess, &mut output)?;
+    }
+    if !opts.keep_src_locs {
+        SrcLocDecoration::remove_all(&mut output);
+    }
+
     if opts.inline {
         let _timer = sess.timer("link_inline");
-        inline::inline(&mut output);
+        inline::inline(&mut output, opts.inline_threshold);
     }
 
     if opts.dce {
@@ -184,17 +241,13 @@ pub fn link(sess: &Session, mut inputs: Vec<Module>, opts: &Options) -> Result<L
     }
 
     let unroll_loops_decorations = UnrollLoopsDecoration::decode_all(&output)
-        .map(|(id, unroll_loops)| (id, unroll_loops.deserialize()))
+        .into_iter()
         .collect::<HashMap<_, _>>();
     UnrollLoopsDecoration::remove_all(&mut output);
 
     let mut output = if opts.structurize {
         let _timer = sess.timer("link_structurize");
-        if opts.use_new_structurizer {
-            new_structurizer::structurize(output, unroll_loops_decorations)
-        } else {
-            structurizer::structurize(sess, output, unroll_loops_decorations)
-        }
+        structurizer::structurize(output, &unroll_loops_decorations)
     } else {
         output
     };
@@ -203,6 +256,7 @@ pub fn link(sess: &Session, mut inputs: Vec<Module>, opts: &Options) -> Result<L
         let _timer = sess.timer("link_block_ordering_pass_and_mem2reg");
         let mut pointer_to_pointee = HashMap::new();
         let mut constants = HashMap::new();
+        let debug_info_import = debuginfo::find_import(&output);
         if opts.mem2reg {
             let mut u32 = None;
             for inst in &output.types_global_values {
@@ -235,6 +289,7 @@ pub fn link(sess: &Session, mut inputs: Vec<Module>, opts: &Options) -> Result<L
                     &mut output.types_global_values,
                     &pointer_to_pointee,
                     &constants,
+                    debug_info_import,
                     func,
                 );
                 // mem2reg produces minimal SSA form, not pruned, so DCE the dead ones
@@ -242,6 +297,30 @@ pub fn link(sess: &Session, mut inputs: Vec<Module>, opts: &Options) -> Result<L
             }
         }
     }
+    if !unroll_loops_decorations.is_empty() {
+        let _timer = sess.timer("link_unroll_loops");
+        unroll::unroll_loops(&mut output, &unroll_loops_decorations);
+    }
+    if opts.opt {
+        let _timer = sess.timer("link_opt");
+        opt::optimize(&mut output);
+    }
+    if opts.narrow_float64 {
+        let _timer = sess.timer("link_narrow_float64");
+        float_narrowing::narrow_float64(sess, &mut output)?;
+    }
+    {
+        let _timer = sess.timer("link_lower_wide_integers");
+        int_lowering::lower_wide_integers(sess, &mut output, opts.emulate_int64)?;
+    }
+    if opts.promote_small_ints {
+        let _timer = sess.timer("link_promote_small_ints");
+        small_int_promotion::promote_small_ints(sess, &mut output)?;
+    }
+    {
+        let _timer = sess.timer("link_fixup_physical_storage_buffer");
+        physical_storage_buffer::fixup_physical_storage_buffer(&mut output);
+    }
     {
         let _timer = sess.timer("link_sort_globals");
         simple_passes::sort_globals(&mut output);
@@ -291,6 +370,20 @@ pub fn link(sess: &Session, mut inputs: Vec<Module>, opts: &Options) -> Result<L
             capability_computation::remove_extra_capabilities(output);
             capability_computation::remove_extra_extensions(output);
         }
+        {
+            let mut unavailable_capabilities = Vec::new();
+            if opts.emulate_int64 {
+                unavailable_capabilities.push(Capability::Int64);
+            }
+            if opts.narrow_float64 {
+                unavailable_capabilities.push(Capability::Float64);
+            }
+            capability_computation::check_unavailable_capabilities(
+                sess,
+                output,
+                &unavailable_capabilities,
+            )?;
+        }
 
         {
             let _timer = sess.timer("link_add_required_capabilities");
@@ -0,0 +1,840 @@
+//! Simple optimizations on the (SSA form, post-`mem2reg`) functions of the linked module, so that
+//! the output stays reasonably lean even when `spirv-opt` isn't run (or fails to run):
+//! * copy propagation (`OpCopyObject`, and `OpPhi`s which only ever take one value)
+//! * constant folding of scalar integer and boolean operations (incl. `OpSelect`)
+//! * composite folding (`OpCompositeExtract` of `OpCompositeConstruct`/`OpCompositeInsert`/
+//!   `OpConstantComposite`)
+//! * local (i.e. within a block) common-subexpression elimination of pure instructions
+//! * dead-branch elimination, for `OpBranchConditional`/`OpSwitch` on constants
+//!
+//! All of these are repeated until none of them can make progress anymore, and then any pure
+//! instructions left unused are removed.
+
+use super::apply_rewrite_rules;
+use super::simple_passes::{outgoing_edges, reachable_blocks};
+use rspirv::binary::Assemble;
+use rspirv::dr::{Block, Function, Instruction, Module, ModuleHeader, Operand};
+use rspirv::spirv::{Op, Word};
+use std::collections::{HashMap, HashSet};
+use std::mem::replace;
+
+pub fn optimize(module: &mut Module) {
+    let mut cx = OptCx::new(
+        module.header.as_mut().unwrap(),
+        &mut module.types_global_values,
+    );
+    for func in &mut module.functions {
+        cx.optimize_function(func);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Const {
+    Bool(bool),
+    /// The value is always zero-extended from the width of the type (see `IntType::truncate`).
+    Int(u64),
+}
+
+#[derive(Copy, Clone)]
+struct IntType {
+    width: u32,
+    signed: bool,
+}
+
+impl IntType {
+    fn truncate(self, value: u64) -> u64 {
+        if self.width >= 64 {
+            value
+        } else {
+            value & ((1 << self.width) - 1)
+        }
+    }
+
+    fn sign_extend(self, value: u64) -> i64 {
+        let shift = 64 - self.width.min(64);
+        ((value << shift) as i64) >> shift
+    }
+}
+
+/// The result of trying to simplify an instruction.
+enum Folded {
+    /// The instruction's result can be replaced with this ID.
+    Replace(Word),
+    /// The instruction was rewritten in place to a simpler form.
+    Changed,
+    Unchanged,
+}
+
+pub struct OptCx<'m> {
+    header: &'m mut ModuleHeader,
+    types_global_values: &'m mut Vec<Instruction>,
+    int_types: HashMap<Word, IntType>,
+    vector_sizes: HashMap<Word, u32>,
+    /// Scalar constants, and their types.
+    constants: HashMap<Word, (Word, Const)>,
+    /// Cache for `constant`.
+    constant_ids: HashMap<(Word, Const), Word>,
+    composite_constants: HashMap<Word, Vec<Word>>,
+    /// Cache for `undef`.
+    undefs: HashMap<Word, Word>,
+}
+
+impl<'m> OptCx<'m> {
+    pub fn new(
+        header: &'m mut ModuleHeader,
+        types_global_values: &'m mut Vec<Instruction>,
+    ) -> Self {
+        let mut cx = Self {
+            header,
+            types_global_values,
+            int_types: HashMap::new(),
+            vector_sizes: HashMap::new(),
+            constants: HashMap::new(),
+            constant_ids: HashMap::new(),
+            composite_constants: HashMap::new(),
+            undefs: HashMap::new(),
+        };
+        for inst in cx.types_global_values.iter() {
+            let result_id = match inst.result_id {
+                Some(result_id) => result_id,
+                None => continue,
+            };
+            let constant = match inst.class.opcode {
+                Op::TypeInt => {
+                    cx.int_types.insert(
+                        result_id,
+                        IntType {
+                            width: inst.operands[0].unwrap_literal_int32(),
+                            signed: inst.operands[1].unwrap_literal_int32() != 0,
+                        },
+                    );
+                    None
+                }
+                Op::TypeVector => {
+                    cx.vector_sizes
+                        .insert(result_id, inst.operands[1].unwrap_literal_int32());
+                    None
+                }
+                Op::ConstantTrue => Some(Const::Bool(true)),
+                Op::ConstantFalse => Some(Const::Bool(false)),
+                Op::Constant => {
+                    // NOTE: integers wider than 64 bits don't fit in `Const::Int`, so constants
+                    // of those types are left unknown, which keeps them from being folded.
+                    let ty = cx
+                        .int_types
+                        .get(&inst.result_type.unwrap())
+                        .filter(|ty| ty.width <= 64);
+                    match (ty, &inst.operands[0]) {
+                        (Some(&ty), &Operand::LiteralInt32(value)) => {
+                            Some(Const::Int(ty.truncate(value.into())))
+                        }
+                        (Some(&ty), &Operand::LiteralInt64(value)) => {
+                            Some(Const::Int(ty.truncate(value)))
+                        }
+                        _ => None,
+                    }
+                }
+                Op::ConstantComposite => {
+                    cx.composite_constants.insert(
+                        result_id,
+                        inst.operands.iter().map(|op| op.unwrap_id_ref()).collect(),
+                    );
+                    None
+                }
+                Op::Undef => {
+                    cx.undefs.insert(inst.result_type.unwrap(), result_id);
+                    None
+                }
+                _ => None,
+            };
+            if let Some(constant) = constant {
+                let ty = inst.result_type.unwrap();
+                cx.constants.insert(result_id, (ty, constant));
+                cx.constant_ids.entry((ty, constant)).or_insert(result_id);
+            }
+        }
+        cx
+    }
+
+    pub fn id(&mut self) -> Word {
+        let result = self.header.bound;
+        self.header.bound += 1;
+        result
+    }
+
+    /// Applies all the optimizations (see the module docs) to `func`.
+    pub fn optimize_function(&mut self, func: &mut Function) {
+        loop {
+            let changed = self.fold_instructions(func);
+            if !(self.eliminate_dead_branches(func) || changed) {
+                break;
+            }
+        }
+        remove_unused_instructions(func);
+    }
+
+    fn constant(&mut self, ty: Word, constant: Const) -> Word {
+        if let Some(&id) = self.constant_ids.get(&(ty, constant)) {
+            return id;
+        }
+        let id = self.id();
+        let inst = match constant {
+            Const::Bool(true) => Instruction::new(Op::ConstantTrue, Some(ty), Some(id), vec![]),
+            Const::Bool(false) => Instruction::new(Op::ConstantFalse, Some(ty), Some(id), vec![]),
+            Const::Int(value) => {
+                let int_ty = self.int_types[&ty];
+                // NOTE: narrower than 32-bit signed integers have to be sign-extended to 32 bits.
+                let operand = if int_ty.width > 32 {
+                    Operand::LiteralInt64(value)
+                } else if int_ty.signed {
+                    Operand::LiteralInt32(int_ty.sign_extend(value) as u32)
+                } else {
+                    Operand::LiteralInt32(value as u32)
+                };
+                Instruction::new(Op::Constant, Some(ty), Some(id), vec![operand])
+            }
+        };
+        self.types_global_values.push(inst);
+        self.constants.insert(id, (ty, constant));
+        self.constant_ids.insert((ty, constant), id);
+        id
+    }
+
+    fn undef(&mut self, ty: Word) -> Word {
+        if let Some(&id) = self.undefs.get(&ty) {
+            return id;
+        }
+        let id = self.id();
+        self.types_global_values
+            .push(Instruction::new(Op::Undef, Some(ty), Some(id), vec![]));
+        self.undefs.insert(ty, id);
+        id
+    }
+
+    /// Copy propagation, constant and composite folding, and local CSE, all in one walk over
+    /// the function (in block order, so definitions are seen before their non-`OpPhi` uses).
+    fn fold_instructions(&mut self, func: &mut Function) -> bool {
+        let mut defs = HashMap::new();
+        let mut def_blocks = HashMap::new();
+        for block in &func.blocks {
+            let label = block.label_id().unwrap();
+            for inst in &block.instructions {
+                if let Some(result_id) = inst.result_id {
+                    def_blocks.insert(result_id, label);
+                    if let Op::CompositeConstruct | Op::CompositeInsert = inst.class.opcode {
+                        defs.insert(result_id, inst.clone());
+                    }
+                }
+            }
+        }
+
+        let mut changed = false;
+        let mut rewrite_rules = HashMap::new();
+        for block in &mut func.blocks {
+            let label = block.label_id().unwrap();
+            // Pure instructions seen so far in this block, keyed by everything but their result.
+            let mut available = HashMap::new();
+            for inst in &mut block.instructions {
+                for op in &mut inst.operands {
+                    if let Some(id) = op.id_ref_any_mut() {
+                        *id = resolve(&rewrite_rules, *id);
+                    }
+                }
+                let result_id = match inst.result_id {
+                    Some(result_id) => result_id,
+                    None => continue,
+                };
+                let replacement = match self.fold(inst, &defs, &rewrite_rules) {
+                    Folded::Replace(replacement) => Some(replacement),
+                    Folded::Changed => {
+                        changed = true;
+                        None
+                    }
+                    Folded::Unchanged => None,
+                };
+                let replacement = replacement.or_else(|| {
+                    if inst.class.opcode == Op::Phi {
+                        trivial_phi_value(inst, label, &def_blocks)
+                    } else if is_pure(inst.class.opcode) {
+                        let key = cse_key(inst);
+                        let existing = available.get(&key).copied();
+                        available.entry(key).or_insert(result_id);
+                        existing
+                    } else {
+                        None
+                    }
+                });
+                if let Some(replacement) = replacement {
+                    rewrite_rules.insert(result_id, replacement);
+                    *inst = Instruction::new(Op::Nop, None, None, vec![]);
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            for block in &mut func.blocks {
+                block
+                    .instructions
+                    .retain(|inst| inst.class.opcode != Op::Nop);
+            }
+            let rewrite_rules = rewrite_rules
+                .keys()
+                .map(|&id| (id, resolve(&rewrite_rules, id)))
+                .collect();
+            apply_rewrite_rules(&rewrite_rules, &mut func.blocks);
+        }
+        changed
+    }
+
+    fn fold(
+        &mut self,
+        inst: &mut Instruction,
+        defs: &HashMap<Word, Instruction>,
+        rewrite_rules: &HashMap<Word, Word>,
+    ) -> Folded {
+        match inst.class.opcode {
+            Op::CopyObject => Folded::Replace(inst.operands[0].unwrap_id_ref()),
+            Op::Select => {
+                let (on_true, on_false) = (
+                    inst.operands[1].unwrap_id_ref(),
+                    inst.operands[2].unwrap_id_ref(),
+                );
+                match self.constants.get(&inst.operands[0].unwrap_id_ref()) {
+                    Some(&(_, Const::Bool(cond))) => {
+                        Folded::Replace(if cond { on_true } else { on_false })
+                    }
+                    _ if on_true == on_false => Folded::Replace(on_true),
+                    _ => Folded::Unchanged,
+                }
+            }
+            Op::CompositeExtract => self.fold_composite_extract(inst, defs, rewrite_rules),
+            _ => match self.fold_constant(inst) {
+                Some(constant) => {
+                    Folded::Replace(self.constant(inst.result_type.unwrap(), constant))
+                }
+                None => Folded::Unchanged,
+            },
+        }
+    }
+
+    /// Looks through the composite an `OpCompositeExtract` extracts from, for as long as that
+    /// composite's relevant constituent is known, rewriting the `OpCompositeExtract` as it goes.
+    fn fold_composite_extract(
+        &self,
+        inst: &mut Instruction,
+        defs: &HashMap<Word, Instruction>,
+        rewrite_rules: &HashMap<Word, Word>,
+    ) -> Folded {
+        let mut changed = false;
+        loop {
+            let composite = inst.operands[0].unwrap_id_ref();
+            let indices = inst.operands[1..]
+                .iter()
+                .map(|op| op.unwrap_literal_int32())
+                .collect::<Vec<_>>();
+            let (&first, rest) = match indices.split_first() {
+                Some(split) => split,
+                None => return Folded::Replace(composite),
+            };
+
+            // The constituent at `first`, if `composite` is built out of one constituent per
+            // element (unlike e.g. vectors built out of smaller vectors).
+            let constituents = match defs.get(&composite) {
+                Some(def) if def.class.opcode == Op::CompositeConstruct => {
+                    let constituents = def
+                        .operands
+                        .iter()
+                        .map(|op| resolve(rewrite_rules, op.unwrap_id_ref()))
+                        .collect::<Vec<_>>();
+                    match self.vector_sizes.get(&def.result_type.unwrap()) {
+                        Some(&size) if size as usize != constituents.len() => None,
+                        _ => Some(constituents),
+                    }
+                }
+                _ => self.composite_constants.get(&composite).cloned(),
+            };
+            if let Some(constituents) = constituents {
+                let constituent = match constituents.get(first as usize) {
+                    Some(&constituent) => constituent,
+                    None => break,
+                };
+                if rest.is_empty() {
+                    return Folded::Replace(constituent);
+                }
+                inst.operands = std::iter::once(Operand::IdRef(constituent))
+                    .chain(rest.iter().map(|&i| Operand::LiteralInt32(i)))
+                    .collect();
+                changed = true;
+                continue;
+            }
+
+            match defs.get(&composite) {
+                Some(def) if def.class.opcode == Op::CompositeInsert => {
+                    let object = resolve(rewrite_rules, def.operands[0].unwrap_id_ref());
+                    let base = resolve(rewrite_rules, def.operands[1].unwrap_id_ref());
+                    let inserted_at = def.operands[2..]
+                        .iter()
+                        .map(|op| op.unwrap_literal_int32())
+                        .collect::<Vec<_>>();
+                    if indices == inserted_at {
+                        return Folded::Replace(object);
+                    } else if indices.starts_with(&inserted_at) {
+                        // Extracting from (a part of) the inserted object.
+                        inst.operands = std::iter::once(Operand::IdRef(object))
+                            .chain(
+                                indices[inserted_at.len()..]
+                                    .iter()
+                                    .map(|&i| Operand::LiteralInt32(i)),
+                            )
+                            .collect();
+                    } else if inserted_at.starts_with(&indices) {
+                        // Extracting a composite which the object was inserted into.
+                        break;
+                    } else {
+                        // Extracting something the insert didn't touch.
+                        inst.operands[0] = Operand::IdRef(base);
+                    }
+                    changed = true;
+                }
+                _ => break,
+            }
+        }
+        if changed {
+            Folded::Changed
+        } else {
+            Folded::Unchanged
+        }
+    }
+
+    fn fold_constant(&self, inst: &Instruction) -> Option<Const> {
+        let operand = |i: usize| -> Option<(Word, Const)> {
+            self.constants
+                .get(&inst.operands.get(i)?.id_ref_any()?)
+                .copied()
+        };
+        let bool_operand = |i| match operand(i)? {
+            (_, Const::Bool(value)) => Some(value),
+            _ => None,
+        };
+        let int_operand = |i| match operand(i)? {
+            (ty, Const::Int(value)) => Some((self.int_types[&ty], value)),
+            _ => None,
+        };
+        // NOTE: only `Const::Int`s of types at most 64 bits wide are ever known (see `new`),
+        // so this keeps e.g. `OpUConvert`s to wider types from being folded.
+        let result_int_type = || {
+            self.int_types
+                .get(&inst.result_type?)
+                .copied()
+                .filter(|ty| ty.width <= 64)
+        };
+
+        let result = match inst.class.opcode {
+            Op::LogicalNot => Const::Bool(!bool_operand(0)?),
+            Op::LogicalAnd => Const::Bool(bool_operand(0)? & bool_operand(1)?),
+            Op::LogicalOr => Const::Bool(bool_operand(0)? | bool_operand(1)?),
+            Op::LogicalEqual => Const::Bool(bool_operand(0)? == bool_operand(1)?),
+            Op::LogicalNotEqual => Const::Bool(bool_operand(0)? != bool_operand(1)?),
+
+            Op::Not | Op::SNegate | Op::UConvert | Op::SConvert => {
+                let (ty, a) = int_operand(0)?;
+                let result_ty = result_int_type()?;
+                let value = match inst.class.opcode {
+                    Op::Not => !a,
+                    Op::SNegate => a.wrapping_neg(),
+                    Op::UConvert => a,
+                    Op::SConvert => ty.sign_extend(a) as u64,
+                    _ => unreachable!(),
+                };
+                Const::Int(result_ty.truncate(value))
+            }
+
+            Op::IEqual
+            | Op::INotEqual
+            | Op::ULessThan
+            | Op::ULessThanEqual
+            | Op::UGreaterThan
+            | Op::UGreaterThanEqual
+            | Op::SLessThan
+            | Op::SLessThanEqual
+            | Op::SGreaterThan
+            | Op::SGreaterThanEqual => {
+                let ((ty, a), (_, b)) = (int_operand(0)?, int_operand(1)?);
+                let (sa, sb) = (ty.sign_extend(a), ty.sign_extend(b));
+                Const::Bool(match inst.class.opcode {
+                    Op::IEqual => a == b,
+                    Op::INotEqual => a != b,
+                    Op::ULessThan => a < b,
+                    Op::ULessThanEqual => a <= b,
+                    Op::UGreaterThan => a > b,
+                    Op::UGreaterThanEqual => a >= b,
+                    Op::SLessThan => sa < sb,
+                    Op::SLessThanEqual => sa <= sb,
+                    Op::SGreaterThan => sa > sb,
+                    Op::SGreaterThanEqual => sa >= sb,
+                    _ => unreachable!(),
+                })
+            }
+
+            Op::IAdd
+            | Op::ISub
+            | Op::IMul
+            | Op::UDiv
+            | Op::SDiv
+            | Op::UMod
+            | Op::SRem
+            | Op::SMod
+            | Op::BitwiseAnd
+            | Op::BitwiseOr
+            | Op::BitwiseXor
+            | Op::ShiftLeftLogical
+            | Op::ShiftRightLogical
+            | Op::ShiftRightArithmetic => {
+                let ((ty, a), (_, b)) = (int_operand(0)?, int_operand(1)?);
+                let result_ty = result_int_type()?;
+                let (sa, sb) = (ty.sign_extend(a), ty.sign_extend(b));
+                // Division by zero, signed overflow in division, and shifting by at least the
+                // bit width, are all undefined, so leave them alone.
+                let is_signed_overflow = sa == ty.sign_extend(1 << (ty.width - 1)) && sb == -1;
+                let value = match inst.class.opcode {
+                    Op::IAdd => a.wrapping_add(b),
+                    Op::ISub => a.wrapping_sub(b),
+                    Op::IMul => a.wrapping_mul(b),
+                    Op::UDiv if b != 0 => a / b,
+                    Op::UMod if b != 0 => a % b,
+                    Op::SDiv if b != 0 && !is_signed_overflow => (sa / sb) as u64,
+                    Op::SRem if b != 0 && !is_signed_overflow => (sa % sb) as u64,
+                    Op::SMod if b != 0 && !is_signed_overflow => {
+                        // The result of `OpSMod` has the sign of the divisor.
+                        let rem = sa % sb;
+                        (if rem != 0 && (rem < 0) != (sb < 0) {
+                            rem + sb
+                        } else {
+                            rem
+                        }) as u64
+                    }
+                    Op::BitwiseAnd => a & b,
+                    Op::BitwiseOr => a | b,
+                    Op::BitwiseXor => a ^ b,
+                    Op::ShiftLeftLogical if b < ty.width.into() => a << b,
+                    Op::ShiftRightLogical if b < ty.width.into() => a >> b,
+                    Op::ShiftRightArithmetic if b < ty.width.into() => (sa >> b) as u64,
+                    _ => return None,
+                };
+                Const::Int(result_ty.truncate(value))
+            }
+
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// Turns `OpBranchConditional`s and `OpSwitch`es on constants into `OpBranch`es (removing
+    /// their `OpSelectionMerge`, if any), then removes the blocks that became unreachable.
+    fn eliminate_dead_branches(&mut self, func: &mut Function) -> bool {
+        let loop_headers = func
+            .blocks
+            .iter()
+            .filter(|block| merge_inst(block).map(|inst| inst.class.opcode) == Some(Op::LoopMerge))
+            .map(|block| block.label_id().unwrap())
+            .collect::<HashSet<_>>();
+
+        let folded_branches = func
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, block)| {
+                let label = block.label_id().unwrap();
+                if loop_headers.contains(&label) {
+                    return None;
+                }
+                let target = self.constant_branch_target(block)?;
+                // Removing a back-edge would leave its loop malformed.
+                let has_back_edge = outgoing_edges(block).any(|target| {
+                    loop_headers.contains(&target)
+                        && !reachable_blocks(func, Some(target)).contains(&label)
+                });
+                if has_back_edge {
+                    None
+                } else {
+                    Some((i, target))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let changed = !folded_branches.is_empty();
+        for (i, target) in folded_branches {
+            let block = &mut func.blocks[i];
+            block.instructions.pop();
+            if merge_inst(block).is_some() {
+                block.instructions.pop();
+            }
+            block.instructions.push(Instruction::new(
+                Op::Branch,
+                None,
+                None,
+                vec![Operand::IdRef(target)],
+            ));
+        }
+
+        if changed {
+            self.remove_unreachable_blocks(func);
+        }
+        changed
+    }
+
+    /// The block `block` always branches to, if its `OpBranchConditional`/`OpSwitch` terminator
+    /// is on a constant.
+    fn constant_branch_target(&self, block: &Block) -> Option<Word> {
+        let terminator = block.instructions.last().unwrap();
+        let condition = self
+            .constants
+            .get(&terminator.operands.get(0)?.id_ref_any()?);
+        let target = match (terminator.class.opcode, condition) {
+            (Op::BranchConditional, Some(&(_, Const::Bool(condition)))) => {
+                &terminator.operands[if condition { 1 } else { 2 }]
+            }
+            (Op::Switch, Some(&(ty, Const::Int(selector)))) => {
+                let ty = self.int_types[&ty];
+                terminator.operands[2..]
+                    .chunks(2)
+                    .find(|case| {
+                        let literal = match case[0] {
+                            Operand::LiteralInt32(literal) => literal.into(),
+                            Operand::LiteralInt64(literal) => literal,
+                            _ => return false,
+                        };
+                        ty.truncate(literal) == selector
+                    })
+                    .map_or(&terminator.operands[1], |case| &case[1])
+            }
+            _ => return None,
+        };
+        Some(target.unwrap_id_ref())
+    }
+
+    /// Removes unreachable blocks, except for those still used as merge blocks or continue
+    /// targets by reachable structured control-flow, which get emptied instead (continue
+    /// targets branching back to their loop header, and merge blocks being `OpUnreachable`).
+    fn remove_unreachable_blocks(&mut self, func: &mut Function) {
+        let reachable = reachable_blocks(func, None);
+
+        let mut merge_blocks = HashSet::new();
+        let mut continue_targets = HashMap::new();
+        for block in &func.blocks {
+            let label = block.label_id().unwrap();
+            if !reachable.contains(&label) {
+                continue;
+            }
+            if let Some(merge) = merge_inst(block) {
+                merge_blocks.insert(merge.operands[0].unwrap_id_ref());
+                if merge.class.opcode == Op::LoopMerge {
+                    continue_targets.insert(merge.operands[1].unwrap_id_ref(), label);
+                }
+            }
+        }
+
+        let mut removed_defs = HashSet::new();
+        let blocks = replace(&mut func.blocks, Vec::new());
+        for mut block in blocks {
+            let label = block.label_id().unwrap();
+            if reachable.contains(&label) {
+                func.blocks.push(block);
+                continue;
+            }
+            removed_defs.extend(block.instructions.iter().filter_map(|inst| inst.result_id));
+            let terminator = if let Some(&header) = continue_targets.get(&label) {
+                Instruction::new(Op::Branch, None, None, vec![Operand::IdRef(header)])
+            } else if merge_blocks.contains(&label) {
+                Instruction::new(Op::Unreachable, None, None, vec![])
+            } else {
+                continue;
+            };
+            block.instructions = vec![terminator];
+            func.blocks.push(block);
+        }
+
+        let mut preds = HashMap::<Word, HashSet<Word>>::new();
+        for block in &func.blocks {
+            for target in outgoing_edges(block) {
+                preds
+                    .entry(target)
+                    .or_default()
+                    .insert(block.label_id().unwrap());
+            }
+        }
+        for block in &mut func.blocks {
+            let label = block.label_id().unwrap();
+            for inst in &mut block.instructions {
+                if inst.class.opcode != Op::Phi {
+                    continue;
+                }
+                let incoming = replace(&mut inst.operands, Vec::new());
+                for pair in incoming.chunks(2) {
+                    let (mut value, parent) = (pair[0].unwrap_id_ref(), pair[1].unwrap_id_ref());
+                    if !preds
+                        .get(&label)
+                        .map_or(false, |preds| preds.contains(&parent))
+                    {
+                        continue;
+                    }
+                    if removed_defs.contains(&value) {
+                        value = self.undef(inst.result_type.unwrap());
+                    }
+                    inst.operands
+                        .extend(vec![Operand::IdRef(value), Operand::IdRef(parent)]);
+                }
+            }
+        }
+    }
+}
+
+fn resolve(rewrite_rules: &HashMap<Word, Word>, mut id: Word) -> Word {
+    while let Some(&rewrite) = rewrite_rules.get(&id) {
+        id = rewrite;
+    }
+    id
+}
+
+/// The `OpSelectionMerge` or `OpLoopMerge` before the terminator of `block`, if any.
+pub fn merge_inst(block: &Block) -> Option<&Instruction> {
+    let merge = block.instructions.iter().rev().nth(1)?;
+    match merge.class.opcode {
+        Op::SelectionMerge | Op::LoopMerge => Some(merge),
+        _ => None,
+    }
+}
+
+/// The only value (other than itself) an `OpPhi` can take, if that value's definition is outside
+/// of the `OpPhi`'s block (and therefore dominates it, as it dominates all of its predecessors).
+fn trivial_phi_value(
+    phi: &Instruction,
+    block: Word,
+    def_blocks: &HashMap<Word, Word>,
+) -> Option<Word> {
+    let phi_id = phi.result_id.unwrap();
+    let mut values = phi
+        .operands
+        .iter()
+        .step_by(2)
+        .map(|op| op.unwrap_id_ref())
+        .filter(|&value| value != phi_id);
+    let value = values.next()?;
+    if values.all(|other| other == value) && def_blocks.get(&value) != Some(&block) {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Instructions without side effects, which only depend on their operands.
+fn is_pure(op: Op) -> bool {
+    matches!(
+        op,
+        Op::CopyObject
+            | Op::Select
+            | Op::CompositeConstruct
+            | Op::CompositeExtract
+            | Op::CompositeInsert
+            | Op::VectorShuffle
+            | Op::VectorExtractDynamic
+            | Op::VectorInsertDynamic
+            | Op::AccessChain
+            | Op::InBoundsAccessChain
+            | Op::PtrAccessChain
+            | Op::InBoundsPtrAccessChain
+            | Op::Bitcast
+            | Op::ConvertFToU
+            | Op::ConvertFToS
+            | Op::ConvertSToF
+            | Op::ConvertUToF
+            | Op::UConvert
+            | Op::SConvert
+            | Op::FConvert
+            | Op::SNegate
+            | Op::FNegate
+            | Op::Not
+            | Op::IAdd
+            | Op::FAdd
+            | Op::ISub
+            | Op::FSub
+            | Op::IMul
+            | Op::FMul
+            | Op::UDiv
+            | Op::SDiv
+            | Op::FDiv
+            | Op::UMod
+            | Op::SRem
+            | Op::SMod
+            | Op::FRem
+            | Op::FMod
+            | Op::VectorTimesScalar
+            | Op::Dot
+            | Op::ShiftRightLogical
+            | Op::ShiftRightArithmetic
+            | Op::ShiftLeftLogical
+            | Op::BitwiseOr
+            | Op::BitwiseXor
+            | Op::BitwiseAnd
+            | Op::LogicalEqual
+            | Op::LogicalNotEqual
+            | Op::LogicalOr
+            | Op::LogicalAnd
+            | Op::LogicalNot
+            | Op::IEqual
+            | Op::INotEqual
+            | Op::UGreaterThan
+            | Op::SGreaterThan
+            | Op::UGreaterThanEqual
+            | Op::SGreaterThanEqual
+            | Op::ULessThan
+            | Op::SLessThan
+            | Op::ULessThanEqual
+            | Op::SLessThanEqual
+            | Op::FOrdEqual
+            | Op::FUnordEqual
+            | Op::FOrdNotEqual
+            | Op::FUnordNotEqual
+            | Op::FOrdLessThan
+            | Op::FUnordLessThan
+            | Op::FOrdGreaterThan
+            | Op::FUnordGreaterThan
+            | Op::FOrdLessThanEqual
+            | Op::FUnordLessThanEqual
+            | Op::FOrdGreaterThanEqual
+            | Op::FUnordGreaterThanEqual
+            | Op::IsNan
+            | Op::IsInf
+    )
+}
+
+fn cse_key(inst: &Instruction) -> Vec<u32> {
+    let mut key = vec![inst.class.opcode as u32, inst.result_type.unwrap_or(0)];
+    for op in &inst.operands {
+        op.assemble_into(&mut key);
+    }
+    key
+}
+
+/// Removes pure instructions (and `OpPhi`s) whose results are never used.
+fn remove_unused_instructions(func: &mut Function) {
+    loop {
+        let mut used = HashSet::new();
+        for inst in func.all_inst_iter() {
+            used.extend(inst.operands.iter().filter_map(|op| op.id_ref_any()));
+        }
+        let mut changed = false;
+        for block in &mut func.blocks {
+            block.instructions.retain(|inst| {
+                let unused = (is_pure(inst.class.opcode) || inst.class.opcode == Op::Phi)
+                    && !used.contains(&inst.result_id.unwrap());
+                changed |= unused;
+                !unused
+            });
+        }
+        if !changed {
+            break;
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Fixups for pointers in the `PhysicalStorageBuffer` storage class (i.e. "buffer device address").
+//!
+//! Such pointers are only created by `OpConvertUToPtr` (see `spirv_std::arch::convert_u_to_ptr`),
+//! and the specializer infers `PhysicalStorageBuffer` for all the pointers they flow into. Using
+//! them however requires more than the right storage class:
+//! * the `PhysicalStorageBuffer64` addressing model
+//! * an `Aligned` memory operand on every `OpLoad`/`OpStore` through them
+//! * an `Aliased` (or `AliasedPointer`) decoration on function parameters (or variables) of
+//!   such pointer types
+
+use rspirv::dr::{Instruction, Module, Operand};
+use rspirv::spirv::{AddressingModel, Decoration, MemoryAccess, Op, StorageClass, Word};
+use std::collections::{HashMap, HashSet};
+
+pub fn fixup_physical_storage_buffer(module: &mut Module) {
+    let physical_pointer_types = module
+        .types_global_values
+        .iter()
+        .filter(|inst| {
+            inst.class.opcode == Op::TypePointer
+                && inst.operands[0].unwrap_storage_class() == StorageClass::PhysicalStorageBuffer
+        })
+        .map(|inst| inst.result_id.unwrap())
+        .collect::<HashSet<Word>>();
+    if physical_pointer_types.is_empty() {
+        return;
+    }
+
+    if let Some(memory_model) = &mut module.memory_model {
+        memory_model.operands[0] =
+            Operand::AddressingModel(AddressingModel::PhysicalStorageBuffer64);
+    }
+
+    let alignments = scalar_alignments(module);
+    let pointees = module
+        .types_global_values
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::TypePointer)
+        .map(|inst| (inst.result_id.unwrap(), inst.operands[1].unwrap_id_ref()))
+        .collect::<HashMap<_, _>>();
+    let value_types = module
+        .all_inst_iter()
+        .filter_map(|inst| Some((inst.result_id?, inst.result_type?)))
+        .collect::<HashMap<_, _>>();
+
+    let mut aliased = module
+        .all_inst_iter()
+        .filter(|inst| {
+            inst.class.opcode == Op::Variable
+                && physical_pointer_types.contains(&pointees[&inst.result_type.unwrap()])
+        })
+        .map(|inst| (inst.result_id.unwrap(), Decoration::AliasedPointer))
+        .collect::<Vec<_>>();
+    for func in &mut module.functions {
+        for param in &func.parameters {
+            if physical_pointer_types.contains(&param.result_type.unwrap()) {
+                aliased.push((param.result_id.unwrap(), Decoration::Aliased));
+            }
+        }
+        for inst in func.blocks.iter_mut().flat_map(|b| &mut b.instructions) {
+            let memory_access_idx = match inst.class.opcode {
+                Op::Load => 1,
+                Op::Store => 2,
+                _ => continue,
+            };
+            let pointer_ty = value_types[&inst.operands[0].unwrap_id_ref()];
+            if !physical_pointer_types.contains(&pointer_ty) {
+                continue;
+            }
+            let alignment = alignments.get(&pointees[&pointer_ty]).copied().unwrap_or(1);
+            add_aligned_memory_operand(inst, memory_access_idx, alignment);
+        }
+    }
+
+    let already_decorated = module
+        .annotations
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::Decorate)
+        .map(|inst| {
+            (
+                inst.operands[0].unwrap_id_ref(),
+                inst.operands[1].unwrap_decoration(),
+            )
+        })
+        .collect::<HashSet<_>>();
+    for (id, decoration) in aliased {
+        let restrict = match decoration {
+            Decoration::AliasedPointer => Decoration::RestrictPointer,
+            _ => Decoration::Restrict,
+        };
+        if !already_decorated.contains(&(id, decoration))
+            && !already_decorated.contains(&(id, restrict))
+        {
+            module.annotations.push(Instruction::new(
+                Op::Decorate,
+                None,
+                None,
+                vec![Operand::IdRef(id), Operand::Decoration(decoration)],
+            ));
+        }
+    }
+}
+
+/// The alignment of each type, as the size of its largest scalar (which matches Rust's layout).
+fn scalar_alignments(module: &Module) -> HashMap<Word, u32> {
+    let mut alignments = HashMap::new();
+    for inst in &module.types_global_values {
+        let alignment = match inst.class.opcode {
+            Op::TypeInt | Op::TypeFloat => inst.operands[0].unwrap_literal_int32() / 8,
+            // FIXME: this assumes `PhysicalStorageBuffer64`, the only addressing model
+            // pointers can be stored in memory with, in shaders.
+            Op::TypePointer => 8,
+            // Forward-declared pointers can be used (by recursive data types)
+            // before their `OpTypePointer` definitions.
+            Op::TypeForwardPointer => {
+                alignments.insert(inst.operands[0].unwrap_id_ref(), 8);
+                continue;
+            }
+            Op::TypeVector
+            | Op::TypeMatrix
+            | Op::TypeArray
+            | Op::TypeRuntimeArray
+            | Op::TypeStruct => inst
+                .operands
+                .iter()
+                .filter_map(|op| alignments.get(&op.id_ref_any()?))
+                .copied()
+                .max()
+                .unwrap_or(1),
+            _ => continue,
+        };
+        alignments.insert(inst.result_id.unwrap(), alignment.max(1));
+    }
+    alignments
+}
+
+/// Add (or keep, if already present) the `Aligned` memory operand on an `OpLoad`/`OpStore`,
+/// whose optional *Memory Operands* start at `memory_access_idx`.
+fn add_aligned_memory_operand(inst: &mut Instruction, memory_access_idx: usize, alignment: u32) {
+    match inst.operands.get_mut(memory_access_idx) {
+        Some(Operand::MemoryAccess(memory_access)) => {
+            if memory_access.contains(MemoryAccess::ALIGNED) {
+                return;
+            }
+            memory_access.insert(MemoryAccess::ALIGNED);
+            // `Aligned`'s literal is the first *Memory Operands* literal, as `Volatile`
+            // (the only lower bit) doesn't take one.
+            inst.operands
+                .insert(memory_access_idx + 1, Operand::LiteralInt32(alignment));
+        }
+        _ => {
+            inst.operands
+                .push(Operand::MemoryAccess(MemoryAccess::ALIGNED));
+            inst.operands.push(Operand::LiteralInt32(alignment));
+        }
+    }
+}
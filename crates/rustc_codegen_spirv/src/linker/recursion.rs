@@ -0,0 +1,147 @@
+//! SPIR-V disallows recursion (direct or indirect), and the inliner can't deal with it either, so
+//! this pass finds the cycles in the call graph (i.e. its strongly connected components), and
+//! reports each one reachable from an entry point as an error, in a similar way to zombies (see
+//! `zombies.rs`), before the module gets to `inline.rs` or `spirv-val`. It runs both before and
+//! after `devirtualize.rs`, as that inlines too, but also turns indirect calls into direct ones.
+
+use super::simple_passes::strongly_connected_components;
+use super::zombies::get_names;
+use super::Result;
+use crate::decorations::{CustomDecoration, SrcLocDecoration};
+use indexmap::{IndexMap, IndexSet};
+use rspirv::dr::Module;
+use rspirv::spirv::{Op, Word};
+use rustc_errors::ErrorReported;
+use rustc_session::Session;
+use rustc_span::{Span, DUMMY_SP};
+use std::collections::{HashMap, VecDeque};
+use std::iter::once;
+
+pub fn report_recursion(sess: &Session, module: &mut Module) -> Result<()> {
+    let spans: HashMap<Word, Span> = SrcLocDecoration::decode_all(module)
+        .into_iter()
+        .filter_map(|(id, src_loc)| {
+            let span = src_loc.span.to_rustc(sess.source_map())?;
+            Some((id, span))
+        })
+        .collect();
+    let call_graph = call_graph(module);
+    let reachable = reachable_functions(module, &call_graph);
+
+    let mut names = None;
+    let mut any_err = false;
+    let functions = call_graph.keys().copied().collect::<Vec<_>>();
+    for scc in strongly_connected_components(&functions, |func| call_graph[&func].iter().copied()) {
+        let start = scc[0];
+        if !reachable.contains(&start) {
+            continue;
+        }
+        let cycle = match find_cycle(&call_graph, &scc, start) {
+            Some(cycle) => cycle,
+            None => continue,
+        };
+        let names = names.get_or_insert_with(|| get_names(module));
+        let name = |id: Word| {
+            names
+                .get(&id)
+                .map(|&n| n.to_string())
+                .unwrap_or_else(|| format!("Unnamed function ID %{}", id))
+        };
+        let span = |id: Word| spans.get(&id).copied().unwrap_or(DUMMY_SP);
+
+        let cycle_note = once("Cycle:".to_string())
+            .chain(cycle.iter().map(|&id| name(id)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut err =
+            sess.struct_span_err(span(start), "recursive function calls are not supported");
+        // The last function in `cycle` is `start` again, which already is the primary span.
+        for &id in &cycle[1..cycle.len() - 1] {
+            if let Some(&span) = spans.get(&id) {
+                err.span_note(span, "...which calls this function");
+            }
+        }
+        err.note(&cycle_note).emit();
+        any_err = true;
+    }
+
+    if any_err {
+        Err(ErrorReported)
+    } else {
+        Ok(())
+    }
+}
+
+/// Map from each function to the functions it calls (in order of their first call).
+fn call_graph(module: &Module) -> IndexMap<Word, IndexSet<Word>> {
+    let mut call_graph: IndexMap<Word, IndexSet<Word>> = module
+        .functions
+        .iter()
+        .map(|func| (func.def_id().unwrap(), IndexSet::new()))
+        .collect();
+    for func in &module.functions {
+        let callees = func
+            .all_inst_iter()
+            .filter(|inst| inst.class.opcode == Op::FunctionCall)
+            .map(|inst| inst.operands[0].unwrap_id_ref())
+            // Calls to imported functions that were never defined have nothing to recurse into.
+            .filter(|callee| call_graph.contains_key(callee))
+            .collect();
+        call_graph[&func.def_id().unwrap()] = callees;
+    }
+    call_graph
+}
+
+fn reachable_functions(
+    module: &Module,
+    call_graph: &IndexMap<Word, IndexSet<Word>>,
+) -> IndexSet<Word> {
+    let mut reachable = IndexSet::new();
+    let mut queue = module
+        .entry_points
+        .iter()
+        .map(|inst| inst.operands[1].unwrap_id_ref())
+        .collect::<Vec<_>>();
+    while let Some(func) = queue.pop() {
+        if let Some(callees) = call_graph.get(&func) {
+            if reachable.insert(func) {
+                queue.extend(callees);
+            }
+        }
+    }
+    reachable
+}
+
+/// Find the shortest chain of calls, within `scc`, from `start` back to itself, returned as all
+/// the functions along the way, starting and ending with `start` (or `None` if there are no such
+/// calls, i.e. `start` is alone in `scc` and doesn't call itself).
+fn find_cycle(
+    call_graph: &IndexMap<Word, IndexSet<Word>>,
+    scc: &[Word],
+    start: Word,
+) -> Option<Vec<Word>> {
+    let mut callers = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(func) = queue.pop_front() {
+        for &callee in &call_graph[&func] {
+            if !scc.contains(&callee) {
+                continue;
+            }
+            if callee == start {
+                // The chain of callers always ends at `start` (which has no caller).
+                let mut cycle = vec![start, func];
+                while let Some(&caller) = callers.get(cycle.last().unwrap()) {
+                    cycle.push(caller);
+                }
+                cycle.reverse();
+                return Some(cycle);
+            }
+            if !callers.contains_key(&callee) {
+                callers.insert(callee, func);
+                queue.push_back(callee);
+            }
+        }
+    }
+    None
+}
@@ -1,4 +1,5 @@
-use rspirv::dr::{Block, Function, Module};
+use super::id;
+use rspirv::dr::{Block, Function, Instruction, Module, ModuleHeader, Operand};
 use rspirv::spirv::{Op, Word};
 use std::collections::{HashMap, HashSet};
 use std::mem::replace;
@@ -92,6 +93,151 @@ pub fn outgoing_edges(block: &Block) -> impl Iterator<Item = Word> + '_ {
     operand_indices.map(move |i| terminator.operands[i].unwrap_id_ref())
 }
 
+/// The labels of all the blocks reachable from the entry block of `func`, without going through
+/// the `avoiding` block (if any). Note that a reachable block not in `reachable_blocks(func,
+/// Some(a))` is dominated by `a`.
+pub fn reachable_blocks(func: &Function, avoiding: Option<Word>) -> HashSet<Word> {
+    let blocks = func
+        .blocks
+        .iter()
+        .map(|block| (block.label_id().unwrap(), block))
+        .collect::<HashMap<_, _>>();
+    let mut reachable = HashSet::new();
+    let mut queue = vec![func.blocks[0].label_id().unwrap()];
+    while let Some(label) = queue.pop() {
+        if Some(label) != avoiding && reachable.insert(label) {
+            queue.extend(outgoing_edges(blocks[&label]));
+        }
+    }
+    reachable
+}
+
+/// Tarjan's algorithm, on the graph made of `nodes` and the edges to their `successors` (which
+/// must all be in `nodes`). Components are returned in reverse topological order (i.e. those
+/// reachable from a component come before it), with each one's nodes kept in `nodes` order.
+pub fn strongly_connected_components<S: IntoIterator<Item = Word>>(
+    nodes: &[Word],
+    successors: impl Fn(Word) -> S,
+) -> Vec<Vec<Word>> {
+    struct Tarjan<'a, F> {
+        nodes: &'a [Word],
+        node_indices: HashMap<Word, usize>,
+        successors: F,
+        next_index: usize,
+        // Map from node index (in `nodes`) to its `(index, lowlink)`.
+        visited: HashMap<usize, (usize, usize)>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        sccs: Vec<Vec<Word>>,
+    }
+
+    impl<S: IntoIterator<Item = Word>, F: Fn(Word) -> S> Tarjan<'_, F> {
+        fn visit(&mut self, node: usize) -> usize {
+            let index = self.next_index;
+            self.next_index += 1;
+            self.visited.insert(node, (index, index));
+            self.stack.push(node);
+            self.on_stack[node] = true;
+
+            let mut lowlink = index;
+            for succ in (self.successors)(self.nodes[node]) {
+                let succ = self.node_indices[&succ];
+                match self.visited.get(&succ) {
+                    None => lowlink = lowlink.min(self.visit(succ)),
+                    Some(&(succ_index, _)) if self.on_stack[succ] => {
+                        lowlink = lowlink.min(succ_index)
+                    }
+                    Some(_) => {}
+                }
+            }
+            self.visited.insert(node, (index, lowlink));
+
+            if lowlink == index {
+                let mut scc = vec![];
+                loop {
+                    let member = self.stack.pop().unwrap();
+                    self.on_stack[member] = false;
+                    scc.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                scc.sort_unstable();
+                let nodes = self.nodes;
+                self.sccs.push(scc.into_iter().map(|i| nodes[i]).collect());
+            }
+            lowlink
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        nodes,
+        node_indices: nodes
+            .iter()
+            .enumerate()
+            .map(|(i, &node)| (node, i))
+            .collect(),
+        successors,
+        next_index: 0,
+        visited: HashMap::new(),
+        stack: vec![],
+        on_stack: vec![false; nodes.len()],
+        sccs: vec![],
+    };
+    for node in 0..nodes.len() {
+        if !tarjan.visited.contains_key(&node) {
+            tarjan.visit(node);
+        }
+    }
+    tarjan.sccs
+}
+
+/// The ID of the `OpTypeInt 32 0` in `globals`, which is added (at the end) if there's none.
+pub fn u32_type(header: &mut ModuleHeader, globals: &mut Vec<Instruction>) -> Word {
+    let existing = globals.iter().find(|inst| {
+        inst.class.opcode == Op::TypeInt
+            && inst.operands[0].unwrap_literal_int32() == 32
+            && inst.operands[1].unwrap_literal_int32() == 0
+    });
+    if let Some(existing) = existing {
+        return existing.result_id.unwrap();
+    }
+    let ty = id(header);
+    globals.push(Instruction::new(
+        Op::TypeInt,
+        None,
+        Some(ty),
+        vec![Operand::LiteralInt32(32), Operand::LiteralInt32(0)],
+    ));
+    ty
+}
+
+/// The ID of the `OpConstant` of `value` with the type `u32_type` in `globals`, which is added
+/// (at the end) if there's none.
+pub fn u32_constant(
+    header: &mut ModuleHeader,
+    globals: &mut Vec<Instruction>,
+    u32_type: Word,
+    value: u32,
+) -> Word {
+    let existing = globals.iter().find(|inst| {
+        inst.class.opcode == Op::Constant
+            && inst.result_type == Some(u32_type)
+            && inst.operands[0] == Operand::LiteralInt32(value)
+    });
+    if let Some(existing) = existing {
+        return existing.result_id.unwrap();
+    }
+    let constant = id(header);
+    globals.push(Instruction::new(
+        Op::Constant,
+        Some(u32_type),
+        Some(constant),
+        vec![Operand::LiteralInt32(value)],
+    ));
+    constant
+}
+
 pub fn compact_ids(module: &mut Module) -> u32 {
     let mut remap = HashMap::new();
 
@@ -123,4 +269,12 @@ pub fn sort_globals(module: &mut Module) {
     // Function declarations come before definitions. TODO: Figure out if it's even possible to
     // have a function declaration without a body in a fully linked module?
     module.functions.sort_by_key(|f| !f.blocks.is_empty());
+
+    // Debug instructions from different modules were merged together, but the spec requires them
+    // to be grouped: first `OpString`/`OpSource*`, then names, then `OpModuleProcessed`.
+    module.debugs.sort_by_key(|inst| match inst.class.opcode {
+        Op::String | Op::SourceExtension | Op::Source | Op::SourceContinued => 0,
+        Op::ModuleProcessed => 2,
+        _ => 1,
+    });
 }
@@ -0,0 +1,642 @@
+//! Promotion of 8-bit and 16-bit integers to 32-bit ones, for targets lacking the `Int8` and
+//! `Int16` capabilities.
+//!
+//! When the `promote-small-ints` target feature is enabled, every small integer *value* (and any
+//! type in `Function`/`Private`/`Workgroup` storage containing one) is promoted to 32 bits. Values
+//! are kept zero-extended (for unsigned types) or sign-extended (for signed types), so that
+//! operations which can overflow the original width are followed by an explicit truncation, and
+//! operations which care about the upper bits first reinterpret their operands as needed.
+//!
+//! Memory visible outside the shader (e.g. storage buffers) keeps its original layout: those
+//! types keep their small integers, which are converted when loaded or stored, and only need the
+//! `*8BitAccess`/`*16BitAccess` capabilities (see `capability_computation`), not `Int8`/`Int16`.
+//! Emulating that access with 32-bit loads and stores (for targets without those capabilities
+//! either) isn't supported.
+
+use super::{duplicates, id, simple_passes, Result};
+use rspirv::dr::{Instruction, Module, ModuleHeader, Operand};
+use rspirv::spirv::{Capability, Op, StorageClass, Word};
+use rustc_errors::ErrorReported;
+use rustc_session::Session;
+use std::collections::HashMap;
+use std::mem::take;
+
+pub fn promote_small_ints(sess: &Session, module: &mut Module) -> Result<()> {
+    let mut int_types = HashMap::new();
+    let mut pointer_types = HashMap::new();
+    let mut u32_constant_values = HashMap::new();
+    for inst in &module.types_global_values {
+        match inst.class.opcode {
+            Op::TypeInt => {
+                let width = inst.operands[0].unwrap_literal_int32();
+                let signed = inst.operands[1].unwrap_literal_int32() != 0;
+                int_types.insert(inst.result_id.unwrap(), (width, signed));
+            }
+            Op::TypePointer => {
+                let storage_class = inst.operands[0].unwrap_storage_class();
+                let pointee = inst.operands[1].unwrap_id_ref();
+                pointer_types.insert(inst.result_id.unwrap(), (storage_class, pointee));
+            }
+            Op::Constant => {
+                if let Operand::LiteralInt32(value) = inst.operands[0] {
+                    u32_constant_values.insert(inst.result_id.unwrap(), value);
+                }
+            }
+            _ => {}
+        }
+    }
+    if !int_types.values().any(|&(width, _)| width < 32) {
+        return Ok(());
+    }
+
+    let value_types = module
+        .all_inst_iter()
+        .filter_map(|inst| Some((inst.result_id?, inst.result_type?)))
+        .collect::<HashMap<_, _>>();
+
+    let mut cx = PromotionCx {
+        sess,
+        header: module.header.as_mut().unwrap(),
+        int_types,
+        pointer_types,
+        u32_constant_values,
+        memory_types: HashMap::new(),
+        promoted: HashMap::new(),
+        u32: None,
+        new_globals: Vec::new(),
+        has_err: false,
+    };
+
+    let types_global_values = take(&mut module.types_global_values);
+    for inst in types_global_values {
+        cx.promote_global(inst, &mut module.types_global_values);
+    }
+    for func in &mut module.functions {
+        let def = func.def.as_mut().unwrap();
+        def.result_type = def.result_type.map(|ty| cx.promoted_type(ty));
+        def.operands[1] = Operand::IdRef(cx.promoted_type(def.operands[1].unwrap_id_ref()));
+        for param in &mut func.parameters {
+            param.result_type = param.result_type.map(|ty| cx.promoted_type(ty));
+        }
+        for block in &mut func.blocks {
+            let mut insts = Vec::with_capacity(block.instructions.len());
+            for inst in take(&mut block.instructions) {
+                cx.promote_inst(inst, &value_types, &mut insts);
+            }
+            block.instructions = insts;
+        }
+    }
+    module.types_global_values.append(&mut cx.new_globals);
+    if cx.has_err {
+        return Err(ErrorReported);
+    }
+
+    // Nothing needs `Int8`/`Int16` anymore, as the small integers left in memory are only ever
+    // loaded, stored or converted.
+    module.capabilities.retain(|inst| {
+        !matches!(
+            inst.operands[0].unwrap_capability(),
+            Capability::Int8 | Capability::Int16
+        )
+    });
+
+    // Promoted types may now be duplicates of existing 32-bit types.
+    duplicates::remove_duplicate_types(module);
+    Ok(())
+}
+
+/// Storage classes whose layout is observable outside of the shader, which must be kept as-is.
+pub fn is_external(storage_class: StorageClass) -> bool {
+    matches!(
+        storage_class,
+        StorageClass::StorageBuffer
+            | StorageClass::PhysicalStorageBuffer
+            | StorageClass::Uniform
+            | StorageClass::PushConstant
+            | StorageClass::Input
+            | StorageClass::Output
+    )
+}
+
+struct PromotionCx<'a> {
+    sess: &'a Session,
+    header: &'a mut ModuleHeader,
+    /// Width and signedness of every (original) integer type.
+    int_types: HashMap<Word, (u32, bool)>,
+    /// Storage class and pointee of every (original) pointer type.
+    pointer_types: HashMap<Word, (StorageClass, Word)>,
+    /// Values of all 32-bit-or-smaller integer constants, used for array lengths.
+    u32_constant_values: HashMap<Word, u32>,
+    /// Original definition of every type containing small integers, used to convert values
+    /// between memory and their promoted form.
+    memory_types: HashMap<Word, Instruction>,
+    /// Map from each type containing small integers to its promoted equivalent.
+    promoted: HashMap<Word, Word>,
+    u32: Option<Word>,
+    /// Constants (and possibly the `u32` type) created by this pass, appended after all other
+    /// globals.
+    new_globals: Vec<Instruction>,
+    has_err: bool,
+}
+
+impl PromotionCx<'_> {
+    fn promoted_type(&self, ty: Word) -> Word {
+        self.promoted.get(&ty).copied().unwrap_or(ty)
+    }
+
+    /// Width and signedness of `ty`, if it's a small integer type.
+    fn small_int(&self, ty: Option<Word>) -> Option<(u32, bool)> {
+        self.int_types
+            .get(&ty?)
+            .copied()
+            .filter(|&(width, _)| width < 32)
+    }
+
+    fn unsupported(&mut self, opcode: Op, width: u32) {
+        self.sess.err(&format!(
+            "unsupported operation on {}-bit integers without the Int{} capability: Op{:?}",
+            width, width, opcode
+        ));
+        self.has_err = true;
+    }
+
+    fn u32_constant(&mut self, value: u32) -> Word {
+        let u32 = match self.u32 {
+            Some(u32) => u32,
+            None => {
+                let u32 = simple_passes::u32_type(self.header, &mut self.new_globals);
+                self.u32 = Some(u32);
+                u32
+            }
+        };
+        simple_passes::u32_constant(self.header, &mut self.new_globals, u32, value)
+    }
+
+    /// Keeps `inst` (a type, constant or global variable) and, for types containing small
+    /// integers, adds their promoted equivalent right after it.
+    fn promote_global(&mut self, mut inst: Instruction, globals: &mut Vec<Instruction>) {
+        let result_id = inst.result_id;
+        let promoted = match inst.class.opcode {
+            Op::TypeInt => {
+                let (width, signed) = self.int_types[&result_id.unwrap()];
+                if width == 32 && !signed && self.u32.is_none() {
+                    self.u32 = result_id;
+                }
+                if width < 32 {
+                    Some(Instruction::new(
+                        Op::TypeInt,
+                        None,
+                        Some(id(self.header)),
+                        vec![
+                            Operand::LiteralInt32(32),
+                            Operand::LiteralInt32(signed as u32),
+                        ],
+                    ))
+                } else {
+                    None
+                }
+            }
+            Op::TypePointer => {
+                let (storage_class, pointee) = self.pointer_types[&result_id.unwrap()];
+                match self.promoted.get(&pointee) {
+                    Some(&pointee) if !is_external(storage_class) => Some(Instruction::new(
+                        Op::TypePointer,
+                        None,
+                        Some(id(self.header)),
+                        vec![
+                            Operand::StorageClass(storage_class),
+                            Operand::IdRef(pointee),
+                        ],
+                    )),
+                    _ => None,
+                }
+            }
+            Op::TypeVector
+            | Op::TypeMatrix
+            | Op::TypeArray
+            | Op::TypeRuntimeArray
+            | Op::TypeStruct
+            | Op::TypeFunction => {
+                let promotes = inst.operands.iter().any(|operand| {
+                    operand
+                        .id_ref_any()
+                        .map_or(false, |id| self.promoted.contains_key(&id))
+                });
+                if promotes {
+                    let mut promoted = inst.clone();
+                    promoted.result_id = Some(id(self.header));
+                    for operand in &mut promoted.operands {
+                        if let Some(ty) = operand.id_ref_any() {
+                            *operand = Operand::IdRef(self.promoted_type(ty));
+                        }
+                    }
+                    Some(promoted)
+                } else {
+                    None
+                }
+            }
+            // Only types need promoted copies, while values (including global variables, which
+            // are pointers) are just retyped.
+            _ => {
+                inst.result_type = inst.result_type.map(|ty| self.promoted_type(ty));
+                None
+            }
+        };
+        match promoted {
+            Some(promoted) => {
+                self.promoted
+                    .insert(result_id.unwrap(), promoted.result_id.unwrap());
+                self.memory_types.insert(result_id.unwrap(), inst.clone());
+                globals.push(inst);
+                globals.push(promoted);
+            }
+            None => globals.push(inst),
+        }
+    }
+
+    fn promote_inst(
+        &mut self,
+        mut inst: Instruction,
+        value_types: &HashMap<Word, Word>,
+        insts: &mut Vec<Instruction>,
+    ) {
+        let result_type = inst.result_type;
+        inst.result_type = result_type.map(|ty| self.promoted_type(ty));
+        let operand_type = |operand: &Operand| value_types.get(&operand.id_ref_any()?).copied();
+
+        // Accessing memory with its original layout requires converting the values.
+        let external_pointee = |cx: &Self, pointer: &Operand| {
+            let &(storage_class, pointee) = cx.pointer_types.get(&operand_type(pointer)?)?;
+            Some(pointee)
+                .filter(|pointee| is_external(storage_class) && cx.promoted.contains_key(pointee))
+        };
+        match inst.class.opcode {
+            Op::Load => {
+                if let Some(pointee) = external_pointee(self, &inst.operands[0]) {
+                    let loaded = id(self.header);
+                    let result_id = inst.result_id.replace(loaded);
+                    inst.result_type = Some(pointee);
+                    insts.push(inst);
+                    self.convert(insts, loaded, pointee, false, result_id);
+                    return;
+                }
+            }
+            Op::Store => {
+                if let Some(pointee) = external_pointee(self, &inst.operands[0]) {
+                    let value = inst.operands[1].unwrap_id_ref();
+                    let value = self.convert(insts, value, pointee, true, None);
+                    inst.operands[1] = Operand::IdRef(value);
+                    insts.push(inst);
+                    return;
+                }
+            }
+            Op::CopyMemory | Op::CopyMemorySized => {
+                let external = |cx: &Self, operand| external_pointee(cx, operand).is_some();
+                if external(self, &inst.operands[0]) != external(self, &inst.operands[1]) {
+                    let pointee = self.pointer_types[&operand_type(&inst.operands[0]).unwrap()].1;
+                    self.unsupported_in(inst.class.opcode, pointee);
+                }
+            }
+            _ => {}
+        }
+
+        let opcode = inst.class.opcode;
+        let small_result = self.small_int(result_type);
+        let small_operands = inst
+            .operands
+            .iter()
+            .map(|operand| self.small_int(operand_type(operand)))
+            .collect::<Vec<_>>();
+        let width = match small_result.or_else(|| small_operands.iter().find_map(|&x| x)) {
+            Some((width, _)) => width,
+            None => {
+                // Vectors of small integers are only supported by operations that don't care
+                // about the upper bits of their components.
+                if !type_agnostic(opcode) {
+                    let small_vector = result_type
+                        .into_iter()
+                        .chain(inst.operands.iter().filter_map(operand_type))
+                        .find_map(|ty| self.small_vector_width(ty));
+                    if let Some(width) = small_vector {
+                        self.unsupported(opcode, width);
+                    }
+                }
+                insts.push(inst);
+                return;
+            }
+        };
+
+        // Reinterprets operands as unsigned (zero-extended) or signed (sign-extended), for
+        // operations that care about their upper bits.
+        let mut extend_operands = |cx: &mut Self, signed: bool, count: usize| {
+            for (operand, small) in inst.operands.iter_mut().zip(&small_operands).take(count) {
+                if let Some((width, operand_signed)) = *small {
+                    if operand_signed != signed {
+                        let value = operand.unwrap_id_ref();
+                        let ty = cx.promoted_type(operand_type(operand).unwrap());
+                        *operand = Operand::IdRef(cx.extend(insts, value, ty, width, signed, None));
+                    }
+                }
+            }
+        };
+        match opcode {
+            opcode if type_agnostic(opcode) => {}
+
+            Op::ShiftRightLogical => extend_operands(self, false, 1),
+            Op::ShiftRightArithmetic => extend_operands(self, true, 1),
+            Op::UDiv
+            | Op::UMod
+            | Op::IEqual
+            | Op::INotEqual
+            | Op::ULessThan
+            | Op::ULessThanEqual
+            | Op::UGreaterThan
+            | Op::UGreaterThanEqual
+            | Op::ConvertUToF
+            | Op::BitCount => extend_operands(self, false, 2),
+            Op::SDiv
+            | Op::SRem
+            | Op::SMod
+            | Op::SLessThan
+            | Op::SLessThanEqual
+            | Op::SGreaterThan
+            | Op::SGreaterThanEqual
+            | Op::ConvertSToF => extend_operands(self, true, 2),
+            Op::IAdd
+            | Op::ISub
+            | Op::IMul
+            | Op::SNegate
+            | Op::Not
+            | Op::ShiftLeftLogical
+            | Op::BitwiseAnd
+            | Op::BitwiseOr
+            | Op::BitwiseXor => {}
+            // Only the (now identical) signedness can change between small integers.
+            Op::Bitcast if small_result.is_some() && small_operands[0].is_some() => {}
+
+            Op::UConvert | Op::SConvert => {
+                self.promote_convert(inst, result_type.unwrap(), value_types, insts);
+                return;
+            }
+
+            opcode => {
+                self.unsupported(opcode, width);
+                insts.push(inst);
+                return;
+            }
+        }
+
+        // Truncate the result back to its original width, if it may have overflowed.
+        match small_result {
+            Some((width, signed)) if !type_agnostic(opcode) && opcode != Op::BitCount => {
+                let result_id = inst.result_id.replace(id(self.header));
+                let value = inst.result_id.unwrap();
+                let ty = inst.result_type.unwrap();
+                insts.push(inst);
+                self.extend(insts, value, ty, width, signed, result_id);
+            }
+            _ => insts.push(inst),
+        }
+    }
+
+    /// Reports `opcode` as unsupported on `ty`, a type containing small integers.
+    fn unsupported_in(&mut self, opcode: Op, ty: Word) {
+        let width = self.small_width_in(ty);
+        self.unsupported(opcode, width);
+    }
+
+    fn small_width_in(&self, ty: Word) -> u32 {
+        if let Some((width, _)) = self.small_int(Some(ty)) {
+            return width;
+        }
+        self.memory_types
+            .get(&ty)
+            .and_then(|inst| {
+                inst.operands
+                    .iter()
+                    .filter_map(|operand| operand.id_ref_any())
+                    .find(|member| self.promoted.contains_key(member))
+            })
+            .map_or(8, |member| self.small_width_in(member))
+    }
+
+    fn small_vector_width(&self, ty: Word) -> Option<u32> {
+        let inst = self.memory_types.get(&ty)?;
+        if inst.class.opcode == Op::TypeVector {
+            Some(self.small_int(Some(inst.operands[0].unwrap_id_ref()))?.0)
+        } else {
+            None
+        }
+    }
+
+    /// Sign-extends (if `signed`) or zero-extends the lowest `width` bits of `value`, of type
+    /// `ty` (a 32-bit integer), to the whole 32 bits.
+    fn extend(
+        &mut self,
+        insts: &mut Vec<Instruction>,
+        value: Word,
+        ty: Word,
+        width: u32,
+        signed: bool,
+        result_id: Option<Word>,
+    ) -> Word {
+        let result_id = result_id.unwrap_or_else(|| id(self.header));
+        if signed {
+            let shift = self.u32_constant(32 - width);
+            let shifted = id(self.header);
+            insts.push(Instruction::new(
+                Op::ShiftLeftLogical,
+                Some(ty),
+                Some(shifted),
+                vec![Operand::IdRef(value), Operand::IdRef(shift)],
+            ));
+            insts.push(Instruction::new(
+                Op::ShiftRightArithmetic,
+                Some(ty),
+                Some(result_id),
+                vec![Operand::IdRef(shifted), Operand::IdRef(shift)],
+            ));
+        } else {
+            let mask = self.u32_constant((1 << width) - 1);
+            insts.push(Instruction::new(
+                Op::BitwiseAnd,
+                Some(ty),
+                Some(result_id),
+                vec![Operand::IdRef(value), Operand::IdRef(mask)],
+            ));
+        }
+        result_id
+    }
+
+    /// `OpUConvert`/`OpSConvert` from or to a small integer type, which is now either a no-op, a
+    /// truncation to the original width, or a conversion between 32 and 64 bits.
+    fn promote_convert(
+        &mut self,
+        mut inst: Instruction,
+        result_type: Word,
+        value_types: &HashMap<Word, Word>,
+        insts: &mut Vec<Instruction>,
+    ) {
+        let signed_extend = inst.class.opcode == Op::SConvert;
+        let from_type = value_types[&inst.operands[0].unwrap_id_ref()];
+        let mut value = inst.operands[0].unwrap_id_ref();
+        if let Some((width, signed)) = self.small_int(Some(from_type)) {
+            if signed != signed_extend {
+                let ty = self.promoted_type(from_type);
+                value = self.extend(insts, value, ty, width, signed_extend, None);
+            }
+        }
+        let (from_width, _) = self.int_types[&from_type];
+        let promoted_result_type = inst.result_type.unwrap();
+        let result_id = inst.result_id;
+        match self.small_int(Some(result_type)) {
+            Some((width, signed)) => {
+                if from_width == 64 {
+                    let truncated = id(self.header);
+                    insts.push(Instruction::new(
+                        Op::UConvert,
+                        Some(promoted_result_type),
+                        Some(truncated),
+                        vec![Operand::IdRef(value)],
+                    ));
+                    value = truncated;
+                }
+                self.extend(insts, value, promoted_result_type, width, signed, result_id);
+            }
+            None => {
+                if self.int_types[&result_type].0 == 32 {
+                    // Both are 32-bit integers now, only their signedness can differ.
+                    let opcode = if self.int_types[&from_type].1 == self.int_types[&result_type].1 {
+                        Op::CopyObject
+                    } else {
+                        Op::Bitcast
+                    };
+                    insts.push(Instruction::new(
+                        opcode,
+                        Some(result_type),
+                        result_id,
+                        vec![Operand::IdRef(value)],
+                    ));
+                } else {
+                    inst.operands[0] = Operand::IdRef(value);
+                    insts.push(inst);
+                }
+            }
+        }
+    }
+
+    /// Converts `value` between the memory type `ty` (containing small integers) and its
+    /// promoted equivalent, in the direction given by `to_memory`.
+    fn convert(
+        &mut self,
+        insts: &mut Vec<Instruction>,
+        value: Word,
+        ty: Word,
+        to_memory: bool,
+        result_id: Option<Word>,
+    ) -> Word {
+        let result_id = result_id.unwrap_or_else(|| id(self.header));
+        let result_type = if to_memory { ty } else { self.promoted[&ty] };
+        let emit = |insts: &mut Vec<Instruction>, opcode, operands| {
+            insts.push(Instruction::new(
+                opcode,
+                Some(result_type),
+                Some(result_id),
+                operands,
+            ));
+            result_id
+        };
+
+        let memory_type = self.memory_types[&ty].clone();
+        let element = match memory_type.class.opcode {
+            Op::TypeInt => Some(ty),
+            Op::TypeVector => Some(memory_type.operands[0].unwrap_id_ref()),
+            _ => None,
+        };
+        if let Some(element) = element {
+            let (_, signed) = self.int_types[&element];
+            let opcode = if signed { Op::SConvert } else { Op::UConvert };
+            return emit(insts, opcode, vec![Operand::IdRef(value)]);
+        }
+
+        let members = match memory_type.class.opcode {
+            Op::TypeStruct => memory_type
+                .operands
+                .iter()
+                .map(|member| member.unwrap_id_ref())
+                .collect::<Vec<_>>(),
+            Op::TypeArray => {
+                let element = memory_type.operands[0].unwrap_id_ref();
+                let length = memory_type.operands[1].unwrap_id_ref();
+                match self.u32_constant_values.get(&length) {
+                    Some(&length) => vec![element; length as usize],
+                    None => {
+                        self.unsupported_in(Op::Load, ty);
+                        return value;
+                    }
+                }
+            }
+            opcode => {
+                self.unsupported_in(opcode, ty);
+                return value;
+            }
+        };
+        let parts = members
+            .into_iter()
+            .enumerate()
+            .map(|(index, member)| {
+                let part = id(self.header);
+                let part_type = if to_memory {
+                    self.promoted_type(member)
+                } else {
+                    member
+                };
+                insts.push(Instruction::new(
+                    Op::CompositeExtract,
+                    Some(part_type),
+                    Some(part),
+                    vec![Operand::IdRef(value), Operand::LiteralInt32(index as u32)],
+                ));
+                if self.promoted.contains_key(&member) {
+                    self.convert(insts, part, member, to_memory, None)
+                } else {
+                    part
+                }
+            })
+            .map(Operand::IdRef)
+            .collect();
+        emit(insts, Op::CompositeConstruct, parts)
+    }
+}
+
+/// Operations whose results don't depend on the upper bits of their (promoted) operands, and
+/// produce results without any set (beyond the sign extension of signed integers).
+fn type_agnostic(opcode: Op) -> bool {
+    matches!(
+        opcode,
+        Op::Load
+            | Op::Store
+            | Op::CopyMemory
+            | Op::CopyMemorySized
+            | Op::Phi
+            | Op::Select
+            | Op::FunctionCall
+            | Op::ReturnValue
+            | Op::CompositeConstruct
+            | Op::CompositeExtract
+            | Op::CompositeInsert
+            | Op::CopyObject
+            | Op::Undef
+            | Op::AccessChain
+            | Op::InBoundsAccessChain
+            | Op::PtrAccessChain
+            | Op::InBoundsPtrAccessChain
+            | Op::VectorExtractDynamic
+            | Op::VectorInsertDynamic
+            | Op::VectorShuffle
+            | Op::Switch
+            // Rust's saturating casts already clamp the float to the integer's range.
+            | Op::ConvertFToU
+            | Op::ConvertFToS
+    )
+}
@@ -8,54 +8,32 @@
 //! Rust's pointer/reference types don't have an "address space" distinction,
 //! and we also wouldn't want users to annotate every single type anyway.
 //!
-//! # Future plans
+//! # Recursive data types
 //!
-//! Recursive data types (using `OpTypeForwardPointer`) are not supported, but
-//! here is an outline of how that could work:
+//! Recursive data types (using `OpTypeForwardPointer`) are supported as follows:
 //! * groups of mutually-recursive `OpTypeForwardPointer`s are computed via SCCs
 //! * each mutual-recursive group gets a single "generic" parameter count, that all
 //!   pointer types in the group will use, and which is the sum of the "generic"
 //!   parameters of all the leaves referenced by the pointer types in the group,
 //!   ignoring the pointer types in the group themselves
-//! * once the pointer types have been assigned their "g"eneric parameter count,
+//! * once the pointer types have been assigned their "generic" parameter count,
 //!   the non-pointer types in each SCC - i.e. (indirectly) referenced by one of
 //!   the pointer types in the group, and which in turn (indirectly) references
 //!   a pointer type in the group - can have their "generic" parameters computed
-//!   as normal, taking care to record where in the combined lists of "generic"
-//!   parameters, any of the pointer types in the group show up
-//! * each pointer type in the group will "fan out" a copy of its full set of
+//!   as normal, as every mention of a pointer type in the group simply takes
+//!   all of the group's "generic" parameters
+//! * each pointer type in the group "fans out" a copy of its full set of
 //!   "generic" parameters to every (indirect) mention of any pointer type in
-//!   the group, using an additional parameter remapping, for which `Generic`:
-//!   * requires this extra documentation:
-//!     ```
-//!     /// The one exception are `OpTypePointer`s involved in recursive data types
-//!     /// (i.e. they were declared by `OpTypeForwardPointer`s, and their pointees are
-//!     /// `OpTypeStruct`s that have the same pointer type as a leaf).
-//!     /// As the pointee `OpTypeStruct` has more parameters than the pointer (each leaf
-//!     /// use of the same pointer type requires its own copy of the pointer parameters),
-//!     /// a mapping (`expand_params`) indicates how to create the flattened list.
-//!     ```
-//!   * and this extra field:
-//!     ```
-//!     /// For every entry in the regular flattened list of parameters expected by
-//!     /// operands, this contains the parameter index (i.e. `0..self.param_count`)
-//!     /// to use for that parameter.
-//!     ///
-//!     /// For example, to duplicate `5` parameters into `10`, `expand_params`
-//!     /// would be `[0, 1, 2, 3, 4, 0, 1, 2, 3, 4]`.
-//!     ///
-//!     /// See also `Generic` documentation above for why this is needed
-//!     /// (i.e. to replicate parameters for recursive data types).
-//!     expand_params: Option<Vec<usize>>,
-//!     ```
+//!   the group, using an additional parameter remapping (`Generic::expand_params`)
 
 use crate::spirv_type_constraints::{self, InstSig, StorageClassPat, TyListPat, TyPat};
 use indexmap::{IndexMap, IndexSet};
 use rspirv::dr::{Builder, Function, Instruction, Module, Operand};
-use rspirv::spirv::{Op, StorageClass, Word};
+use rspirv::spirv::{AddressingModel, Op, StorageClass, Word};
 use rustc_data_structures::captures::Captures;
 use smallvec::SmallVec;
-use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::convert::{TryFrom, TryInto};
 use std::ops::{Range, RangeTo};
 use std::{fmt, io, iter, mem, slice};
@@ -124,8 +102,16 @@ pub fn specialize(module: Module, specialization: impl Specialization) -> Module
             .collect();
     }
 
+    let addressing_model = module
+        .memory_model
+        .as_ref()
+        .map_or(AddressingModel::Logical, |inst| {
+            inst.operands[0].unwrap_addressing_model()
+        });
+
     let mut specializer = Specializer {
         specialization,
+        addressing_model,
 
         debug,
         debug_names,
@@ -548,9 +534,27 @@ impl Replacements {
 ///
 /// As an example, for `%T = OpTypeStruct %A %B`, if `%A` and `%B` have 2 and 3
 /// parameters, respectively, `%T` will have `A0, A1, B0, B1, B2` as parameters.
+///
+/// The one exception are `OpTypePointer`s involved in recursive data types
+/// (i.e. they were declared by `OpTypeForwardPointer`s, and their pointees are
+/// `OpTypeStruct`s that have the same pointer type as a leaf).
+/// As the pointee `OpTypeStruct` has more parameters than the pointer (each leaf
+/// use of the same pointer type requires its own copy of the pointer parameters),
+/// a mapping (`expand_params`) indicates how to create the flattened list.
 struct Generic {
     param_count: u32,
 
+    /// For every entry in the regular flattened list of parameters expected by
+    /// operands, this contains the parameter index (i.e. `0..self.param_count`)
+    /// to use for that parameter.
+    ///
+    /// For example, to duplicate `5` parameters into `10`, `expand_params`
+    /// would be `[0, 1, 2, 3, 4, 0, 1, 2, 3, 4]`.
+    ///
+    /// See also `Generic` documentation above for why this is needed
+    /// (i.e. to replicate parameters for recursive data types).
+    expand_params: Option<Vec<u32>>,
+
     /// Defining instruction for this global (`OpType...`, `OpConstant...`, etc.)
     /// or function (`OpFunction`).
     // FIXME(eddyb) consider using `SmallVec` for the operands, or converting
@@ -572,9 +576,51 @@ struct Generic {
     replacements: Replacements,
 }
 
+impl Generic {
+    /// Map the "generic" args of an instance of this `Generic` to the ones
+    /// expected by the operands of its defining instruction (see `expand_params`).
+    fn def_generic_args(&self, generic_args: InferVarList) -> InferVarList {
+        match &self.expand_params {
+            Some(expand_params) => generic_args.remap(expand_params),
+            None => generic_args,
+        }
+    }
+
+    /// Like `def_generic_args`, but for the concrete "generic" args of an instance
+    /// (i.e. the ones the `Replacements` of this `Generic` expect).
+    fn def_concrete_generic_args<'a>(
+        &self,
+        generic_args: &'a [CopyOperand],
+    ) -> Cow<'a, [CopyOperand]> {
+        match &self.expand_params {
+            Some(expand_params) => expand_params
+                .iter()
+                .map(|&i| generic_args[i as usize])
+                .collect(),
+            None => Cow::Borrowed(generic_args),
+        }
+    }
+}
+
+/// Leaf (i.e. operand which takes "generic" parameters) reachable from
+/// the definition of a forward-declared pointer type, without going through
+/// the definitions of other forward-declared pointer types.
+enum ForwardDeclaredPointerLeaf {
+    /// An operand the specializer specializes by (i.e. one parameter).
+    Specialized,
+
+    /// A forward-declared pointer type, taking either the same parameters as
+    /// the pointer type it's reachable from (if they're mutually-recursive),
+    /// or its own, already computed, parameters (see `collect_generics`).
+    Pointer(Word),
+}
+
 struct Specializer<S: Specialization> {
     specialization: S,
 
+    /// Used to pick instruction signatures (see `spirv_type_constraints`).
+    addressing_model: AddressingModel,
+
     // FIXME(eddyb) use `log`/`tracing` instead.
     debug: bool,
 
@@ -607,6 +653,10 @@ impl<S: Specialization> Specializer<S> {
     }
 
     fn collect_generics(&mut self, module: &Module) {
+        // Forward-declared pointers can be used before their definitions,
+        // so their "generic" shapes have to be known ahead of time.
+        self.collect_forward_declared_pointer_generics(module);
+
         // Process all defining instructions for globals (types, constants,
         // and module-scoped variables), and functions' `OpFunction` instructions,
         // but note that for `OpFunction`s only the signature is considered,
@@ -616,8 +666,13 @@ impl<S: Specialization> Specializer<S> {
             .iter()
             .chain(module.functions.iter().filter_map(|f| f.def.as_ref()));
 
-        let mut forward_declared_pointers = HashSet::new();
         for inst in types_global_values_and_functions {
+            // `OpTypeForwardPointer` doesn't define anything by itself, and
+            // gets expanded alongside the `OpTypePointer` it declares.
+            if inst.class.opcode == Op::TypeForwardPointer {
+                continue;
+            }
+
             let result_id = inst.result_id.unwrap_or_else(|| {
                 unreachable!(
                     "Op{:?} is in `types_global_values` but not have a result ID",
@@ -625,18 +680,6 @@ impl<S: Specialization> Specializer<S> {
                 );
             });
 
-            if inst.class.opcode == Op::TypeForwardPointer {
-                forward_declared_pointers.insert(inst.operands[0].unwrap_id_ref());
-            }
-            if forward_declared_pointers.remove(&result_id) {
-                // HACK(eddyb) this is a forward-declared pointer, pretend
-                // it's not "generic" at all to avoid breaking the rest of
-                // the logic - see module-level docs for how this should be
-                // handled in the future to support recursive data types.
-                assert_eq!(inst.class.opcode, Op::TypePointer);
-                continue;
-            }
-
             // Record all integer `OpConstant`s (used for `IndexComposite`).
             if inst.class.opcode == Op::Constant {
                 if let Operand::LiteralInt32(x) = inst.operands[0] {
@@ -671,12 +714,32 @@ impl<S: Specialization> Specializer<S> {
                 )
             };
 
+            // Forward-declared pointers already have a `Generic` (which only
+            // lacks the `replacements`), with a parameter for every inference
+            // variable in `inst` (or fewer, if they were remapped).
+            if let Some(generic) = self.generics.get_mut(&result_id) {
+                assert_eq!(inst.class.opcode, Op::TypePointer);
+                assert_eq!(
+                    generic
+                        .expand_params
+                        .as_ref()
+                        .map_or(generic.param_count, |expand_params| expand_params.len()
+                            as u32),
+                    param_count
+                );
+                assert!(param_values.is_none());
+
+                generic.replacements = replacements;
+                continue;
+            }
+
             // Inference variables become "generic" parameters.
             if param_count > 0 {
                 self.generics.insert(
                     result_id,
                     Generic {
                         param_count,
+                        expand_params: None,
                         def: inst.clone(),
                         param_values,
                         replacements,
@@ -686,6 +749,194 @@ impl<S: Specialization> Specializer<S> {
         }
     }
 
+    /// Create the `Generic`s of all pointer types declared by `OpTypeForwardPointer`
+    /// (which may be used by recursive data types), ahead of `collect_generics`
+    /// reaching their definitions (at which point only `replacements` are added).
+    ///
+    /// Mutually-recursive pointer types form groups (SCCs), and all the pointer
+    /// types in a group share the same "generic" parameters: the concatenation,
+    /// for each pointer type, of the parameters of all the leaves it (indirectly)
+    /// references, other than pointer types in the same group. Every mention of
+    /// a pointer type in the same group then gets a copy of all of those parameters
+    /// (via `Generic::expand_params`), keeping the group finitely parameterized.
+    fn collect_forward_declared_pointer_generics(&mut self, module: &Module) {
+        let forward_declared_pointers: IndexSet<Word> = module
+            .types_global_values
+            .iter()
+            .filter(|inst| inst.class.opcode == Op::TypeForwardPointer)
+            .map(|inst| inst.operands[0].unwrap_id_ref())
+            .collect();
+        if forward_declared_pointers.is_empty() {
+            return;
+        }
+
+        let defs: HashMap<Word, &Instruction> = module
+            .types_global_values
+            .iter()
+            .filter_map(|inst| Some((inst.result_id?, inst)))
+            .collect();
+
+        // Flatten the leaves (i.e. the operands taking "generic" parameters)
+        // of each forward-declared pointer type's definition, in the same
+        // order `instantiate_instruction` would assign parameters to them.
+        let leaves: IndexMap<Word, Vec<ForwardDeclaredPointerLeaf>> = forward_declared_pointers
+            .iter()
+            .map(|&ptr| {
+                let mut leaves = vec![];
+                self.collect_forward_declared_pointer_leaves(
+                    &defs,
+                    &forward_declared_pointers,
+                    defs[&ptr],
+                    &mut leaves,
+                );
+                (ptr, leaves)
+            })
+            .collect();
+
+        // Compute the set of forward-declared pointer types each one can reach.
+        let reachable: IndexMap<Word, IndexSet<Word>> = leaves
+            .keys()
+            .map(|&ptr| {
+                let mut reachable = IndexSet::new();
+                let mut queue = vec![ptr];
+                while let Some(ptr) = queue.pop() {
+                    for leaf in &leaves[&ptr] {
+                        if let &ForwardDeclaredPointerLeaf::Pointer(other) = leaf {
+                            if reachable.insert(other) {
+                                queue.push(other);
+                            }
+                        }
+                    }
+                }
+                (ptr, reachable)
+            })
+            .collect();
+
+        // Process groups in dependency order: if `P` reaches `Q` but not
+        // the other way around, `Q` reaches strictly fewer pointer types
+        // (or as many, if `P` isn't recursive itself, but `Q` is).
+        let mut ordered_pointers: Vec<Word> = forward_declared_pointers.iter().copied().collect();
+        ordered_pointers.sort_by_key(|ptr| {
+            let reachable = &reachable[ptr];
+            (reachable.len(), !reachable.contains(ptr))
+        });
+
+        for ptr in ordered_pointers {
+            if self.generics.contains_key(&ptr) {
+                continue;
+            }
+
+            let group: IndexSet<Word> = iter::once(ptr)
+                .chain(
+                    reachable[&ptr]
+                        .iter()
+                        .copied()
+                        .filter(|other| reachable[other].contains(&ptr)),
+                )
+                .collect();
+
+            // Every pointer type in `group` gets a contiguous block of parameters,
+            // for all of its leaves, other than pointer types in the same group.
+            let params_needed_by_leaf = |leaf: &ForwardDeclaredPointerLeaf| match *leaf {
+                ForwardDeclaredPointerLeaf::Specialized => 1,
+                ForwardDeclaredPointerLeaf::Pointer(other) if group.contains(&other) => 0,
+                ForwardDeclaredPointerLeaf::Pointer(other) => self
+                    .generics
+                    .get(&other)
+                    .map_or(0, |generic| generic.param_count),
+            };
+            let mut param_count = 0;
+            let mut expand_params_per_pointer = vec![];
+            for &ptr in &group {
+                let block_start = param_count;
+                param_count += leaves[&ptr].iter().map(params_needed_by_leaf).sum::<u32>();
+                expand_params_per_pointer.push((ptr, block_start));
+            }
+
+            if param_count == 0 {
+                continue;
+            }
+
+            let generics: Vec<_> = expand_params_per_pointer
+                .into_iter()
+                .map(|(ptr, block_start)| {
+                    let mut next_param = block_start;
+                    let mut expand_params = vec![];
+                    for leaf in &leaves[&ptr] {
+                        match *leaf {
+                            ForwardDeclaredPointerLeaf::Pointer(other)
+                                if group.contains(&other) =>
+                            {
+                                expand_params.extend(0..param_count);
+                            }
+                            _ => {
+                                let needed = params_needed_by_leaf(leaf);
+                                expand_params.extend(next_param..next_param + needed);
+                                next_param += needed;
+                            }
+                        }
+                    }
+
+                    // Avoid remapping when it wouldn't change anything.
+                    let expand_params = if expand_params.iter().copied().eq(0..param_count) {
+                        None
+                    } else {
+                        Some(expand_params)
+                    };
+
+                    (
+                        ptr,
+                        Generic {
+                            param_count,
+                            expand_params,
+                            def: defs[&ptr].clone(),
+                            param_values: None,
+                            // Filled in by `collect_generics`, once it reaches `ptr`.
+                            replacements: Replacements {
+                                with_instance: IndexMap::new(),
+                                with_concrete_or_param: vec![],
+                            },
+                        },
+                    )
+                })
+                .collect();
+            self.generics.extend(generics);
+        }
+    }
+
+    /// Append to `leaves` all the leaves of `inst` (i.e. its *Result Type*,
+    /// if any, and operands), recursing into definitions, except for those of
+    /// forward-declared pointer types (see `ForwardDeclaredPointerLeaf`).
+    fn collect_forward_declared_pointer_leaves(
+        &self,
+        defs: &HashMap<Word, &Instruction>,
+        forward_declared_pointers: &IndexSet<Word>,
+        inst: &Instruction,
+        leaves: &mut Vec<ForwardDeclaredPointerLeaf>,
+    ) {
+        for operand in inst
+            .result_type
+            .map(Operand::IdRef)
+            .iter()
+            .chain(inst.operands.iter())
+        {
+            if self.specialization.specialize_operand(operand) {
+                leaves.push(ForwardDeclaredPointerLeaf::Specialized);
+            } else if let &Operand::IdRef(id) = operand {
+                if forward_declared_pointers.contains(&id) {
+                    leaves.push(ForwardDeclaredPointerLeaf::Pointer(id));
+                } else if let Some(def) = defs.get(&id) {
+                    self.collect_forward_declared_pointer_leaves(
+                        defs,
+                        forward_declared_pointers,
+                        def,
+                        leaves,
+                    );
+                }
+            }
+        }
+    }
+
     /// Perform inference across the entire definition of `func`, including all
     /// the instructions in its body, and either store the resulting `Replacements`
     /// in its `Generic` (if `func` is "generic"), or return them otherwise.
@@ -753,6 +1004,61 @@ impl InferVar {
     }
 }
 
+/// List of `InferVar`s, used as the "generic" args of an `InferOperand::Instance`
+/// (or an `InferOperandList`). This is almost always a contiguous range, but
+/// the operands of recursive pointer types need their "generic" args remapped
+/// (see `Generic::expand_params`), which can repeat and/or reorder them.
+#[derive(Clone, Debug, PartialEq)]
+enum InferVarList {
+    Range(Range<InferVar>),
+    Remapped(SmallVec<[InferVar; 4]>),
+}
+
+impl InferVarList {
+    fn len(&self) -> u32 {
+        match self {
+            Self::Range(range) => range.end.0 - range.start.0,
+            Self::Remapped(vars) => vars.len() as u32,
+        }
+    }
+
+    fn get(&self, i: u32) -> InferVar {
+        assert!(i < self.len());
+        match self {
+            Self::Range(range) => InferVar(range.start.0 + i),
+            Self::Remapped(vars) => vars[i as usize],
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = InferVar> + Clone + '_ {
+        (0..self.len()).map(move |i| self.get(i))
+    }
+
+    /// Split off the first `n` `InferVar`s, returning them and the rest.
+    fn split_at(self, n: u32) -> (Self, Self) {
+        match self {
+            Self::Range(range) => {
+                let split = InferVar(range.start.0 + n);
+                assert!(split <= range.end);
+                (
+                    Self::Range(range.start..split),
+                    Self::Range(split..range.end),
+                )
+            }
+            Self::Remapped(mut vars) => {
+                let rest = vars.drain(n as usize..).collect();
+                (Self::Remapped(vars), Self::Remapped(rest))
+            }
+        }
+    }
+
+    /// Apply `expand_params` (see `Generic::expand_params`), i.e. map every
+    /// parameter index in `expand_params` to the `InferVar` at that index.
+    fn remap(&self, expand_params: &[u32]) -> Self {
+        Self::Remapped(expand_params.iter().map(|&i| self.get(i)).collect())
+    }
+}
+
 struct InferCx<'a, S: Specialization> {
     specializer: &'a Specializer<S>,
 
@@ -773,7 +1079,7 @@ struct InferCx<'a, S: Specialization> {
 
     /// Operands that need to be replaced with instances of "generic" globals/functions
     /// (taking as "generic" arguments the results of inference).
-    instantiated_operands: Vec<(OperandLocation, Instance<Range<InferVar>>)>,
+    instantiated_operands: Vec<(OperandLocation, Instance<InferVarList>)>,
 
     /// Operands that need to be replaced with results of inference.
     inferred_operands: Vec<(OperandLocation, InferVar)>,
@@ -797,7 +1103,7 @@ enum InferOperand {
     Unknown,
     Var(InferVar),
     Concrete(CopyOperand),
-    Instance(Instance<Range<InferVar>>),
+    Instance(Instance<InferVarList>),
 }
 
 impl InferOperand {
@@ -806,12 +1112,11 @@ impl InferOperand {
     /// Also returns the remaining inference variables, not used by this operand.
     fn from_operand_and_generic_args(
         operand: &Operand,
-        generic_args: Range<InferVar>,
+        generic_args: InferVarList,
         cx: &InferCx<'_, impl Specialization>,
-    ) -> (Self, Range<InferVar>) {
+    ) -> (Self, InferVarList) {
         let (needed, generic) = cx.specializer.params_needed_by(operand);
-        let split = InferVar(generic_args.start.0 + needed);
-        let (generic_args, rest) = (generic_args.start..split, split..generic_args.end);
+        let (generic_args, rest) = generic_args.split_at(needed);
         (
             if generic.is_some() {
                 Self::Instance(Instance {
@@ -822,7 +1127,7 @@ impl InferOperand {
                 CopyOperand::try_from(operand).map_or(Self::Unknown, Self::Concrete)
             } else {
                 assert_eq!(needed, 1);
-                Self::Var(generic_args.start)
+                Self::Var(generic_args.get(0))
             },
             rest,
         )
@@ -850,9 +1155,7 @@ impl InferOperand {
                 Self::Instance(instance) => write!(
                     f,
                     "{}",
-                    instance.display(|generic_args| {
-                        InferVar::range_iter(generic_args).map(var_with_value)
-                    })
+                    instance.display(|generic_args| generic_args.iter().map(var_with_value))
                 ),
             }
         })
@@ -904,9 +1207,9 @@ enum InferOperandListTransform {
 struct InferOperandList<'a> {
     operands: &'a [Operand],
 
-    /// Joined ranges of all `InferVar`s needed by individual `Operand`s,
+    /// Joined lists of all `InferVar`s needed by individual `Operand`s,
     /// either for `InferOperand::Instance` or `InferOperand::Var`.
-    all_generic_args: Range<InferVar>,
+    all_generic_args: InferVarList,
 
     transform: Option<InferOperandListTransform>,
 }
@@ -972,7 +1275,7 @@ impl<'a> InferOperandList<'a> {
                             Some(type_of_result) => {
                                 InferOperand::from_operand_and_generic_args(
                                     &Operand::IdRef(type_of_result),
-                                    instance.generic_args,
+                                    generic.def_generic_args(instance.generic_args),
                                     cx,
                                 )
                                 .0
@@ -1079,6 +1382,10 @@ struct Match<'a> {
     /// `StorageClassPat::Var(i)` (currently `i` is always `0`, aka `StorageClassPat::S`).
     storage_class_var_found: SmallIntMap<[SmallVec<[InferOperand; 2]>; 1]>,
 
+    /// `storage_class_concrete_found[..]` holds all the `InferOperand`s matched by
+    /// `StorageClassPat::Concrete(_)`, alongside the storage class they must equal.
+    storage_class_concrete_found: SmallVec<[(StorageClass, InferOperand); 1]>,
+
     /// `ty_var_found[i][..]` holds all the `InferOperand`s matched by
     /// `TyPat::Var(i)` (currently `i` is always `0`, aka `TyPat::T`).
     ty_var_found: SmallIntMap<[SmallVec<[InferOperand; 4]>; 1]>,
@@ -1099,6 +1406,7 @@ impl<'a> Match<'a> {
         let Match {
             ambiguous,
             storage_class_var_found,
+            storage_class_concrete_found,
             ty_var_found,
             index_composite_ty_var_found,
             ty_list_var_found,
@@ -1110,6 +1418,7 @@ impl<'a> Match<'a> {
                 .get_mut_or_default(i)
                 .extend(other_found);
         }
+        storage_class_concrete_found.extend(other.storage_class_concrete_found);
         for (i, other_found) in other.ty_var_found {
             ty_var_found.get_mut_or_default(i).extend(other_found);
         }
@@ -1130,6 +1439,7 @@ impl<'a> Match<'a> {
         let Match {
             ambiguous,
             storage_class_var_found,
+            storage_class_concrete_found,
             ty_var_found,
             index_composite_ty_var_found,
             ty_list_var_found,
@@ -1144,6 +1454,7 @@ impl<'a> Match<'a> {
                 .unwrap_or(&[]);
             self_found.retain(|x| other_found.contains(x));
         }
+        storage_class_concrete_found.retain(|x| other.storage_class_concrete_found.contains(x));
         for (i, self_found) in ty_var_found {
             let other_found = other.ty_var_found.get(i).map(|xs| &xs[..]).unwrap_or(&[]);
             self_found.retain(|x| other_found.contains(x));
@@ -1194,6 +1505,7 @@ impl<'a> Match<'a> {
             let Self {
                 ambiguous,
                 storage_class_var_found,
+                storage_class_concrete_found,
                 ty_var_found,
                 index_composite_ty_var_found,
                 ty_list_var_found,
@@ -1203,6 +1515,18 @@ impl<'a> Match<'a> {
             list.entries(debug_var_found(storage_class_var_found, &move |operand| {
                 operand.display_with_infer_cx(cx)
             }));
+            list.entries(storage_class_concrete_found.iter().map(
+                move |(storage_class, operand)| {
+                    FmtBy(move |f| {
+                        write!(
+                            f,
+                            "{} = {:?}",
+                            operand.display_with_infer_cx(cx),
+                            storage_class
+                        )
+                    })
+                },
+            ));
             list.entries(debug_var_found(ty_var_found, &move |operand| {
                 operand.display_with_infer_cx(cx)
             }));
@@ -1276,6 +1600,12 @@ impl<'a, S: Specialization> InferCx<'a, S> {
                     .push(storage_class);
                 m
             }
+            &StorageClassPat::Concrete(expected) => {
+                let mut m = Match::default();
+                m.storage_class_concrete_found
+                    .push((expected, storage_class));
+                m
+            }
         }
     }
 
@@ -1331,7 +1661,7 @@ impl<'a, S: Specialization> InferCx<'a, S> {
 
                 let ty_operands = InferOperandList {
                     operands: &generic.def.operands,
-                    all_generic_args: instance.generic_args,
+                    all_generic_args: generic.def_generic_args(instance.generic_args),
                     transform: None,
                 };
                 let simple = |op, inner_pat| {
@@ -1440,7 +1770,7 @@ impl<'a, S: Specialization> InferCx<'a, S> {
         &self,
         sig: &InstSig<'_>,
         inst: &'a Instruction,
-        inputs_generic_args: Range<InferVar>,
+        inputs_generic_args: InferVarList,
         result_type: Option<InferOperand>,
     ) -> Result<Match<'a>, Unapplicable> {
         let mut m = Match::default();
@@ -1521,7 +1851,7 @@ impl<'a, S: Specialization> InferCx<'a, S> {
         &self,
         sigs: &[InstSig<'_>],
         inst: &'a Instruction,
-        inputs_generic_args: Range<InferVar>,
+        inputs_generic_args: InferVarList,
         result_type: Option<InferOperand>,
     ) -> Result<Match<'a>, Unapplicable> {
         let mut result = Err(Unapplicable);
@@ -1633,26 +1963,34 @@ impl<'a, S: Specialization> InferCx<'a, S> {
         Ok(older)
     }
 
-    /// Enforce that `a = b`, returning a combined `Range<InferVar>`, if successful.
-    fn equate_infer_var_ranges(
+    /// Enforce that `a = b`, returning a combined `InferVarList`, if successful.
+    fn equate_infer_var_lists(
         &mut self,
-        a: Range<InferVar>,
-        b: Range<InferVar>,
-    ) -> Result<Range<InferVar>, InferError> {
+        a: InferVarList,
+        b: InferVarList,
+    ) -> Result<InferVarList, InferError> {
         if a == b {
             return Ok(a);
         }
 
-        assert_eq!(a.end.0 - a.start.0, b.end.0 - b.start.0);
+        assert_eq!(a.len(), b.len());
 
-        for (a, b) in InferVar::range_iter(&a).zip(InferVar::range_iter(&b)) {
+        for (a, b) in a.iter().zip(b.iter()) {
             self.equate_infer_vars(a, b)?;
         }
 
         // Pick the "oldest" range to maintain the invariant that "newer" variables
         // are redirected to "older" ones, while keeping a contiguous range
         // (instead of splitting it into individual variables), for performance.
-        Ok(if a.start < b.start { a } else { b })
+        Ok(match (&a, &b) {
+            (InferVarList::Range(a_range), InferVarList::Range(b_range))
+                if b_range.start < a_range.start =>
+            {
+                b
+            }
+            (InferVarList::Remapped(_), InferVarList::Range(_)) => b,
+            _ => a,
+        })
     }
 
     /// Enforce that `a = b`, returning a combined `InferOperand`, if successful.
@@ -1684,7 +2022,7 @@ impl<'a, S: Specialization> InferCx<'a, S> {
                 }
                 InferOperand::Instance(Instance {
                     generic_id: a_id,
-                    generic_args: self.equate_infer_var_ranges(a_args, b_args)?,
+                    generic_args: self.equate_infer_var_lists(a_args, b_args)?,
                 })
             }
 
@@ -1750,7 +2088,7 @@ impl<'a, S: Specialization> InferCx<'a, S> {
             let ty_opcode = generic.def.class.opcode;
             let ty_operands = InferOperandList {
                 operands: &generic.def.operands,
-                all_generic_args: instance.generic_args,
+                all_generic_args: generic.def_generic_args(instance.generic_args),
                 transform: None,
             };
 
@@ -1790,6 +2128,7 @@ impl<'a, S: Specialization> InferCx<'a, S> {
             ambiguous: _,
 
             storage_class_var_found,
+            storage_class_concrete_found,
             ty_var_found,
             index_composite_ty_var_found,
             ty_list_var_found,
@@ -1802,6 +2141,13 @@ impl<'a, S: Specialization> InferCx<'a, S> {
             }
         }
 
+        for (expected, found) in storage_class_concrete_found {
+            self.equate_infer_operands(
+                InferOperand::Concrete(CopyOperand::StorageClass(expected)),
+                found,
+            )?;
+        }
+
         for (i, found) in ty_var_found {
             let mut found = found.into_iter();
             if let Some(first) = found.next() {
@@ -1896,14 +2242,14 @@ impl<'a, S: Specialization> InferCx<'a, S> {
                     .map(|(i, o)| (OperandIdx::Input(i), o)),
             )
         {
-            // HACK(eddyb) use `v..InferVar(u32::MAX)` as an open-ended range of sorts.
-            let (operand, rest) = InferOperand::from_operand_and_generic_args(
+            let (needed, _) = self.specializer.params_needed_by(operand);
+            let generic_args = all_generic_args.end..InferVar(all_generic_args.end.0 + needed);
+            all_generic_args.end = generic_args.end;
+            let (operand, _) = InferOperand::from_operand_and_generic_args(
                 operand,
-                all_generic_args.end..InferVar(u32::MAX),
+                InferVarList::Range(generic_args.clone()),
                 self,
             );
-            let generic_args = all_generic_args.end..rest.start;
-            all_generic_args.end = generic_args.end;
 
             let generic = match &operand {
                 InferOperand::Instance(instance) => {
@@ -1943,7 +2289,7 @@ impl<'a, S: Specialization> InferCx<'a, S> {
         if let Some(ret_ty) = record_fn_ret_ty {
             let (ret_ty, _) = InferOperand::from_operand_and_generic_args(
                 &Operand::IdRef(ret_ty),
-                all_generic_args.clone(),
+                InferVarList::Range(all_generic_args.clone()),
                 self,
             );
             self.record_instantiated_operand(
@@ -1960,19 +2306,19 @@ impl<'a, S: Specialization> InferCx<'a, S> {
             Some(type_of_result) => {
                 let (type_of_result, rest) = InferOperand::from_operand_and_generic_args(
                     &Operand::IdRef(type_of_result),
-                    all_generic_args.clone(),
+                    InferVarList::Range(all_generic_args.clone()),
                     self,
                 );
                 (
                     Some(type_of_result),
                     // HACK(eddyb) workaround for `OpFunction`, see earlier HACK commment.
                     match inst.class.opcode {
-                        Op::Function => all_generic_args,
+                        Op::Function => InferVarList::Range(all_generic_args),
                         _ => rest,
                     },
                 )
             }
-            None => (None, all_generic_args),
+            None => (None, InferVarList::Range(all_generic_args)),
         };
 
         let debug_dump_if_enabled = |cx: &Self, prefix| {
@@ -2011,7 +2357,10 @@ impl<'a, S: Specialization> InferCx<'a, S> {
         };
 
         // If we have some instruction signatures for `inst`, enforce them.
-        if let Some(sigs) = spirv_type_constraints::instruction_signatures(inst.class.opcode) {
+        if let Some(sigs) = spirv_type_constraints::instruction_signatures(
+            inst.class.opcode,
+            self.specializer.addressing_model,
+        ) {
             // HACK(eddyb) workaround for `OpFunction`, see earlier HACK commment.
             // (specifically, `type_of_result` isn't *Result Type* for `OpFunction`)
             assert_ne!(inst.class.opcode, Op::Function);
@@ -2224,7 +2573,9 @@ impl<'a, S: Specialization> InferCx<'a, S> {
             with_instance
                 .entry(Instance {
                     generic_id: instance.generic_id,
-                    generic_args: InferVar::range_iter(&instance.generic_args)
+                    generic_args: instance
+                        .generic_args
+                        .iter()
                         .map(|v| self.resolve_infer_var_to_concrete_or_param(v, generic_params))
                         .collect(),
                 })
@@ -2327,10 +2678,11 @@ impl<'a, S: Specialization> Expander<'a, S> {
     fn propagate_instances(&mut self) {
         while let Some(instance) = self.propagate_instances_queue.pop_back() {
             // Drain the iterator to generate all the `alloc_instance_id` calls.
-            for _ in self.specializer.generics[&instance.generic_id]
-                .replacements
-                .to_concrete(&instance.generic_args, |i| self.alloc_instance_id(i))
-            {}
+            let generic = &self.specializer.generics[&instance.generic_id];
+            for _ in generic.replacements.to_concrete(
+                &generic.def_concrete_generic_args(&instance.generic_args),
+                |i| self.alloc_instance_id(i),
+            ) {}
         }
     }
 
@@ -2422,28 +2774,52 @@ impl<'a, S: Specialization> Expander<'a, S> {
         // Expand `Op(Member)Decorate* %target ...`, when `target` is "generic".
         let expanded_annotations = expand_debug_or_annotation(annotations);
 
+        // Expand the definition of an instance of a "generic" global.
+        let expand_global =
+            |generic: &Generic, instance: &Instance<SmallVec<[CopyOperand; 4]>>, instance_id| {
+                let mut expanded_inst = generic.def.clone();
+                expanded_inst.result_id = Some(instance_id);
+                for (loc, operand) in generic.replacements.to_concrete(
+                    &generic.def_concrete_generic_args(&instance.generic_args),
+                    |i| self.instances[&i],
+                ) {
+                    expanded_inst.index_set(loc, operand.into());
+                }
+                expanded_inst
+            };
+
         // Expand "generic" globals (types, constants and module-scoped variables).
         let mut expanded_types_global_values =
             Vec::with_capacity(types_global_values.len().next_power_of_two());
         for inst in types_global_values {
-            if let Some(result_id) = inst.result_id {
-                if let Some(generic) = self.specializer.generics.get(&result_id) {
-                    expanded_types_global_values.extend(self.all_instances_of(result_id).map(
+            // `OpTypeForwardPointer %ptr StorageClass` has no *Result*, and instead
+            // gets expanded for every instance of `%ptr`, using its storage class.
+            if inst.class.opcode == Op::TypeForwardPointer {
+                let ptr = inst.operands[0].unwrap_id_ref();
+                if let Some(generic) = self.specializer.generics.get(&ptr) {
+                    expanded_types_global_values.extend(self.all_instances_of(ptr).map(
                         |(instance, &instance_id)| {
+                            let expanded_ptr = expand_global(generic, instance, instance_id);
                             let mut expanded_inst = inst.clone();
-                            expanded_inst.result_id = Some(instance_id);
-                            for (loc, operand) in generic
-                                .replacements
-                                .to_concrete(&instance.generic_args, |i| self.instances[&i])
-                            {
-                                expanded_inst.index_set(loc, operand.into());
-                            }
+                            expanded_inst.operands = vec![
+                                Operand::IdRef(instance_id),
+                                expanded_ptr.operands[0].clone(),
+                            ];
                             expanded_inst
                         },
                     ));
                     continue;
                 }
             }
+
+            if let Some(result_id) = inst.result_id {
+                if let Some(generic) = self.specializer.generics.get(&result_id) {
+                    expanded_types_global_values.extend(self.all_instances_of(result_id).map(
+                        |(instance, &instance_id)| expand_global(generic, instance, instance_id),
+                    ));
+                    continue;
+                }
+            }
             expanded_types_global_values.push(inst);
         }
 
@@ -2543,7 +2919,13 @@ impl<'a, S: Specialization> Expander<'a, S> {
                 .display(Param::range_iter),
                 generic.def.class.opcode
             )?;
-            let mut next_param = Param(0);
+            // NOTE: the parameters of the defining instruction's operands may
+            // be remapped (see `Generic::expand_params`).
+            let def_params: Vec<_> = match &generic.expand_params {
+                Some(expand_params) => expand_params.iter().map(|&i| Param(i)).collect(),
+                None => Param::range_iter(&(Param(0)..Param(generic.param_count))).collect(),
+            };
+            let mut next_param = 0;
             for operand in generic
                 .def
                 .result_type
@@ -2553,11 +2935,11 @@ impl<'a, S: Specialization> Expander<'a, S> {
             {
                 write!(w, " ")?;
                 let (needed, used_generic) = self.specializer.params_needed_by(operand);
-                let params = next_param..Param(next_param.0 + needed);
+                let params = &def_params[next_param..next_param + needed as usize];
 
                 // NOTE(eddyb) see HACK comment in `instantiate_instruction`.
                 if generic.def.class.opcode != Op::Function {
-                    next_param = params.end;
+                    next_param += needed as usize;
                 }
 
                 if used_generic.is_some() {
@@ -2568,10 +2950,10 @@ impl<'a, S: Specialization> Expander<'a, S> {
                             generic_id: operand.unwrap_id_ref(),
                             generic_args: params
                         }
-                        .display(Param::range_iter)
+                        .display(|generic_args| generic_args.iter().copied())
                     )?;
                 } else if needed == 1 {
-                    write!(w, "{}", params.start)?;
+                    write!(w, "{}", params[0])?;
                 } else {
                     write!(w, "{}", operand)?;
                 }
@@ -33,7 +33,6 @@ fn assemble_spirv(spirv: &str) -> Vec<u8> {
     contents.to_vec()
 }
 
-#[allow(unused)]
 fn validate(spirv: &[u32]) {
     use spirv_tools::val::{self, Validator};
 
@@ -50,7 +49,29 @@ fn load(bytes: &[u8]) -> Module {
     loader.module()
 }
 
+fn default_options() -> Options {
+    Options {
+        compact_ids: true,
+        dce: false,
+        inline: false,
+        inline_threshold: 0,
+        mem2reg: false,
+        opt: false,
+        structurize: false,
+        emit_multiple_modules: false,
+        emulate_int64: false,
+        narrow_float64: false,
+        promote_small_ints: false,
+        keep_src_locs: false,
+        library_exports: None,
+    }
+}
+
 fn assemble_and_link(binaries: &[&[u8]]) -> Result<Module, String> {
+    assemble_and_link_with_options(binaries, &default_options())
+}
+
+fn assemble_and_link_with_options(binaries: &[&[u8]], opts: &Options) -> Result<Module, String> {
     let modules = binaries.iter().cloned().map(load).collect::<Vec<_>>();
 
     // need pipe here because Config takes ownership of the writer, and the writer must be 'static.
@@ -85,19 +106,7 @@ fn assemble_and_link(binaries: &[&[u8]]) -> Result<Module, String> {
         registry: Registry::new(&[]),
     };
     rustc_interface::interface::run_compiler(config, |compiler| {
-        let res = link(
-            compiler.session(),
-            modules,
-            &Options {
-                compact_ids: true,
-                dce: false,
-                inline: false,
-                mem2reg: false,
-                structurize: false,
-                use_new_structurizer: false,
-                emit_multiple_modules: false,
-            },
-        );
+        let res = link(compiler.session(), modules, opts);
         assert_eq!(compiler.session().has_errors(), res.is_err());
         res.map(|res| match res {
             LinkResult::SingleModule(m) => m,
@@ -224,8 +233,173 @@ fn type_mismatch() {
     let result = assemble_and_link(&[&a, &b]);
     assert_eq!(
         result.err().as_deref(),
-        Some("error: Types mismatch for \"foo\"")
+        Some("error: Types mismatch for \"foo\": imported as `f32`, exported as `u32`")
+    );
+}
+
+#[test]
+fn signature_mismatch() {
+    let a = assemble_spirv(
+        r#"OpCapability Linkage
+            OpDecorate %1 LinkageAttributes "noise" Import
+            %2 = OpTypeFloat 32
+            %3 = OpTypeVector %2 2
+            %4 = OpTypeFunction %2 %3
+            %1 = OpFunction %2 None %4
+            OpFunctionEnd"#,
+    );
+
+    let b = assemble_spirv(
+        r#"OpCapability Linkage
+            OpDecorate %1 LinkageAttributes "noise" Export
+            %2 = OpTypeFloat 32
+            %3 = OpTypeVector %2 3
+            %4 = OpTypeFunction %2 %3
+            %1 = OpFunction %2 None %4
+            %5 = OpFunctionParameter %3
+            %6 = OpLabel
+            %7 = OpCompositeExtract %2 %5 0
+            OpReturnValue %7
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link(&[&a, &b]);
+    assert_eq!(
+        result.err().as_deref(),
+        Some(
+            "error: Types mismatch for \"noise\": \
+             imported as `fn(vector<f32, 2>) -> f32`, exported as `fn(vector<f32, 3>) -> f32`"
+        )
+    );
+}
+
+/// Sets the version in the header of an assembled module.
+fn set_version(binary: &mut [u8], major: u8, minor: u8) {
+    let version = (u32::from(major) << 16) | (u32::from(minor) << 8);
+    binary[4..8].copy_from_slice(&version.to_le_bytes());
+}
+
+#[test]
+fn link_older_library() {
+    let mut a = assemble_spirv(
+        r#"OpCapability Linkage
+            OpDecorate %1 LinkageAttributes "noise" Import
+            %2 = OpTypeFloat 32
+            %3 = OpTypeFunction %2 %2
+            %1 = OpFunction %2 None %3
+            OpFunctionEnd
+            %4 = OpFunction %2 None %3
+            %5 = OpFunctionParameter %2
+            %6 = OpLabel
+            %7 = OpFunctionCall %2 %1 %5
+            OpReturnValue %7
+            OpFunctionEnd"#,
+    );
+    set_version(&mut a, 1, 3);
+
+    let mut b = assemble_spirv(
+        r#"OpCapability Linkage
+            OpDecorate %1 LinkageAttributes "noise" Export
+            %2 = OpTypeFloat 32
+            %3 = OpTypeFunction %2 %2
+            %1 = OpFunction %2 None %3
+            %4 = OpFunctionParameter %2
+            %5 = OpLabel
+            OpReturnValue %4
+            OpFunctionEnd"#,
+    );
+    set_version(&mut b, 1, 0);
+
+    let result = assemble_and_link(&[&a, &b]).unwrap();
+    assert_eq!(result.header.as_ref().unwrap().version(), (1, 3));
+
+    let expect = r#"%1 = OpTypeFloat 32
+        %2 = OpTypeFunction %1 %1
+        %3 = OpFunction %1 None %2
+        %4 = OpFunctionParameter %1
+        %5 = OpLabel
+        %6 = OpFunctionCall %1 %7 %4
+        OpReturnValue %6
+        OpFunctionEnd
+        %7 = OpFunction %1 None %2
+        %8 = OpFunctionParameter %1
+        %9 = OpLabel
+        OpReturnValue %8
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+
+    // Linking a newer module into an older one isn't possible, however.
+    let result = assemble_and_link(&[&b, &a]);
+    assert_eq!(
+        result.err().as_deref(),
+        Some("error: cannot link a SPIR-V v1.3 module into a v1.0 one")
+    );
+}
+
+#[test]
+fn library_exports() {
+    let a = assemble_spirv(
+        r#"OpCapability Linkage
+            OpDecorate %1 LinkageAttributes "noise" Export
+            OpDecorate %2 LinkageAttributes "helper" Import
+            %3 = OpTypeFloat 32
+            %4 = OpTypeFunction %3 %3
+            %2 = OpFunction %3 None %4
+            OpFunctionEnd
+            %1 = OpFunction %3 None %4
+            %5 = OpFunctionParameter %3
+            %6 = OpLabel
+            %7 = OpFunctionCall %3 %2 %5
+            OpReturnValue %7
+            OpFunctionEnd
+            %8 = OpFunction %3 None %4
+            %9 = OpFunctionParameter %3
+            %10 = OpLabel
+            OpReturnValue %9
+            OpFunctionEnd"#,
+    );
+
+    let b = assemble_spirv(
+        r#"OpCapability Linkage
+            OpDecorate %1 LinkageAttributes "helper" Export
+            %2 = OpTypeFloat 32
+            %3 = OpTypeFunction %2 %2
+            %1 = OpFunction %2 None %3
+            %4 = OpFunctionParameter %2
+            %5 = OpLabel
+            OpReturnValue %4
+            OpFunctionEnd"#,
     );
+
+    let result = assemble_and_link_with_options(
+        &[&a, &b],
+        &Options {
+            dce: true,
+            library_exports: Some(std::iter::once("noise".to_string()).collect()),
+            ..default_options()
+        },
+    )
+    .unwrap();
+
+    // Only "noise" is still exported (and keeps "helper" alive), everything else is gone.
+    let expect = r#"OpCapability Linkage
+        OpDecorate %1 LinkageAttributes "noise" Export
+        %2 = OpTypeFloat 32
+        %3 = OpTypeFunction %2 %2
+        %1 = OpFunction %2 None %3
+        %4 = OpFunctionParameter %2
+        %5 = OpLabel
+        %6 = OpFunctionCall %2 %7 %4
+        OpReturnValue %6
+        OpFunctionEnd
+        %7 = OpFunction %2 None %3
+        %8 = OpFunctionParameter %2
+        %9 = OpLabel
+        OpReturnValue %8
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
 }
 
 #[test]
@@ -403,18 +577,16 @@ fn use_exported_func_param_attr() {
 
     let expect = r#"OpCapability Kernel
         OpDecorate %1 FuncParamAttr Zext
-        %1 = OpDecorationGroup
-        OpGroupDecorate %1 %2
-        OpDecorate %3 FuncParamAttr Sext
-        %4 = OpTypeVoid
-        %5 = OpTypeInt 32 0
-        %6 = OpTypeFunction %4 %5
-        %7 = OpFunction %4 None %6
-        %2 = OpFunctionParameter %5
+        OpDecorate %2 FuncParamAttr Sext
+        %3 = OpTypeVoid
+        %4 = OpTypeInt 32 0
+        %5 = OpTypeFunction %3 %4
+        %6 = OpFunction %3 None %5
+        %1 = OpFunctionParameter %4
         OpFunctionEnd
-        %8 = OpFunction %4 None %6
-        %3 = OpFunctionParameter %5
-        %9 = OpLabel
+        %7 = OpFunction %3 None %5
+        %2 = OpFunctionParameter %4
+        %8 = OpLabel
         OpReturn
         OpFunctionEnd"#;
 
@@ -470,23 +642,868 @@ fn names_and_decorations() {
     let expect = r#"OpCapability Kernel
         OpName %1 "foo"
         OpName %2 "param"
+        OpDecorate %3 NonWritable
         OpDecorate %3 Restrict
-        OpDecorate %4 NonWritable
-        %3 = OpDecorationGroup
-        OpGroupDecorate %3 %4
         OpDecorate %2 Restrict
-        %5 = OpTypeVoid
-        %6 = OpTypeInt 32 0
-        %7 = OpTypePointer Function %6
-        %8 = OpTypeFunction %5 %7
-        %9 = OpFunction %5 None %8
-        %4 = OpFunctionParameter %7
+        %4 = OpTypeVoid
+        %5 = OpTypeInt 32 0
+        %6 = OpTypePointer Function %5
+        %7 = OpTypeFunction %4 %6
+        %8 = OpFunction %4 None %7
+        %3 = OpFunctionParameter %6
         OpFunctionEnd
-        %1 = OpFunction %5 None %8
-        %2 = OpFunctionParameter %7
+        %1 = OpFunction %4 None %7
+        %2 = OpFunctionParameter %6
+        %9 = OpLabel
+        OpReturn
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn dedupe_annotations_and_names() {
+    let a = assemble_spirv(
+        r#"OpMemberName %1 0 "x"
+            OpDecorate %1 Block
+            OpMemberDecorate %1 0 Offset 0
+            %2 = OpTypeInt 32 0
+            %1 = OpTypeStruct %2
+            %3 = OpConstant %2 7"#,
+    );
+
+    let b = assemble_spirv(
+        r#"OpName %1 "S"
+            OpMemberName %1 0 "x"
+            OpDecorate %4 Block
+            OpMemberDecorate %1 0 Offset 0
+            %4 = OpDecorationGroup
+            OpGroupDecorate %4 %1
+            %2 = OpTypeInt 32 0
+            %1 = OpTypeStruct %2
+            %3 = OpConstant %2 7"#,
+    );
+
+    let result = assemble_and_link(&[&a, &b]).unwrap();
+
+    // The decoration group is flattened, so the structs (and then the constants) are merged.
+    let expect = r#"OpMemberName %1 0 "x"
+        OpName %1 "S"
+        OpDecorate %1 Block
+        OpMemberDecorate %1 0 Offset 0
+        %2 = OpTypeInt 32 0
+        %1 = OpTypeStruct %2
+        %3 = OpConstant %2 7"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn empty_decoration_group() {
+    let a = assemble_spirv(
+        r#"%1 = OpDecorationGroup
+            OpGroupDecorate %1 %2
+            OpGroupMemberDecorate %1 %2 0
+            %3 = OpTypeInt 32 0
+            %2 = OpTypeStruct %3"#,
+    );
+
+    let result = assemble_and_link(&[&a]).unwrap();
+
+    let expect = r#"%1 = OpTypeInt 32 0
+        %2 = OpTypeStruct %1"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn lower_u128_comparison() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeInt 128 0
+            %2 = OpTypeBool
+            %3 = OpTypeFunction %2 %1 %1
+            %4 = OpFunction %2 None %3
+            %5 = OpFunctionParameter %1
+            %6 = OpFunctionParameter %1
+            %7 = OpLabel
+            %8 = OpIEqual %2 %5 %6
+            OpReturnValue %8
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link(&[&a]).unwrap();
+
+    let expect = r#"%1 = OpTypeBool
+        %2 = OpTypeInt 32 0
+        %3 = OpTypeVector %2 4
+        %4 = OpTypeFunction %1 %3 %3
+        %5 = OpTypeVector %1 4
+        %6 = OpFunction %1 None %4
+        %7 = OpFunctionParameter %3
+        %8 = OpFunctionParameter %3
+        %9 = OpLabel
+        %10 = OpIEqual %5 %7 %8
+        %11 = OpAll %1 %10
+        OpReturnValue %11
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn unsupported_u128_operation() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeInt 128 0
+            %2 = OpTypeFunction %1 %1
+            %3 = OpFunction %1 None %2
+            %4 = OpFunctionParameter %1
+            %5 = OpLabel
+            %6 = OpBitReverse %1 %4
+            OpReturnValue %6
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link(&[&a]);
+    assert_eq!(
+        result.err().as_deref(),
+        Some("error: unsupported operation on 128-bit integers: OpBitReverse")
+    );
+}
+
+#[test]
+fn emulate_int64() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeInt 64 0
+            %2 = OpTypeFunction %1 %1 %1
+            %3 = OpFunction %1 None %2
+            %4 = OpFunctionParameter %1
+            %5 = OpFunctionParameter %1
+            %6 = OpLabel
+            %7 = OpBitwiseXor %1 %4 %5
+            OpReturnValue %7
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link_with_options(
+        &[&a],
+        &Options {
+            emulate_int64: true,
+            ..default_options()
+        },
+    )
+    .unwrap();
+
+    let expect = r#"%1 = OpTypeInt 32 0
+        %2 = OpTypeBool
+        %3 = OpTypeVector %1 2
+        %4 = OpTypeFunction %3 %3 %3
+        %5 = OpFunction %3 None %4
+        %6 = OpFunctionParameter %3
+        %7 = OpFunctionParameter %3
+        %8 = OpLabel
+        %9 = OpBitwiseXor %3 %6 %7
+        OpReturnValue %9
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn narrow_float64() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeFloat 32
+            %2 = OpTypeFloat 64
+            %3 = OpTypeFunction %1 %2
+            %4 = OpFunction %1 None %3
+            %5 = OpFunctionParameter %2
+            %6 = OpLabel
+            %7 = OpFConvert %1 %5
+            OpReturnValue %7
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link_with_options(
+        &[&a],
+        &Options {
+            narrow_float64: true,
+            ..default_options()
+        },
+    )
+    .unwrap();
+
+    let expect = r#"%1 = OpTypeFloat 32
+        %2 = OpTypeFunction %1 %1
+        %3 = OpFunction %1 None %2
+        %4 = OpFunctionParameter %1
+        %5 = OpLabel
+        %6 = OpCopyObject %1 %4
+        OpReturnValue %6
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn narrow_float64_in_storage_buffer() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeFloat 64
+            %2 = OpTypeRuntimeArray %1
+            %3 = OpTypeStruct %2
+            %4 = OpTypePointer StorageBuffer %3
+            %5 = OpVariable %4 StorageBuffer"#,
+    );
+
+    let result = assemble_and_link_with_options(
+        &[&a],
+        &Options {
+            narrow_float64: true,
+            ..default_options()
+        },
+    );
+    assert_eq!(
+        result.err().as_deref(),
+        Some("error: 64-bit floats in `StorageBuffer` storage cannot be narrowed to 32 bits, as their layout is observable outside of the shader")
+    );
+}
+
+#[test]
+fn promote_small_ints() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeInt 8 0
+            %2 = OpTypeFunction %1 %1 %1
+            %3 = OpFunction %1 None %2
+            %4 = OpFunctionParameter %1
+            %5 = OpFunctionParameter %1
+            %6 = OpLabel
+            %7 = OpIAdd %1 %4 %5
+            OpReturnValue %7
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link_with_options(
+        &[&a],
+        &Options {
+            promote_small_ints: true,
+            ..default_options()
+        },
+    )
+    .unwrap();
+
+    let expect = r#"%1 = OpTypeInt 8 0
+        %2 = OpTypeInt 32 0
+        %3 = OpTypeFunction %1 %1 %1
+        %4 = OpTypeFunction %2 %2 %2
+        %5 = OpConstant %2 255
+        %6 = OpFunction %2 None %4
+        %7 = OpFunctionParameter %2
+        %8 = OpFunctionParameter %2
+        %9 = OpLabel
+        %10 = OpIAdd %2 %7 %8
+        %11 = OpBitwiseAnd %2 %10 %5
+        OpReturnValue %11
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn unsupported_promoted_u8_operation() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeInt 8 0
+            %2 = OpTypeFunction %1 %1
+            %3 = OpFunction %1 None %2
+            %4 = OpFunctionParameter %1
+            %5 = OpLabel
+            %6 = OpBitReverse %1 %4
+            OpReturnValue %6
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link_with_options(
+        &[&a],
+        &Options {
+            promote_small_ints: true,
+            ..default_options()
+        },
+    );
+    assert_eq!(
+        result.err().as_deref(),
+        Some("error: unsupported operation on 8-bit integers without the Int8 capability: OpBitReverse")
+    );
+}
+
+#[test]
+fn physical_storage_buffer_pointer() {
+    let a = assemble_spirv(
+        r#"OpMemoryModel Logical GLSL450
+            %1 = OpTypeInt 64 0
+            %2 = OpTypeInt 32 0
+            %3 = OpTypePointer Generic %2
+            %4 = OpTypeFunction %2 %1
+            %5 = OpFunction %2 None %4
+            %6 = OpFunctionParameter %1
+            %7 = OpLabel
+            %8 = OpConvertUToPtr %3 %6
+            %9 = OpLoad %2 %8
+            OpReturnValue %9
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link(&[&a]).unwrap();
+
+    let expect = r#"OpMemoryModel PhysicalStorageBuffer64 GLSL450
+        %1 = OpTypeInt 64 0
+        %2 = OpTypeInt 32 0
+        %3 = OpTypePointer PhysicalStorageBuffer %2
+        %4 = OpTypeFunction %2 %1
+        %5 = OpFunction %2 None %4
+        %6 = OpFunctionParameter %1
+        %7 = OpLabel
+        %8 = OpConvertUToPtr %3 %6
+        %9 = OpLoad %2 %8 Aligned 4
+        OpReturnValue %9
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn recursive_pointer_type() {
+    let a = assemble_spirv(
+        r#"OpMemoryModel Logical GLSL450
+            OpTypeForwardPointer %3 Generic
+            %1 = OpTypeInt 64 0
+            %2 = OpTypeInt 32 0
+            %4 = OpTypeStruct %2 %3
+            %3 = OpTypePointer Generic %4
+            %5 = OpTypeFunction %2 %1
+            %6 = OpFunction %2 None %5
+            %7 = OpFunctionParameter %1
+            %8 = OpLabel
+            %9 = OpConvertUToPtr %3 %7
+            %10 = OpLoad %4 %9
+            %11 = OpCompositeExtract %3 %10 1
+            %12 = OpLoad %4 %11
+            %13 = OpCompositeExtract %2 %12 0
+            OpReturnValue %13
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link(&[&a]).unwrap();
+
+    let expect = r#"OpMemoryModel PhysicalStorageBuffer64 GLSL450
+        OpTypeForwardPointer %1 PhysicalStorageBuffer
+        %2 = OpTypeInt 64 0
+        %3 = OpTypeInt 32 0
+        %4 = OpTypeStruct %3 %1
+        %1 = OpTypePointer PhysicalStorageBuffer %4
+        %5 = OpTypeFunction %3 %2
+        %6 = OpFunction %3 None %5
+        %7 = OpFunctionParameter %2
+        %8 = OpLabel
+        %9 = OpConvertUToPtr %1 %7
+        %10 = OpLoad %4 %9 Aligned 8
+        %11 = OpCompositeExtract %1 %10 1
+        %12 = OpLoad %4 %11 Aligned 8
+        %13 = OpCompositeExtract %3 %12 0
+        OpReturnValue %13
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn mutually_recursive_pointer_types() {
+    let a = assemble_spirv(
+        r#"OpMemoryModel Logical GLSL450
+            OpTypeForwardPointer %3 Generic
+            OpTypeForwardPointer %4 Generic
+            %1 = OpTypeInt 64 0
+            %2 = OpTypeInt 32 0
+            %5 = OpTypeStruct %2 %4
+            %6 = OpTypeStruct %2 %3
+            %3 = OpTypePointer Generic %5
+            %4 = OpTypePointer Generic %6
+            %7 = OpTypeFunction %1 %1
+            %8 = OpFunction %1 None %7
+            %9 = OpFunctionParameter %1
+            %10 = OpLabel
+            %11 = OpConvertUToPtr %3 %9
+            %12 = OpLoad %5 %11
+            %13 = OpCompositeExtract %4 %12 1
+            %14 = OpConvertPtrToU %1 %13
+            OpReturnValue %14
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link(&[&a]).unwrap();
+
+    let expect = r#"OpMemoryModel PhysicalStorageBuffer64 GLSL450
+        OpTypeForwardPointer %1 PhysicalStorageBuffer
+        OpTypeForwardPointer %2 PhysicalStorageBuffer
+        %3 = OpTypeInt 64 0
+        %4 = OpTypeInt 32 0
+        %5 = OpTypeStruct %4 %2
+        %6 = OpTypeStruct %4 %1
+        %1 = OpTypePointer PhysicalStorageBuffer %5
+        %2 = OpTypePointer PhysicalStorageBuffer %6
+        %7 = OpTypeFunction %3 %3
+        %8 = OpFunction %3 None %7
+        %9 = OpFunctionParameter %3
+        %10 = OpLabel
+        %11 = OpConvertUToPtr %1 %9
+        %12 = OpLoad %5 %11 Aligned 8
+        %13 = OpCompositeExtract %2 %12 1
+        %14 = OpConvertPtrToU %3 %13
+        OpReturnValue %14
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn indirect_call_to_known_function() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeInt 32 0
+            %2 = OpTypeFunction %1 %1
+            %3 = OpTypePointer Function %2
+            %4 = OpConstantComposite %3 %5
+            %5 = OpFunction %1 None %2
+            %6 = OpFunctionParameter %1
+            %7 = OpLabel
+            OpReturnValue %6
+            OpFunctionEnd
+            %8 = OpFunction %1 None %2
+            %9 = OpFunctionParameter %1
+            %10 = OpLabel
+            %11 = OpFunctionPointerCallINTEL %1 %4 %9
+            OpReturnValue %11
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link(&[&a]).unwrap();
+
+    let expect = r#"%1 = OpTypeInt 32 0
+        %2 = OpTypeFunction %1 %1
+        %3 = OpConstant %1 1
+        %4 = OpFunction %1 None %2
+        %5 = OpFunctionParameter %1
+        %6 = OpLabel
+        OpReturnValue %5
+        OpFunctionEnd
+        %7 = OpFunction %1 None %2
+        %8 = OpFunctionParameter %1
+        %9 = OpLabel
+        %10 = OpFunctionCall %1 %4 %8
+        OpReturnValue %10
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn mutual_recursion() {
+    let a = assemble_spirv(
+        r#"OpEntryPoint GLCompute %3 "main"
+            OpName %3 "main"
+            OpName %6 "is_even"
+            OpName %11 "is_odd"
+            %1 = OpTypeVoid
+            %2 = OpTypeFunction %1
+            %4 = OpTypeBool
+            %5 = OpTypeFunction %4
+            %3 = OpFunction %1 None %2
+            %7 = OpLabel
+            %8 = OpFunctionCall %4 %6
+            OpReturn
+            OpFunctionEnd
+            %6 = OpFunction %4 None %5
+            %9 = OpLabel
+            %10 = OpFunctionCall %4 %11
+            OpReturnValue %10
+            OpFunctionEnd
+            %11 = OpFunction %4 None %5
+            %12 = OpLabel
+            %13 = OpFunctionCall %4 %6
+            OpReturnValue %13
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link(&[&a]);
+    assert_eq!(
+        result.err().as_deref(),
+        Some(
+            "error: recursive function calls are not supported\n  \
+            |\n  \
+            = note: Cycle:\n          \
+            is_even\n          \
+            is_odd\n          \
+            is_even"
+        )
+    );
+}
+
+#[test]
+fn keep_src_locs() {
+    use crate::decorations::{CustomDecoration, SrcLocDecoration};
+
+    let a = assemble_spirv(
+        r#"OpExtension "SPV_KHR_non_semantic_info"
+            %1 = OpExtInstImport "NonSemantic.RustGPU.Decorations"
+            OpEntryPoint GLCompute %4 "main"
+            %10 = OpString "src/lib.rs"
+            %11 = OpString "md5:00"
+            %2 = OpTypeVoid
+            %3 = OpTypeFunction %2
+            %12 = OpTypeInt 32 0
+            %13 = OpConstant %12 1
+            %14 = OpConstant %12 2
+            %15 = OpExtInst %2 %1 3 %4 %10 %11 %13 %14
+            %16 = OpExtInst %2 %1 3 %6 %10 %11 %14 %14
+            %4 = OpFunction %2 None %3
+            %5 = OpLabel
+            OpReturn
+            OpFunctionEnd
+            %6 = OpFunction %2 None %3
+            %7 = OpLabel
+            OpReturn
+            OpFunctionEnd"#,
+    );
+
+    for &keep_src_locs in &[false, true] {
+        let result = assemble_and_link_with_options(
+            &[&a],
+            &Options {
+                dce: true,
+                keep_src_locs,
+                ..default_options()
+            },
+        )
+        .unwrap();
+
+        // Only the `SrcLocDecoration` of the (rooted) entry point can be kept.
+        let src_locs = SrcLocDecoration::decode_all(&result);
+        if keep_src_locs {
+            let main = result.entry_points[0].operands[1].unwrap_id_ref();
+            assert_eq!(src_locs.len(), 1);
+            assert_eq!(src_locs[0].0, main);
+        } else {
+            assert!(src_locs.is_empty());
+            assert!(result.ext_inst_imports.is_empty());
+        }
+    }
+}
+
+#[test]
+fn fold_constants_and_copies() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeInt 32 0
+            %2 = OpConstant %1 1
+            %3 = OpConstant %1 2
+            %4 = OpTypeFunction %1
+            %5 = OpFunction %1 None %4
+            %6 = OpLabel
+            %7 = OpIAdd %1 %2 %3
+            %8 = OpCopyObject %1 %7
+            %9 = OpIAdd %1 %8 %2
+            %10 = OpIAdd %1 %3 %2
+            %11 = OpIMul %1 %9 %10
+            OpReturnValue %11
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link_with_options(
+        &[&a],
+        &Options {
+            opt: true,
+            ..default_options()
+        },
+    )
+    .unwrap();
+
+    let expect = r#"%1 = OpTypeInt 32 0
+        %2 = OpConstant %1 1
+        %3 = OpConstant %1 2
+        %4 = OpTypeFunction %1
+        %5 = OpConstant %1 3
+        %6 = OpConstant %1 4
+        %7 = OpConstant %1 12
+        %8 = OpFunction %1 None %4
+        %9 = OpLabel
+        OpReturnValue %7
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn no_fold_wider_than_64_bits() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeInt 64 0
+            %2 = OpTypeInt 128 0
+            %3 = OpConstant %1 5
+            %4 = OpTypeFunction %2
+            %5 = OpFunction %2 None %4
+            %6 = OpLabel
+            %7 = OpUConvert %2 %3
+            OpReturnValue %7
+            OpFunctionEnd"#,
+    );
+
+    // NOTE: this runs `opt` on its own, as the full linker would also lower the 128-bit integers.
+    let mut result = load(&a);
+    super::opt::optimize(&mut result);
+
+    let expect = r#"%1 = OpTypeInt 64 0
+        %2 = OpTypeInt 128 0
+        %3 = OpConstant %1 5
+        %4 = OpTypeFunction %2
+        %5 = OpFunction %2 None %4
+        %6 = OpLabel
+        %7 = OpUConvert %2 %3
+        OpReturnValue %7
+        OpFunctionEnd"#;
+
+    without_header_eq(result, expect);
+}
+
+#[test]
+fn eliminate_dead_branches() {
+    let a = assemble_spirv(
+        r#"%1 = OpTypeVoid
+            %2 = OpTypeFunction %1
+            %3 = OpTypeBool
+            %4 = OpConstantTrue %3
+            %5 = OpTypeInt 32 0
+            %6 = OpConstant %5 1
+            %7 = OpConstant %5 2
+            %8 = OpFunction %1 None %2
+            %9 = OpLabel
+            OpSelectionMerge %12 None
+            OpBranchConditional %4 %10 %11
+            %10 = OpLabel
+            OpBranch %12
+            %11 = OpLabel
+            OpBranch %12
+            %12 = OpLabel
+            %13 = OpPhi %5 %6 %10 %7 %11
+            %14 = OpIEqual %3 %13 %6
+            OpReturn
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link_with_options(
+        &[&a],
+        &Options {
+            opt: true,
+            ..default_options()
+        },
+    )
+    .unwrap();
+
+    // Only one incoming value is left for the `OpPhi`, so it (and its use) fold away.
+    let expect = r#"%1 = OpTypeVoid
+        %2 = OpTypeFunction %1
+        %3 = OpTypeBool
+        %4 = OpConstantTrue %3
+        %5 = OpTypeInt 32 0
+        %6 = OpConstant %5 1
+        %7 = OpConstant %5 2
+        %8 = OpFunction %1 None %2
+        %9 = OpLabel
+        OpBranch %10
         %10 = OpLabel
+        OpBranch %11
+        %11 = OpLabel
         OpReturn
         OpFunctionEnd"#;
 
     without_header_eq(result, expect);
 }
+
+#[test]
+fn unroll_loops() {
+    use rspirv::dr::Function;
+    use rspirv::spirv::Op;
+
+    // `sum` of `0..3`, in two functions, only the first of which allows unrolling 3 iterations.
+    let a = assemble_spirv(
+        r#"OpExtension "SPV_KHR_non_semantic_info"
+            %1 = OpExtInstImport "NonSemantic.RustGPU.Decorations"
+            %2 = OpTypeInt 32 0
+            %3 = OpTypeBool
+            %4 = OpTypeFunction %2
+            %5 = OpTypeVoid
+            %6 = OpConstant %2 0
+            %7 = OpConstant %2 1
+            %8 = OpConstant %2 3
+            %9 = OpExtInst %5 %1 1 %20
+            %10 = OpExtInst %5 %1 1 %30 %7
+            %20 = OpFunction %2 None %4
+            %21 = OpLabel
+            OpBranch %22
+            %22 = OpLabel
+            %23 = OpPhi %2 %6 %21 %27 %25
+            %24 = OpPhi %2 %6 %21 %28 %25
+            %29 = OpULessThan %3 %23 %8
+            OpLoopMerge %26 %25 None
+            OpBranchConditional %29 %25 %26
+            %25 = OpLabel
+            %27 = OpIAdd %2 %23 %7
+            %28 = OpIAdd %2 %24 %23
+            OpBranch %22
+            %26 = OpLabel
+            OpReturnValue %24
+            OpFunctionEnd
+            %30 = OpFunction %2 None %4
+            %31 = OpLabel
+            OpBranch %32
+            %32 = OpLabel
+            %33 = OpPhi %2 %6 %31 %37 %35
+            %34 = OpPhi %2 %6 %31 %38 %35
+            %39 = OpULessThan %3 %33 %8
+            OpLoopMerge %36 %35 None
+            OpBranchConditional %39 %35 %36
+            %35 = OpLabel
+            %37 = OpIAdd %2 %33 %7
+            %38 = OpIAdd %2 %34 %33
+            OpBranch %32
+            %36 = OpLabel
+            OpReturnValue %34
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link(&[&a]).unwrap();
+
+    let constant_value = |id| {
+        result
+            .types_global_values
+            .iter()
+            .find(|inst| inst.result_id == Some(id) && inst.class.opcode == Op::Constant)
+            .map(|inst| inst.operands[0].unwrap_literal_int32())
+    };
+    let has_loop = |func: &Function| {
+        func.all_inst_iter()
+            .any(|inst| inst.class.opcode == Op::LoopMerge)
+    };
+    let return_value = |func: &Function| {
+        let terminator = func.blocks.last().unwrap().instructions.last().unwrap();
+        assert_eq!(terminator.class.opcode, Op::ReturnValue);
+        terminator.operands[0].unwrap_id_ref()
+    };
+
+    // The first loop is gone, and its result folded, while the second one is left alone.
+    let (unrolled, kept) = (&result.functions[0], &result.functions[1]);
+    assert!(!has_loop(unrolled));
+    assert_eq!(constant_value(return_value(unrolled)), Some(3));
+    assert!(has_loop(kept));
+    assert_eq!(kept.blocks.len(), 4);
+}
+
+#[test]
+fn inline_heuristics() {
+    use rspirv::spirv::Op;
+
+    let a = assemble_spirv(
+        r#"OpEntryPoint GLCompute %10 "main"
+            %1 = OpTypeVoid
+            %2 = OpTypeFunction %1
+            %10 = OpFunction %1 None %2
+            %11 = OpLabel
+            %12 = OpFunctionCall %1 %20
+            %13 = OpFunctionCall %1 %20
+            %14 = OpFunctionCall %1 %30
+            %15 = OpFunctionCall %1 %30
+            %16 = OpFunctionCall %1 %40
+            %17 = OpFunctionCall %1 %50
+            %18 = OpFunctionCall %1 %50
+            OpReturn
+            OpFunctionEnd
+            %20 = OpFunction %1 None %2
+            %21 = OpLabel
+            OpReturn
+            OpFunctionEnd
+            %30 = OpFunction %1 DontInline %2
+            %31 = OpLabel
+            OpReturn
+            OpFunctionEnd
+            %40 = OpFunction %1 None %2
+            %41 = OpLabel
+            OpNop
+            OpNop
+            OpNop
+            OpReturn
+            OpFunctionEnd
+            %50 = OpFunction %1 None %2
+            %51 = OpLabel
+            OpNop
+            OpNop
+            OpNop
+            OpReturn
+            OpFunctionEnd"#,
+    );
+
+    // With a threshold of `0`, nothing is inlined, otherwise functions small enough (`%20`) or
+    // called only once (`%40`) are, but not `DontInline` (`%30`) or larger (`%50`) ones.
+    for &(inline_threshold, expected_functions, expected_calls) in &[(0, 5, 7), (2, 3, 4)] {
+        let result = assemble_and_link_with_options(
+            &[&a],
+            &Options {
+                inline: true,
+                inline_threshold,
+                ..default_options()
+            },
+        )
+        .unwrap();
+
+        let calls = result
+            .all_inst_iter()
+            .filter(|inst| inst.class.opcode == Op::FunctionCall)
+            .count();
+        assert_eq!(result.functions.len(), expected_functions);
+        assert_eq!(calls, expected_calls);
+    }
+}
+
+#[test]
+fn irreducible_control_flow() {
+    use rspirv::binary::Assemble;
+    use rspirv::spirv::Op;
+
+    // `%7` and `%8` form a cycle, which can be entered through either of them.
+    let a = assemble_spirv(
+        r#"OpCapability Shader
+            OpMemoryModel Logical Simple
+            OpEntryPoint GLCompute %5 "main"
+            OpExecutionMode %5 LocalSize 1 1 1
+            %1 = OpTypeVoid
+            %2 = OpTypeFunction %1
+            %3 = OpTypeBool
+            %4 = OpConstantTrue %3
+            %5 = OpFunction %1 None %2
+            %6 = OpLabel
+            OpBranchConditional %4 %7 %8
+            %7 = OpLabel
+            OpBranchConditional %4 %8 %9
+            %8 = OpLabel
+            OpBranchConditional %4 %7 %9
+            %9 = OpLabel
+            OpReturn
+            OpFunctionEnd"#,
+    );
+
+    let result = assemble_and_link_with_options(
+        &[&a],
+        &Options {
+            structurize: true,
+            ..default_options()
+        },
+    )
+    .unwrap();
+
+    // The cycle became a loop, with an `OpSwitch` choosing between the original entries.
+    let has_op = |op| result.all_inst_iter().any(|inst| inst.class.opcode == op);
+    assert!(has_op(Op::LoopMerge));
+    assert!(has_op(Op::Switch));
+    validate(&result.assemble());
+}
@@ -0,0 +1,317 @@
+//! Unrolling of the loops in functions with `#[spirv(unroll_loops)]` (see
+//! `UnrollLoopsDecoration`), for loops which provably run at most `max_trip_count` times.
+//!
+//! Instead of working out the trip count of a loop upfront, iterations get peeled off the front
+//! of the loop one at a time (each followed by the folding in `opt.rs`), until the loop itself
+//! becomes unreachable, i.e. the exit conditions of all the peeled iterations were constant.
+//! If that doesn't happen within `max_trip_count` peeled iterations (plus one, for loops which
+//! check their condition before every iteration), the loop is left as it was, with only the
+//! `LoopControl::UNROLL` hint added by the structurizer.
+//!
+//! This relies on functions being structured, and in SSA form (i.e. after `mem2reg`), as loop
+//! exit conditions depending on `OpLoad`s can't be folded.
+
+use super::opt::{merge_inst, OptCx};
+use super::simple_passes::{outgoing_edges, reachable_blocks};
+use crate::decorations::UnrollLoopsDecoration;
+use rspirv::dr::{Function, Instruction, Module, Operand};
+use rspirv::spirv::{Op, Word};
+use std::collections::{HashMap, HashSet};
+
+pub fn unroll_loops(
+    module: &mut Module,
+    unroll_loops_decorations: &HashMap<Word, UnrollLoopsDecoration>,
+) {
+    let mut cx = OptCx::new(
+        module.header.as_mut().unwrap(),
+        &mut module.types_global_values,
+    );
+    for func in &mut module.functions {
+        let max_peels = match unroll_loops_decorations.get(&func.def_id().unwrap()) {
+            Some(unroll_loops) => unroll_loops.max_trip_count().saturating_add(1),
+            None => continue,
+        };
+
+        // The headers of loops which couldn't be unrolled.
+        let mut kept_loops = HashSet::new();
+        // NOTE: inner loops come after their outer loops in block order, so they're unrolled
+        // first (and an outer loop unrolled later may still have a constant trip count).
+        while let Some(header) = func
+            .blocks
+            .iter()
+            .rev()
+            .filter(|block| merge_inst(block).map(|inst| inst.class.opcode) == Some(Op::LoopMerge))
+            .map(|block| block.label_id().unwrap())
+            .find(|header| !kept_loops.contains(header))
+        {
+            let original = func.clone();
+            if !unroll_loop(&mut cx, func, header, max_peels) {
+                *func = original;
+                kept_loops.insert(header);
+            }
+        }
+    }
+}
+
+/// Peels iterations off the loop with the header `header`, until the loop is gone (returning
+/// `true`), or `max_peels` is reached (returning `false`, leaving `func` in an invalid state).
+fn unroll_loop(cx: &mut OptCx<'_>, func: &mut Function, header: Word, max_peels: u32) -> bool {
+    for _ in 0..max_peels {
+        if !peel_iteration(cx, func, header) {
+            return false;
+        }
+        cx.optimize_function(func);
+        if !func
+            .blocks
+            .iter()
+            .any(|block| block.label_id() == Some(header))
+        {
+            // Breaks/continues of the loop which didn't fold away would be left stranded.
+            return !has_unstructured_branches(func);
+        }
+    }
+    false
+}
+
+/// Copies the first iteration of the loop with the header `header` in front of the loop, with
+/// the loop now being entered from the back-edges of that copy (so it has to be removed by
+/// folding those away, as the copy isn't structured by itself).
+///
+/// Returns `false` if the loop can be exited through anything other than its merge block.
+fn peel_iteration(cx: &mut OptCx<'_>, func: &mut Function, header: Word) -> bool {
+    let block_idx = |func: &Function, label: Word| {
+        func.blocks
+            .iter()
+            .position(|block| block.label_id().unwrap() == label)
+            .unwrap()
+    };
+    let merge = merge_inst(&func.blocks[block_idx(func, header)])
+        .unwrap()
+        .operands[0]
+        .unwrap_id_ref();
+
+    // The loop construct is made out of the blocks dominated by the header, but not the merge.
+    let reachable = reachable_blocks(func, None);
+    let not_dominated_by_header = reachable_blocks(func, Some(header));
+    let not_dominated_by_merge = reachable_blocks(func, Some(merge));
+    let construct = reachable
+        .iter()
+        .copied()
+        .filter(|label| {
+            !not_dominated_by_header.contains(label) && not_dominated_by_merge.contains(label)
+        })
+        .collect::<HashSet<_>>();
+
+    let mut merge_preds = Vec::new();
+    let mut entering_preds = Vec::new();
+    for block in &func.blocks {
+        let label = block.label_id().unwrap();
+        for target in outgoing_edges(block) {
+            let from_construct = construct.contains(&label);
+            if from_construct && !(construct.contains(&target) || target == merge) {
+                return false;
+            }
+            if target == merge {
+                if !from_construct {
+                    return false;
+                }
+                if !merge_preds.contains(&label) {
+                    merge_preds.push(label);
+                }
+            }
+            if target == header && !from_construct {
+                entering_preds.push(label);
+            }
+        }
+    }
+    let merge_block = &func.blocks[block_idx(func, merge)];
+    if merge_inst(merge_block).map(|inst| inst.class.opcode) == Some(Op::LoopMerge) {
+        return false;
+    }
+
+    // Clone the construct, with fresh IDs for all the blocks and values in it.
+    let mut clone_ids = HashMap::new();
+    for block in &func.blocks {
+        if construct.contains(&block.label_id().unwrap()) {
+            let ids = block.label.iter().chain(&block.instructions);
+            for id in ids.filter_map(|inst| inst.result_id) {
+                clone_ids.insert(id, cx.id());
+            }
+        }
+    }
+    let remap = |id: Word| clone_ids.get(&id).copied().unwrap_or(id);
+    let peeled_header = remap(header);
+    let mut peeled = Vec::new();
+    for block in &func.blocks {
+        if !construct.contains(&block.label_id().unwrap()) {
+            continue;
+        }
+        let mut block = block.clone();
+        if block.label_id() == Some(header) {
+            // The peeled iteration is only entered from outside the loop, and isn't a loop.
+            block
+                .instructions
+                .retain(|inst| inst.class.opcode != Op::LoopMerge);
+            for phi in &mut block.instructions {
+                if phi.class.opcode == Op::Phi {
+                    phi.operands = phi
+                        .operands
+                        .chunks(2)
+                        .filter(|pair| !construct.contains(&pair[1].unwrap_id_ref()))
+                        .flatten()
+                        .cloned()
+                        .collect();
+                }
+            }
+        }
+        for inst in block.label.iter_mut().chain(&mut block.instructions) {
+            if let Some(id) = &mut inst.result_id {
+                *id = remap(*id);
+            }
+            for op in &mut inst.operands {
+                if let Some(id) = op.id_ref_any_mut() {
+                    *id = remap(*id);
+                }
+            }
+        }
+        // Back-edges of the peeled iteration continue onto the loop itself.
+        for op in &mut block.instructions.last_mut().unwrap().operands {
+            if *op == Operand::IdRef(peeled_header) {
+                *op = Operand::IdRef(header);
+            }
+        }
+        peeled.push(block);
+    }
+
+    // Values defined in the loop and used after it (i.e. in blocks dominated by the merge block)
+    // now need an `OpPhi` in the merge block, to also take them from the peeled iteration.
+    let mut def_types = HashMap::new();
+    for block in &func.blocks {
+        if construct.contains(&block.label_id().unwrap()) {
+            for inst in &block.instructions {
+                if let (Some(id), Some(ty)) = (inst.result_id, inst.result_type) {
+                    def_types.insert(id, ty);
+                }
+            }
+        }
+    }
+    let mut live_outs = HashMap::new();
+    let mut live_out_phis = Vec::new();
+    for block in &mut func.blocks {
+        let label = block.label_id().unwrap();
+        if construct.contains(&label) {
+            continue;
+        }
+        for inst in &mut block.instructions {
+            if label == merge && inst.class.opcode == Op::Phi {
+                continue;
+            }
+            for op in &mut inst.operands {
+                let id = match op.id_ref_any_mut() {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let ty = match def_types.get(&*id) {
+                    Some(&ty) => ty,
+                    None => continue,
+                };
+                let value = *id;
+                *id = *live_outs.entry(value).or_insert_with(|| {
+                    let phi = cx.id();
+                    let operands = merge_preds
+                        .iter()
+                        .flat_map(|&pred| {
+                            vec![
+                                Operand::IdRef(value),
+                                Operand::IdRef(pred),
+                                Operand::IdRef(remap(value)),
+                                Operand::IdRef(remap(pred)),
+                            ]
+                        })
+                        .collect();
+                    live_out_phis.push(Instruction::new(Op::Phi, Some(ty), Some(phi), operands));
+                    phi
+                });
+            }
+        }
+    }
+
+    for block in &mut func.blocks {
+        let label = block.label_id().unwrap();
+        if label == header {
+            // The loop is now entered from the back-edges of the peeled iteration.
+            for phi in &mut block.instructions {
+                if phi.class.opcode == Op::Phi {
+                    let back_edges = phi
+                        .operands
+                        .chunks(2)
+                        .filter(|pair| construct.contains(&pair[1].unwrap_id_ref()))
+                        .map(|pair| (pair[0].unwrap_id_ref(), pair[1].unwrap_id_ref()))
+                        .collect::<Vec<_>>();
+                    phi.operands = back_edges
+                        .iter()
+                        .chain(
+                            &back_edges
+                                .iter()
+                                .map(|&(value, pred)| (remap(value), remap(pred)))
+                                .collect::<Vec<_>>(),
+                        )
+                        .flat_map(|&(value, pred)| {
+                            vec![Operand::IdRef(value), Operand::IdRef(pred)]
+                        })
+                        .collect();
+                }
+            }
+        } else if label == merge {
+            // The merge block can now also be reached from the exits of the peeled iteration.
+            for phi in &mut block.instructions {
+                if phi.class.opcode == Op::Phi {
+                    let exits = phi
+                        .operands
+                        .chunks(2)
+                        .filter(|pair| construct.contains(&pair[1].unwrap_id_ref()))
+                        .flat_map(|pair| {
+                            vec![
+                                Operand::IdRef(remap(pair[0].unwrap_id_ref())),
+                                Operand::IdRef(remap(pair[1].unwrap_id_ref())),
+                            ]
+                        })
+                        .collect::<Vec<_>>();
+                    phi.operands.extend(exits);
+                }
+            }
+            block.instructions.splice(0..0, live_out_phis.drain(..));
+        } else if entering_preds.contains(&label) {
+            let len = block.instructions.len();
+            for inst in &mut block.instructions[len.saturating_sub(2)..] {
+                for op in &mut inst.operands {
+                    if *op == Operand::IdRef(header) {
+                        *op = Operand::IdRef(peeled_header);
+                    }
+                }
+            }
+        }
+    }
+
+    let header_idx = block_idx(func, header);
+    func.blocks.splice(header_idx..header_idx, peeled);
+    true
+}
+
+/// Whether any conditional branch lacks a merge instruction, without being a break/continue
+/// (i.e. targeting the merge block or continue target of some loop).
+fn has_unstructured_branches(func: &Function) -> bool {
+    let loop_exits = func
+        .blocks
+        .iter()
+        .filter_map(|block| merge_inst(block))
+        .filter(|merge| merge.class.opcode == Op::LoopMerge)
+        .flat_map(|merge| merge.operands[..2].iter().map(|op| op.unwrap_id_ref()))
+        .collect::<HashSet<_>>();
+    func.blocks.iter().any(|block| {
+        let terminator = block.instructions.last().unwrap();
+        matches!(terminator.class.opcode, Op::BranchConditional | Op::Switch)
+            && merge_inst(block).is_none()
+            && !outgoing_edges(block).any(|target| loop_exits.contains(&target))
+    })
+}
@@ -0,0 +1,89 @@
+//! With the Vulkan memory model, `Volatile` alone doesn't make an `OpLoad`/`OpStore` coherent
+//! with other invocations, so volatile accesses to memory shared with them are also made visible
+//! (loads) or available (stores), like they would be in the other memory models.
+//!
+//! The memory operands for that are only valid on pointers into such memory, but codegen only
+//! knows the storage class of its pointers as `Generic`, so they're added here instead, after
+//! the specializer has inferred the actual storage classes.
+
+use super::simple_passes;
+use rspirv::dr::{Module, Operand};
+use rspirv::spirv::{MemoryAccess, MemoryModel, Op, Scope, StorageClass, Word};
+use std::collections::{HashMap, HashSet};
+
+pub fn make_volatile_accesses_coherent(module: &mut Module) {
+    let vulkan = module.memory_model.as_ref().map_or(false, |inst| {
+        inst.operands[1].unwrap_memory_model() == MemoryModel::Vulkan
+    });
+    if !vulkan {
+        return;
+    }
+
+    let shared_pointer_types = module
+        .types_global_values
+        .iter()
+        .filter(|inst| {
+            inst.class.opcode == Op::TypePointer
+                && is_shared(inst.operands[0].unwrap_storage_class())
+        })
+        .map(|inst| inst.result_id.unwrap())
+        .collect::<HashSet<Word>>();
+    if shared_pointer_types.is_empty() {
+        return;
+    }
+    let value_types = module
+        .all_inst_iter()
+        .filter_map(|inst| Some((inst.result_id?, inst.result_type?)))
+        .collect::<HashMap<_, _>>();
+
+    let header = module.header.as_mut().unwrap();
+    let types_global_values = &mut module.types_global_values;
+    let mut scope = None;
+    for func in &mut module.functions {
+        for inst in func.blocks.iter_mut().flat_map(|b| &mut b.instructions) {
+            let (memory_access_idx, make_coherent) = match inst.class.opcode {
+                Op::Load => (1, MemoryAccess::MAKE_POINTER_VISIBLE),
+                Op::Store => (2, MemoryAccess::MAKE_POINTER_AVAILABLE),
+                _ => continue,
+            };
+            let pointer_ty = value_types[&inst.operands[0].unwrap_id_ref()];
+            if !shared_pointer_types.contains(&pointer_ty) {
+                continue;
+            }
+            match inst.operands.get_mut(memory_access_idx) {
+                Some(Operand::MemoryAccess(memory_access))
+                    if memory_access.contains(MemoryAccess::VOLATILE) =>
+                {
+                    memory_access.insert(MemoryAccess::NON_PRIVATE_POINTER | make_coherent);
+                }
+                _ => continue,
+            }
+            // NOTE: `QueueFamily` is the widest scope that doesn't also require
+            // `OpCapability VulkanMemoryModelDeviceScope`.
+            let scope = *scope.get_or_insert_with(|| {
+                let u32_type = simple_passes::u32_type(header, types_global_values);
+                simple_passes::u32_constant(
+                    header,
+                    types_global_values,
+                    u32_type,
+                    Scope::QueueFamily as u32,
+                )
+            });
+            // Codegen emits no other memory operands taking extra operands (and `Aligned`'s
+            // literal, added later by `physical_storage_buffer`, goes before this one).
+            inst.operands.push(Operand::IdScope(scope));
+        }
+    }
+}
+
+/// Storage classes of memory which other invocations can access too.
+fn is_shared(storage_class: StorageClass) -> bool {
+    matches!(
+        storage_class,
+        StorageClass::StorageBuffer
+            | StorageClass::PhysicalStorageBuffer
+            | StorageClass::Uniform
+            | StorageClass::Workgroup
+            | StorageClass::CrossWorkgroup
+    )
+}
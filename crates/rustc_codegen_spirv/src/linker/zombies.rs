@@ -1,11 +1,12 @@
 //! See documentation on `CodegenCx::zombie` for a description of the zombie system.
 
-use crate::decorations::{CustomDecoration, ZombieDecoration};
+use super::dce;
+use crate::decorations::{CustomDecoration, SrcLocDecoration, ZombieDecoration};
 use rspirv::dr::{Instruction, Module};
 use rspirv::spirv::{Op, Word};
 use rustc_session::Session;
 use rustc_span::{Span, DUMMY_SP};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::iter::once;
 
@@ -103,7 +104,7 @@ fn spread_zombie(module: &mut Module, zombie: &mut HashMap<Word, ZombieInfo<'_>>
     any
 }
 
-fn get_names(module: &Module) -> HashMap<Word, &str> {
+pub fn get_names(module: &Module) -> HashMap<Word, &str> {
     module
         .debugs
         .iter()
@@ -117,35 +118,70 @@ fn get_names(module: &Module) -> HashMap<Word, &str> {
         .collect()
 }
 
-// If an entry point references a zombie'd value, then the entry point would normally get removed.
-// That's an absolutely horrible experience to debug, though, so instead, create a nice error
-// message containing the stack trace of how the entry point got to the zombie value.
+// If an entry point (or an export of a library module) references a zombie'd value, then it would
+// normally get removed. That's an absolutely horrible experience to debug, though, so instead,
+// create a nice error message containing the chain of calls from the entry point down to the
+// zombie value.
 fn report_error_zombies(sess: &Session, module: &Module, zombie: &HashMap<Word, ZombieInfo<'_>>) {
     let mut names = None;
-    for root in super::dce::collect_roots(module) {
-        if let Some(reason) = zombie.get(&root) {
+    let mut spans = None;
+    let mut reported = HashSet::new();
+    let entry_points = module.entry_points.iter().map(|entry| {
+        let entry_name = entry.operands[2].unwrap_literal_string();
+        // The entry point function, followed by its interface variables.
+        let roots = entry
+            .operands
+            .iter()
+            .filter_map(|op| op.id_ref_any())
+            .collect::<Vec<_>>();
+        (format!("entry point `{}`", entry_name), roots)
+    });
+    let exports = dce::exports(module).map(|(id, name)| (format!("export `{}`", name), vec![id]));
+    for (user, roots) in entry_points.chain(exports) {
+        let roots = roots.into_iter().filter(|root| reported.insert(*root));
+        for root in roots {
+            let reason = match zombie.get(&root) {
+                Some(reason) => reason,
+                None => continue,
+            };
             let names = names.get_or_insert_with(|| get_names(module));
-            let stack = reason.stack.iter().map(|s| {
-                names
-                    .get(s)
-                    .map(|&n| n.to_string())
-                    .unwrap_or_else(|| format!("Unnamed function ID %{}", s))
-            });
-            let stack_note = once("Stack:".to_string())
-                .chain(stack)
-                .collect::<Vec<_>>()
-                .join("\n");
-            sess.struct_span_err(reason.span, reason.reason)
-                .note(&stack_note)
-                .emit();
+            let spans = spans.get_or_insert_with(|| get_spans(sess, module));
+
+            let mut err = sess.struct_span_err(reason.span, reason.reason);
+            err.note(&format!("used by {}", user));
+            // `stack` goes from the function containing the zombie value, to `root`, which is the
+            // function of the entry point (or export) itself, so it's skipped (as it was just noted).
+            for &id in reason.stack.iter().rev().skip(1) {
+                let name = names.get(&id).map_or_else(
+                    || format!("unnamed function ID %{}", id),
+                    |name| format!("`{:#}`", rustc_demangle::demangle(name)),
+                );
+                let msg = format!("...which calls {}", name);
+                match spans.get(&id) {
+                    Some(&span) => err.span_note(span, &msg),
+                    None => err.note(&msg),
+                };
+            }
+            err.emit();
         }
     }
 }
 
+/// The spans of the Rust `fn` definitions of functions (see `SrcLocDecoration`).
+fn get_spans(sess: &Session, module: &Module) -> HashMap<Word, Span> {
+    SrcLocDecoration::decode_all(module)
+        .into_iter()
+        .filter_map(|(id, src_loc)| {
+            let span = src_loc.span.to_rustc(sess.source_map())?;
+            Some((id, span))
+        })
+        .collect()
+}
+
 pub fn remove_zombies(sess: &Session, module: &mut Module) {
     let zombies_owned = ZombieDecoration::decode_all(module)
-        .map(|(id, zombie)| {
-            let ZombieDecoration { reason, span } = zombie.deserialize();
+        .into_iter()
+        .map(|(id, ZombieDecoration { reason, span })| {
             let span = span
                 .and_then(|span| span.to_rustc(sess.source_map()))
                 .unwrap_or(DUMMY_SP);
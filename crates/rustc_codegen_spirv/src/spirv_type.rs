@@ -50,6 +50,12 @@ pub enum SpirvType {
         /// Note: vector count is literal.
         count: u32,
     },
+    Matrix {
+        /// Note: this is the column type, which must be a `Vector`.
+        element: Word,
+        /// Note: matrix column count is literal.
+        count: u32,
+    },
     Array {
         element: Word,
         /// Note: array count is ref to constant.
@@ -111,8 +117,8 @@ impl SpirvType {
                     64 if !cx.builder.has_capability(Capability::Int64) => {
                         cx.zombie_with_span(result, def_span, "u64 without OpCapability Int64")
                     }
-                    8 | 16 | 32 | 64 => (),
-                    128 => cx.zombie_with_span(result, def_span, "u128"),
+                    // NOTE: 128-bit integers are lowered to 32-bit words by the linker.
+                    8 | 16 | 32 | 64 | 128 => (),
                     other => cx
                         .tcx
                         .sess
@@ -123,10 +129,13 @@ impl SpirvType {
             Self::Float(width) => {
                 let result = cx.emit_global().type_float(width);
                 match width {
+                    16 if !cx.builder.has_capability(Capability::Float16) => {
+                        cx.zombie_with_span(result, def_span, "f16 without OpCapability Float16")
+                    }
                     64 if !cx.builder.has_capability(Capability::Float64) => {
                         cx.zombie_with_span(result, def_span, "f64 without OpCapability Float64")
                     }
-                    32 | 64 => (),
+                    16 | 32 | 64 => (),
                     other => cx
                         .tcx
                         .sess
@@ -142,6 +151,10 @@ impl SpirvType {
                 ref field_offsets,
                 ref field_names,
             } => {
+                let matrix_strides = field_types
+                    .iter()
+                    .map(|&field| matrix_stride(cx, field))
+                    .collect::<Vec<_>>();
                 let mut emit = cx.emit_global();
                 // Ensure a unique struct is emitted each time, due to possibly having different OpMemberDecorates
                 let id = emit.id();
@@ -158,6 +171,9 @@ impl SpirvType {
                                 .iter()
                                 .cloned(),
                         );
+                        if let Some(stride) = matrix_strides[index] {
+                            decorate_matrix_member(&mut emit, result, index as u32, stride);
+                        }
                     }
                 }
                 if let Some(field_names) = field_names {
@@ -169,6 +185,13 @@ impl SpirvType {
             }
             Self::Opaque { ref name } => cx.emit_global().type_opaque(name),
             Self::Vector { element, count } => cx.emit_global().type_vector(element, count),
+            Self::Matrix { element, count } => {
+                let result = cx.emit_global().type_matrix(element, count);
+                if cx.kernel_mode {
+                    cx.zombie_with_span(result, def_span, "Matrix in kernel mode");
+                }
+                result
+            }
             Self::Array { element, count } => {
                 // ArrayStride decoration wants in *bytes*
                 let element_size = cx
@@ -251,6 +274,7 @@ impl SpirvType {
             Self::SampledImage { image_type } => cx.emit_global().type_sampled_image(image_type),
 
             Self::InterfaceBlock { inner_type } => {
+                let inner_matrix_stride = matrix_stride(cx, inner_type);
                 let mut emit = cx.emit_global();
                 let id = emit.id();
                 let result = emit.type_struct_id(Some(id), iter::once(inner_type));
@@ -261,6 +285,9 @@ impl SpirvType {
                     Decoration::Offset,
                     [Operand::LiteralInt32(0)].iter().cloned(),
                 );
+                if let Some(stride) = inner_matrix_stride {
+                    decorate_matrix_member(&mut emit, result, 0, stride);
+                }
                 result
             }
         };
@@ -343,6 +370,7 @@ impl SpirvType {
             Self::Vector { element, count } => {
                 cx.lookup_type(element).sizeof(cx)? * count.next_power_of_two() as u64
             }
+            Self::Matrix { element, count } => cx.lookup_type(element).sizeof(cx)? * count as u64,
             Self::Array { element, count } => {
                 cx.lookup_type(element).sizeof(cx)? * cx.builder.lookup_const_u64(count).unwrap()
             }
@@ -371,9 +399,9 @@ impl SpirvType {
                     .bytes(),
             )
             .expect("alignof: Vectors must have power-of-2 size"),
-            Self::Array { element, .. } | Self::RuntimeArray { element } => {
-                cx.lookup_type(element).alignof(cx)
-            }
+            Self::Array { element, .. }
+            | Self::RuntimeArray { element }
+            | Self::Matrix { element, .. } => cx.lookup_type(element).alignof(cx),
             Self::Pointer { .. } => cx.tcx.data_layout.pointer_align.abi,
             Self::Image { .. } | Self::Sampler | Self::SampledImage { .. } => {
                 Align::from_bytes(4).unwrap()
@@ -384,6 +412,38 @@ impl SpirvType {
     }
 }
 
+/// If `ty` is a `SpirvType::Matrix`, returns the `MatrixStride` (in bytes) that a struct member
+/// of that type has to be decorated with.
+fn matrix_stride(cx: &CodegenCx<'_>, ty: Word) -> Option<u32> {
+    match cx.lookup_type(ty) {
+        SpirvType::Matrix { element, .. } => Some(
+            cx.lookup_type(element)
+                .sizeof(cx)
+                .expect("Matrix columns must be sized")
+                .bytes() as u32,
+        ),
+        _ => None,
+    }
+}
+
+/// Matrices can only be laid out in memory (e.g. inside `Uniform` or `StorageBuffer` blocks)
+/// when the struct member containing them specifies their layout. Our matrices are always
+/// made of column vectors, so they are `ColMajor`.
+fn decorate_matrix_member(
+    emit: &mut rspirv::dr::Builder,
+    struct_id: Word,
+    index: u32,
+    stride: u32,
+) {
+    emit.member_decorate(struct_id, index, Decoration::ColMajor, iter::empty());
+    emit.member_decorate(
+        struct_id,
+        index,
+        Decoration::MatrixStride,
+        iter::once(Operand::LiteralInt32(stride)),
+    );
+}
+
 pub struct SpirvTypePrinter<'cx, 'tcx> {
     id: Word,
     ty: SpirvType,
@@ -452,6 +512,12 @@ impl fmt::Debug for SpirvTypePrinter<'_, '_> {
                 .field("element", &self.cx.debug_type(element))
                 .field("count", &count)
                 .finish(),
+            SpirvType::Matrix { element, count } => f
+                .debug_struct("Matrix")
+                .field("id", &self.id)
+                .field("element", &self.cx.debug_type(element))
+                .field("count", &count)
+                .finish(),
             SpirvType::Array { element, count } => f
                 .debug_struct("Array")
                 .field("id", &self.id)
@@ -613,6 +679,10 @@ impl SpirvTypePrinter<'_, '_> {
                 ty(self.cx, stack, f, element)?;
                 write!(f, "x{}", count)
             }
+            SpirvType::Matrix { element, count } => {
+                ty(self.cx, stack, f, element)?;
+                write!(f, "x{}", count)
+            }
             SpirvType::Array { element, count } => {
                 let len = self.cx.builder.lookup_const_u64(count);
                 let len = len.expect("Array type has invalid count value");
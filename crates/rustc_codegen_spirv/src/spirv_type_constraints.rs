@@ -2,9 +2,9 @@
 //! or for inference purposes.
 //!
 //! Only type/storage-class equality is currently handled here, no concrete
-//! type/storage-class constraints, nor anything involving non-type/storage-class
-//! operands. While more constraints could be supported, encoding all the possible
-//! rules for them may be challenging.
+//! type constraints (and only a few concrete storage-class ones), nor anything
+//! involving non-type/storage-class operands. While more constraints could be
+//! supported, encoding all the possible rules for them may be challenging.
 //!
 //! Type constraints could be provided in two representations:
 //! * static/generic: the constraints are built up from generic types
@@ -20,7 +20,7 @@
 //! If the "static representation" ends up being required (for performance reasons),
 //! the "dynamic representation" could be generated from it using associated `const`s.
 
-use rspirv::spirv::Op;
+use rspirv::spirv::{AddressingModel, Op, StorageClass};
 
 /// Helper trait to allow macros to work uniformly across different pattern types.
 trait Pat {
@@ -38,6 +38,10 @@ pub enum StorageClassPat {
     /// identical storage classes. For convenience, these associated consts are provided:
     /// * `StorageClassPat::S` for `StorageClassPat::Var(0)`
     Var(usize),
+
+    /// Concrete storage class, for instructions only valid with one storage class
+    /// (e.g. `OpConvertUToPtr` in shaders, which requires `PhysicalStorageBuffer`).
+    Concrete(StorageClass),
 }
 
 impl Pat for StorageClassPat {
@@ -178,12 +182,19 @@ pub struct InstSig<'a> {
     pub output_type: Option<&'a TyPat<'a>>,
 }
 
-/// Returns an array of valid signatures for an instruction with opcode `op`,
-/// or `None` if there aren't any known type constraints for that instruction.
-pub fn instruction_signatures(op: Op) -> Option<&'static [InstSig<'static>]> {
+/// Returns an array of valid signatures for an instruction with opcode `op`
+/// (in a module using `addressing_model`), or `None` if there aren't any known
+/// type constraints for that instruction.
+pub fn instruction_signatures(
+    op: Op,
+    addressing_model: AddressingModel,
+) -> Option<&'static [InstSig<'static>]> {
     // Restrict the names the `pat!` macro can take as pattern constructors.
     mod pat_ctors {
         pub const S: super::StorageClassPat = super::StorageClassPat::S;
+        #[allow(non_upper_case_globals)]
+        pub const PhysicalStorageBuffer: super::StorageClassPat =
+            super::StorageClassPat::Concrete(super::StorageClass::PhysicalStorageBuffer);
         // NOTE(eddyb) it would be really nice if we could import `TyPat::{* - Any, Var}`,
         // i.e. all but those two variants.
         pub use super::TyPat::{
@@ -424,7 +435,18 @@ pub fn instruction_signatures(op: Op) -> Option<&'static [InstSig<'static>]> {
         | Op::SConvert
         | Op::FConvert => {}
         Op::QuantizeToF16 => sig! { (T) -> T },
-        Op::ConvertPtrToU | Op::SatConvertSToU | Op::SatConvertUToS | Op::ConvertUToPtr => {}
+        // NOTE: kernels (i.e. the `Physical32`/`Physical64` addressing models) can convert
+        // integers to/from pointers in any storage class, but shaders only to/from
+        // `PhysicalStorageBuffer` ones (which need `PhysicalStorageBuffer64`, but that's
+        // only set by the linker, after inference, so `Logical` is treated the same).
+        Op::ConvertPtrToU | Op::ConvertUToPtr
+            if matches!(
+                addressing_model,
+                AddressingModel::Physical32 | AddressingModel::Physical64
+            ) => {}
+        Op::ConvertPtrToU => sig! { (Pointer(PhysicalStorageBuffer, _)) -> _ },
+        Op::SatConvertSToU | Op::SatConvertUToS => {}
+        Op::ConvertUToPtr => sig! { (_) -> Pointer(PhysicalStorageBuffer, _) },
         Op::PtrCastToGeneric | Op::GenericCastToPtr => sig! { (Pointer(_, T)) -> Pointer(_, T) },
         Op::GenericCastToPtrExplicit => sig! { {S} (Pointer(_, T)) -> Pointer(S, T) },
         Op::Bitcast => {}
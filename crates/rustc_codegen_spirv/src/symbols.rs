@@ -24,6 +24,9 @@ pub struct Symbols {
     pub simple: Symbol,
     pub vulkan: Symbol,
     pub glsl450: Symbol,
+    pub emulate_int64: Symbol,
+    pub narrow_float64: Symbol,
+    pub promote_small_ints: Symbol,
     pub spirv10: Symbol,
     pub spirv11: Symbol,
     pub spirv12: Symbol,
@@ -34,6 +37,8 @@ pub struct Symbols {
     descriptor_set: Symbol,
     binding: Symbol,
     image_type: Symbol,
+    unroll_loops: Symbol,
+    max: Symbol,
     dim: Symbol,
     depth: Symbol,
     arrayed: Symbol,
@@ -341,7 +346,11 @@ impl Symbols {
                 "sampled_image",
                 SpirvAttribute::IntrinsicType(IntrinsicType::SampledImage),
             ),
-            ("unroll_loops", SpirvAttribute::UnrollLoops),
+            (
+                "matrix",
+                SpirvAttribute::IntrinsicType(IntrinsicType::Matrix),
+            ),
+            ("f16", SpirvAttribute::IntrinsicType(IntrinsicType::Float16)),
         ]
         .iter()
         .cloned();
@@ -380,6 +389,9 @@ impl Symbols {
             simple: Symbol::intern("simple"),
             vulkan: Symbol::intern("vulkan"),
             glsl450: Symbol::intern("glsl450"),
+            emulate_int64: Symbol::intern("emulate-int64"),
+            narrow_float64: Symbol::intern("narrow-float64"),
+            promote_small_ints: Symbol::intern("promote-small-ints"),
             spirv10: Symbol::intern("spirv1.0"),
             spirv11: Symbol::intern("spirv1.1"),
             spirv12: Symbol::intern("spirv1.2"),
@@ -389,6 +401,8 @@ impl Symbols {
             descriptor_set: Symbol::intern("descriptor_set"),
             binding: Symbol::intern("binding"),
             image_type: Symbol::intern("image_type"),
+            unroll_loops: Symbol::intern("unroll_loops"),
+            max: Symbol::intern("max"),
             dim: Symbol::intern("dim"),
             depth: Symbol::intern("depth"),
             arrayed: Symbol::intern("arrayed"),
@@ -459,6 +473,8 @@ pub(crate) fn parse_attrs_for_checking<'a>(
                     SpirvAttribute::DescriptorSet(parse_attr_int_value(arg)?)
                 } else if arg.has_name(sym.binding) {
                     SpirvAttribute::Binding(parse_attr_int_value(arg)?)
+                } else if arg.has_name(sym.unroll_loops) {
+                    parse_unroll_loops(sym, arg)?
                 } else {
                     let name = match arg.ident() {
                         Some(i) => i,
@@ -597,6 +613,31 @@ fn parse_image_type(
     }))
 }
 
+fn parse_unroll_loops(
+    sym: &Symbols,
+    arg: &NestedMetaItem,
+) -> Result<SpirvAttribute, ParseAttrError> {
+    let args = match arg.meta_item_list() {
+        Some(args) => args,
+        None if arg.is_word() => return Ok(SpirvAttribute::UnrollLoops(None)),
+        None => {
+            return Err((
+                arg.span(),
+                "unroll_loops attribute must be unroll_loops or unroll_loops(max = N)".to_string(),
+            ))
+        }
+    };
+    match args {
+        [max] if max.has_name(sym.max) => Ok(SpirvAttribute::UnrollLoops(Some(
+            parse_attr_int_value(max)?,
+        ))),
+        _ => Err((
+            arg.span(),
+            "unroll_loops attribute must be unroll_loops or unroll_loops(max = N)".to_string(),
+        )),
+    }
+}
+
 fn parse_attr_int_value(arg: &NestedMetaItem) -> Result<u32, ParseAttrError> {
     let arg = match arg.meta_item() {
         Some(arg) => arg,
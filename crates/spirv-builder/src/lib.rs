@@ -60,7 +60,7 @@ mod depfile;
 
 use raw_string::{RawStr, RawString};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::error::Error;
 use std::fmt;
@@ -75,6 +75,7 @@ pub enum SpirvBuilderError {
     MultiModuleWithPrintMetadata,
     MetadataFileMissing(std::io::Error),
     MetadataFileMalformed(serde_json::Error),
+    SpirvLibraryPathWithSpaces(PathBuf),
 }
 
 impl fmt::Display for SpirvBuilderError {
@@ -90,6 +91,11 @@ impl fmt::Display for SpirvBuilderError {
             SpirvBuilderError::MetadataFileMalformed(_) => {
                 f.write_str("Unable to parse multi-module metadata file")
             }
+            SpirvBuilderError::SpirvLibraryPathWithSpaces(path) => write!(
+                f,
+                "SPIR-V library path `{}` cannot contain spaces",
+                path.display()
+            ),
         }
     }
 }
@@ -102,12 +108,38 @@ pub enum MemoryModel {
     GLSL450,
 }
 
+/// The `spirv-opt` passes to run on the built module(s).
+pub enum SpirvOptPreset {
+    /// Passes optimizing for performance.
+    Performance,
+    /// Passes optimizing for size.
+    Size,
+    /// Only the passes needed to legalize the module, without optimizing it further.
+    Legalization,
+    /// Runs no passes, besides stripping debuginfo if it's off (as with the other presets).
+    None,
+}
+
 pub struct SpirvBuilder {
     path_to_crate: PathBuf,
     print_metadata: bool,
     release: bool,
     spirv_version: Option<(u8, u8)>,
     memory_model: Option<MemoryModel>,
+    emulate_int64: bool,
+    narrow_float64: bool,
+    promote_small_ints: bool,
+    inline_threshold: Option<usize>,
+    spirv_libraries: Vec<PathBuf>,
+    spirv_opt_preset: Option<SpirvOptPreset>,
+    spirv_opt_passes: Vec<String>,
+    relax_struct_store: bool,
+    relax_logical_pointer: bool,
+    relax_block_layout: bool,
+    uniform_buffer_standard_layout: bool,
+    scalar_block_layout: bool,
+    skip_block_layout: bool,
+    before_legalization: bool,
 }
 impl SpirvBuilder {
     pub fn new(path_to_crate: impl AsRef<Path>) -> Self {
@@ -117,6 +149,20 @@ impl SpirvBuilder {
             release: true,
             spirv_version: None,
             memory_model: None,
+            emulate_int64: false,
+            narrow_float64: false,
+            promote_small_ints: false,
+            inline_threshold: None,
+            spirv_libraries: Vec::new(),
+            spirv_opt_preset: None,
+            spirv_opt_passes: Vec::new(),
+            relax_struct_store: false,
+            relax_logical_pointer: false,
+            relax_block_layout: false,
+            uniform_buffer_standard_layout: false,
+            scalar_block_layout: false,
+            skip_block_layout: false,
+            before_legalization: false,
         }
     }
 
@@ -144,6 +190,113 @@ impl SpirvBuilder {
         self
     }
 
+    /// Emulate 64-bit integers with pairs of 32-bit ones, for targets without the `Int64`
+    /// capability. Defaults to false.
+    pub fn emulate_int64(mut self, v: bool) -> Self {
+        self.emulate_int64 = v;
+        self
+    }
+
+    /// Compute 64-bit floats with 32-bit precision, for targets without the `Float64`
+    /// capability. 64-bit floats in buffers (or shader inputs/outputs) are an error, as their
+    /// layout can't change. Defaults to false.
+    pub fn narrow_float64(mut self, v: bool) -> Self {
+        self.narrow_float64 = v;
+        self
+    }
+
+    /// Compute 8-bit and 16-bit integers as 32-bit ones, for targets without the `Int8`/`Int16`
+    /// capabilities. Buffers still hold the original types, which requires the 8-bit/16-bit
+    /// storage capabilities. Defaults to false.
+    pub fn promote_small_ints(mut self, v: bool) -> Self {
+        self.promote_small_ints = v;
+        self
+    }
+
+    /// Sets the maximum size (in instructions) of functions to inline, besides those called only
+    /// once, with 0 disabling both. Functions marked `#[inline]`/`#[inline(always)]` are inlined
+    /// regardless (even with 0), and `#[inline(never)]` ones never are (unless required by
+    /// SPIR-V). Ignored at `opt-level=0` (e.g. in debug builds), which behaves like 0.
+    /// Defaults to 32.
+    pub fn inline_threshold(mut self, threshold: usize) -> Self {
+        self.inline_threshold = Some(threshold);
+        self
+    }
+
+    /// Links a precompiled SPIR-V module (e.g. one compiled from GLSL) into the built one. The
+    /// functions and variables it exports (with the `Linkage` capability) can be used from Rust
+    /// through `extern` declarations of the same name and signature. Can be called multiple times.
+    /// The path can't contain spaces, as it's passed to the compiler through `RUSTFLAGS`.
+    pub fn link_spirv_library(mut self, path: impl AsRef<Path>) -> Self {
+        self.spirv_libraries.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the `spirv-opt` passes to run. Defaults to picking them from `release`. Either way,
+    /// debuginfo is stripped if it's off (e.g. in release builds).
+    pub fn spirv_opt_preset(mut self, preset: SpirvOptPreset) -> Self {
+        self.spirv_opt_preset = Some(preset);
+        self
+    }
+
+    /// Adds a `spirv-opt` pass to run, by the name of its `spirv_tools::opt::Passes` variant
+    /// (e.g. `"AggressiveDCE"`). Passes run in the order they're added, instead of any others
+    /// (besides stripping debuginfo if it's off). Can't be combined with `spirv_opt_preset`.
+    pub fn spirv_opt_pass(mut self, pass: impl Into<String>) -> Self {
+        self.spirv_opt_passes.push(pass.into());
+        self
+    }
+
+    /// Allows store types mismatching in structs of the same layout. Defaults to false.
+    pub fn relax_struct_store(mut self, v: bool) -> Self {
+        self.relax_struct_store = v;
+        self
+    }
+
+    /// Allows pointers in variables and as return values, in logical addressing mode. Defaults to
+    /// false.
+    pub fn relax_logical_pointer(mut self, v: bool) -> Self {
+        self.relax_logical_pointer = v;
+        self
+    }
+
+    /// Validates uniform/storage buffers and push constants with the rules of
+    /// `VK_KHR_relaxed_block_layout`. Defaults to false (but is always on for Vulkan 1.1 and
+    /// later).
+    pub fn relax_block_layout(mut self, v: bool) -> Self {
+        self.relax_block_layout = v;
+        self
+    }
+
+    /// Validates uniform buffers with the rules of `VK_KHR_uniform_buffer_standard_layout`.
+    /// Defaults to false.
+    pub fn uniform_buffer_standard_layout(mut self, v: bool) -> Self {
+        self.uniform_buffer_standard_layout = v;
+        self
+    }
+
+    /// Validates uniform/storage buffers and push constants with the rules of
+    /// `VK_EXT_scalar_block_layout`. Defaults to false.
+    pub fn scalar_block_layout(mut self, v: bool) -> Self {
+        self.scalar_block_layout = v;
+        self
+    }
+
+    /// Skips validating the layout of uniform/storage buffers and push constants. Defaults to
+    /// false.
+    pub fn skip_block_layout(mut self, v: bool) -> Self {
+        self.skip_block_layout = v;
+        self
+    }
+
+    /// Relaxes the validation rules which legalization is expected to satisfy (e.g. for use with
+    /// `SpirvOptPreset::None`, leaving legalization to a later `spirv-opt` run). Defaults to
+    /// false.
+    pub fn before_legalization(mut self, v: bool) -> Self {
+        self.before_legalization = v;
+        self
+    }
+
     /// Builds the module. Returns the path to the built spir-v file. If `print_metadata` is true,
     /// you usually don't have to inspect the path, as the environment variable will already be
     /// set.
@@ -156,7 +309,9 @@ impl SpirvBuilder {
         Ok(spirv_module)
     }
 
-    pub fn build_multimodule(self) -> Result<HashMap<String, PathBuf>, SpirvBuilderError> {
+    /// Builds one module per entry point. Returns the paths to the built spir-v files, by the name
+    /// of their entry points.
+    pub fn build_multimodule(self) -> Result<BTreeMap<String, PathBuf>, SpirvBuilderError> {
         if self.print_metadata {
             return Err(SpirvBuilderError::MultiModuleWithPrintMetadata);
         }
@@ -227,16 +382,72 @@ fn invoke_rustc(builder: &SpirvBuilder, multimodule: bool) -> Result<PathBuf, Sp
             .to_string(),
         );
     }
+    if builder.emulate_int64 {
+        target_features.push("+emulate-int64".to_string());
+    }
+    if builder.narrow_float64 {
+        target_features.push("+narrow-float64".to_string());
+    }
+    if builder.promote_small_ints {
+        target_features.push("+promote-small-ints".to_string());
+    }
     let feature_flag = if target_features.is_empty() {
         String::new()
     } else {
         format!(" -C target-feature={}", target_features.join(","))
     };
-    let llvm_args = if multimodule {
-        " -C llvm-args=--module-output=multiple"
-    } else {
-        ""
-    };
+    let mut llvm_args = Vec::new();
+    if multimodule {
+        llvm_args.push("--module-output=multiple".to_string());
+    }
+    if let Some(threshold) = builder.inline_threshold {
+        llvm_args.push(format!("--inline-threshold={}", threshold));
+    }
+    if let Some(preset) = &builder.spirv_opt_preset {
+        llvm_args.push(format!(
+            "--spirv-opt={}",
+            match preset {
+                SpirvOptPreset::Performance => "performance",
+                SpirvOptPreset::Size => "size",
+                SpirvOptPreset::Legalization => "legalization",
+                SpirvOptPreset::None => "none",
+            }
+        ));
+    }
+    for pass in &builder.spirv_opt_passes {
+        llvm_args.push(format!("--spirv-opt-pass={}", pass));
+    }
+    let spirv_val_flags = [
+        (builder.relax_struct_store, "--relax-struct-store"),
+        (builder.relax_logical_pointer, "--relax-logical-pointer"),
+        (builder.relax_block_layout, "--relax-block-layout"),
+        (
+            builder.uniform_buffer_standard_layout,
+            "--uniform-buffer-standard-layout",
+        ),
+        (builder.scalar_block_layout, "--scalar-block-layout"),
+        (builder.skip_block_layout, "--skip-block-layout"),
+        (builder.before_legalization, "--before-legalization"),
+    ];
+    for &(enabled, flag) in &spirv_val_flags {
+        if enabled {
+            llvm_args.push(flag.to_string());
+        }
+    }
+    // NOTE: cargo runs in `path_to_crate`, so relative paths are resolved here first.
+    let current_dir = env::current_dir().expect("failed to get the current directory");
+    for path in &builder.spirv_libraries {
+        let path = current_dir.join(path);
+        if path.to_string_lossy().contains(' ') {
+            return Err(SpirvBuilderError::SpirvLibraryPathWithSpaces(path));
+        }
+        llvm_args.push(format!("--spirv-library={}", path.display()));
+    }
+    // NOTE: `-C llvm-args` accumulates, and can't contain spaces (`RUSTFLAGS` is split on them).
+    let llvm_args = llvm_args
+        .iter()
+        .map(|arg| format!(" -C llvm-args={}", arg))
+        .collect::<String>();
     let rustflags = format!(
         "-Z codegen-backend={} -Z symbol-mangling-version=v0{}{}",
         rustc_codegen_spirv.display(),
@@ -1,23 +1,4 @@
-use super::{dis_entry_fn, dis_fn, dis_globals, val};
-use std::ffi::OsStr;
-
-struct SetEnvVar<'a> {
-    k: &'a OsStr,
-}
-
-impl<'a> SetEnvVar<'a> {
-    fn new(k: &'a impl AsRef<OsStr>, v: impl AsRef<OsStr>) -> Self {
-        let k = k.as_ref();
-        std::env::set_var(k, v);
-        Self { k }
-    }
-}
-
-impl<'a> Drop for SetEnvVar<'a> {
-    fn drop(&mut self) {
-        std::env::remove_var(self.k)
-    }
-}
+use super::{dis_entry_fn, dis_fn, dis_fn_with, dis_globals, val, SetEnvVar};
 
 #[test]
 fn custom_entry_point() {
@@ -455,6 +436,52 @@ fn ptr_copy_from_method() {
     );
 }
 
+// NOTE: `Function`-local pointers can't be made available/visible to other invocations (and
+// don't need to be), so only `Volatile` is used for them.
+#[test]
+fn ptr_read_volatile() {
+    test_copy_via_raw_ptr!(
+        "*dst = core::ptr::read_volatile(src)" => r#"
+            %8 = OpLoad %9 %4 Volatile
+            OpStore %6 %8
+        "#
+    );
+}
+
+#[test]
+fn ptr_write_volatile() {
+    test_copy_via_raw_ptr!(
+        "core::ptr::write_volatile(dst, *src)" => r#"
+            %8 = OpLoad %9 %4
+            OpStore %6 %8 Volatile
+        "#
+    );
+}
+
+#[test]
+fn ptr_volatile_storage_buffer() {
+    dis_entry_fn(
+        r#"
+#[spirv(fragment)]
+pub fn main(#[spirv(storage_buffer, descriptor_set = 0, binding = 0)] flags: &mut u32) {
+    unsafe {
+        let value = core::ptr::read_volatile(flags);
+        core::ptr::write_volatile(flags, value | 1);
+    }
+}
+        "#,
+        "main",
+        r#"%1 = OpFunction %2 None %3
+%4 = OpLabel
+%5 = OpAccessChain %6 %7 %8
+%9 = OpLoad %10 %5 Volatile|MakePointerVisible|NonPrivatePointer %11
+%12 = OpBitwiseOr %10 %9 %13
+OpStore %5 %12 Volatile|MakePointerAvailable|NonPrivatePointer %11
+OpReturn
+OpFunctionEnd"#,
+    );
+}
+
 #[test]
 fn index_user_dst() {
     dis_entry_fn(
@@ -540,3 +567,124 @@ OpDecorate %4 Location 10
 %4 = OpVariable %15 Input"#,
     );
 }
+
+static SWITCH_U64_SRC: &str = r#"
+fn switch_u64(x: u64) -> u32 {
+    match x {
+        0 => 1,
+        0x1_0000_0000 => 2,
+        _ => 3,
+    }
+}
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] x: u32, out: &mut u32) {
+    *out = switch_u64((x as u64) << 32);
+}
+"#;
+
+static SWITCH_I64_SRC: &str = r#"
+fn switch_i64(x: i64) -> u32 {
+    match x {
+        i64::MIN => 1,
+        -1 => 2,
+        _ => 3,
+    }
+}
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] x: i32, out: &mut u32) {
+    *out = switch_i64((x as i64) << 32);
+}
+"#;
+
+#[test]
+fn switch_u64() {
+    dis_fn(
+        SWITCH_U64_SRC,
+        "switch_u64",
+        // The case literals must be 64-bit, not truncated to 32 bits.
+        r#"%1 = OpFunction %2 None %3
+%4 = OpFunctionParameter %5
+%6 = OpLabel
+OpSelectionMerge %7 None
+OpSwitch %4 %8 0 %9 4294967296 %10
+%8 = OpLabel
+OpBranch %7
+%9 = OpLabel
+OpBranch %7
+%10 = OpLabel
+OpBranch %7
+%7 = OpLabel
+%11 = OpPhi %2 %12 %8 %13 %9 %14 %10
+OpReturnValue %11
+OpFunctionEnd"#,
+    );
+}
+
+#[test]
+fn switch_i64() {
+    dis_fn(
+        SWITCH_I64_SRC,
+        "switch_i64",
+        // Negative case literals are encoded as their two's complement bits.
+        r#"%1 = OpFunction %2 None %3
+%4 = OpFunctionParameter %5
+%6 = OpLabel
+OpSelectionMerge %7 None
+OpSwitch %4 %8 9223372036854775808 %9 18446744073709551615 %10
+%8 = OpLabel
+OpBranch %7
+%9 = OpLabel
+OpBranch %7
+%10 = OpLabel
+OpBranch %7
+%7 = OpLabel
+%11 = OpPhi %2 %12 %8 %13 %9 %14 %10
+OpReturnValue %11
+OpFunctionEnd"#,
+    );
+}
+
+/// With `emulate-int64`, there's no `OpSwitch` on 64-bit selectors (as those are lowered to
+/// vectors of 32-bit words), but instead a chain of comparisons against each case.
+fn test_switch_emulated(src: &str, func: &str) {
+    dis_fn_with(
+        src,
+        func,
+        r#"%1 = OpFunction %2 None %3
+%4 = OpFunctionParameter %5
+%6 = OpLabel
+%7 = OpIEqual %8 %4 %9
+%10 = OpAll %11 %7
+OpSelectionMerge %12 None
+OpBranchConditional %10 %13 %14
+%13 = OpLabel
+OpBranch %12
+%14 = OpLabel
+%15 = OpIEqual %8 %4 %16
+%17 = OpAll %11 %15
+OpSelectionMerge %18 None
+OpBranchConditional %17 %19 %20
+%19 = OpLabel
+OpBranch %18
+%20 = OpLabel
+OpBranch %18
+%18 = OpLabel
+%21 = OpPhi %2 %22 %19 %23 %20
+OpBranch %12
+%12 = OpLabel
+%24 = OpPhi %2 %25 %13 %21 %18
+OpReturnValue %24
+OpFunctionEnd"#,
+        |builder| builder.emulate_int64(true),
+    )
+}
+
+#[test]
+fn switch_u64_emulated() {
+    test_switch_emulated(SWITCH_U64_SRC, "switch_u64");
+}
+
+#[test]
+fn switch_i64_emulated() {
+    test_switch_emulated(SWITCH_I64_SRC, "switch_i64");
+}
@@ -0,0 +1,135 @@
+use super::{build, global_lock, read_module, SetEnvVar};
+use rustc_codegen_spirv::rspirv::dr::{Instruction, Module, Operand};
+use rustc_codegen_spirv::rspirv::spirv::{Op, Word};
+use std::collections::HashMap;
+
+// Opcodes of the `NonSemantic.Shader.DebugInfo.100` instructions checked below.
+const DEBUG_COMPILATION_UNIT: u32 = 1;
+const DEBUG_FUNCTION: u32 = 20;
+const DEBUG_SCOPE: u32 = 23;
+const DEBUG_INLINED_AT: u32 = 25;
+const DEBUG_LOCAL_VARIABLE: u32 = 26;
+const DEBUG_DECLARE: u32 = 28;
+const DEBUG_VALUE: u32 = 29;
+
+/// Builds `src` with debuginfo on (unlike every other test, see `CARGO_TOML`).
+fn build_with_debuginfo(src: &str) -> Module {
+    let _lock = global_lock();
+    let _var = SetEnvVar::new(&"CARGO_PROFILE_DEV_DEBUG", "2");
+    read_module(&build(src)).unwrap()
+}
+
+/// The operands (after the instruction set and number) of every `NonSemantic.Shader.DebugInfo.100`
+/// instruction `op` in `insts`.
+fn debug_insts<'a>(
+    module: &Module,
+    insts: impl IntoIterator<Item = &'a Instruction>,
+    op: u32,
+) -> Vec<&'a [Operand]> {
+    let import = module
+        .ext_inst_imports
+        .iter()
+        .find(|inst| inst.operands[0].unwrap_literal_string() == "NonSemantic.Shader.DebugInfo.100")
+        .expect("no `NonSemantic.Shader.DebugInfo.100` import")
+        .result_id
+        .unwrap();
+    insts
+        .into_iter()
+        .filter_map(|inst| match &inst.operands[..] {
+            [Operand::IdRef(set), Operand::LiteralExtInstInteger(inst_op), rest @ ..]
+                if inst.class.opcode == Op::ExtInst && *set == import && *inst_op == op =>
+            {
+                Some(rest)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn debuginfo_locals_and_inlining() {
+    let module = build_with_debuginfo(
+        r#"
+#[inline(always)]
+fn scale(x: u32, by: f32) -> f32 {
+    let scaled = x as f32 * by;
+    scaled + 1.0
+}
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] count: u32, out: &mut f32) {
+    let mut sum = 0.0;
+    for i in 0..count {
+        sum += scale(i, 0.5);
+    }
+    *out = sum;
+}
+"#,
+    );
+    let strings: HashMap<Word, &str> = module
+        .debug_string_source
+        .iter()
+        .filter(|inst| inst.class.opcode == Op::String)
+        .map(|inst| {
+            (
+                inst.result_id.unwrap(),
+                inst.operands[0].unwrap_literal_string(),
+            )
+        })
+        .collect();
+    let name_of = |operands: &[Operand]| strings[&operands[0].unwrap_id_ref()];
+    let globals = &module.types_global_values;
+
+    assert_eq!(
+        debug_insts(&module, globals, DEBUG_COMPILATION_UNIT).len(),
+        1
+    );
+
+    let functions = debug_insts(&module, globals, DEBUG_FUNCTION);
+    for name in &["main", "scale"] {
+        assert!(
+            functions.iter().any(|f| name_of(f).ends_with(name)),
+            "no `DebugFunction` for `{}`",
+            name
+        );
+    }
+
+    let locals: HashMap<Word, &str> = globals
+        .iter()
+        .filter(|inst| !debug_insts(&module, Some(*inst), DEBUG_LOCAL_VARIABLE).is_empty())
+        .map(|inst| (inst.result_id.unwrap(), name_of(&inst.operands[2..])))
+        .collect();
+    for name in &["count", "out", "sum", "i", "x", "by", "scaled"] {
+        assert!(
+            locals.values().any(|local| local == name),
+            "no `DebugLocalVariable` for `{}`",
+            name
+        );
+    }
+
+    // `sum` is only ever in an `OpVariable` before `mem2reg`, which has to replace its
+    // `DebugDeclare` with a `DebugValue` for every value stored to it.
+    let sum = locals
+        .iter()
+        .find(|&(_, &name)| name == "sum")
+        .map(|(&id, _)| id)
+        .unwrap();
+    let body = || module.functions.iter().flat_map(|f| f.all_inst_iter());
+    assert!(debug_insts(&module, body(), DEBUG_DECLARE)
+        .iter()
+        .all(|declare| declare[0].unwrap_id_ref() != sum));
+    assert!(debug_insts(&module, body(), DEBUG_VALUE)
+        .iter()
+        .any(|value| value[0].unwrap_id_ref() == sum));
+
+    // `scale` gets inlined into `main`, so its instructions must be in a `DebugScope` with
+    // an `InlinedAt` operand, pointing to a `DebugInlinedAt`.
+    let inlined_at: Vec<Word> = globals
+        .iter()
+        .filter(|inst| !debug_insts(&module, Some(*inst), DEBUG_INLINED_AT).is_empty())
+        .map(|inst| inst.result_id.unwrap())
+        .collect();
+    assert!(debug_insts(&module, body(), DEBUG_SCOPE)
+        .iter()
+        .any(|scope| scope.len() == 2 && inlined_at.contains(&scope[1].unwrap_id_ref())));
+}
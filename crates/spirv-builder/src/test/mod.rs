@@ -1,8 +1,11 @@
 mod basic;
+mod debuginfo;
+mod reproducible;
 
 use lazy_static::lazy_static;
 use rustc_codegen_spirv::rspirv;
 use std::error::Error;
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::sync::{Mutex, MutexGuard};
 
@@ -32,6 +35,25 @@ fn global_lock() -> MutexGuard<'static, ()> {
     }
 }
 
+/// Sets an environment variable (e.g. for the `cargo` invoked by `SpirvBuilder`), until dropped.
+struct SetEnvVar<'a> {
+    k: &'a OsStr,
+}
+
+impl<'a> SetEnvVar<'a> {
+    fn new(k: &'a impl AsRef<OsStr>, v: impl AsRef<OsStr>) -> Self {
+        let k = k.as_ref();
+        std::env::set_var(k, v);
+        Self { k }
+    }
+}
+
+impl<'a> Drop for SetEnvVar<'a> {
+    fn drop(&mut self) {
+        std::env::remove_var(self.k)
+    }
+}
+
 static CARGO_TOML: &str = r#"[package]
 name = "test-project"
 version = "0.1.0"
@@ -44,6 +66,8 @@ crate-type = ["dylib"]
 [profile.dev]
 overflow-checks = false
 debug-assertions = false
+# Keep debug info out of the disassembly the tests compare against.
+debug = false
 
 [dependencies]
 spirv-std = { path = "../../crates/spirv-std", features=["const-generics"] }
@@ -78,12 +102,23 @@ fn setup(src: &str) -> Result<PathBuf, Box<dyn Error>> {
 }
 
 fn build(src: &str) -> PathBuf {
+    build_with(src, |builder| builder)
+}
+
+/// Like `build`, but with `configure` applied to the `SpirvBuilder` first (e.g. to enable
+/// target features).
+fn build_with(
+    src: &str,
+    configure: impl FnOnce(crate::SpirvBuilder) -> crate::SpirvBuilder,
+) -> PathBuf {
     let project = setup(src).expect("Failed to set up project");
-    crate::SpirvBuilder::new(&project)
-        .print_metadata(false)
-        .release(false)
-        .build()
-        .expect("Failed to build test")
+    configure(
+        crate::SpirvBuilder::new(&project)
+            .print_metadata(false)
+            .release(false),
+    )
+    .build()
+    .expect("Failed to build test")
 }
 
 fn read_module(path: &Path) -> Result<rspirv::dr::Module, Box<dyn Error>> {
@@ -116,8 +151,17 @@ fn assert_str_eq(expected: &str, result: &str) {
 }
 
 fn dis_fn(src: &str, func: &str, expect: &str) {
+    dis_fn_with(src, func, expect, |builder| builder)
+}
+
+fn dis_fn_with(
+    src: &str,
+    func: &str,
+    expect: &str,
+    configure: impl FnOnce(crate::SpirvBuilder) -> crate::SpirvBuilder,
+) {
     let _lock = global_lock();
-    let module = read_module(&build(src)).unwrap();
+    let module = read_module(&build_with(src, configure)).unwrap();
     let abs_func_path = format!("test_project::{}", func);
     let id = module
         .debugs
@@ -0,0 +1,38 @@
+use super::{global_lock, SetEnvVar};
+use crate::SpirvBuilder;
+use std::path::Path;
+
+fn build(shader: &str, target_dir: &str) -> Vec<u8> {
+    // NOTE: this has to be absolute, as `cargo` runs in the shader's directory.
+    let target_dir = std::env::current_dir()
+        .unwrap()
+        .join("../../target/test-reproducible")
+        .join(target_dir);
+    let _var = SetEnvVar::new(&"CARGO_TARGET_DIR", target_dir);
+    let path = SpirvBuilder::new(Path::new("../../examples/shaders").join(shader))
+        .print_metadata(false)
+        .build()
+        .unwrap_or_else(|err| panic!("failed to build `{}`: {}", shader, err));
+    std::fs::read(path).unwrap()
+}
+
+/// Builds the example shaders into two separate target directories (so that nothing, not even
+/// the dependencies, is reused between the two builds), and checks that the outputs are identical.
+#[test]
+fn example_shaders_are_reproducible() {
+    let _lock = global_lock();
+    for &shader in &[
+        "sky-shader",
+        "simplest-shader",
+        "compute-shader",
+        "mouse-shader",
+    ] {
+        let first = build(shader, "first");
+        let second = build(shader, "second");
+        assert!(
+            first == second,
+            "building `{}` twice gave different results",
+            shader
+        );
+    }
+}
@@ -9,12 +9,14 @@ mod arithmetic;
 #[cfg(feature = "const-generics")]
 mod barrier;
 mod derivative;
+mod matrix;
 mod primitive;
 
 pub use arithmetic::*;
 #[cfg(feature = "const-generics")]
 pub use barrier::*;
 pub use derivative::*;
+pub use matrix::*;
 pub use primitive::*;
 
 /// Result is true if any component of `vector` is true, otherwise result is
@@ -138,3 +140,52 @@ pub unsafe fn vector_insert_dynamic<T: Scalar, V: Vector<T, N>, const N: usize>(
 
     result
 }
+
+/// Convert a 64-bit GPU virtual address (e.g. from `vkGetBufferDeviceAddress`)
+/// into a pointer to memory in the `PhysicalStorageBuffer` storage class.
+///
+/// See [`crate::device_pointer::DevicePointer`] for a safer wrapper.
+///
+/// # Safety
+/// The returned pointer is only valid to dereference if `address` points to
+/// memory that was made accessible to the shader, with `T`'s layout.
+#[spirv_std_macros::gpu_only]
+#[doc(alias = "OpConvertUToPtr")]
+#[inline]
+pub unsafe fn convert_u_to_ptr<T>(address: u64) -> *mut T {
+    let result: *mut T;
+
+    asm! {
+        "OpExtension \"SPV_KHR_physical_storage_buffer\"",
+        "OpCapability PhysicalStorageBufferAddresses",
+        "{result} = OpConvertUToPtr typeof{result} {address}",
+        address = in(reg) address,
+        result = out(reg) result,
+    }
+
+    result
+}
+
+/// Convert a pointer to memory in the `PhysicalStorageBuffer` storage class
+/// back into its 64-bit GPU virtual address.
+///
+/// # Safety
+/// `pointer` must have been obtained (e.g. through [`convert_u_to_ptr`]) from
+/// a `PhysicalStorageBuffer` address, and not e.g. from a local variable.
+#[spirv_std_macros::gpu_only]
+#[doc(alias = "OpConvertPtrToU")]
+#[inline]
+pub unsafe fn convert_ptr_to_u<T>(pointer: *const T) -> u64 {
+    let mut result = 0u64;
+
+    asm! {
+        "OpExtension \"SPV_KHR_physical_storage_buffer\"",
+        "OpCapability PhysicalStorageBufferAddresses",
+        "%address = OpConvertPtrToU typeof*{result} {pointer}",
+        "OpStore {result} %address",
+        pointer = in(reg) pointer,
+        result = in(reg) &mut result,
+    }
+
+    result
+}
@@ -0,0 +1,119 @@
+// The new preferred style is still to use `unsafe` blocks in `unsafe` functions
+// but the compiler/clippy hasn't caught up to that style yet, so we just
+// disable the lint.
+#![allow(unused_unsafe)]
+
+use crate::{float::Float, matrix::Matrix, vector::Vector};
+
+/// Linear-algebraic multiply of `matrix` by `vector`, where `vector` is
+/// treated as a column vector.
+#[spirv_std_macros::gpu_only]
+#[doc(alias = "OpMatrixTimesVector")]
+#[inline]
+pub fn matrix_times_vector<F, M, V, R, const ROWS: usize, const COLS: usize>(
+    matrix: M,
+    vector: V,
+) -> R
+where
+    F: Float,
+    M: Matrix<F, ROWS, COLS>,
+    V: Vector<F, COLS>,
+    R: Vector<F, ROWS>,
+{
+    let mut result = R::default();
+
+    unsafe {
+        asm! {
+            "%float_type = OpTypeFloat {width}",
+            "%column_type = OpTypeVector %float_type {rows}",
+            "%matrix_type = OpTypeMatrix %column_type {cols}",
+            "%vector_type = OpTypeVector %float_type {cols}",
+            "%matrix = OpLoad %matrix_type {matrix}",
+            "%vector = OpLoad %vector_type {vector}",
+            "%result = OpMatrixTimesVector %column_type %matrix %vector",
+            "OpStore {result} %result",
+            matrix = in(reg) &matrix,
+            vector = in(reg) &vector,
+            width = const F::WIDTH,
+            rows = const ROWS,
+            cols = const COLS,
+            result = in(reg) &mut result,
+        }
+    }
+
+    result
+}
+
+/// Linear-algebraic multiply of `left` by `right`.
+#[spirv_std_macros::gpu_only]
+#[doc(alias = "OpMatrixTimesMatrix")]
+#[inline]
+pub fn matrix_times_matrix<F, L, R, O, const ROWS: usize, const INNER: usize, const COLS: usize>(
+    left: L,
+    right: R,
+) -> O
+where
+    F: Float,
+    L: Matrix<F, ROWS, INNER>,
+    R: Matrix<F, INNER, COLS>,
+    O: Matrix<F, ROWS, COLS>,
+{
+    let mut result = O::default();
+
+    unsafe {
+        asm! {
+            "%float_type = OpTypeFloat {width}",
+            "%left_column_type = OpTypeVector %float_type {rows}",
+            "%right_column_type = OpTypeVector %float_type {inner}",
+            "%left_type = OpTypeMatrix %left_column_type {inner}",
+            "%right_type = OpTypeMatrix %right_column_type {cols}",
+            "%result_type = OpTypeMatrix %left_column_type {cols}",
+            "%left = OpLoad %left_type {left}",
+            "%right = OpLoad %right_type {right}",
+            "%result = OpMatrixTimesMatrix %result_type %left %right",
+            "OpStore {result} %result",
+            left = in(reg) &left,
+            right = in(reg) &right,
+            width = const F::WIDTH,
+            rows = const ROWS,
+            inner = const INNER,
+            cols = const COLS,
+            result = in(reg) &mut result,
+        }
+    }
+
+    result
+}
+
+/// Transpose `matrix`, i.e. its columns become the rows of the result.
+#[spirv_std_macros::gpu_only]
+#[doc(alias = "OpTranspose")]
+#[inline]
+pub fn transpose<F, M, T, const ROWS: usize, const COLS: usize>(matrix: M) -> T
+where
+    F: Float,
+    M: Matrix<F, ROWS, COLS>,
+    T: Matrix<F, COLS, ROWS>,
+{
+    let mut result = T::default();
+
+    unsafe {
+        asm! {
+            "%float_type = OpTypeFloat {width}",
+            "%column_type = OpTypeVector %float_type {rows}",
+            "%row_type = OpTypeVector %float_type {cols}",
+            "%matrix_type = OpTypeMatrix %column_type {cols}",
+            "%result_type = OpTypeMatrix %row_type {rows}",
+            "%matrix = OpLoad %matrix_type {matrix}",
+            "%result = OpTranspose %result_type %matrix",
+            "OpStore {result} %result",
+            matrix = in(reg) &matrix,
+            width = const F::WIDTH,
+            rows = const ROWS,
+            cols = const COLS,
+            result = in(reg) &mut result,
+        }
+    }
+
+    result
+}
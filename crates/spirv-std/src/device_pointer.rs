@@ -0,0 +1,110 @@
+//! Pointers to GPU memory by virtual address (aka "buffer device address").
+
+use core::marker::PhantomData;
+
+/// A 64-bit GPU virtual address (e.g. from `vkGetBufferDeviceAddress`) of a `T`
+/// in memory accessible through the `PhysicalStorageBuffer` storage class.
+///
+/// Unlike a pointer, a `DevicePointer<T>` is stored as a plain `u64`, so it can
+/// be passed in buffers and push constants, and used in recursive data structures
+/// (e.g. linked lists or BVHs), where each node points to others:
+///
+/// ```ignore
+/// #[derive(Copy, Clone)]
+/// struct Node {
+///     value: u32,
+///     next: DevicePointer<Node>,
+/// }
+/// ```
+#[repr(transparent)]
+pub struct DevicePointer<T> {
+    address: u64,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T> Copy for DevicePointer<T> {}
+impl<T> Clone for DevicePointer<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> DevicePointer<T> {
+    /// Create a `DevicePointer` from a 64-bit GPU virtual address.
+    #[inline]
+    pub fn from_address(address: u64) -> Self {
+        Self {
+            address,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The 64-bit GPU virtual address this `DevicePointer` points to.
+    #[inline]
+    pub fn address(self) -> u64 {
+        self.address
+    }
+
+    /// Returns `true` if the address is `0`.
+    #[inline]
+    pub fn is_null(self) -> bool {
+        self.address == 0
+    }
+
+    /// Offset the address by `count` elements of type `T`.
+    #[inline]
+    pub fn add(self, count: u64) -> Self {
+        Self::from_address(self.address + count * core::mem::size_of::<T>() as u64)
+    }
+
+    /// Convert to a raw pointer to memory in the `PhysicalStorageBuffer` storage class.
+    ///
+    /// # Safety
+    /// See [`crate::arch::convert_u_to_ptr`].
+    #[inline]
+    pub unsafe fn as_ptr(self) -> *mut T {
+        crate::arch::convert_u_to_ptr(self.address)
+    }
+
+    /// Borrow the `T` this `DevicePointer` points to.
+    ///
+    /// # Safety
+    /// The address must be non-null, suitably aligned for `T`, point to memory
+    /// with `T`'s layout, and that memory must not be written to while borrowed.
+    #[inline]
+    pub unsafe fn as_ref<'a>(self) -> &'a T {
+        &*self.as_ptr()
+    }
+
+    /// Mutably borrow the `T` this `DevicePointer` points to.
+    ///
+    /// # Safety
+    /// Same as [`Self::as_ref`], and the memory must not be accessed through
+    /// any other pointer while borrowed.
+    #[inline]
+    pub unsafe fn as_mut<'a>(self) -> &'a mut T {
+        &mut *self.as_ptr()
+    }
+
+    /// Read the `T` this `DevicePointer` points to.
+    ///
+    /// # Safety
+    /// See [`Self::as_ref`].
+    #[inline]
+    pub unsafe fn read(self) -> T
+    where
+        T: Copy,
+    {
+        *self.as_ptr()
+    }
+
+    /// Overwrite the `T` this `DevicePointer` points to.
+    ///
+    /// # Safety
+    /// See [`Self::as_mut`].
+    #[inline]
+    pub unsafe fn write(self, value: T) {
+        *self.as_ptr() = value;
+    }
+}
@@ -0,0 +1,553 @@
+//! Half-precision (16-bit) floating point types.
+//!
+//! Rust doesn't have a primitive `f16` type, so these types are stored as
+//! `u16`s, and `#[spirv(f16)]` makes them lower to `OpTypeFloat 16` instead.
+//! Values can only be created through constants (e.g. [`f16::ONE`]), or
+//! through conversions (e.g. [`f16::from_f32`]), which map to SPIR-V
+//! instructions.
+
+// The new preferred style is still to use `unsafe` blocks in `unsafe` functions
+// but the compiler/clippy hasn't caught up to that style yet, so we just
+// disable the lint.
+#![allow(unused_unsafe)]
+
+use crate::{float::Float, scalar::Scalar, vector::Vector};
+use core::cmp::Ordering;
+use core::num::FpCategory;
+use num_traits::{Num, NumCast, One, ToPrimitive, Zero};
+
+/// A 16-bit floating point type, lowered to `OpTypeFloat 16`.
+///
+/// Arithmetic is performed directly on 16-bit values, which requires the
+/// `Float16` capability, while operations without a matching SPIR-V
+/// instruction (e.g. those of [`num_traits::Float`]) go through `f32`.
+#[spirv(f16)]
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+pub struct f16(u16);
+
+impl f16 {
+    pub const ZERO: Self = Self(0x0000);
+    pub const NEG_ZERO: Self = Self(0x8000);
+    pub const ONE: Self = Self(0x3c00);
+    pub const NAN: Self = Self(0x7e00);
+    pub const INFINITY: Self = Self(0x7c00);
+    pub const NEG_INFINITY: Self = Self(0xfc00);
+    /// Smallest finite value.
+    pub const MIN: Self = Self(0xfbff);
+    /// Largest finite value.
+    pub const MAX: Self = Self(0x7bff);
+    /// Smallest positive normal value.
+    pub const MIN_POSITIVE: Self = Self(0x0400);
+    /// Difference between `1.0` and the next larger representable number.
+    pub const EPSILON: Self = Self(0x1400);
+
+    /// Reinterpret the bits of a `u16` as an `f16`.
+    #[spirv_std_macros::gpu_only]
+    #[doc(alias = "OpBitcast")]
+    #[inline]
+    pub fn from_bits(bits: u16) -> Self {
+        let mut result = Self::ZERO;
+
+        unsafe {
+            asm! {
+                "%half = OpTypeFloat 16",
+                "%u16 = OpTypeInt 16 0",
+                "%bits = OpLoad %u16 {bits}",
+                "%result = OpBitcast %half %bits",
+                "OpStore {result} %result",
+                bits = in(reg) &bits,
+                result = in(reg) &mut result,
+            }
+        }
+
+        result
+    }
+
+    /// Reinterpret the bits of this `f16` as a `u16`.
+    #[spirv_std_macros::gpu_only]
+    #[doc(alias = "OpBitcast")]
+    #[inline]
+    pub fn to_bits(self) -> u16 {
+        let mut result = 0;
+
+        unsafe {
+            asm! {
+                "%half = OpTypeFloat 16",
+                "%u16 = OpTypeInt 16 0",
+                "%value = OpLoad %half {value}",
+                "%result = OpBitcast %u16 %value",
+                "OpStore {result} %result",
+                value = in(reg) &self,
+                result = in(reg) &mut result,
+            }
+        }
+
+        result
+    }
+
+    /// Convert an `f32` to the nearest `f16`.
+    #[spirv_std_macros::gpu_only]
+    #[doc(alias = "OpFConvert")]
+    #[inline]
+    pub fn from_f32(value: f32) -> Self {
+        let mut result = Self::ZERO;
+
+        unsafe {
+            asm! {
+                "%half = OpTypeFloat 16",
+                "%float = OpTypeFloat 32",
+                "%value = OpLoad %float {value}",
+                "%result = OpFConvert %half %value",
+                "OpStore {result} %result",
+                value = in(reg) &value,
+                result = in(reg) &mut result,
+            }
+        }
+
+        result
+    }
+
+    /// Convert this `f16` to an `f32`, which is always exact.
+    #[spirv_std_macros::gpu_only]
+    #[doc(alias = "OpFConvert")]
+    #[inline]
+    pub fn to_f32(self) -> f32 {
+        let mut result = 0.0;
+
+        unsafe {
+            asm! {
+                "%half = OpTypeFloat 16",
+                "%float = OpTypeFloat 32",
+                "%value = OpLoad %half {value}",
+                "%result = OpFConvert %float %value",
+                "OpStore {result} %result",
+                value = in(reg) &self,
+                result = in(reg) &mut result,
+            }
+        }
+
+        result
+    }
+}
+
+unsafe impl Scalar for f16 {}
+impl crate::sealed::Sealed for f16 {}
+
+unsafe impl Float for f16 {
+    const WIDTH: usize = 16;
+}
+
+impl Default for f16 {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl From<f16> for f32 {
+    fn from(value: f16) -> Self {
+        value.to_f32()
+    }
+}
+
+impl From<f16> for f64 {
+    fn from(value: f16) -> Self {
+        value.to_f32().into()
+    }
+}
+
+// NOTE: `f16::to_f32(*self)` is used below (instead of `self.to_f32()`)
+// to avoid picking `ToPrimitive::to_f32` through auto-ref.
+impl PartialEq for f16 {
+    fn eq(&self, other: &Self) -> bool {
+        f16::to_f32(*self) == f16::to_f32(*other)
+    }
+}
+
+impl PartialOrd for f16 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        f16::to_f32(*self).partial_cmp(&f16::to_f32(*other))
+    }
+}
+
+impl core::ops::Neg for f16 {
+    type Output = Self;
+
+    #[spirv_std_macros::gpu_only]
+    #[doc(alias = "OpFNegate")]
+    #[inline]
+    fn neg(self) -> Self {
+        let mut result = Self::ZERO;
+
+        unsafe {
+            asm! {
+                "%half = OpTypeFloat 16",
+                "%value = OpLoad %half {value}",
+                "%result = OpFNegate %half %value",
+                "OpStore {result} %result",
+                value = in(reg) &self,
+                result = in(reg) &mut result,
+            }
+        }
+
+        result
+    }
+}
+
+macro_rules! impl_binary_op {
+    ($($trait:ident, $method:ident, $alias:literal, $inst:literal;)+) => {
+        $(
+            impl core::ops::$trait for f16 {
+                type Output = Self;
+
+                #[spirv_std_macros::gpu_only]
+                #[doc(alias = $alias)]
+                #[inline]
+                fn $method(self, rhs: Self) -> Self {
+                    let mut result = Self::ZERO;
+
+                    unsafe {
+                        asm! {
+                            "%half = OpTypeFloat 16",
+                            "%lhs = OpLoad %half {lhs}",
+                            "%rhs = OpLoad %half {rhs}",
+                            $inst,
+                            "OpStore {result} %result",
+                            lhs = in(reg) &self,
+                            rhs = in(reg) &rhs,
+                            result = in(reg) &mut result,
+                        }
+                    }
+
+                    result
+                }
+            }
+        )+
+    };
+}
+
+impl_binary_op! {
+    Add, add, "OpFAdd", "%result = OpFAdd %half %lhs %rhs";
+    Sub, sub, "OpFSub", "%result = OpFSub %half %lhs %rhs";
+    Mul, mul, "OpFMul", "%result = OpFMul %half %lhs %rhs";
+    Div, div, "OpFDiv", "%result = OpFDiv %half %lhs %rhs";
+    Rem, rem, "OpFRem", "%result = OpFRem %half %lhs %rhs";
+}
+
+impl Zero for f16 {
+    fn zero() -> Self {
+        Self::ZERO
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+}
+
+impl One for f16 {
+    fn one() -> Self {
+        Self::ONE
+    }
+}
+
+impl Num for f16 {
+    type FromStrRadixErr = <f32 as Num>::FromStrRadixErr;
+
+    fn from_str_radix(str: &str, radix: u32) -> Result<Self, Self::FromStrRadixErr> {
+        f32::from_str_radix(str, radix).map(Self::from_f32)
+    }
+}
+
+impl ToPrimitive for f16 {
+    fn to_i64(&self) -> Option<i64> {
+        f16::to_f32(*self).to_i64()
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        f16::to_f32(*self).to_u64()
+    }
+
+    fn to_f32(&self) -> Option<f32> {
+        Some(f16::to_f32(*self))
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        Some(f16::to_f32(*self).into())
+    }
+}
+
+impl NumCast for f16 {
+    fn from<T: ToPrimitive>(n: T) -> Option<Self> {
+        n.to_f32().map(Self::from_f32)
+    }
+}
+
+/// Implements unary `num_traits::Float` methods by converting to `f32`, which
+/// can represent every `f16` value exactly, and converting the result back.
+macro_rules! delegate_to_f32 {
+    (bool: $($method:ident),+) => {
+        $(
+            #[inline]
+            fn $method(self) -> bool {
+                num_traits::Float::$method(self.to_f32())
+            }
+        )+
+    };
+    (Self: $($method:ident),+) => {
+        $(
+            #[inline]
+            fn $method(self) -> Self {
+                Self::from_f32(num_traits::Float::$method(self.to_f32()))
+            }
+        )+
+    };
+}
+
+/// Like `delegate_to_f32!`, but for methods taking other `f16` arguments.
+macro_rules! delegate_binary_to_f32 {
+    ($($method:ident($($arg:ident),+)),+) => {
+        $(
+            #[inline]
+            fn $method(self, $($arg: Self),+) -> Self {
+                Self::from_f32(num_traits::Float::$method(self.to_f32(), $($arg.to_f32()),+))
+            }
+        )+
+    };
+}
+
+impl num_traits::Float for f16 {
+    fn nan() -> Self {
+        Self::NAN
+    }
+
+    fn infinity() -> Self {
+        Self::INFINITY
+    }
+
+    fn neg_infinity() -> Self {
+        Self::NEG_INFINITY
+    }
+
+    fn neg_zero() -> Self {
+        Self::NEG_ZERO
+    }
+
+    fn min_value() -> Self {
+        Self::MIN
+    }
+
+    fn min_positive_value() -> Self {
+        Self::MIN_POSITIVE
+    }
+
+    fn max_value() -> Self {
+        Self::MAX
+    }
+
+    fn epsilon() -> Self {
+        Self::EPSILON
+    }
+
+    // NOTE: unlike most methods, these can't go through `f32`, as
+    // subnormal `f16` values are normal `f32` values.
+    fn is_normal(self) -> bool {
+        self.classify() == FpCategory::Normal
+    }
+
+    fn classify(self) -> FpCategory {
+        let bits = self.to_bits();
+        match (bits & 0x7c00, bits & 0x03ff) {
+            (0, 0) => FpCategory::Zero,
+            (0, _) => FpCategory::Subnormal,
+            (0x7c00, 0) => FpCategory::Infinite,
+            (0x7c00, _) => FpCategory::Nan,
+            _ => FpCategory::Normal,
+        }
+    }
+
+    fn integer_decode(self) -> (u64, i16, i8) {
+        num_traits::Float::integer_decode(self.to_f32())
+    }
+
+    fn powi(self, n: i32) -> Self {
+        Self::from_f32(num_traits::Float::powi(self.to_f32(), n))
+    }
+
+    fn sin_cos(self) -> (Self, Self) {
+        let (sin, cos) = num_traits::Float::sin_cos(self.to_f32());
+        (Self::from_f32(sin), Self::from_f32(cos))
+    }
+
+    delegate_to_f32!(
+        bool: is_nan,
+        is_infinite,
+        is_finite,
+        is_sign_positive,
+        is_sign_negative
+    );
+
+    delegate_to_f32!(
+        Self: floor,
+        ceil,
+        round,
+        trunc,
+        fract,
+        abs,
+        signum,
+        recip,
+        sqrt,
+        exp,
+        exp2,
+        ln,
+        log2,
+        log10,
+        cbrt,
+        sin,
+        cos,
+        tan,
+        asin,
+        acos,
+        atan,
+        exp_m1,
+        ln_1p,
+        sinh,
+        cosh,
+        tanh,
+        asinh,
+        acosh,
+        atanh
+    );
+
+    delegate_binary_to_f32!(
+        mul_add(a, b),
+        powf(n),
+        log(base),
+        max(other),
+        min(other),
+        abs_sub(other),
+        hypot(other),
+        atan2(other)
+    );
+}
+
+macro_rules! half_vector {
+    ($(#[$attr:meta])* $name:ident, $len:literal, $($component:ident),+) => {
+        $(#[$attr])*
+        #[spirv(f16)]
+        #[derive(Copy, Clone)]
+        #[cfg_attr(target_arch = "spirv", repr(simd))]
+        #[cfg_attr(not(target_arch = "spirv"), repr(C))]
+        // The components are only accessed through SPIR-V instructions.
+        #[allow(dead_code)]
+        pub struct $name {
+            $($component: u16),+
+        }
+
+        impl $name {
+            pub const ZERO: Self = Self { $($component: 0),+ };
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self::ZERO
+            }
+        }
+
+        unsafe impl Vector<f16, $len> for $name {}
+    };
+}
+
+half_vector! {
+    /// A vector of 2 [`f16`]s, lowered to `OpTypeVector`.
+    F16Vec2, 2, x, y
+}
+half_vector! {
+    /// A vector of 3 [`f16`]s, lowered to `OpTypeVector`.
+    F16Vec3, 3, x, y, z
+}
+half_vector! {
+    /// A vector of 4 [`f16`]s, lowered to `OpTypeVector`.
+    F16Vec4, 4, x, y, z, w
+}
+
+impl F16Vec2 {
+    #[spirv_std_macros::gpu_only]
+    #[doc(alias = "OpCompositeConstruct")]
+    #[inline]
+    pub fn new(x: f16, y: f16) -> Self {
+        let mut result = Self::ZERO;
+
+        unsafe {
+            asm! {
+                "%half = OpTypeFloat 16",
+                "%vector_type = OpTypeVector %half 2",
+                "%x = OpLoad %half {x}",
+                "%y = OpLoad %half {y}",
+                "%result = OpCompositeConstruct %vector_type %x %y",
+                "OpStore {result} %result",
+                x = in(reg) &x,
+                y = in(reg) &y,
+                result = in(reg) &mut result,
+            }
+        }
+
+        result
+    }
+}
+
+impl F16Vec3 {
+    #[spirv_std_macros::gpu_only]
+    #[doc(alias = "OpCompositeConstruct")]
+    #[inline]
+    pub fn new(x: f16, y: f16, z: f16) -> Self {
+        let mut result = Self::ZERO;
+
+        unsafe {
+            asm! {
+                "%half = OpTypeFloat 16",
+                "%vector_type = OpTypeVector %half 3",
+                "%x = OpLoad %half {x}",
+                "%y = OpLoad %half {y}",
+                "%z = OpLoad %half {z}",
+                "%result = OpCompositeConstruct %vector_type %x %y %z",
+                "OpStore {result} %result",
+                x = in(reg) &x,
+                y = in(reg) &y,
+                z = in(reg) &z,
+                result = in(reg) &mut result,
+            }
+        }
+
+        result
+    }
+}
+
+impl F16Vec4 {
+    #[spirv_std_macros::gpu_only]
+    #[doc(alias = "OpCompositeConstruct")]
+    #[inline]
+    pub fn new(x: f16, y: f16, z: f16, w: f16) -> Self {
+        let mut result = Self::ZERO;
+
+        unsafe {
+            asm! {
+                "%half = OpTypeFloat 16",
+                "%vector_type = OpTypeVector %half 4",
+                "%x = OpLoad %half {x}",
+                "%y = OpLoad %half {y}",
+                "%z = OpLoad %half {z}",
+                "%w = OpLoad %half {w}",
+                "%result = OpCompositeConstruct %vector_type %x %y %z %w",
+                "OpStore {result} %result",
+                x = in(reg) &x,
+                y = in(reg) &y,
+                z = in(reg) &z,
+                w = in(reg) &w,
+                result = in(reg) &mut result,
+            }
+        }
+
+        result
+    }
+}
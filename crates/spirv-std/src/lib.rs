@@ -75,8 +75,11 @@
 pub extern crate spirv_std_macros as macros;
 
 pub mod arch;
+pub mod device_pointer;
 pub mod float;
+pub mod half;
 pub mod integer;
+pub mod matrix;
 pub mod memory;
 pub mod scalar;
 pub(crate) mod sealed;
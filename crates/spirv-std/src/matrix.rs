@@ -0,0 +1,6 @@
+/// Abstract trait representing a SPIR-V matrix type, i.e. a `#[spirv(matrix)]`
+/// struct made of `COLS` column vectors, each with `ROWS` components.
+pub unsafe trait Matrix<T: crate::float::Float, const ROWS: usize, const COLS: usize>:
+    Default
+{
+}
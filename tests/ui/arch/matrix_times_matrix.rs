@@ -0,0 +1,22 @@
+// Test `OpMatrixTimesMatrix`
+// build-pass
+
+use spirv_std::arch;
+
+#[spirv(matrix)]
+#[derive(Copy, Clone, Default)]
+pub struct Mat2 {
+    pub x_axis: glam::Vec2,
+    pub y_axis: glam::Vec2,
+}
+
+unsafe impl spirv_std::matrix::Matrix<f32, 2, 2> for Mat2 {}
+
+#[spirv(fragment)]
+pub fn main(input: Mat2, output: &mut Mat2) {
+    let identity = Mat2 {
+        x_axis: glam::Vec2::new(1.0, 0.0),
+        y_axis: glam::Vec2::new(0.0, 1.0),
+    };
+    *output = arch::matrix_times_matrix(input, identity);
+}
@@ -0,0 +1,24 @@
+// Test `OpMatrixTimesVector`, with the matrix coming from a uniform buffer
+// (which requires `ColMajor` and `MatrixStride` decorations).
+// build-pass
+
+use spirv_std::arch;
+
+#[spirv(matrix)]
+#[derive(Copy, Clone, Default)]
+pub struct Mat3 {
+    pub x_axis: glam::Vec3,
+    pub y_axis: glam::Vec3,
+    pub z_axis: glam::Vec3,
+}
+
+unsafe impl spirv_std::matrix::Matrix<f32, 3, 3> for Mat3 {}
+
+#[spirv(fragment)]
+pub fn main(
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] input: &Mat3,
+    output: &mut glam::Vec3,
+) {
+    let vector = arch::matrix_times_vector(*input, glam::Vec3::new(1.0, 2.0, 3.0));
+    *output = vector;
+}
@@ -0,0 +1,19 @@
+// Test `OpTranspose`
+// build-pass
+
+use spirv_std::arch;
+
+#[spirv(matrix)]
+#[derive(Copy, Clone, Default)]
+pub struct Mat2 {
+    pub x_axis: glam::Vec2,
+    pub y_axis: glam::Vec2,
+}
+
+unsafe impl spirv_std::matrix::Matrix<f32, 2, 2> for Mat2 {}
+
+#[spirv(fragment)]
+pub fn main(input: Mat2, output: &mut Mat2) {
+    let transposed: Mat2 = arch::transpose(input);
+    *output = transposed;
+}
@@ -0,0 +1,34 @@
+// Test that `memcpy` (here via `copy_nonoverlapping`) copies arrays one element at a time
+// when the count is only known at runtime.
+// build-pass
+
+use spirv_std as _;
+
+#[derive(Copy, Clone)]
+struct Data {
+    a: u32,
+    b: f32,
+    c: bool,
+}
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] i: u32, output: &mut u32) {
+    let src = [i; 4];
+    let mut dst = [0; 4];
+    let data_src = [Data {
+        a: i,
+        b: 1.0,
+        c: true,
+    }; 2];
+    let mut data_dst = [Data {
+        a: 0,
+        b: 0.0,
+        c: false,
+    }; 2];
+    let count = (i & 1) as usize;
+    unsafe {
+        core::ptr::copy_nonoverlapping(&src, &mut dst, count);
+        core::ptr::copy_nonoverlapping(&data_src, &mut data_dst, count);
+    }
+    *output = dst[3] + data_dst[1].a + data_dst[0].b as u32 + data_dst[1].c as u32;
+}
@@ -0,0 +1,28 @@
+// Test that `memset` (here via `write_bytes`) works on structs and arrays of them.
+// build-pass
+
+use spirv_std as _;
+
+#[derive(Copy, Clone)]
+struct Data {
+    a: u32,
+    b: f32,
+    c: bool,
+    v: [u32; 4],
+}
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] i: u32, output: &mut u32) {
+    let mut data = Data {
+        a: i,
+        b: 2.0,
+        c: true,
+        v: [i; 4],
+    };
+    let mut array = [data; 2];
+    unsafe {
+        core::ptr::write_bytes(&mut data, 0, 1);
+        core::ptr::write_bytes(&mut array, 0, 1);
+    }
+    *output = data.a + data.v[3] + data.c as u32 + array[1].a + data.b as u32;
+}
@@ -0,0 +1,16 @@
+// Test that `memset` (here via `write_bytes`) works with a count only known at runtime,
+// by setting array elements one at a time in a loop.
+// build-pass
+
+use spirv_std as _;
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] i: u32, output: &mut u32) {
+    let mut array = [i; 4];
+    let mut flags = [true; 4];
+    unsafe {
+        core::ptr::write_bytes(&mut array, 0, (i & 1) as usize);
+        core::ptr::write_bytes(&mut flags, 0, (i & 1) as usize);
+    }
+    *output = array[3] + flags[0] as u32;
+}
@@ -0,0 +1,31 @@
+// Test that `memset` (here via `write_bytes`) works with nonzero and dynamic fill bytes,
+// including on `bool`s (which become `true` for any nonzero byte).
+// build-pass
+
+use spirv_std as _;
+
+#[derive(Copy, Clone)]
+struct Data {
+    a: u32,
+    b: f32,
+    c: bool,
+    v: [u32; 4],
+}
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] i: u32, output: &mut u32) {
+    let mut data = Data {
+        a: i,
+        b: 2.0,
+        c: false,
+        v: [i; 4],
+    };
+    let mut other = data;
+    let mut flags = [false; 4];
+    unsafe {
+        core::ptr::write_bytes(&mut data, 0xab, 1);
+        core::ptr::write_bytes(&mut other, i as u8, 1);
+        core::ptr::write_bytes(&mut flags, i as u8, 1);
+    }
+    *output = data.a + data.v[3] + data.c as u32 + other.a + other.b as u32 + flags[2] as u32;
+}
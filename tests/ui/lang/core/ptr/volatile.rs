@@ -0,0 +1,16 @@
+// Test that `read_volatile`/`write_volatile` pass validation, with the
+// memory operands required by the (default) Vulkan memory model, which
+// are only valid on pointers into memory shared between invocations.
+// build-pass
+
+use spirv_std as _;
+
+#[spirv(compute(threads(1)))]
+pub fn main(#[spirv(storage_buffer, descriptor_set = 0, binding = 0)] flags: &mut [u32]) {
+    let mut local = 0;
+    unsafe {
+        while core::ptr::read_volatile(&flags[0]) == 0 {}
+        core::ptr::write_volatile(&mut local, flags[2]);
+        core::ptr::write_volatile(&mut flags[1], core::ptr::read_volatile(&local));
+    }
+}
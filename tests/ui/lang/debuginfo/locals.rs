@@ -0,0 +1,27 @@
+// Test that source-level debug info (locals, structs, loops and inlined calls) is emitted
+// without breaking the rest of the compilation.
+// build-pass
+// compile-flags: -C debuginfo=2
+
+use spirv_std as _;
+
+struct Pair {
+    x: u32,
+    y: f32,
+}
+
+#[inline(always)]
+fn scale(pair: &Pair, by: f32) -> f32 {
+    let scaled = pair.x as f32 * by;
+    scaled + pair.y
+}
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] count: u32, out: &mut f32) {
+    let mut sum = 0.0;
+    for i in 0..count {
+        let pair = Pair { x: i, y: sum };
+        sum = scale(&pair, 0.5);
+    }
+    *out = sum;
+}
@@ -0,0 +1,43 @@
+// Test that calls through `&dyn Trait` and `fn` pointers are devirtualized by
+// the linker, as long as every possible callee is known.
+// build-pass
+
+use spirv_std as _;
+
+trait Material {
+    fn shade(&self, x: f32) -> f32;
+}
+
+struct Diffuse {
+    albedo: f32,
+}
+
+impl Material for Diffuse {
+    fn shade(&self, x: f32) -> f32 {
+        self.albedo * x
+    }
+}
+
+struct Emissive;
+
+impl Material for Emissive {
+    fn shade(&self, _: f32) -> f32 {
+        1.0
+    }
+}
+
+fn double(x: f32) -> f32 {
+    x * 2.0
+}
+
+fn square(x: f32) -> f32 {
+    x * x
+}
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] i: u32, x: f32, output: &mut f32) {
+    let diffuse = Diffuse { albedo: 0.5 };
+    let material: &dyn Material = if i == 0 { &diffuse } else { &Emissive };
+    let op: fn(f32) -> f32 = if i == 1 { double } else { square };
+    *output = op(material.shade(x));
+}
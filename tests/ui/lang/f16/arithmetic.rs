@@ -0,0 +1,11 @@
+// Test that `f16` arithmetic and conversions work.
+// build-pass
+
+use spirv_std::half::f16;
+
+#[spirv(fragment)]
+pub fn main(i: f32, o: &mut f32) {
+    let half = f16::from_f32(i);
+    let result = (half + f16::ONE) * half - half / f16::from_f32(2.0);
+    *o = (-result % half).to_f32();
+}
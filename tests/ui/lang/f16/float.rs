@@ -0,0 +1,11 @@
+// Test that `f16` can be used through `num_traits::Float`.
+// build-pass
+
+use spirv_std::half::f16;
+use spirv_std::num_traits::Float;
+
+#[spirv(fragment)]
+pub fn main(i: f32, o: &mut f32) {
+    let half = f16::from_f32(i);
+    *o = half.signum().max(half.sqrt()).to_f32();
+}
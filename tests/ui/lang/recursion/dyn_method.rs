@@ -0,0 +1,30 @@
+// Test that recursion through calls of `&dyn Trait` methods is reported, once
+// devirtualization turns them into direct calls.
+// build-fail
+
+use spirv_std as _;
+
+trait Node {
+    fn depth(&self, n: u32) -> u32;
+}
+
+struct Leaf;
+
+impl Node for Leaf {
+    fn depth(&self, n: u32) -> u32 {
+        if n == 0 {
+            0
+        } else {
+            depth_of(self, n - 1) + 1
+        }
+    }
+}
+
+fn depth_of(node: &dyn Node, n: u32) -> u32 {
+    node.depth(n)
+}
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] n: u32, output: &mut u32) {
+    *output = depth_of(&Leaf, n);
+}
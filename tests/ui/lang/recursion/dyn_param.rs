@@ -0,0 +1,30 @@
+// Test that recursion in a function taking a `&dyn Trait` is reported, instead of
+// hanging the inlining done for devirtualization.
+// build-fail
+
+use spirv_std as _;
+
+trait Node {
+    fn depth(&self, n: u32) -> u32;
+}
+
+struct Leaf;
+
+impl Node for Leaf {
+    fn depth(&self, n: u32) -> u32 {
+        n
+    }
+}
+
+fn depth_of(node: &dyn Node, n: u32) -> u32 {
+    if n > 8 {
+        depth_of(node, n / 2)
+    } else {
+        node.depth(n)
+    }
+}
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] n: u32, output: &mut u32) {
+    *output = depth_of(&Leaf, n);
+}
@@ -0,0 +1,16 @@
+// build-fail
+
+use spirv_std as _;
+
+fn is_even(n: u32) -> bool {
+    n == 0 || is_odd(n - 1)
+}
+
+fn is_odd(n: u32) -> bool {
+    n != 0 && is_even(n - 1)
+}
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] n: u32, output: &mut u32) {
+    *output = is_even(n) as u32;
+}
@@ -0,0 +1,20 @@
+// Test that `u128`/`i128` arithmetic, shifts and comparisons work, despite
+// SPIR-V not supporting 128-bit integers.
+// build-pass
+
+use spirv_std as _;
+
+#[spirv(compute(threads(1)))]
+pub fn main(
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] input: &[u128],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] output: &mut [u128],
+) {
+    let (a, b) = (input[0], input[1]);
+    output[0] = a.wrapping_add(b);
+    output[1] = a.wrapping_sub(b) ^ 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210;
+    output[2] = a.wrapping_mul(b);
+    output[3] = a.wrapping_shl(b as u32) | a.wrapping_shr(3);
+    output[4] = (a as i128).wrapping_shr(65) as u128;
+    output[5] = (a < b) as u128 + ((a as i128) >= (b as i128)) as u128 + (a == b) as u128;
+    output[6] = (a as u32 as u128) + (a as i64 as i128 as u128);
+}
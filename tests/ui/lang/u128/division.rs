@@ -0,0 +1,17 @@
+// Test that `u128`/`i128` division and remainder work, despite SPIR-V not
+// supporting 128-bit integers.
+// build-pass
+
+use spirv_std as _;
+
+#[spirv(compute(threads(1)))]
+pub fn main(
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] input: &[u128],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] output: &mut [u128],
+) {
+    let (a, b) = (input[0], input[1]);
+    output[0] = a.wrapping_div(b);
+    output[1] = a.wrapping_rem(b);
+    output[2] = (a as i128).wrapping_div(b as i128) as u128;
+    output[3] = (a as i128).wrapping_rem(b as i128) as u128;
+}
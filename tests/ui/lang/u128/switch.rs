@@ -0,0 +1,23 @@
+// Test that `match`es on `u128`/`i128` values work, by comparing against each
+// case in turn.
+// build-pass
+
+use spirv_std as _;
+
+#[spirv(compute(threads(1)))]
+pub fn main(
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] input: &[u128],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] output: &mut [u32],
+) {
+    output[0] = match input[0] {
+        0 => 1,
+        0x1_0000_0000_0000_0000 => 2,
+        u128::MAX => 3,
+        _ => 4,
+    };
+    output[1] = match input[1] as i128 {
+        i128::MIN => 1,
+        -1 => 2,
+        _ => 3,
+    };
+}
@@ -0,0 +1,21 @@
+// Test that `u64`/`i64` still work when emulated with 32-bit words, for targets
+// without the `Int64` capability, and that `f64` narrows to `f32` alongside it
+// (only outside of buffers, whose layout can't change, see `narrowed_f64_buffer.rs`).
+// build-pass
+// compile-flags: -C target-feature=+emulate-int64,+narrow-float64
+
+use spirv_std as _;
+
+#[spirv(compute(threads(1)))]
+pub fn main(
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] input: &[u64],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] output: &mut [u64],
+) {
+    let (a, b) = (input[0], input[1]);
+    output[0] = a.wrapping_add(b) ^ a.wrapping_mul(b);
+    output[1] = a.wrapping_shl(b as u32) | (a as i64).wrapping_shr(7) as u64;
+    output[2] = a / b.max(1) + a % b.max(1);
+    output[3] = (a < b) as u64 + ((a as i64) >= (b as i64)) as u64;
+    let f = a as f64 * 0.5 + (b as i64) as f64;
+    output[4] = f as u64;
+}
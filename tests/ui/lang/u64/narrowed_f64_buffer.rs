@@ -0,0 +1,10 @@
+// Test that `f64`s in buffers aren't narrowed to `f32`, as that would change their layout.
+// build-fail
+// compile-flags: -C target-feature=+narrow-float64
+
+use spirv_std as _;
+
+#[spirv(compute(threads(1)))]
+pub fn main(#[spirv(storage_buffer, descriptor_set = 0, binding = 0)] floats: &mut [f64]) {
+    floats[1] = floats[0] * 0.5;
+}
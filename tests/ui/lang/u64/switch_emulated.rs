@@ -0,0 +1,25 @@
+// Test that `match`es on `u64`/`i64` values beyond 32 bits still work when
+// 64-bit integers are emulated, by comparing against each case in turn.
+// build-pass
+// compile-flags: -C target-feature=+emulate-int64
+
+use spirv_std as _;
+
+#[spirv(compute(threads(1)))]
+pub fn main(
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] input: &[u64],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] output: &mut [u32],
+) {
+    output[0] = match input[0] {
+        0 => 1,
+        0x1_0000_0000 => 2,
+        u64::MAX => 3,
+        _ => 4,
+    };
+    output[1] = match input[1] as i64 {
+        i64::MIN => 1,
+        -0x1_0000_0000 => 2,
+        -1 => 3,
+        _ => 4,
+    };
+}
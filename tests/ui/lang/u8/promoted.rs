@@ -0,0 +1,19 @@
+// Test that `u8`/`i8`/`u16`/`i16` still work when promoted to 32 bits, for
+// targets without the `Int8`/`Int16` capabilities.
+// build-pass
+// compile-flags: -C target-feature=+promote-small-ints
+
+use spirv_std as _;
+
+#[spirv(compute(threads(1)))]
+pub fn main(
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] bytes: &mut [u8],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] halves: &mut [u16],
+) {
+    let (a, b) = (bytes[0], bytes[1]);
+    bytes[2] = a.wrapping_add(b).wrapping_mul(3);
+    bytes[3] = ((a as i8).wrapping_neg() >> 2) as u8;
+    bytes[4] = (a < b) as u8 + ((a as i8) < (b as i8)) as u8;
+    halves[0] = (a as u16) << 8 | b as u16;
+    halves[1] = (halves[0] as i16 / -3) as u16 ^ !halves[0];
+}
@@ -17,21 +17,21 @@
 // * builtin: `position`
 
 // NOTE(eddyb) accounting for the number of errors this test actually produces:
-// * 422 "attribute is only valid on" errors (see `invalid-target.stderr`)
+// * 539 "attribute is only valid on" errors (see `invalid-target.stderr`)
 // * 40 `#[spirv(...)]` (excluding `macro_rules!`, which doesn't get the above error)
-// * at most 11 attributes per `#[spirv(...)]`, so an upper bound of `40*11 = 440`
-// * the difference between 440 and 422 is 18, i.e. valid attributes, made up of:
-//   * 4 on `_Struct`
+// * at most 14 attributes per `#[spirv(...)]`, so an upper bound of `40*14 = 560`
+// * the difference between 560 and 539 is 21, i.e. valid attributes, made up of:
+//   * 6 on `_Struct`
 //   * 8 on functions, i.e. 2 on each of:
 //     * `_inherent_method`
 //     * `_trait_method_with_default`,
 //     * `_trait_method` (in `impl _Trait for ()`)
 //     * `_fn`
-//   * 5 on `_entry_param`
+//   * 6 on `_entry_param`
 //   * 1 on `_closure`
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -42,7 +42,7 @@ macro_rules! _macro {
 }
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -51,7 +51,7 @@ macro_rules! _macro {
 extern crate spirv_std as _;
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -60,7 +60,7 @@ extern crate spirv_std as _;
 use spirv_std as _;
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -69,7 +69,7 @@ use spirv_std as _;
 mod _mod {}
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -77,7 +77,7 @@ mod _mod {}
 )]
 extern "C" {
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -86,7 +86,7 @@ extern "C" {
     type _ForeignTy;
 
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -95,7 +95,7 @@ extern "C" {
     static _FOREIGN_STATIC: ();
 
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -105,7 +105,7 @@ extern "C" {
 }
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -114,7 +114,7 @@ extern "C" {
 static _STATIC: () = ();
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -123,7 +123,7 @@ static _STATIC: () = ();
 const _CONST: () = ();
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -132,7 +132,7 @@ const _CONST: () = ();
 type _TyAlias = ();
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -145,7 +145,7 @@ fn _opaque_ty_definer() -> _OpaqueTy {
 }
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -153,7 +153,7 @@ fn _opaque_ty_definer() -> _OpaqueTy {
 )]
 enum _Enum {
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -161,7 +161,7 @@ enum _Enum {
     )]
     _Variant {
         #[spirv(
-            sampler, block, sampled_image, // struct-only (incl. `image_type`)
+            sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
             image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
             vertex, // fn-only
             uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -172,7 +172,7 @@ enum _Enum {
 }
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -180,7 +180,7 @@ enum _Enum {
 )]
 union _Union {
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -196,7 +196,7 @@ union _Union {
 )]
 struct _Struct {
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -206,7 +206,7 @@ struct _Struct {
 }
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -214,7 +214,7 @@ struct _Struct {
 )]
 impl _Struct {
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -223,7 +223,7 @@ impl _Struct {
     const _INHERENT_ASSOC_CONST: () = ();
 
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
     )]
@@ -231,7 +231,7 @@ impl _Struct {
 }
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -240,7 +240,7 @@ impl _Struct {
 trait _TraitAlias = Copy;
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -248,7 +248,7 @@ trait _TraitAlias = Copy;
 )]
 trait _Trait {
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -257,7 +257,7 @@ trait _Trait {
     type _AssocTy;
 
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -266,7 +266,7 @@ trait _Trait {
     const _TRAIT_ASSOC_CONST: ();
 
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -275,7 +275,7 @@ trait _Trait {
     fn _trait_method();
 
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
     )]
@@ -283,7 +283,7 @@ trait _Trait {
 }
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     vertex, // fn-only
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -291,7 +291,7 @@ trait _Trait {
 )]
 impl _Trait for () {
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -300,7 +300,7 @@ impl _Trait for () {
     type _AssocTy = ();
 
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -309,7 +309,7 @@ impl _Trait for () {
     const _TRAIT_ASSOC_CONST: () = ();
 
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
     )]
@@ -317,13 +317,13 @@ impl _Trait for () {
 }
 
 #[spirv(
-    sampler, block, sampled_image, // struct-only (incl. `image_type`)
+    sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
     image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
     uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
 )]
 fn _fn(
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         unroll_loops, // fn/closure-only
@@ -331,7 +331,7 @@ fn _fn(
     _entry_param: (),
 ) {
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -340,7 +340,7 @@ fn _fn(
     let _statement = ();
 
     let _closure = #[spirv(
-            sampler, block, sampled_image, // struct-only (incl. `image_type`)
+            sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
             image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
             vertex, // fn-only
             uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -349,7 +349,7 @@ fn _fn(
 
     (
         #[spirv(
-            sampler, block, sampled_image, // struct-only (incl. `image_type`)
+            sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
             image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
             vertex, // fn-only
             uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -360,7 +360,7 @@ fn _fn(
 
     match () {
         #[spirv(
-            sampler, block, sampled_image, // struct-only (incl. `image_type`)
+            sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
             image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
             vertex, // fn-only
             uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
@@ -372,21 +372,21 @@ fn _fn(
 
 fn _fn_with_generics<
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
         unroll_loops, // fn/closure-only
     )] '_lifetime_param,
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
         unroll_loops, // fn/closure-only
     )] _TyParam,
     #[spirv(
-        sampler, block, sampled_image, // struct-only (incl. `image_type`)
+        sampler, block, sampled_image, matrix, f16, // struct-only (incl. `image_type`)
         image_type(dim = "Dim2D", depth = 0, arrayed = 0, multisampled = 0, sampled = 1, image_format = "Unknown"),
         vertex, // fn-only
         uniform, position, descriptor_set = 0, binding = 0, flat, invariant, // param-only
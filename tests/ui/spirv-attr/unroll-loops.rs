@@ -0,0 +1,32 @@
+// Tests that both forms of the unroll_loops attribute work
+// build-pass
+
+use spirv_std as _;
+
+#[spirv(unroll_loops)]
+fn sum(values: &[u32; 4]) -> u32 {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < 4 {
+        sum += values[i];
+        i += 1;
+    }
+    sum
+}
+
+#[spirv(unroll_loops(max = 2))]
+fn sum_first(values: &[u32; 4], count: u32) -> u32 {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < count as usize {
+        sum += values[i];
+        i += 1;
+    }
+    sum
+}
+
+#[spirv(fragment)]
+pub fn main(#[spirv(flat)] count: u32, output: &mut u32) {
+    let values = [1, 2, 3, 4];
+    *output = sum(&values) + sum_first(&values, count);
+}
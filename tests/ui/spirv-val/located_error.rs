@@ -0,0 +1,21 @@
+// Test that `spirv-val` errors are pointed at the Rust source of the instruction
+// they mention, with the failing instruction in a note.
+
+// build-fail
+// normalize-stderr-test "\d+\[%" -> "N[%"
+// normalize-stderr-test "%\d+" -> "%N"
+
+use spirv_std as _;
+
+#[spirv(fragment)]
+pub fn main(x: f32, out: &mut f32) {
+    unsafe {
+        asm!(
+            "%u32 = OpTypeInt 32 0",
+            "%bits = OpBitcast %u32 {x}",
+            "OpStore {out} %bits",
+            x = in(reg) x,
+            out = in(reg) out,
+        );
+    }
+}
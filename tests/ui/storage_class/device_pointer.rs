@@ -0,0 +1,30 @@
+// Test walking a linked list through `PhysicalStorageBuffer` device pointers.
+
+// build-pass
+use spirv_std::device_pointer::DevicePointer;
+
+#[derive(Copy, Clone)]
+pub struct Node {
+    pub value: u32,
+    pub next: DevicePointer<Node>,
+}
+
+#[derive(Copy, Clone)]
+pub struct ShaderConstants {
+    pub list: DevicePointer<Node>,
+    pub output: DevicePointer<u32>,
+}
+
+#[spirv(compute(threads(1)))]
+pub fn main(#[spirv(push_constant)] constants: &ShaderConstants) {
+    let mut sum = 0;
+    let mut node = constants.list;
+    while !node.is_null() {
+        let current = unsafe { node.read() };
+        sum += current.value;
+        node = current.next;
+    }
+    unsafe {
+        constants.output.write(sum);
+    }
+}
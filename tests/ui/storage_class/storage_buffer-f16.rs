@@ -0,0 +1,17 @@
+// Test that vectors of `f16`s can be used in storage buffers
+// (which requires `StorageBuffer16BitAccess`).
+
+// build-pass
+
+use spirv_std::arch;
+use spirv_std::half::{f16, F16Vec4};
+
+#[spirv(compute(threads(1)))]
+pub fn main(
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] input: &[F16Vec4],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] output: &mut [F16Vec4],
+) {
+    let two = f16::from_f32(2.0);
+    output[0] = arch::vector_times_scalar(input[0], two);
+    output[1] = F16Vec4::new(two, f16::ONE, f16::ZERO, -two);
+}